@@ -1,12 +1,16 @@
 use glib::clone;
-use log::{debug, info};
+use log::debug;
 use std::convert::TryInto;
+use std::path::PathBuf;
 
 use crate::app::AppRuntime;
-use crate::appop::AppOp;
+use crate::appop::{AppOp, DirectoryNetwork};
 use crate::model::message::Message;
+use crate::model::room::RoomTag;
+use crate::util;
 use crate::util::i18n::i18n;
-use crate::widgets::FileDialog::open;
+use crate::widgets::FileDialog::open_multiple;
+use crate::widgets::RoomSortOrder;
 use gio::prelude::*;
 use gio::SimpleAction;
 use gtk::prelude::*;
@@ -82,15 +86,54 @@ pub fn new(appop: &AppOp) {
     let main_menu = SimpleAction::new("main_menu", None);
 
     let open_room = SimpleAction::new("open-room", glib::VariantTy::new("s").ok());
+    // Joins the room when needed, for the upgraded rooms
+    let go_to_room = SimpleAction::new("go-to-room", glib::VariantTy::new("s").ok());
+    let mark_room_as_read = SimpleAction::new("mark-room-as-read", glib::VariantTy::new("s").ok());
+    // The room id, a space and the text, room ids can't have spaces
+    let reply = SimpleAction::new("reply", glib::VariantTy::new("s").ok());
+    // The room id, a space and the name of the tag to add to the room
+    let set_room_tag = SimpleAction::new("set-room-tag", glib::VariantTy::new("s").ok());
+    // The room id, a space and the name of the tag to remove from the room
+    let remove_room_tag = SimpleAction::new("remove-room-tag", glib::VariantTy::new("s").ok());
+    let new_room_tag = SimpleAction::new("new-room-tag", glib::VariantTy::new("s").ok());
+    let rename_room_tag = SimpleAction::new("rename-room-tag", glib::VariantTy::new("s").ok());
+    let delete_room_tag = SimpleAction::new("delete-room-tag", glib::VariantTy::new("s").ok());
+    let move_room_tag_up = SimpleAction::new("move-room-tag-up", glib::VariantTy::new("s").ok());
+    let move_room_tag_down =
+        SimpleAction::new("move-room-tag-down", glib::VariantTy::new("s").ok());
+    // The space id, empty to show all the rooms
+    let select_space = SimpleAction::new("select-space", glib::VariantTy::new("s").ok());
+    let add_room_to_space = SimpleAction::new("add-room-to-space", None);
+    // The space id and the room id, separated by a space
+    let remove_room_from_space =
+        SimpleAction::new("remove-room-from-space", glib::VariantTy::new("s").ok());
+    let room_list_sort = SimpleAction::new_stateful(
+        "room-list-sort",
+        glib::VariantTy::new("s").ok(),
+        &RoomSortOrder::from(util::get_room_list_sort_schema().as_str())
+            .as_str()
+            .to_variant(),
+    );
+    let room_list_unread_only = SimpleAction::new_stateful(
+        "room-list-unread-only",
+        None,
+        &util::get_room_list_unread_only_schema().to_variant(),
+    );
     let deck_back = SimpleAction::new("deck-back", None);
     let back = SimpleAction::new("back", None);
     let media_viewer = SimpleAction::new("open-media-viewer", glib::VariantTy::new("s").ok());
     let account = SimpleAction::new("open-account-settings", None);
     let directory = SimpleAction::new("directory", None);
+    // The target of a `DirectoryNetwork`
+    let directory_network = SimpleAction::new("directory-network", glib::VariantTy::new("s").ok());
+    let remove_directory_server =
+        SimpleAction::new("remove-directory-server", glib::VariantTy::new("s").ok());
+    let directory_preview = SimpleAction::new("directory-preview", glib::VariantTy::new("s").ok());
     //TODO: use roomid as value
     let room_settings = SimpleAction::new("open-room-settings", None);
     // TODO: send file should be a message action
     let send_file = SimpleAction::new("send-file", None);
+    let remove_attachment = SimpleAction::new("remove-attachment", glib::VariantTy::new("s").ok());
     let send_message = SimpleAction::new("send-message", None);
 
     let previous_room = SimpleAction::new("previous-room", None);
@@ -117,15 +160,34 @@ pub fn new(appop: &AppOp) {
     app.add_action(&shortcuts);
     app.add_action(&about);
     app.add_action(&open_room);
+    app.add_action(&go_to_room);
+    app.add_action(&mark_room_as_read);
+    app.add_action(&reply);
+    app.add_action(&set_room_tag);
+    app.add_action(&remove_room_tag);
+    app.add_action(&new_room_tag);
+    app.add_action(&rename_room_tag);
+    app.add_action(&delete_room_tag);
+    app.add_action(&move_room_tag_up);
+    app.add_action(&move_room_tag_down);
+    app.add_action(&select_space);
+    app.add_action(&add_room_to_space);
+    app.add_action(&remove_room_from_space);
+    app.add_action(&room_list_sort);
+    app.add_action(&room_list_unread_only);
     app.add_action(&deck_back);
     app.add_action(&back);
     app.add_action(&directory);
+    app.add_action(&directory_network);
+    app.add_action(&remove_directory_server);
+    app.add_action(&directory_preview);
     app.add_action(&room_settings);
     app.add_action(&media_viewer);
     app.add_action(&account);
     app.add_action(&main_menu);
 
     app.add_action(&send_file);
+    app.add_action(&remove_attachment);
     app.add_action(&send_message);
 
     app.add_action(&previous_room);
@@ -149,10 +211,9 @@ pub fn new(appop: &AppOp) {
         app_runtime.update_state_with(|state| state.main_menu());
     }));
 
-    settings.connect_activate(move |_, _| {
-        info!("SETTINGS");
-    });
-    settings.set_enabled(false);
+    settings.connect_activate(clone!(@strong app_runtime => move |_, _| {
+        app_runtime.update_state_with(|state| state.show_preferences());
+    }));
 
     logout.connect_activate(clone!(@strong app_runtime => move |_, _| {
         app_runtime.update_state_with(|state| state.logout());
@@ -248,6 +309,25 @@ pub fn new(appop: &AppOp) {
         });
     }));
 
+    directory_network.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(target) = data.and_then(|d| d.get_str()) {
+            let network = DirectoryNetwork::from_target(target);
+            app_runtime.update_state_with(move |state| state.set_directory_network(network));
+        }
+    }));
+
+    remove_directory_server.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(server) = data.and_then(|d| d.get_str()).map(String::from) {
+            app_runtime.update_state_with(move |state| state.remove_directory_server(server));
+        }
+    }));
+
+    directory_preview.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(room_id) = get_room_id(data) {
+            app_runtime.update_state_with(move |state| state.show_room_preview(room_id));
+        }
+    }));
+
     /* TODO: We could pass a message to this to highlight it in the room history, might be
      * handy when opening the room from a notification */
     open_room.connect_activate(clone!(@strong app_runtime => move |_, data| {
@@ -270,6 +350,137 @@ pub fn new(appop: &AppOp) {
         });
     }));
 
+    go_to_room.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(room_id) = get_room_id(data) {
+            app_runtime.update_state_with(move |state| {
+                state.go_to_room(room_id);
+            });
+        }
+    }));
+
+    mark_room_as_read.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(room_id) = get_room_id(data) {
+            app_runtime.update_state_with(move |state| {
+                state.mark_room_as_read(room_id);
+            });
+        }
+    }));
+
+    reply.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let mut parts = data.and_then(|data| data.get_str()).unwrap_or_default().splitn(2, ' ');
+        let room_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        let text = parts.next().unwrap_or_default().to_string();
+        if let Some(room_id) = room_id {
+            app_runtime.update_state_with(move |state| {
+                state.quick_reply(room_id, text);
+            });
+        }
+    }));
+
+    set_room_tag.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let mut parts = data.and_then(|data| data.get_str()).unwrap_or_default().splitn(2, ' ');
+        let room_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        let tag = RoomTag::from_name(parts.next().unwrap_or_default());
+        if let Some(room_id) = room_id {
+            app_runtime.update_state_with(move |state| {
+                state.set_room_tag(room_id, tag);
+            });
+        }
+    }));
+
+    remove_room_tag.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let mut parts = data.and_then(|data| data.get_str()).unwrap_or_default().splitn(2, ' ');
+        let room_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        let tag = RoomTag::from_name(parts.next().unwrap_or_default());
+        if let Some(room_id) = room_id {
+            app_runtime.update_state_with(move |state| {
+                state.remove_room_tag(room_id, tag);
+            });
+        }
+    }));
+
+    new_room_tag.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let room_id = get_room_id(data);
+        app_runtime.update_state_with(move |state| {
+            state.new_room_tag_dialog(room_id);
+        });
+    }));
+
+    rename_room_tag.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(tag) = data.and_then(|data| data.get_str()).map(String::from) {
+            app_runtime.update_state_with(move |state| {
+                state.rename_room_tag_dialog(tag);
+            });
+        }
+    }));
+
+    delete_room_tag.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(tag) = data.and_then(|data| data.get_str()).map(String::from) {
+            app_runtime.update_state_with(move |state| {
+                state.delete_room_tag(tag);
+            });
+        }
+    }));
+
+    move_room_tag_up.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(tag) = data.and_then(|data| data.get_str()).map(String::from) {
+            app_runtime.update_state_with(move |state| {
+                state.move_room_tag(tag, true);
+            });
+        }
+    }));
+
+    move_room_tag_down.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(tag) = data.and_then(|data| data.get_str()).map(String::from) {
+            app_runtime.update_state_with(move |state| {
+                state.move_room_tag(tag, false);
+            });
+        }
+    }));
+
+    select_space.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let space_id = get_room_id(data);
+        app_runtime.update_state_with(move |state| {
+            state.select_space(space_id);
+        });
+    }));
+
+    add_room_to_space.connect_activate(clone!(@strong app_runtime => move |_, _| {
+        app_runtime.update_state_with(|state| {
+            state.add_room_to_space_dialog();
+        });
+    }));
+
+    remove_room_from_space.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        let mut parts = data.and_then(|data| data.get_str()).unwrap_or_default().splitn(2, ' ');
+        let space_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        let room_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        if let (Some(space_id), Some(room_id)) = (space_id, room_id) {
+            app_runtime.update_state_with(move |state| {
+                state.remove_room_from_space(space_id, room_id);
+            });
+        }
+    }));
+
+    room_list_sort.connect_change_state(clone!(@strong app_runtime => move |action, data| {
+        if let Some(sort) = data.and_then(|data| data.get_str()) {
+            let sort = RoomSortOrder::from(sort);
+            action.set_state(&sort.as_str().to_variant());
+            app_runtime.update_state_with(move |state| {
+                state.set_room_list_sort(sort);
+            });
+        }
+    }));
+
+    room_list_unread_only.connect_change_state(clone!(@strong app_runtime => move |action, data| {
+        if let Some(unread_only) = data.and_then(|data| data.get::<bool>()) {
+            action.set_state(&unread_only.to_variant());
+            app_runtime.update_state_with(move |state| {
+                state.set_room_list_unread_only(unread_only);
+            });
+        }
+    }));
+
     room_settings.connect_activate(clone!(@strong app_runtime => move |_, _| {
         app_runtime.update_state_with(|state| {
             state.create_room_settings();
@@ -317,32 +528,46 @@ pub fn new(appop: &AppOp) {
 
     send_file.connect_activate(clone!(@weak app => move |_, _| {
         if let Some(window) = app.get_active_window() {
-            if let Some(path) = open(&window, i18n("Select a file").as_str(), &[]) {
-                APPOP!(attach_message, (path));
+            let paths = open_multiple(&window, i18n("Select Files").as_str(), &[]);
+            if !paths.is_empty() {
+                APPOP!(add_attachments, (paths));
             }
         }
     }));
 
+    remove_attachment.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(path) = data.and_then(|d| d.get::<String>()) {
+            let path = PathBuf::from(path);
+            app_runtime.update_state_with(move |state| state.remove_attachment(path));
+        }
+    }));
+
     send_message.connect_activate(move |_, _| {
         app_runtime.update_state_with(|state| {
-            let msg_entry = state.ui.sventry.view.clone();
-            if let Some(buffer) = msg_entry.get_buffer() {
-                let start = buffer.get_start_iter();
-                let end = buffer.get_end_iter();
-
-                if let Some(text) = buffer.get_text(&start, &end, false) {
-                    state.send_message(text.to_string());
+            let (text, mentions) = state.ui.sventry.get_text_with_mentions();
+            let formatted = state.ui.sventry.get_formatted_text();
+
+            // The files go first, so the text works as their caption
+            state.send_attachments();
+            let sent = match formatted {
+                // Commands are run as usual, whatever their format
+                Some((body, html)) if !body.starts_with('/') => {
+                    state.send_formatted_message(body, html);
+                    true
                 }
-
-                buffer.set_text("");
+                _ => state.send_message(text, mentions),
+            };
+            if sent {
+                state.ui.sventry.clear();
             }
         });
     });
 
     send_message.set_enabled(false);
     let buffer = appop.ui.sventry.buffer.clone();
+    let attachment_tray = appop.ui.attachment_tray.clone();
     buffer.connect_changed(move |buffer| {
-        if 0 < buffer.get_char_count() {
+        if 0 < buffer.get_char_count() || !attachment_tray.is_empty() {
             send_message.set_enabled(true);
         } else {
             send_message.set_enabled(false);
@@ -351,6 +576,7 @@ pub fn new(appop: &AppOp) {
 
     /* Add Keybindings to actions */
     app.set_accels_for_action("app.quit", &["<Ctrl>Q"]);
+    app.set_accels_for_action("app.settings", &["<Ctrl>comma"]);
     app.set_accels_for_action("app.previous-room", &["<Ctrl>Page_Up"]);
     app.set_accels_for_action("app.next-room", &["<Ctrl>Page_Down"]);
     app.set_accels_for_action("app.prev-unread-room", &["<Ctrl><Shift>Page_Up"]);
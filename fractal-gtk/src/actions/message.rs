@@ -3,6 +3,7 @@ use glib::clone;
 use log::error;
 use matrix_sdk::identifiers::RoomId;
 use std::fs;
+use std::path::PathBuf;
 use std::process::Command;
 
 use crate::actions::AppState;
@@ -34,6 +35,7 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
     let copy_text = SimpleAction::new("copy_text", glib::VariantTy::new("s").ok());
     let delete = SimpleAction::new("delete", glib::VariantTy::new("s").ok());
     let show_source = SimpleAction::new("show_source", glib::VariantTy::new("s").ok());
+    let cancel_upload = SimpleAction::new("cancel_upload", glib::VariantTy::new("s").ok());
 
     /* TODO: use stateful action to keep  track if the user already requested new messages */
     let load_more_messages =
@@ -46,6 +48,7 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
     actions.add_action(&copy_text);
     actions.add_action(&delete);
     actions.add_action(&show_source);
+    actions.add_action(&cancel_upload);
     actions.add_action(&load_more_messages);
 
     show_source.connect_activate(clone!(@strong app_runtime => move |_, data| {
@@ -86,6 +89,7 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
                         + "\n";
                     buffer.insert(&mut start, &quote);
                     state.ui.sventry.view.grab_focus();
+                    state.set_reply_to(m.id);
                 }
             }
         });
@@ -94,11 +98,13 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
     open_with.connect_activate(clone!(@strong app_runtime => move |_, data| {
         let data = data.cloned();
         app_runtime.update_state_with(move |state| {
-            let url = unwrap_or_unit_return!(get_message(state, data.as_ref()).and_then(|m| m.url));
+            let (url, room_id) = unwrap_or_unit_return!(
+                get_message(state, data.as_ref()).and_then(|m| Some((m.url?, m.room)))
+            );
             let session_client =
                 unwrap_or_unit_return!(state.login_data.as_ref().map(|ld| ld.session_client.clone()));
             RUNTIME.spawn(async move {
-                match dw_media(session_client, &url, ContentType::Download, None).await {
+                match dw_media(session_client, &url, ContentType::Download, None, Some(&room_id)).await {
                     Ok(fname) => {
                         Command::new("xdg-open")
                             .arg(&fname)
@@ -114,14 +120,14 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
     save_as.connect_activate(clone!(@strong app_runtime => move |_, data| {
         let data = data.cloned();
         app_runtime.update_state_with(move |state| {
-            let (url, name) = unwrap_or_unit_return!(
-                get_message(state, data.as_ref()).and_then(|m| Some((m.url?, m.body)))
+            let (url, name, room_id) = unwrap_or_unit_return!(
+                get_message(state, data.as_ref()).and_then(|m| Some((m.url?, m.body, m.room)))
             );
             let session_client = unwrap_or_unit_return!(
                 state.login_data.as_ref().map(|ld| ld.session_client.clone())
             );
             let response = RUNTIME.spawn(async move {
-                media::get_media(session_client, &url).await
+                media::get_media(session_client, &url, Some(&room_id)).await
             });
 
             let window = state.ui.main_window.clone();
@@ -150,11 +156,14 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
     copy_image.connect_activate(clone!(@strong app_runtime => move |_, data| {
         let data = data.cloned();
         app_runtime.update_state_with(move |state| {
-            let url = unwrap_or_unit_return!(get_message(state, data.as_ref()).and_then(|m| m.url));
+            let (url, room_id) = unwrap_or_unit_return!(
+                get_message(state, data.as_ref()).and_then(|m| Some((m.url?, m.room)))
+            );
             let session_client =
                 unwrap_or_unit_return!(state.login_data.as_ref().map(|ld| ld.session_client.clone()));
-            let response =
-                RUNTIME.spawn(async move { media::get_media(session_client, &url).await });
+            let response = RUNTIME.spawn(async move {
+                media::get_media(session_client, &url, Some(&room_id)).await
+            });
 
             glib::MainContext::default().spawn_local(async move {
                 match response.await {
@@ -216,6 +225,15 @@ pub fn new(app_runtime: AppRuntime) -> gio::SimpleActionGroup {
         });
     }));
 
+    cancel_upload.connect_activate(clone!(@strong app_runtime => move |_, data| {
+        if let Some(path) = data.and_then(|d| d.get::<String>()) {
+            let path = PathBuf::from(path);
+            app_runtime.update_state_with(move |state| {
+                state.cancel_upload(path);
+            });
+        }
+    }));
+
     load_more_messages.connect_activate(move |_, data| {
         let data = data.cloned();
         app_runtime.update_state_with(move |state| {
@@ -3,9 +3,9 @@ use gio::prelude::*;
 use gio::SimpleAction;
 use gio::SimpleActionGroup;
 use glib::clone;
-use matrix_sdk::identifiers::RoomId;
+use matrix_sdk::identifiers::{RoomId, RoomVersionId};
 use matrix_sdk::Client as MatrixClient;
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
 
 use crate::app::RUNTIME;
 use crate::backend::HandleError;
@@ -25,8 +25,11 @@ pub fn new(window: &gtk::Window, session_client: MatrixClient) -> gio::SimpleAct
         glib::VariantTy::new("s").ok(),
         &ButtonState::Sensitive.into(),
     );
+    // The room id and the version to upgrade it to, separated by a space
+    let upgrade_room = SimpleAction::new("upgrade-room", glib::VariantTy::new("s").ok());
 
     actions.add_action(&change_avatar);
+    actions.add_action(&upgrade_room);
 
     change_avatar.connect_activate(clone!(@weak window => move |a, data| {
         if let Some(room_id) = data
@@ -55,5 +58,17 @@ pub fn new(window: &gtk::Window, session_client: MatrixClient) -> gio::SimpleAct
         }
     }));
 
+    upgrade_room.connect_activate(move |_, data| {
+        let mut parts = data
+            .and_then(|data| data.get_str())
+            .unwrap_or_default()
+            .splitn(2, ' ');
+        let room_id: Option<RoomId> = parts.next().and_then(|rid| rid.try_into().ok());
+        let version: Option<RoomVersionId> = parts.next().and_then(|v| v.try_into().ok());
+        if let (Some(room_id), Some(version)) = (room_id, version) {
+            APPOP!(upgrade_room_dialog, (room_id, version));
+        }
+    });
+
     actions
 }
@@ -1,5 +1,7 @@
 pub mod account;
 pub mod contact;
+pub mod media;
+pub mod room;
 pub mod server;
 
 use matrix_sdk::identifiers::ServerName;
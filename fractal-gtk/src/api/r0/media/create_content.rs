@@ -0,0 +1,41 @@
+use crate::api::r0::AccessToken;
+use matrix_sdk::identifiers::MxcUri;
+use matrix_sdk::reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use matrix_sdk::reqwest::Body;
+use matrix_sdk::reqwest::Client;
+use matrix_sdk::reqwest::Error;
+use matrix_sdk::reqwest::Request;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub content_uri: MxcUri,
+}
+
+pub fn request(
+    base: Url,
+    params: &Parameters,
+    content_type: &str,
+    content_length: u64,
+    body: Body,
+) -> Result<Request, Error> {
+    let url = base
+        .join("_matrix/media/r0/upload")
+        .expect("Malformed URL in create_content");
+
+    Client::new()
+        .post(url)
+        .query(params)
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, content_length)
+        .body(body)
+        .build()
+}
@@ -0,0 +1,35 @@
+use crate::api::r0::AccessToken;
+use matrix_sdk::identifiers::RoomId;
+use matrix_sdk::reqwest::Client;
+use matrix_sdk::reqwest::Error;
+use matrix_sdk::reqwest::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub messages: Option<PaginationChunk>,
+    #[serde(default)]
+    pub state: Vec<JsonValue>,
+}
+
+/// The last events of the room, oldest first
+#[derive(Clone, Debug, Deserialize)]
+pub struct PaginationChunk {
+    #[serde(default)]
+    pub chunk: Vec<JsonValue>,
+}
+
+pub fn request(base: Url, params: &Parameters, room_id: &RoomId) -> Result<Request, Error> {
+    let url = base
+        .join(&format!("_matrix/client/r0/rooms/{}/initialSync", room_id))
+        .expect("Malformed URL in room initial_sync");
+
+    Client::new().get(url).query(params).build()
+}
@@ -0,0 +1,2 @@
+pub mod knock;
+pub mod space_summary;
@@ -0,0 +1,47 @@
+use crate::api::r0::AccessToken;
+use matrix_sdk::identifiers::{RoomId, RoomIdOrAliasId, ServerName};
+use matrix_sdk::reqwest::Client;
+use matrix_sdk::reqwest::Error;
+use matrix_sdk::reqwest::Request;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Body {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    pub room_id: RoomId,
+}
+
+pub fn request(
+    base: Url,
+    params: &Parameters,
+    room_id_or_alias: &RoomIdOrAliasId,
+    server_names: &[Box<ServerName>],
+    body: &Body,
+) -> Result<Request, Error> {
+    let mut url = base
+        .join("_matrix/client/unstable/xyz.amorgan.knock/knock")
+        .expect("Malformed URL in knock");
+    // Pushing the segment escapes the `#` of the aliases
+    url.path_segments_mut()
+        .expect("Malformed URL in knock")
+        .push(room_id_or_alias.as_str());
+    for server_name in server_names {
+        url.query_pairs_mut()
+            .append_pair("server_name", server_name.as_str());
+    }
+
+    let data = serde_json::to_vec(body).unwrap();
+
+    Client::new().post(url).query(params).body(data).build()
+}
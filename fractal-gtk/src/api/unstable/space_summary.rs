@@ -0,0 +1,73 @@
+use crate::api::r0::AccessToken;
+use matrix_sdk::identifiers::{MxcUri, RoomAliasId, RoomId};
+use matrix_sdk::reqwest::Client;
+use matrix_sdk::reqwest::Error;
+use matrix_sdk::reqwest::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::Map as JsonObject;
+use serde_json::Value as JsonValue;
+use url::Url;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Parameters {
+    pub access_token: AccessToken,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Body {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rooms_per_space: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default)]
+    pub rooms: Vec<SpaceRoom>,
+    #[serde(default)]
+    pub events: Vec<SpaceChildEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpaceRoom {
+    pub room_id: RoomId,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub canonical_alias: Option<RoomAliasId>,
+    pub avatar_url: Option<MxcUri>,
+    #[serde(default)]
+    pub num_joined_members: u64,
+    #[serde(default)]
+    pub world_readable: bool,
+    #[serde(default)]
+    pub guest_can_join: bool,
+    pub room_type: Option<String>,
+}
+
+/// A `m.space.child` event, `room_id` is the one of the space
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpaceChildEvent {
+    pub room_id: RoomId,
+    pub state_key: String,
+    #[serde(default)]
+    pub content: JsonObject<String, JsonValue>,
+}
+
+pub fn request(
+    base: Url,
+    params: &Parameters,
+    room_id: &RoomId,
+    body: &Body,
+) -> Result<Request, Error> {
+    let url = base
+        .join(&format!(
+            "_matrix/client/unstable/org.matrix.msc2946/rooms/{}/spaces",
+            room_id
+        ))
+        .expect("Malformed URL in space_summary");
+
+    let data = serde_json::to_vec(body).unwrap();
+
+    Client::new().post(url).query(params).body(data).build()
+}
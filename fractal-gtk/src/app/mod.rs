@@ -217,7 +217,7 @@ fn on_activate(ui: &ui::UI) {
     ui.main_window.present();
 }
 
-fn on_shutdown(appop: &AppOp) {
+fn on_shutdown(appop: &mut AppOp) {
     appop.quit();
 }
 
@@ -0,0 +1,222 @@
+use std::convert::{TryFrom, TryInto};
+
+use gtk::prelude::*;
+use matrix_sdk::identifiers::{RoomIdOrAliasId, UserId};
+
+use crate::app::RUNTIME;
+use crate::appop::message::TextFormat;
+use crate::appop::AppOp;
+use crate::backend::{room, user, HandleError};
+use crate::model::command::{Command, CommandError};
+use crate::model::member::Member;
+use crate::util::i18n::i18n_f;
+use crate::widgets::SourceDialog;
+
+const SHRUG: &str = "¯\\_(ツ)_/¯";
+
+/// The power level `/op` gives when none is given, the one of moderators
+const DEFAULT_OP_LEVEL: i64 = 50;
+
+impl AppOp {
+    /// Runs a command typed in the active room. The mentions are relative to `args`.
+    pub fn run_command(&mut self, command: Command, args: String, mentions: Vec<(usize, Member)>) {
+        match command {
+            Command::Me => self.send_text_message(args, mentions, "m.emote", TextFormat::Plain),
+            Command::Shrug => {
+                let msg = if args.is_empty() {
+                    String::from(SHRUG)
+                } else {
+                    format!("{} {}", SHRUG, args)
+                };
+                let prefix_len = msg.len() - args.len();
+                let mentions = mentions
+                    .into_iter()
+                    .map(|(offset, member)| (offset + prefix_len, member))
+                    .collect();
+                self.send_text_message(msg, mentions, "m.text", TextFormat::Plain);
+            }
+            Command::Notice => self.send_text_message(args, mentions, "m.notice", TextFormat::Auto),
+            Command::Plain => self.send_text_message(args, mentions, "m.text", TextFormat::Plain),
+            Command::Html => self.send_text_message(args, mentions, "m.text", TextFormat::Html),
+            Command::Markdown => {
+                self.send_text_message(args, mentions, "m.text", TextFormat::Markdown)
+            }
+            Command::Spoiler => {
+                self.send_text_message(args, mentions, "m.text", TextFormat::Spoiler)
+            }
+            Command::Topic => self.set_topic_from_command(args),
+            Command::Nick => self.set_nick_from_command(args),
+            Command::MyRoomNick => self.set_room_nick_from_command(args),
+            Command::Invite | Command::Kick | Command::Ban | Command::Op | Command::Deop => {
+                let (user_id, rest) = match target_user(&args, &mentions) {
+                    Some(target) => target,
+                    None => {
+                        self.show_error(CommandError::InvalidArgs(command).message());
+                        return;
+                    }
+                };
+                let reason = Some(rest).filter(|r| !r.is_empty()).map(str::to_string);
+                self.moderate_from_command(command, user_id, reason);
+            }
+            Command::Join => match RoomIdOrAliasId::try_from(args.trim()) {
+                Ok(room_id_or_alias) => self.join_room_by_id_or_alias(room_id_or_alias),
+                Err(_) => self.show_error(CommandError::InvalidArgs(command).message()),
+            },
+            Command::Part => self.part_from_command(args),
+            Command::Devtools => self.get_room_state(),
+        }
+    }
+
+    fn set_topic_from_command(&self, topic: String) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        RUNTIME.spawn(async move {
+            if let Err(err) = room::set_room_topic(session_client, &room_id, topic).await {
+                err.handle_error();
+            }
+        });
+    }
+
+    fn set_nick_from_command(&self, nick: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+
+        RUNTIME.spawn(async move {
+            let query =
+                user::set_username(login_data.session_client, &login_data.uid, Some(nick)).await;
+            match query {
+                Ok(username) => {
+                    APPOP!(show_new_username, (username));
+                }
+                Err(err) => err.handle_error(),
+            }
+        });
+    }
+
+    fn set_room_nick_from_command(&self, nick: String) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        RUNTIME.spawn(async move {
+            let query =
+                room::set_room_nick(login_data.session_client, &room_id, &login_data.uid, nick)
+                    .await;
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    fn moderate_from_command(&self, command: Command, user_id: UserId, args: Option<String>) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        let level = match (command, args.as_deref()) {
+            (Command::Op, Some(level)) => match level.parse() {
+                Ok(level) => Some(level),
+                Err(_) => {
+                    self.show_error(CommandError::InvalidArgs(command).message());
+                    return;
+                }
+            },
+            (Command::Op, None) => Some(DEFAULT_OP_LEVEL),
+            _ => None,
+        };
+
+        RUNTIME.spawn(async move {
+            let reason = args.as_deref();
+            match command {
+                Command::Invite => {
+                    if let Err(err) = room::invite(session_client, &room_id, &user_id).await {
+                        err.handle_error();
+                    }
+                }
+                Command::Kick => {
+                    let query = room::kick_user(session_client, &room_id, &user_id, reason).await;
+                    if let Err(err) = query {
+                        err.handle_error();
+                    }
+                }
+                Command::Ban => {
+                    let query = room::ban_user(session_client, &room_id, &user_id, reason).await;
+                    if let Err(err) = query {
+                        err.handle_error();
+                    }
+                }
+                _ => {
+                    let query =
+                        room::set_power_level(session_client, &room_id, &user_id, level).await;
+                    if let Err(err) = query {
+                        err.handle_error();
+                    }
+                }
+            }
+        });
+    }
+
+    fn part_from_command(&mut self, args: String) {
+        let args = args.trim();
+        let room_id = if args.is_empty() {
+            self.active_room.clone()
+        } else {
+            match RoomIdOrAliasId::try_from(args) {
+                Ok(room_id_or_alias) => match room_id_or_alias.try_into() {
+                    Ok(room_id) => Some(room_id).filter(|id| self.rooms.contains_key(id)),
+                    Err(alias) => self
+                        .rooms
+                        .values()
+                        .find(|room| room.alias.as_ref() == Some(&alias))
+                        .map(|room| room.id.clone()),
+                },
+                Err(_) => {
+                    self.show_error(CommandError::InvalidArgs(Command::Part).message());
+                    return;
+                }
+            }
+        };
+
+        match room_id {
+            Some(room_id) => self.leave_room(room_id),
+            None => self.show_error(i18n_f("You aren’t a member of {}.", &[args])),
+        }
+    }
+
+    fn get_room_state(&self) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+
+        RUNTIME.spawn(async move {
+            match room::get_room_state(session_client, &room_id).await {
+                Ok(source) => {
+                    APPOP!(show_room_state, (source));
+                }
+                Err(err) => err.handle_error(),
+            }
+        });
+    }
+
+    pub fn show_room_state(&self, source: String) {
+        let viewer = SourceDialog::new();
+        viewer.set_parent_window(self.ui.main_window.upcast_ref());
+        viewer.show(&source);
+    }
+}
+
+/// Finds the user the arguments of a command start with, either a mention pill or a user ID,
+/// and returns it with the rest of the arguments
+fn target_user<'a>(args: &'a str, mentions: &[(usize, Member)]) -> Option<(UserId, &'a str)> {
+    if let Some((_, member)) = mentions.iter().find(|(offset, _)| *offset == 0) {
+        let rest = args.get(member.get_alias().len()..).unwrap_or_default();
+        return Some((member.uid.clone(), rest.trim()));
+    }
+
+    let (word, rest) = match args.find(char::is_whitespace) {
+        Some(pos) => (&args[..pos], &args[pos..]),
+        None => (args, ""),
+    };
+    let user_id = UserId::try_from(word).ok()?;
+    Some((user_id, rest.trim()))
+}
@@ -1,15 +1,22 @@
-use super::RoomSearchPagination;
+use super::{DirectoryNetwork, RoomSearchPagination};
 use crate::app::RUNTIME;
 use crate::appop::AppOp;
+use crate::backend::directory::RoomPreview;
 use crate::backend::{directory, HandleError};
 use crate::model::room::Room;
+use crate::util::i18n::i18n;
+use crate::util::{get_directory_servers_schema, set_directory_servers_schema};
+use log::debug;
 use matrix_sdk::directory::RoomNetwork;
+use matrix_sdk::identifiers::{RoomId, ServerName};
 use matrix_sdk::thirdparty::ProtocolInstance;
+use std::convert::{TryFrom, TryInto};
 
 impl AppOp {
     pub fn init_protocols(&self) {
         let session_client =
             unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        self.update_directory_networks();
         RUNTIME.spawn(async move {
             match directory::protocols(session_client).await {
                 Ok(protocols) => {
@@ -22,15 +29,79 @@ impl AppOp {
         });
     }
 
-    pub fn set_protocols(&self, protocols: Vec<ProtocolInstance>) {
-        self.ui.set_protocols(protocols);
+    pub fn set_protocols(&mut self, protocols: Vec<ProtocolInstance>) {
+        self.directory_protocols = protocols;
+        self.update_directory_networks();
+    }
+
+    fn update_directory_networks(&self) {
+        let homeserver = unwrap_or_unit_return!(self
+            .login_data
+            .as_ref()
+            .map(|ld| ld.uid.server_name().to_string()));
+
+        self.ui.set_directory_networks(
+            &homeserver,
+            &get_directory_servers_schema(),
+            &self.directory_protocols,
+            &self.directory_network,
+        );
+    }
+
+    pub fn set_directory_network(&mut self, network: DirectoryNetwork) {
+        if self.directory_network == network {
+            return;
+        }
+
+        self.directory_network = network;
+        self.update_directory_networks();
+
+        self.directory_pagination = RoomSearchPagination::Initial;
+        self.search_rooms();
+    }
+
+    pub fn add_directory_server(&mut self, server: String) {
+        if <&ServerName>::try_from(server.as_str()).is_err() {
+            self.show_error(i18n("The server name is not valid."));
+            return;
+        }
+
+        let mut servers = get_directory_servers_schema();
+        if !servers.contains(&server) {
+            servers.push(server.clone());
+            set_directory_servers_schema(&servers);
+        }
+
+        self.set_directory_network(DirectoryNetwork::Server(server));
+    }
+
+    pub fn remove_directory_server(&mut self, server: String) {
+        let mut servers = get_directory_servers_schema();
+        servers.retain(|s| s != &server);
+        set_directory_servers_schema(&servers);
+
+        if self.directory_network == DirectoryNetwork::Server(server) {
+            self.set_directory_network(DirectoryNetwork::Homeserver);
+        } else {
+            self.update_directory_networks();
+        }
+    }
+
+    /// The servers to join the rooms found in the directory through
+    fn directory_via(&self) -> Vec<Box<ServerName>> {
+        match &self.directory_network {
+            DirectoryNetwork::Server(server) => {
+                server.as_str().try_into().ok().into_iter().collect()
+            }
+            _ => vec![],
+        }
     }
 
     pub fn search_rooms(&mut self) {
         let session_client =
             unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
 
-        let (protocol, homeserver, search_term) = self
+        let search_term = self
             .ui
             .get_search_rooms_query(self.directory_pagination.clone());
 
@@ -39,6 +110,12 @@ impl AppOp {
             return;
         }
 
+        let (homeserver, protocol) = match self.directory_network.clone() {
+            DirectoryNetwork::Homeserver => (None, None),
+            DirectoryNetwork::Server(server) => (Some(server), None),
+            DirectoryNetwork::Protocol(network_id) => (None, Some(network_id)),
+        };
+
         let rooms_since: Option<String> = self.directory_pagination.clone().into();
         RUNTIME.spawn(async move {
             let query = directory::room_search(
@@ -72,11 +149,57 @@ impl AppOp {
         let session_client =
             unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
         rooms.sort_by_key(|a| -i128::from(a.n_members));
+
+        if !self.directory_pagination.has_more() {
+            self.directory_rooms.clear();
+        }
+        self.directory_rooms.extend(rooms.iter().cloned());
+
         self.directory_pagination = rooms_since
             .map(RoomSearchPagination::Next)
             .unwrap_or(RoomSearchPagination::NoMorePages);
 
-        self.ui.append_directory_rooms(rooms, session_client);
+        let via = self.directory_via();
+        self.ui.append_directory_rooms(rooms, session_client, via);
+    }
+
+    pub fn show_room_preview(&mut self, room_id: RoomId) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let room = unwrap_or_unit_return!(self
+            .directory_rooms
+            .iter()
+            .find(|r| r.id == room_id)
+            .cloned());
+
+        let via = self.directory_via();
+        self.ui.show_room_preview(&room, session_client, via);
+
+        if room.world_readable {
+            self.peek_room(room_id);
+        }
+    }
+
+    /// Reads the state and the last messages of a room we aren't in, for its preview
+    pub fn peek_room(&self, room_id: RoomId) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let base = login_data.session_client.homeserver().clone();
+
+        RUNTIME.spawn(async move {
+            let query = directory::room_preview(base, login_data.access_token, &room_id).await;
+            let preview = match query {
+                Ok(preview) => Some(preview),
+                Err(err) => {
+                    debug!("Couldn’t peek into {}: {:?}", room_id, err);
+                    None
+                }
+            };
+            APPOP!(set_room_preview, (room_id, preview));
+        });
+    }
+
+    pub fn set_room_preview(&self, room_id: RoomId, preview: Option<RoomPreview>) {
+        self.ui.set_room_preview(&room_id, preview);
     }
 
     pub fn reset_directory_state(&self) {
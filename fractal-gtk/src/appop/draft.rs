@@ -0,0 +1,185 @@
+use crate::app::RUNTIME;
+use crate::appop::AppOp;
+use crate::backend::{room, HandleError};
+use crate::cache;
+use crate::model::draft::Draft;
+use crate::util;
+use glib::source::Continue;
+use gtk::prelude::*;
+use log::error;
+use matrix_sdk::identifiers::{EventId, RoomId};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How long to wait after the last change before writing the drafts
+const SAVE_DELAY_MS: u32 = 2000;
+/// How long quitting waits for the drafts to be synced
+const QUIT_SYNC_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl AppOp {
+    pub fn load_drafts(&mut self) {
+        let uid = unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.uid.clone()));
+        self.unsaved_drafts.clear();
+        // There's nothing saved yet the first time
+        self.drafts = cache::load_drafts(&uid).unwrap_or_default();
+    }
+
+    /// Takes the text typed in the entry into the draft of the active room
+    pub fn update_active_draft(&mut self) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let buffer = &self.ui.sventry.buffer;
+        let mark = unwrap_or_unit_return!(buffer.get_insert());
+        let cursor = buffer.get_iter_at_mark(&mark).get_offset();
+        let (text, _) = self.ui.sventry.get_text_with_mentions();
+        let formats = self.ui.sventry.get_formats();
+
+        let unchanged = self.drafts.get(&room_id).map_or(text.is_empty(), |draft| {
+            draft.text == text && draft.formats == formats
+        });
+        if unchanged {
+            if let Some(draft) = self.drafts.get_mut(&room_id) {
+                draft.cursor = cursor;
+            }
+            return;
+        }
+
+        let draft = self.drafts.entry(room_id.clone()).or_default();
+        draft.text = text;
+        draft.formats = formats;
+        draft.cursor = cursor;
+        // The quote was removed along with the text
+        if draft.text.trim().is_empty() {
+            draft.reply_to = None;
+        }
+        self.draft_changed(room_id);
+    }
+
+    pub fn set_reply_to(&mut self, event_id: Option<EventId>) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        self.drafts.entry(room_id.clone()).or_default().reply_to = event_id;
+        self.draft_changed(room_id);
+    }
+
+    /// Takes the message the next one sent in the room replies to
+    pub fn take_reply_to(&mut self, room_id: &RoomId) -> Option<EventId> {
+        self.drafts.get_mut(room_id)?.reply_to.take()
+    }
+
+    pub fn set_draft_attachments(&mut self, room_id: RoomId, attachments: Vec<PathBuf>) {
+        self.drafts.entry(room_id.clone()).or_default().attachments = attachments;
+        self.draft_changed(room_id);
+    }
+
+    /// Shows the new draft of the room in the room list and plans to save it
+    fn draft_changed(&mut self, room_id: RoomId) {
+        self.show_draft(&room_id);
+
+        if self.unsaved_drafts.is_empty() {
+            glib::timeout_add_local(SAVE_DELAY_MS, || {
+                APPOP!(save_drafts);
+                Continue(false)
+            });
+        }
+        self.unsaved_drafts.insert(room_id);
+    }
+
+    fn show_draft(&mut self, room_id: &RoomId) {
+        if self.drafts.get(room_id).map_or(false, Draft::is_empty) {
+            self.drafts.remove(room_id);
+        }
+
+        let draft = self.drafts.get(room_id).cloned();
+        if let Some(room) = self.rooms.get_mut(room_id) {
+            room.draft = draft.clone();
+        }
+        self.ui.roomlist.set_room_draft(room_id.clone(), draft);
+    }
+
+    /// Writes the drafts to the cache, and to the account data of their room when they
+    /// are synced between devices
+    pub fn save_drafts(&mut self) {
+        self.sync_drafts();
+    }
+
+    /// Writes the drafts before quitting, waiting a moment for the synced ones to reach
+    /// the server. Only the local copy is kept of the ones that take longer.
+    pub fn save_drafts_at_quit(&mut self) {
+        let requests = self.sync_drafts();
+        if requests.is_empty() {
+            return;
+        }
+
+        let requests = futures_util::future::join_all(requests);
+        if RUNTIME
+            .block_on(tokio::time::timeout(QUIT_SYNC_TIMEOUT, requests))
+            .is_err()
+        {
+            error!("The drafts couldn’t be synced before quitting");
+        }
+    }
+
+    /// Stores the drafts and sends the changed ones, returning the requests
+    fn sync_drafts(&mut self) -> Vec<JoinHandle<()>> {
+        let login_data = match self.login_data.clone() {
+            Some(login_data) => login_data,
+            None => return vec![],
+        };
+        self.store_drafts();
+
+        let unsaved = std::mem::take(&mut self.unsaved_drafts);
+        if !util::get_sync_drafts_schema() {
+            return vec![];
+        }
+
+        unsaved
+            .into_iter()
+            .map(|room_id| {
+                let session_client = login_data.session_client.clone();
+                let uid = login_data.uid.clone();
+                let draft = self.drafts.get(&room_id).cloned().unwrap_or_default();
+                RUNTIME.spawn(async move {
+                    let query = room::set_draft(session_client, &uid, &room_id, draft).await;
+                    if let Err(err) = query {
+                        err.handle_error();
+                    }
+                })
+            })
+            .collect()
+    }
+
+    pub fn store_drafts(&self) {
+        let uid = unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.uid.clone()));
+        if let Err(err) = cache::store_drafts(uid, self.drafts.clone()) {
+            error!("Can't store the drafts: {:?}", err);
+        }
+    }
+
+    /// Takes the draft written on another device, unless the room is open here
+    pub fn receive_synced_draft(&mut self, room_id: RoomId, synced: Draft) {
+        if !util::get_sync_drafts_schema() || self.active_room.as_ref() == Some(&room_id) {
+            return;
+        }
+
+        let unchanged = self
+            .drafts
+            .get(&room_id)
+            .map_or(synced.is_empty(), |draft| {
+                draft.text == synced.text
+                    && draft.formats == synced.formats
+                    && draft.reply_to == synced.reply_to
+            });
+        if unchanged {
+            return;
+        }
+
+        let draft = self.drafts.entry(room_id.clone()).or_default();
+        draft.text = synced.text;
+        draft.formats = synced.formats;
+        draft.cursor = synced.cursor;
+        draft.reply_to = synced.reply_to;
+
+        self.show_draft(&room_id);
+        self.store_drafts();
+    }
+}
@@ -1,9 +1,10 @@
 use crate::app::RUNTIME;
 use crate::appop::member::SearchType;
 use crate::appop::AppOp;
-use crate::backend::room;
 use crate::backend::HandleError;
+use crate::backend::{room, user};
 use crate::model::member::Member;
+use crate::model::room::RoomMembership;
 use matrix_sdk::identifiers::RoomId;
 
 impl AppOp {
@@ -54,6 +55,12 @@ impl AppOp {
         self.ui.roomlist.remove_room(room_id);
     }
 
+    pub fn remove_invs(&mut self, room_ids: Vec<RoomId>) {
+        for room_id in room_ids.iter() {
+            self.remove_inv(room_id);
+        }
+    }
+
     pub fn accept_inv(&mut self, accept: bool) {
         let room_id = unwrap_or_unit_return!(self.invitation_roomid.take().clone());
         let session_client =
@@ -61,7 +68,7 @@ impl AppOp {
         self.remove_inv(&room_id);
         RUNTIME.spawn(async move {
             if accept {
-                match room::join_room(session_client, &room_id.into()).await {
+                match room::join_room(session_client, &room_id.into(), &[]).await {
                     Ok(jtr) => {
                         let jtr = Some(jtr);
                         APPOP!(set_join_to_room, (jtr));
@@ -72,7 +79,60 @@ impl AppOp {
                     }
                 }
             } else {
-                let query = room::leave_room(session_client, &room_id).await;
+                let query = room::reject_invite(session_client, &room_id, false).await;
+                if let Err(err) = query {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    /// Rejects the invite and forgets the room, it won't be kept with the rooms we left
+    pub fn reject_and_forget_inv(&mut self) {
+        let room_id = unwrap_or_unit_return!(self.invitation_roomid.take());
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        self.remove_inv(&room_id);
+        RUNTIME.spawn(async move {
+            let query = room::reject_invite(session_client, &room_id, true).await;
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+
+    /// Ignores the user who sent the invite and rejects all their pending invites
+    pub fn block_inviter(&mut self) {
+        let room_id = unwrap_or_unit_return!(self.invitation_roomid.take());
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let inviter = match self.rooms.get(&room_id).map(|r| &r.membership) {
+            Some(RoomMembership::Invited(inviter)) => inviter.clone(),
+            _ => return,
+        };
+
+        let invites: Vec<RoomId> = self
+            .rooms
+            .values()
+            .filter(
+                |r| matches!(&r.membership, RoomMembership::Invited(sender) if sender == &inviter),
+            )
+            .map(|r| r.id.clone())
+            .collect();
+
+        RUNTIME.spawn(async move {
+            let session_client = login_data.session_client;
+            let query = user::ignore_user(session_client.clone(), &login_data.uid, &inviter).await;
+            if let Err(err) = query {
+                err.handle_error();
+                return;
+            }
+
+            // The invites stay listed when the user couldn't be ignored
+            let room_ids = invites.clone();
+            APPOP!(remove_invs, (room_ids));
+
+            for room_id in invites {
+                let query = room::reject_invite(session_client.clone(), &room_id, false).await;
                 if let Err(err) = query {
                     err.handle_error();
                 }
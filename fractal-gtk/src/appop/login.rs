@@ -47,6 +47,7 @@ impl AppOp {
             avatar: None,
             identity_url,
         });
+        self.load_drafts();
 
         let _ = RUNTIME.block_on(matrix_client.restore_login(Session {
             access_token: access_token.to_string(),
@@ -57,6 +58,8 @@ impl AppOp {
         self.set_state(AppState::NoRoom);
         self.since = None;
         self.get_username();
+        self.get_max_upload_size();
+        self.update_push_rules();
 
         // initial sync, we're shoing some feedback to the user
         self.initial_sync(true);
@@ -69,11 +72,19 @@ impl AppOp {
         if cache::get().destroy().is_err() {
             error!("Error removing cache file");
         }
+        if let Err(err) = cache::crypto::purge_decrypted() {
+            error!("Can't remove the decrypted cache files: {}", err);
+        }
 
         self.syncing = false;
 
         self.set_state(AppState::Login);
         self.login_data = None;
+        self.max_upload_size = None;
+        self.push_rules = None;
+        self.local_notifications.clear();
+        self.drafts.clear();
+        self.unsaved_drafts.clear();
     }
 
     pub fn connect(
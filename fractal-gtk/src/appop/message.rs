@@ -1,35 +1,52 @@
-use crate::backend::{room, HandleError};
-use crate::model::fileinfo::ExtraContent;
+use crate::backend::{media, remove_matrix_access_token_if_present, room, HandleError};
 use comrak::{markdown_to_html, ComrakOptions};
-use gdk_pixbuf::Pixbuf;
-use gio::prelude::FileExt;
+use gio::prelude::*;
 use glib::source::Continue;
 use gtk::prelude::*;
+use html2pango::html_escape;
 use lazy_static::lazy_static;
 use log::error;
 use matrix_sdk::identifiers::{EventId, RoomId};
 use matrix_sdk::Client as MatrixClient;
-use rand::Rng;
 use serde_json::json;
 use serde_json::Value as JsonValue;
-use std::env::temp_dir;
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use url::Url;
 
+use crate::api::r0::AccessToken;
 use crate::app::RUNTIME;
 use crate::appop::room::Force;
 use crate::appop::AppOp;
 
 use crate::ui::MessageContent;
 use crate::ui::RowType;
+use crate::util;
+use crate::util::i18n::i18n_f;
+use crate::util::upload;
 use crate::widgets;
 
+use crate::model::command::Command;
+use crate::model::member::Member;
 use crate::model::message::Message;
 
+/// How the text of a message is turned into its formatted body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextFormat {
+    /// Markdown when it's enabled in the preferences
+    Auto,
+    Plain,
+    Markdown,
+    Html,
+    Spoiler,
+}
+
 pub struct TmpMsg {
     pub msg: Message,
     pub widget: Option<gtk::Widget>,
+    pub progress: Option<gtk::ProgressBar>,
 }
 
 impl AppOp {
@@ -77,12 +94,19 @@ impl AppOp {
             let m = mb.get_widget();
             messages.add(m);
 
+            let progress = mb.get_progress_bar().cloned();
+            if let (Some(progress), true) = (&progress, msg.url.is_some()) {
+                // Only the event is left to send
+                progress.set_fraction(1.0);
+            }
+
             if let Some(w) = messages.get_children().iter().last() {
                 self.msg_queue.insert(
                     0,
                     TmpMsg {
                         msg: msg.clone(),
                         widget: Some(w.clone()),
+                        progress,
                     },
                 );
             };
@@ -97,6 +121,7 @@ impl AppOp {
                 messages.remove(w);
             }
             t.widget = None;
+            t.progress = None;
         }
         None
     }
@@ -118,13 +143,14 @@ impl AppOp {
                 messages.add(m);
 
                 if let Some(w) = messages.get_children().iter().last() {
-                    widgets.push(w.clone());
+                    widgets.push((w.clone(), mb.get_progress_bar().cloned()));
                 }
             }
         }
 
-        for (t, w) in self.msg_queue.iter_mut().rev().zip(widgets.iter()) {
-            t.widget = Some(w.clone());
+        for (t, (w, progress)) in self.msg_queue.iter_mut().rev().zip(widgets.into_iter()) {
+            t.widget = Some(w);
+            t.progress = progress;
         }
         None
     }
@@ -196,7 +222,9 @@ impl AppOp {
     }
 
     pub fn dequeue_message(&mut self) -> Option<()> {
-        let session_client = self.login_data.as_ref()?.session_client.clone();
+        let login_data = self.login_data.as_ref()?;
+        let session_client = login_data.session_client.clone();
+        let access_token = login_data.access_token.clone();
         if self.sending_message {
             return None;
         }
@@ -206,7 +234,9 @@ impl AppOp {
             let msg = next.msg.clone();
             match next.msg.mtype.as_str() {
                 "m.image" | "m.file" | "m.audio" | "m.video" => {
-                    RUNTIME.spawn(attach_file(session_client, msg));
+                    self.upload = Some(RUNTIME.spawn(async move {
+                        let _ = attach_file(session_client, access_token, msg).await;
+                    }));
                 }
                 _ => {
                     RUNTIME.spawn(async move {
@@ -230,111 +260,409 @@ impl AppOp {
         None
     }
 
-    pub fn send_message(&mut self, msg: String) {
+    /// Sends the message typed in the entry, or runs the command it starts with. Returns
+    /// `false` when the command is wrong, so the text can be fixed instead of lost.
+    pub fn send_message(&mut self, msg: String, mentions: Vec<(usize, Member)>) -> bool {
         if msg.is_empty() {
             // Not sending empty messages
+            return true;
+        }
+
+        match Command::parse(&msg) {
+            Some(Ok((command, args))) => {
+                let mentions = shift_mentions(mentions, msg.len() - args.len());
+                self.run_command(command, args.to_string(), mentions);
+            }
+            Some(Err(err)) => {
+                self.show_error(err.message());
+                return false;
+            }
+            // A leading "//" escapes the slash
+            None if msg.starts_with("//") => {
+                let mentions = shift_mentions(mentions, 1);
+                self.send_text_message(msg[1..].to_string(), mentions, "m.text", TextFormat::Auto);
+            }
+            None => self.send_text_message(msg, mentions, "m.text", TextFormat::Auto),
+        }
+
+        true
+    }
+
+    pub fn send_text_message(
+        &mut self,
+        msg: String,
+        mentions: Vec<(usize, Member)>,
+        mtype: &str,
+        format: TextFormat,
+    ) {
+        if msg.is_empty() {
             return;
         }
+        let mut m = unwrap_or_unit_return!(self.new_text_message(msg.clone(), mtype));
+
+        // Element (Riot) does not properly show emotes with Markdown;
+        // Emotes with markdown have a newline after the username
+        let markdown = match format {
+            TextFormat::Auto => mtype != "m.emote" && self.md_enabled,
+            TextFormat::Markdown => true,
+            _ => false,
+        };
 
-        if let Some(room) = self.active_room.clone() {
-            if let Some(sender) = self.login_data.as_ref().map(|ld| ld.uid.clone()) {
-                let body = msg.clone();
-                let mtype = String::from("m.text");
-                let mut m = Message::new(room, sender, body, mtype, None);
+        if markdown {
+            let md_msg = replace_mentions(&msg, &mentions, mention_markdown, str::to_owned);
 
-                if msg.starts_with("/me ") {
-                    m.body = msg.trim_start_matches("/me ").to_owned();
-                    m.mtype = String::from("m.emote");
-                }
+            let (md_msg, maths) = extract_math(&md_msg);
+            let mut md_options = ComrakOptions::default();
+            md_options.hardbreaks = true;
+            let mut md_parsed_msg = insert_math(&markdown_to_html(&md_msg, &md_options), &maths);
 
-                // Element (Riot) does not properly show emotes with Markdown;
-                // Emotes with markdown have a newline after the username
-                if m.mtype != "m.emote" && self.md_enabled {
-                    let mut md_options = ComrakOptions::default();
-                    md_options.hardbreaks = true;
-                    let mut md_parsed_msg = markdown_to_html(&msg, &md_options);
-
-                    // Removing wrap tag: <p>..</p>\n
-                    let limit = md_parsed_msg.len() - 5;
-                    let trim = match (md_parsed_msg.get(0..3), md_parsed_msg.get(limit..)) {
-                        (Some(open), Some(close)) if open == "<p>" && close == "</p>\n" => true,
-                        _ => false,
-                    };
-                    if trim {
-                        md_parsed_msg = md_parsed_msg
-                            .get(3..limit)
-                            .unwrap_or(&md_parsed_msg)
-                            .to_string();
-                    }
+            // Removing wrap tag: <p>..</p>\n
+            let limit = md_parsed_msg.len() - 5;
+            let trim = match (md_parsed_msg.get(0..3), md_parsed_msg.get(limit..)) {
+                (Some(open), Some(close)) if open == "<p>" && close == "</p>\n" => true,
+                _ => false,
+            };
+            if trim {
+                md_parsed_msg = md_parsed_msg
+                    .get(3..limit)
+                    .unwrap_or(&md_parsed_msg)
+                    .to_string();
+            }
 
-                    if md_parsed_msg != msg {
-                        m.formatted_body = Some(md_parsed_msg);
-                        m.format = Some(String::from("org.matrix.custom.html"));
-                    }
+            if md_parsed_msg != msg {
+                m.formatted_body = Some(md_parsed_msg);
+                m.format = Some(String::from("org.matrix.custom.html"));
+            }
+        } else if let TextFormat::Html = format {
+            let html = replace_mentions(&msg, &mentions, mention_html, str::to_owned);
+            m.body = util::html::to_plain(&html);
+            m.formatted_body = Some(html);
+            m.format = Some(String::from("org.matrix.custom.html"));
+        } else if let TextFormat::Spoiler = format {
+            let html = replace_mentions(&msg, &mentions, mention_html, |text| {
+                html_escape(text).replace('\n', "<br>")
+            });
+            m.formatted_body = Some(format!("<span data-mx-spoiler>{}</span>", html));
+            m.format = Some(String::from("org.matrix.custom.html"));
+        } else if !mentions.is_empty() {
+            let html = replace_mentions(&msg, &mentions, mention_html, |text| {
+                html_escape(text).replace('\n', "<br>")
+            });
+
+            m.formatted_body = Some(html);
+            m.format = Some(String::from("org.matrix.custom.html"));
+        }
+
+        self.add_tmp_room_message(m);
+        self.dequeue_message();
+    }
+
+    /// Sends a message formatted with the toolbar of the entry
+    pub fn send_formatted_message(&mut self, body: String, html: String) {
+        if body.is_empty() {
+            return;
+        }
+        let mut m = unwrap_or_unit_return!(self.new_text_message(body, "m.text"));
+        m.formatted_body = Some(html);
+        m.format = Some(String::from("org.matrix.custom.html"));
+
+        self.add_tmp_room_message(m);
+        self.dequeue_message();
+    }
+
+    /// Makes a message sent by the user in the active room, replying to the message picked
+    /// in the draft
+    fn new_text_message(&mut self, body: String, mtype: &str) -> Option<Message> {
+        let room = match self.active_room.clone() {
+            Some(room) => room,
+            None => {
+                error!("Can't send message: No active room");
+                return None;
+            }
+        };
+        let sender = match self.login_data.as_ref().map(|ld| ld.uid.clone()) {
+            Some(sender) => sender,
+            None => {
+                error!("Can't send message: No user is logged in");
+                return None;
+            }
+        };
+
+        let in_reply_to = self.take_reply_to(&room);
+        let mut m = Message::new(room, sender, body, mtype.to_string(), None);
+        m.in_reply_to = in_reply_to;
+        Some(m)
+    }
+
+    pub fn get_max_upload_size(&self) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            match media::get_max_upload_size(session_client).await {
+                Ok(size) => {
+                    APPOP!(set_max_upload_size, (size));
+                }
+                Err(err) => {
+                    err.handle_error();
                 }
+            }
+        });
+    }
 
-                self.add_tmp_room_message(m);
-                self.dequeue_message();
-            } else {
+    pub fn set_max_upload_size(&mut self, size: u64) {
+        self.max_upload_size = Some(size);
+    }
+
+    /// Tells the user the file at `path` can't be sent if it's bigger than what the server
+    /// accepts. Files are let through when the limit is still unknown.
+    fn check_upload_size(&self, path: &Path) -> bool {
+        let max_size = match self.max_upload_size {
+            Some(max_size) => max_size,
+            None => return true,
+        };
+        let size = fs::metadata(path).map(|md| md.len()).unwrap_or_default();
+        if size <= max_size {
+            return true;
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let msg = i18n_f("“{}” is too big to be sent", &[&name]);
+        let format_size =
+            |size| glib::format_size(size).map_or_else(String::new, |size| size.to_string());
+        let info = i18n_f(
+            "The server only accepts files up to {}, this one is {}.",
+            &[&format_size(max_size), &format_size(size)],
+        );
+        self.show_error_with_info(msg, Some(info));
+
+        false
+    }
+
+    pub fn set_upload_progress(&self, path: PathBuf, fraction: f64) {
+        let progress = self
+            .msg_queue
+            .iter()
+            .find(|t| t.msg.local_path.as_ref() == Some(&path))
+            .and_then(|t| t.progress.as_ref());
+        if let Some(progress) = progress {
+            progress.set_fraction(fraction);
+        }
+    }
+
+    /// Removes the file at `path` from the messages waiting to be sent, stopping its
+    /// upload if it's the one in progress
+    pub fn cancel_upload(&mut self, path: PathBuf) -> Option<()> {
+        let messages = self.ui.history.as_ref()?.get_listbox();
+        let pos = self
+            .msg_queue
+            .iter()
+            .position(|t| t.msg.local_path.as_ref() == Some(&path) && t.msg.url.is_none())?;
+        // The message being sent is always the last one of the queue
+        let in_progress = self.sending_message && pos + 1 == self.msg_queue.len();
+
+        let tmp = self.msg_queue.remove(pos);
+        if let Some(w) = tmp.widget {
+            messages.remove(&w);
+        }
+        upload::remove_temp_file(&path);
+        if let Some(ref thumb) = tmp.msg.local_path_thumb {
+            upload::remove_temp_file(thumb);
+        }
+
+        if in_progress {
+            if let Some(upload) = self.upload.take() {
+                upload.abort();
+            }
+            self.force_dequeue_message();
+        }
+        None
+    }
+
+    /// The server refused the file being uploaded because of its size
+    pub fn upload_rejected(&mut self) -> Option<()> {
+        let path = self.msg_queue.last()?.msg.local_path.clone()?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let msg = i18n_f("“{}” is too big to be sent", &[&name]);
+        self.show_error(msg);
+
+        self.cancel_upload(path)
+    }
+
+    /// Queues the files at `paths` to be sent in the active room, in that order. When
+    /// `compress` is set, large photos are sent scaled down.
+    ///
+    /// Getting the files ready can take a while, so it's done out of the main thread and
+    /// every message is queued once its file is ready.
+    pub fn attach_messages(&mut self, paths: Vec<PathBuf>, compress: bool) {
+        let room = match self.active_room.clone() {
+            Some(room) => room,
+            None => {
+                error!("Can't send message: No active room");
+                return;
+            }
+        };
+        let sender = match self.login_data.as_ref().map(|ld| ld.uid.clone()) {
+            Some(sender) => sender,
+            None => {
                 error!("Can't send message: No user is logged in");
+                return;
             }
-        } else {
-            error!("Can't send message: No active room");
-        }
-    }
-
-    pub fn attach_message(&mut self, path: PathBuf) {
-        if let Some(room) = self.active_room.clone() {
-            if let Some(sender) = self.login_data.as_ref().map(|ld| ld.uid.clone()) {
-                if let Ok(uri) = Url::from_file_path(&path) {
-                    if let Ok(info) = gio::File::new_for_path(&path).query_info(
-                        &gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
-                        gio::FileQueryInfoFlags::NONE,
-                        gio::NONE_CANCELLABLE,
-                    ) {
-                        // This should always return a type
-                        let mime = info
-                            .get_content_type()
-                            .expect("Could not parse content type from file");
-                        let mtype = match mime.as_ref() {
-                            m if m.starts_with("image") => "m.image",
-                            m if m.starts_with("audio") => "m.audio",
-                            "application/x-riff" => "m.audio",
-                            m if m.starts_with("video") => "m.video",
-                            "application/x-mpegURL" => "m.video",
-                            _ => "m.file",
-                        };
-                        let body = path
-                            .file_name()
-                            .and_then(|s| s.to_str())
-                            .map(Into::into)
-                            .unwrap_or_default();
-
-                        let mut m = Message::new(room, sender, body, mtype.to_string(), None);
-                        let info = match mtype {
-                            "m.image" => get_image_media_info(&path, mime.as_ref()),
-                            "m.audio" => get_audio_video_media_info(&uri, mime.as_ref()),
-                            "m.video" => get_audio_video_media_info(&uri, mime.as_ref()),
-                            "m.file" => get_file_media_info(&path, mime.as_ref()),
-                            _ => None,
-                        };
-
-                        m.extra_content = info;
-                        m.local_path = Some(path);
-
-                        self.add_tmp_room_message(m);
-                        self.dequeue_message();
-                    } else {
-                        error!("Can't send message: Could not query info");
-                    }
+        };
+
+        let mut msgs = vec![];
+        for path in paths {
+            if let Ok(uri) = Url::from_file_path(&path) {
+                if let Ok(info) = gio::File::new_for_path(&path).query_info(
+                    &gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                    gio::FileQueryInfoFlags::NONE,
+                    gio::NONE_CANCELLABLE,
+                ) {
+                    // This should always return a type
+                    let mime = info
+                        .get_content_type()
+                        .expect("Could not parse content type from file")
+                        .to_string();
+                    let mtype = match mime.as_ref() {
+                        m if m.starts_with("image") => "m.image",
+                        m if m.starts_with("audio") => "m.audio",
+                        "application/x-riff" => "m.audio",
+                        m if m.starts_with("video") => "m.video",
+                        "application/x-mpegURL" => "m.video",
+                        _ => "m.file",
+                    };
+                    let body = path
+                        .file_name()
+                        .and_then(|s| s.to_str())
+                        .map(Into::into)
+                        .unwrap_or_default();
+
+                    let mut m =
+                        Message::new(room.clone(), sender.clone(), body, mtype.to_string(), None);
+                    m.local_path = Some(path.clone());
+                    msgs.push((m, uri, mime));
                 } else {
-                    error!("Can't send message: Path is not absolute")
+                    error!("Can't send message: Could not query info");
                 }
             } else {
-                error!("Can't send message: No user is logged in");
+                error!("Can't send message: Path is not absolute")
+            }
+        }
+
+        let strip_metadata = util::get_strip_image_metadata_schema();
+        RUNTIME.spawn_blocking(move || {
+            for (mut m, uri, mime) in msgs {
+                let path = m.local_path.clone().unwrap_or_default();
+                let info = match m.mtype.as_str() {
+                    "m.image" => {
+                        get_image_media_info(&mut m, &path, &mime, strip_metadata, compress)
+                    }
+                    "m.audio" => get_audio_video_media_info(&uri, &mime),
+                    "m.video" => get_video_media_info(&mut m, &uri, &mime),
+                    "m.file" => get_file_media_info(&path, &mime),
+                    _ => None,
+                };
+
+                m.extra_content = info;
+                APPOP!(queue_attachment, (m));
             }
+        });
+    }
+
+    /// Adds the file message `msg`, once its file is ready, to the ones waiting to be sent
+    pub fn queue_attachment(&mut self, msg: Message) {
+        if self.active_room.as_ref() == Some(&msg.room) {
+            self.add_tmp_room_message(msg);
         } else {
-            error!("Can't send message: No active room");
+            // It's shown when going back to its room
+            self.msg_queue.insert(
+                0,
+                TmpMsg {
+                    msg,
+                    widget: None,
+                    progress: None,
+                },
+            );
+        }
+        self.dequeue_message();
+    }
+
+    /// Adds files to the tray of the active room, they are sent along with the next message
+    pub fn add_attachments(&mut self, paths: Vec<PathBuf>) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .filter(|path| path.is_file() && self.check_upload_size(path))
+            .collect();
+        let mut attachments = self
+            .drafts
+            .get(&room_id)
+            .map(|draft| draft.attachments.clone())
+            .unwrap_or_default();
+        for path in paths {
+            if !attachments.contains(&path) {
+                attachments.push(path);
+            }
+        }
+        self.set_draft_attachments(room_id, attachments);
+
+        self.update_attachment_tray();
+    }
+
+    pub fn remove_attachment(&mut self, path: PathBuf) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let mut attachments = self
+            .drafts
+            .get(&room_id)
+            .map(|draft| draft.attachments.clone())
+            .unwrap_or_default();
+        attachments.retain(|p| *p != path);
+        self.set_draft_attachments(room_id, attachments);
+        // Pasted images only exist for the tray
+        upload::remove_temp_file(&path);
+
+        self.update_attachment_tray();
+    }
+
+    /// Queues every file in the tray of the active room, in the order they were added
+    pub fn send_attachments(&mut self) {
+        let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        let attachments = self
+            .drafts
+            .get(&room_id)
+            .map(|draft| draft.attachments.clone())
+            .unwrap_or_default();
+        if attachments.is_empty() {
+            return;
+        }
+        self.set_draft_attachments(room_id, vec![]);
+        let compress = self.ui.attachment_tray.compress_photos();
+        self.attach_messages(attachments, compress);
+
+        self.update_attachment_tray();
+    }
+
+    pub fn update_attachment_tray(&self) {
+        let attachments = self
+            .active_room
+            .as_ref()
+            .and_then(|room_id| self.drafts.get(room_id))
+            .map(|draft| draft.attachments.as_slice())
+            .unwrap_or_default();
+        self.ui.attachment_tray.set_attachments(attachments);
+
+        if let Some(action) = self.ui.gtk_app.lookup_action("send-message") {
+            if let Some(action) = action.downcast_ref::<gio::SimpleAction>() {
+                action.set_enabled(
+                    !attachments.is_empty() || self.ui.sventry.buffer.get_char_count() > 0,
+                );
+            }
         }
     }
 
@@ -342,6 +670,10 @@ impl AppOp {
     /// to the matrix media server and we've the real url to use so we can
     /// replace the tmp message with the same id with this new one
     pub fn attached_file(&mut self, msg: Message) -> Option<()> {
+        self.upload = None;
+        if let Some(ref path) = msg.local_path {
+            upload::remove_temp_file(path);
+        }
         let messages = self.ui.history.as_ref()?.get_listbox();
         let p = self.msg_queue.iter().position(|m| m.msg == msg);
         if let Some(i) = p {
@@ -367,9 +699,12 @@ impl AppOp {
             }
         }
 
+        let rooms: HashSet<RoomId> = msgs.iter().map(|msg| msg.room.clone()).collect();
+        for room_id in rooms {
+            self.update_room_last_message(room_id);
+        }
+
         let mut msg_in_active = false;
-        let login_data = self.login_data.clone()?;
-        let uid = login_data.uid;
         for msg in msgs.iter() {
             if !msg.redacted && self.active_room.as_ref().map_or(false, |x| x == &msg.room) {
                 self.add_room_message(msg.clone());
@@ -381,15 +716,13 @@ impl AppOp {
                 continue;
             }
 
-            let should_notify = msg.sender != uid
-                && (msg.body.contains(&login_data.username.clone()?)
-                    || self.rooms.get(&msg.room).map_or(false, |r| r.direct));
-
-            if should_notify {
+            let actions = self.push_actions(msg);
+            if actions.notify {
+                self.count_notification(msg.room.clone(), actions.highlight);
                 if let (Some(app), Some(event_id)) =
                     (self.ui.main_window.get_application(), msg.id.clone())
                 {
-                    self.notify(app, msg.room.clone(), event_id);
+                    self.notify(app, msg.room.clone(), event_id, actions.sound);
                 }
             }
 
@@ -446,6 +779,7 @@ impl AppOp {
                 msg.redacted = true;
                 room.take_new_message(msg);
             }
+            self.update_room_last_message(room_id);
         }
         None
     }
@@ -473,20 +807,22 @@ impl AppOp {
             "m.video" => RowType::Video,
             "m.file" => RowType::File,
             _ => {
-                /* set message type to mention if the body contains the username, we should
-                 * also match for MXID */
-                let is_mention = if let Some(user) = login_data.username.clone() {
-                    msg.sender != login_data.uid && msg.body.contains(&user)
-                } else {
-                    false
-                };
-
-                if is_mention {
+                /* set message type to mention if the push rules highlight it, the keywords
+                 * are highlighted in the text along with our name */
+                if self.push_actions(&msg).highlight {
                     if let Some(user) = login_data.username {
                         highlights.push(user);
                     }
                     highlights.push(login_data.uid.to_string());
                     highlights.push(String::from("message_menu"));
+                    let keywords = self.push_rules.iter().flat_map(|rules| rules.keywords());
+                    highlights.extend(
+                        keywords
+                            .filter(|rule| rule.enabled)
+                            .filter_map(|rule| rule.pattern.clone())
+                            // The highlighting only looks for the plain text
+                            .filter(|pattern| !pattern.contains(&['*', '?'][..])),
+                    );
 
                     RowType::Mention
                 } else if EMOJI_REGEX.is_match(&msg.body) {
@@ -511,6 +847,11 @@ impl AppOp {
             .unwrap_or_default();
         let redactable = admin != 0 || login_data.uid == msg.sender;
 
+        // Previewing a link makes the homeserver fetch it, which leaks it from encrypted rooms
+        let preview_urls = util::get_url_previews_schema()
+            && room.disable_url_previews != Some(true)
+            && (!room.encrypted || util::get_url_previews_encrypted_schema());
+
         let is_last_viewed = msg.receipt.contains_key(&login_data.uid);
         Some(MessageContent {
             msg,
@@ -519,52 +860,62 @@ impl AppOp {
             highlights,
             redactable,
             last_viewed: is_last_viewed,
+            preview_urls,
             widget: None,
         })
     }
 }
 
-/// This function opens the image, creates a thumbnail
-/// and populates the info Json with the information it has
-
-fn get_image_media_info(file: &Path, mimetype: &str) -> Option<JsonValue> {
-    // We need to load the file to read the orientation in the EXIF data
-    let image = Pixbuf::from_file(&file)
-        .ok()?
-        .apply_embedded_orientation()?;
-    let size = fs::metadata(file).ok()?.len();
-
-    // make thumbnail max 800x600
-    let thumb = Pixbuf::from_file_at_scale(&file, 800, 600, true)
-        .ok()?
-        .apply_embedded_orientation()?;
-    let mut rng = rand::thread_rng();
-    let x: u64 = rng.gen_range(1, 9_223_372_036_854_775_807);
-    let thumb_path = format!(
-        "{}/fractal_{}.png",
-        temp_dir().to_str().unwrap_or_default(),
-        x.to_string()
-    );
-    thumb.savev(&thumb_path, "png", &[]).ok()?;
-    let thumb_size = fs::metadata(&thumb_path).ok()?.len();
-
-    let info = json!({
+/// Gets the image ready to be sent as `msg`, removing its metadata or compressing it
+/// depending on the settings, and populates the info Json with what it has. The
+/// thumbnail is uploaded along with the image.
+fn get_image_media_info(
+    msg: &mut Message,
+    file: &Path,
+    mimetype: &str,
+    strip_metadata: bool,
+    compress: bool,
+) -> Option<JsonValue> {
+    let image = upload::prepare_image(file, mimetype, strip_metadata, compress)
+        .map_err(|err| error!("Can't prepare image {}: {:?}", file.display(), err))
+        .ok()?;
+
+    let mut info = json!({
         "info": {
-            "thumbnail_url": thumb_path,
-            "thumbnail_info": {
-                "w": thumb.get_width(),
-                "h": thumb.get_height(),
-                "size": thumb_size,
-                "mimetype": "image/png"
-            },
-            "w": image.get_width(),
-            "h": image.get_height(),
-            "size": size,
-            "mimetype": mimetype,
+            "w": image.width,
+            "h": image.height,
+            "size": image.size,
+            "mimetype": image.mimetype,
             "orientation": 0
         }
     });
 
+    if let Some(thumb) = image.thumbnail {
+        info["info"]["thumbnail_info"] = thumb.info();
+        msg.local_path_thumb = Some(thumb.path);
+    }
+
+    if let Some(name) = image.path.file_name().and_then(|name| name.to_str()) {
+        msg.body = name.to_string();
+    }
+    // A pasted image isn't needed anymore once it has been copied
+    if image.path != file {
+        upload::remove_temp_file(file);
+    }
+    msg.local_path = Some(image.path);
+
+    Some(info)
+}
+
+fn get_video_media_info(msg: &mut Message, uri: &Url, mimetype: &str) -> Option<JsonValue> {
+    let mut info = get_audio_video_media_info(uri, mimetype)?;
+
+    // A frame of the video is shown until it's played
+    if let Some(thumb) = upload::create_video_thumbnail(uri) {
+        info["info"]["thumbnail_info"] = thumb.info();
+        msg.local_path_thumb = Some(thumb.path);
+    }
+
     Some(info)
 }
 
@@ -607,55 +958,72 @@ fn get_file_media_info(file: &Path, mimetype: &str) -> Option<JsonValue> {
 
 struct NonMediaMsg;
 
-async fn attach_file(session_client: MatrixClient, mut msg: Message) -> Result<(), NonMediaMsg> {
-    let mut extra_content: Option<ExtraContent> = msg
-        .extra_content
-        .clone()
-        .and_then(|c| serde_json::from_value(c).ok());
-
-    let thumb_url = extra_content.clone().and_then(|c| c.info.thumbnail_url);
-
-    match (msg.url.clone(), msg.local_path.as_ref(), thumb_url) {
-        (Some(_), _, Some(_)) => {
+async fn attach_file(
+    session_client: MatrixClient,
+    access_token: AccessToken,
+    mut msg: Message,
+) -> Result<(), NonMediaMsg> {
+    // The thumbnail is uploaded before the file, so once there's a URL only the event is left
+    match (msg.url.clone(), msg.local_path.as_ref()) {
+        (Some(_), _) => {
             send_msg_and_manage(session_client, msg).await;
 
             Ok(())
         }
-        (_, Some(local_path), _) => {
-            if let Some(ref local_path_thumb) = msg.local_path_thumb {
-                let response = room::upload_file(session_client.clone(), local_path_thumb)
+        (_, Some(local_path)) => {
+            if let Some(local_path_thumb) = msg.local_path_thumb.clone() {
+                let response = room::upload_file(session_client.clone(), &local_path_thumb)
                     .await
                     .map(|response| response.content_uri);
 
                 match response {
                     Ok(thumb_uri) => {
-                        msg.thumb = Some(thumb_uri.clone());
-                        if let Some(ref mut xctx) = extra_content {
-                            xctx.info.thumbnail_url = Some(thumb_uri);
+                        if let Some(info) = msg
+                            .extra_content
+                            .as_mut()
+                            .and_then(|content| content.get_mut("info"))
+                        {
+                            info["thumbnail_url"] = json!(thumb_uri);
                         }
-                        msg.extra_content = serde_json::to_value(&extra_content).ok();
+                        msg.thumb = Some(thumb_uri);
                     }
                     Err(err) => {
-                        err.handle_error();
+                        // The file can still be sent without it
+                        let err_str = format!("{:?}", err);
+                        error!(
+                            "Can't upload thumbnail: {}",
+                            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+                        );
                     }
                 }
+            }
 
-                if let Err(_e) = std::fs::remove_file(local_path_thumb) {
-                    error!("Can't remove thumbnail: {}", local_path_thumb.display());
+            let path = local_path.clone();
+            let last_percent = AtomicU64::new(0);
+            let progress = move |sent: u64, size: u64| {
+                // Updating the bar for every chunk would flood the main loop
+                let percent = (sent * 100).checked_div(size).unwrap_or(100);
+                if percent != last_percent.swap(percent, Ordering::Relaxed) {
+                    let fraction = percent as f64 / 100.0;
+                    APPOP!(set_upload_progress, (path, fraction));
                 }
-            }
+            };
 
-            let query = room::upload_file(session_client.clone(), &local_path)
+            let base = session_client.homeserver().clone();
+            let query = room::upload_attachment(base, access_token, &local_path, progress)
                 .await
-                .and_then(|response| {
-                    msg.url = Some(response.content_uri);
+                .map(|content_uri| {
+                    msg.url = Some(content_uri);
                     RUNTIME.spawn(send_msg_and_manage(session_client, msg.clone()));
 
-                    Ok(msg)
+                    msg
                 });
 
             match query {
                 Ok(msg) => {
+                    if let Some(ref local_path_thumb) = msg.local_path_thumb {
+                        upload::remove_temp_file(local_path_thumb);
+                    }
                     APPOP!(attached_file, (msg));
                 }
                 Err(err) => {
@@ -682,3 +1050,247 @@ async fn send_msg_and_manage(session_client: MatrixClient, msg: Message) {
         }
     };
 }
+
+/// Builds a new text from `text`, formatting the names of the mentioned members with `link`
+/// and the text around them with `other`
+fn replace_mentions(
+    text: &str,
+    mentions: &[(usize, Member)],
+    link: impl Fn(&Member) -> String,
+    other: impl Fn(&str) -> String,
+) -> String {
+    let mut mentions: Vec<&(usize, Member)> = mentions.iter().collect();
+    mentions.sort_by_key(|(offset, _)| *offset);
+
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (offset, member) in mentions {
+        let end = offset + member.get_alias().len();
+        if let (Some(before), Some(_)) = (text.get(last..*offset), text.get(*offset..end)) {
+            result.push_str(&other(before));
+            result.push_str(&link(member));
+            last = end;
+        }
+    }
+    result.push_str(&other(&text[last..]));
+
+    result
+}
+
+fn mention_markdown(member: &Member) -> String {
+    format!(
+        "[{}]({})",
+        escape_markdown(&member.get_alias()),
+        util::mention_link(&member.uid)
+    )
+}
+
+fn mention_html(member: &Member) -> String {
+    format!(
+        "<a href=\"{}\">{}</a>",
+        util::mention_link(&member.uid),
+        html_escape(&member.get_alias())
+    )
+}
+
+/// Moves the mentions of a message to the text left after removing its first `len` bytes
+pub fn shift_mentions(mentions: Vec<(usize, Member)>, len: usize) -> Vec<(usize, Member)> {
+    mentions
+        .into_iter()
+        .filter(|(offset, _)| *offset >= len)
+        .map(|(offset, member)| (offset - len, member))
+        .collect()
+}
+
+/// Takes the `$inline$` and `$$display$$` math out of Markdown, so it's left alone when it's
+/// turned into HTML. The math is replaced by placeholders, with its LaTeX returned along with
+/// whether it's a block.
+fn extract_math(md: &str) -> (String, Vec<(String, bool)>) {
+    lazy_static! {
+        // Like in Pandoc, there's no space inside the dollars of inline math and no digit
+        // right after it, so prices aren't taken for math. The code blocks and spans come
+        // first to be left as they are.
+        static ref MATH_REGEX: regex::Regex = regex::Regex::new(
+            r"(?ms)^ {0,3}```.*?(?:^ {0,3}```|\z)|^ {0,3}~~~.*?(?:^ {0,3}~~~|\z)|``.*?``|`[^`]*`|\$\$(.+?)\$\$|\$([^\s$](?:[^$\n]*[^\s$])?)\$(\D|\z)"
+        )
+        .unwrap();
+    }
+
+    let mut maths = vec![];
+    let md = MATH_REGEX.replace_all(md, |c: &regex::Captures| {
+        let placeholder = format!("\u{E000}{}\u{E001}", maths.len());
+        match (c.get(1), c.get(2)) {
+            (Some(tex), _) => {
+                maths.push((tex.as_str().trim().to_string(), true));
+                placeholder
+            }
+            (None, Some(tex)) => {
+                maths.push((tex.as_str().to_string(), false));
+                format!("{}{}", placeholder, &c[3])
+            }
+            (None, None) => c[0].to_string(),
+        }
+    });
+
+    (md.into_owned(), maths)
+}
+
+/// Puts the math taken out by `extract_math` back in the HTML, as described by MSC2191
+fn insert_math(html: &str, maths: &[(String, bool)]) -> String {
+    let mut html = html.to_string();
+    for (i, (tex, block)) in maths.iter().enumerate() {
+        let placeholder = format!("\u{E000}{}\u{E001}", i);
+        let tex = html_escape(tex);
+        if *block {
+            let div = format!("<div data-mx-maths=\"{}\"><code>{}</code></div>", tex, tex);
+            // Blocks can't be in paragraphs
+            html = html
+                .replace(&format!("<p>{}</p>", placeholder), &div)
+                .replace(&placeholder, &div);
+        } else {
+            let span = format!(
+                "<span data-mx-maths=\"{}\"><code>{}</code></span>",
+                tex, tex
+            );
+            html = html.replace(&placeholder, &span);
+        }
+    }
+    html
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            if "\\`*_[]<>#!".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::identifiers::UserId;
+    use std::convert::TryFrom;
+
+    fn member(uid: &str, alias: &str) -> Member {
+        Member {
+            uid: UserId::try_from(uid).unwrap(),
+            alias: Some(alias.to_string()),
+            avatar: None,
+        }
+    }
+
+    #[test]
+    fn replace_several_mentions() {
+        let text = "Bob and Alice <3";
+        let bob = member("@bob:example.org", "Bob");
+        let alice = member("@alice:example.org", "Alice");
+        // The order of the mentions doesn't matter
+        let mentions = vec![(8, alice), (0, bob)];
+
+        assert_eq!(
+            replace_mentions(text, &mentions, mention_html, html_escape),
+            "<a href=\"https://matrix.to/#/@bob:example.org\">Bob</a> and \
+             <a href=\"https://matrix.to/#/@alice:example.org\">Alice</a> &lt;3"
+        );
+    }
+
+    #[test]
+    fn replace_mentions_after_non_ascii_text() {
+        let text = "Héllo 🎉 [Bob]!";
+        let bob = member("@bob:example.org", "[Bob]");
+        let offset = text.find('[').unwrap();
+
+        assert_eq!(
+            replace_mentions(text, &[(offset, bob)], mention_markdown, str::to_owned),
+            "Héllo 🎉 [\\[Bob\\]](https://matrix.to/#/@bob:example.org)!"
+        );
+    }
+
+    #[test]
+    fn replace_mentions_out_of_the_text() {
+        let bob = member("@bob:example.org", "Bob");
+        // The name doesn't fit in the text anymore, it's left as it is
+        let text = "Hi Bo";
+        assert_eq!(
+            replace_mentions(text, &[(3, bob)], mention_html, str::to_owned),
+            "Hi Bo"
+        );
+    }
+
+    #[test]
+    fn shift_mentions_after_command() {
+        let bob = member("@bob:example.org", "Bob");
+        let alice = member("@alice:example.org", "Alice");
+        let mentions = shift_mentions(vec![(0, alice), (4, bob)], 4);
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].0, 0);
+        assert_eq!(mentions[0].1.uid.as_str(), "@bob:example.org");
+    }
+
+    #[test]
+    fn mention_user_permalinks() {
+        let bob = UserId::try_from("@bob:example.org").unwrap();
+        assert_eq!(
+            util::mention_user("https://matrix.to/#/@bob:example.org"),
+            Some(bob.clone())
+        );
+        assert_eq!(
+            util::mention_user("https://matrix.to/#/%40bob%3Aexample.org"),
+            Some(bob.clone())
+        );
+        assert_eq!(
+            util::mention_user("https://matrix.to/#/%40bob%3aexample.org?via=example.org"),
+            Some(bob)
+        );
+        assert_eq!(
+            util::mention_user("https://matrix.to/#/!room:example.org?via=example.org"),
+            None
+        );
+        assert_eq!(
+            util::mention_user("https://example.org/#/@bob:example.org"),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_inline_and_block_math() {
+        let (md, maths) = extract_math("Let $x^2$ be\n$$\n\\frac{1}{2}\n$$\nfor $5 and $3.");
+        assert_eq!(
+            md,
+            "Let \u{E000}0\u{E001} be\n\u{E000}1\u{E001}\nfor $5 and $3."
+        );
+        assert_eq!(
+            maths,
+            vec![
+                (String::from("x^2"), false),
+                (String::from("\\frac{1}{2}"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_math_outside_code() {
+        let md = "`$x$` and ``a `$y$` b`` then $z$\n```latex\n$$w$$ and $v$\n```\n~~~\n$u$";
+        let (extracted, maths) = extract_math(md);
+        assert_eq!(extracted, md.replacen("$z$", "\u{E000}0\u{E001}", 1));
+        assert_eq!(maths, vec![(String::from("z"), false)]);
+    }
+
+    #[test]
+    fn insert_inline_and_block_math() {
+        let maths = vec![
+            (String::from("a<b"), false),
+            (String::from("\\sum x"), true),
+        ];
+        let html = "<p>So \u{E000}0\u{E001}:</p>\n<p>\u{E000}1\u{E001}</p>\n";
+        assert_eq!(
+            insert_math(html, &maths),
+            "<p>So <span data-mx-maths=\"a&lt;b\"><code>a&lt;b</code></span>:</p>\n\
+             <div data-mx-maths=\"\\sum x\"><code>\\sum x</code></div>\n"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -7,13 +7,18 @@ use crate::api::r0::AccessToken;
 use matrix_sdk::identifiers::{DeviceId, RoomId, ServerName, UserId};
 
 use gtk::prelude::*;
+use log::error;
+use matrix_sdk::thirdparty::ProtocolInstance;
 use matrix_sdk::Client as MatrixClient;
 
 use crate::cache::CacheMap;
 
+use crate::util;
 use crate::util::i18n;
 
-use crate::model::room::RoomList;
+use crate::model::draft::Draft;
+use crate::model::push_rules::Ruleset;
+use crate::model::room::{Room, RoomList};
 use crate::passwd::PasswordStorage;
 
 use crate::actions::AppState;
@@ -22,7 +27,9 @@ use crate::cache;
 use crate::ui;
 
 mod account;
+mod command;
 mod directory;
+mod draft;
 mod invite;
 mod login;
 mod media_viewer;
@@ -30,8 +37,12 @@ pub mod member;
 mod message;
 mod notifications;
 mod notify;
+mod preferences;
 pub mod room;
 mod room_settings;
+mod room_tags;
+mod room_upgrade;
+mod spaces;
 mod start_chat;
 pub mod state;
 mod sync;
@@ -64,6 +75,45 @@ impl RoomSearchPagination {
     }
 }
 
+/// Where the room directory looks for rooms
+#[derive(Clone, Debug, PartialEq)]
+pub enum DirectoryNetwork {
+    /// The public rooms of our homeserver
+    Homeserver,
+    /// The public rooms of another server, by its name
+    Server(String),
+    /// The rooms of a network bridged to our homeserver, by its network ID
+    Protocol(String),
+}
+
+impl Default for DirectoryNetwork {
+    fn default() -> Self {
+        DirectoryNetwork::Homeserver
+    }
+}
+
+impl DirectoryNetwork {
+    /// The target of the `app.directory-network` action selecting it
+    pub fn to_target(&self) -> String {
+        match self {
+            DirectoryNetwork::Homeserver => String::new(),
+            DirectoryNetwork::Server(server) => format!("server {}", server),
+            DirectoryNetwork::Protocol(network_id) => format!("protocol {}", network_id),
+        }
+    }
+
+    pub fn from_target(target: &str) -> Self {
+        let mut parts = target.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some("server"), Some(server)) => DirectoryNetwork::Server(server.to_string()),
+            (Some("protocol"), Some(network_id)) => {
+                DirectoryNetwork::Protocol(network_id.to_string())
+            }
+            _ => DirectoryNetwork::Homeserver,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LoginData {
     pub session_client: MatrixClient,
@@ -82,17 +132,37 @@ pub struct AppOp {
     pub syncing: bool, // TODO: Replace with a Mutex
     pub msg_queue: Vec<TmpMsg>,
     pub sending_message: bool,
+    /// The task uploading the file of the message being sent, if it's a media one
+    pub upload: Option<tokio::task::JoinHandle<()>>,
+    /// In bytes, as reported by the server
+    pub max_upload_size: Option<u64>,
+    /// The push rules of the account, used to decide which messages notify
+    pub push_rules: Option<Ruleset>,
+    /// Notifications and highlights counted with the push rules since the room was last read
+    pub local_notifications: HashMap<RoomId, (u64, u64)>,
+    /// The bodies of the messages in the desktop notification of each room
+    pub notified_messages: HashMap<RoomId, Vec<String>>,
 
     pub login_data: Option<LoginData>,
 
     pub active_room: Option<RoomId>,
+    /// The space the room list is limited to
+    pub active_space: Option<RoomId>,
     pub join_to_room: Option<RoomId>,
     pub rooms: RoomList,
     unread_rooms: usize,
-    pub unsent_messages: HashMap<RoomId, (String, i32)>,
+    /// The messages being written in each room, with their attachments
+    pub drafts: HashMap<RoomId, Draft>,
+    /// The rooms whose draft changed since the drafts were last saved
+    unsaved_drafts: HashSet<RoomId>,
     pub typing: HashMap<RoomId, std::time::Instant>,
 
     pub directory_pagination: RoomSearchPagination,
+    pub directory_network: DirectoryNetwork,
+    /// The networks bridged to our homeserver
+    pub directory_protocols: Vec<ProtocolInstance>,
+    /// The rooms found in the directory, for their preview
+    pub directory_rooms: Vec<Room>,
     pub state: AppState,
     pub since: Option<String>,
 
@@ -111,17 +181,27 @@ impl AppOp {
             app_runtime,
             ui,
             active_room: None,
+            active_space: None,
             join_to_room: None,
             rooms: HashMap::new(),
             login_data: None,
             syncing: false,
             msg_queue: vec![],
             sending_message: false,
+            upload: None,
+            max_upload_size: None,
+            push_rules: None,
+            local_notifications: HashMap::new(),
+            notified_messages: HashMap::new(),
             state: AppState::Login,
             directory_pagination: RoomSearchPagination::Initial,
+            directory_network: Default::default(),
+            directory_protocols: vec![],
+            directory_rooms: vec![],
             unread_rooms: 0,
             since: None,
-            unsent_messages: HashMap::new(),
+            drafts: HashMap::new(),
+            unsaved_drafts: HashSet::new(),
             typing: HashMap::new(),
 
             md_enabled: false,
@@ -137,6 +217,9 @@ impl AppOp {
     pub fn init(&mut self) {
         self.set_state(AppState::Loading);
 
+        cache::crypto::set_enabled(util::get_cache_encryption_schema());
+        cache::media::set_quota(util::get_media_cache_size_schema() as u64 * 1024 * 1024);
+
         // FIXME: Username and uid should not be duplicated in cache.
         let device_id = if let Ok(data) = cache::load() {
             self.since = data.since.filter(|s| !s.is_empty());
@@ -179,8 +262,14 @@ impl AppOp {
         }
     }
 
-    pub fn quit(&self) {
+    pub fn quit(&mut self) {
+        self.update_active_draft();
+        self.save_drafts_at_quit();
         self.cache_rooms();
+        cache::media::save();
+        if let Err(err) = cache::crypto::purge_decrypted() {
+            error!("Can't remove the decrypted cache files: {}", err);
+        }
         self.disconnect();
     }
 
@@ -1,14 +1,28 @@
+use crate::app::RUNTIME;
 use crate::appop::AppOp;
+use crate::backend::{user, HandleError};
+use crate::model::message::Message;
+use crate::model::push_rules::{PushActions, PushContext, RuleKind, Ruleset};
 use matrix_sdk::identifiers::RoomId;
 
 impl AppOp {
     pub fn clear_room_notifications(&mut self, room_id: RoomId) {
+        self.local_notifications.remove(&room_id);
+        self.withdraw_notification(&room_id);
         self.set_room_notifications(room_id.clone(), 0, 0);
         self.ui.roomlist.set_bold(room_id, false);
         self.update_title();
     }
 
     pub fn set_room_notifications(&mut self, room_id: RoomId, n: u64, h: u64) {
+        // Our own count can only be higher, when the server missed some events
+        let (local_n, local_h) = self
+            .local_notifications
+            .get(&room_id)
+            .copied()
+            .unwrap_or_default();
+        let (n, h) = (n.max(local_n), h.max(local_h));
+
         if let Some(r) = self.rooms.get_mut(&room_id) {
             r.notifications = n;
             r.highlight = h;
@@ -18,4 +32,115 @@ impl AppOp {
         }
         self.update_title();
     }
+
+    pub fn update_push_rules(&self) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            match user::get_push_rules(session_client).await {
+                Ok(rules) => {
+                    APPOP!(set_push_rules, (rules));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn set_push_rules(&mut self, rules: Ruleset) {
+        self.ui
+            .preferences
+            .set_push_rules(&rules, &self.app_runtime);
+        self.ui.roomlist.set_muted_rooms(rules.muted_rooms());
+        self.push_rules = Some(rules);
+    }
+
+    pub fn set_push_rule_enabled(&self, kind: RuleKind, rule_id: String, enabled: bool) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            let query = user::set_push_rule_enabled(session_client, kind, &rule_id, enabled).await;
+            if let Err(err) = query {
+                err.handle_error();
+            }
+            // Also puts the switch back if it failed
+            APPOP!(update_push_rules);
+        });
+    }
+
+    pub fn add_keyword(&self, keyword: String) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            if let Err(err) = user::add_keyword(session_client, &keyword).await {
+                err.handle_error();
+            }
+            APPOP!(update_push_rules);
+        });
+    }
+
+    pub fn remove_keyword(&self, keyword: String) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            if let Err(err) = user::remove_keyword(session_client, &keyword).await {
+                err.handle_error();
+            }
+            APPOP!(update_push_rules);
+        });
+    }
+
+    /// What to do about the message according to the push rules of the account. Until
+    /// they are loaded, only the direct messages and the mentions notify.
+    pub fn push_actions(&self, msg: &Message) -> PushActions {
+        let login_data = match self.login_data.as_ref() {
+            Some(login_data) => login_data,
+            None => return PushActions::default(),
+        };
+        if msg.sender == login_data.uid {
+            return PushActions::default();
+        }
+
+        let room = self.rooms.get(&msg.room);
+        match self.push_rules.as_ref() {
+            Some(rules) => {
+                let ctx = PushContext::new(msg, room, login_data.username.as_deref());
+                rules.evaluate(&ctx)
+            }
+            None => {
+                let mention = login_data
+                    .username
+                    .as_ref()
+                    .map_or(false, |name| msg.body.contains(name));
+                PushActions {
+                    notify: mention || room.map_or(false, |r| r.direct),
+                    highlight: mention,
+                    sound: false,
+                }
+            }
+        }
+    }
+
+    /// Counts the message in the unread badge of its room, for the messages
+    /// the server doesn't count, like the encrypted ones
+    pub fn count_notification(&mut self, room_id: RoomId, highlight: bool) {
+        let counts = self.local_notifications.entry(room_id.clone()).or_default();
+        counts.0 += 1;
+        if highlight {
+            counts.1 += 1;
+        }
+
+        if let Some((n, h)) = self
+            .rooms
+            .get(&room_id)
+            .map(|r| (r.notifications, r.highlight))
+        {
+            self.set_room_notifications(room_id, n, h);
+        }
+    }
 }
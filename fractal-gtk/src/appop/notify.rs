@@ -1,16 +1,32 @@
 use crate::app::RUNTIME;
 use crate::appop::AppOp;
-use crate::backend::user;
-use crate::util::i18n::i18n;
+use crate::backend::{room, user, HandleError};
+use crate::model::message::Message;
+use crate::util::i18n::{i18n, i18n_f};
+use gio::ActionGroupExt;
 use gio::ApplicationExt;
 use gio::FileExt;
 use gio::Notification;
-use log::info;
+use gst::prelude::*;
+use gtk::prelude::*;
+use log::{error, info};
 use matrix_sdk::identifiers::{EventId, RoomId};
 use std::path::Path;
 
+/// The number of messages kept in the notification of a room, the older ones are dropped
+const GROUPED_MESSAGES: usize = 3;
+
+/// Looked up in the system data directories
+const MESSAGE_SOUND: &str = "sounds/freedesktop/stereo/message-new-instant.oga";
+
 impl AppOp {
-    pub fn notify(&self, app: gtk::Application, room_id: RoomId, id: EventId) -> Option<()> {
+    pub fn notify(
+        &mut self,
+        app: gtk::Application,
+        room_id: RoomId,
+        id: EventId,
+        sound: bool,
+    ) -> Option<()> {
         let session_client = self.login_data.as_ref()?.session_client.clone();
         let msg = self.get_message_by_id(&room_id, &id)?;
         let r = self.rooms.get(&room_id)?;
@@ -22,6 +38,14 @@ impl AppOp {
             _ => dirty_truncate(&msg.body, 80).to_string(),
         };
 
+        // Every room has a single notification, with its last messages
+        let bodies = self.notified_messages.entry(room_id.clone()).or_default();
+        bodies.push(short_body);
+        if bodies.len() > GROUPED_MESSAGES {
+            bodies.remove(0);
+        }
+        let body = bodies.join("\n");
+
         let title = if r.direct {
             i18n(" (direct message)")
         } else if let Some(name) = r.name.clone() {
@@ -39,14 +63,140 @@ impl AppOp {
         glib::MainContext::default().spawn_local(async move {
             if let Ok(Ok((name, avatar_path))) = response.await {
                 let title = format!("{}{}", name, title);
-                let n = create_notification(room_id.as_str(), &title, &short_body, &avatar_path);
-                app.send_notification(Some(id.as_str()), &n);
+                let n = create_notification(room_id.as_str(), &title, &body, &avatar_path);
+                app.send_notification(Some(room_id.as_str()), &n);
+                if sound {
+                    play_sound();
+                }
+            }
+        });
+
+        None
+    }
+
+    pub fn withdraw_notification(&mut self, room_id: &RoomId) {
+        if self.notified_messages.remove(room_id).is_some() {
+            if let Some(app) = self.ui.main_window.get_application() {
+                app.withdraw_notification(room_id.as_str());
+            }
+        }
+    }
+
+    /// Sends the read receipt for the last message of the room, without opening it
+    pub fn mark_room_as_read(&mut self, room_id: RoomId) -> Option<()> {
+        let session_client = self.login_data.as_ref()?.session_client.clone();
+        let event_id = self
+            .rooms
+            .get(&room_id)?
+            .messages
+            .iter()
+            .last()?
+            .id
+            .clone()?;
+
+        RUNTIME.spawn(async move {
+            match room::mark_as_read(session_client, room_id, event_id).await {
+                Ok((r, _)) => {
+                    APPOP!(clear_room_notifications, (r));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
             }
         });
 
         None
     }
 
+    /// Sends `text` to the room without opening it. Without any text, a small window
+    /// with the last notified messages is shown to write the reply.
+    pub fn quick_reply(&mut self, room_id: RoomId, text: String) -> Option<()> {
+        if text.trim().is_empty() {
+            return self.show_reply_dialog(room_id);
+        }
+
+        let login_data = self.login_data.as_ref()?;
+        let session_client = login_data.session_client.clone();
+        let mtype = String::from("m.text");
+        let msg = Message::new(room_id.clone(), login_data.uid.clone(), text, mtype, None);
+
+        RUNTIME.spawn(async move {
+            match room::send_msg(session_client.clone(), msg).await {
+                // Replying means the room has been read
+                Ok(event_id) => match room::mark_as_read(session_client, room_id, event_id).await {
+                    Ok((r, _)) => {
+                        APPOP!(clear_room_notifications, (r));
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                },
+                Err(err) => {
+                    error!("Couldn’t send the reply: {:?}", err);
+                    let error = i18n("Couldn’t send the reply.");
+                    APPOP!(show_error, (error));
+                }
+            }
+        });
+
+        None
+    }
+
+    /// The window opened by the Reply button of the notifications. Sending the reply
+    /// activates `app.reply` again, this time with the text.
+    fn show_reply_dialog(&self, room_id: RoomId) -> Option<()> {
+        let room = self.rooms.get(&room_id)?;
+        let name = room.name.clone().unwrap_or_else(|| i18n("Room"));
+        let title = i18n_f("Reply to {}", &[&name]);
+
+        let no_parent: Option<&gtk::Window> = None;
+        let dialog = gtk::Dialog::with_buttons(
+            Some(title.as_str()),
+            no_parent,
+            gtk::DialogFlags::USE_HEADER_BAR,
+            &[
+                (i18n("Cancel").as_str(), gtk::ResponseType::Cancel),
+                (i18n("Send").as_str(), gtk::ResponseType::Accept),
+            ],
+        );
+        dialog.set_application(Some(&self.ui.gtk_app));
+        dialog.set_default_response(gtk::ResponseType::Accept);
+        dialog.set_default_size(360, -1);
+
+        let content = dialog.get_content_area();
+        content.set_spacing(12);
+        content.set_property_margin(12);
+
+        if let Some(messages) = self.notified_messages.get(&room_id) {
+            let label = gtk::Label::new(Some(messages.join("\n").as_str()));
+            label.set_xalign(0.0);
+            label.set_line_wrap(true);
+            label.set_line_wrap_mode(pango::WrapMode::WordChar);
+            label.get_style_context().add_class("dim-label");
+            content.pack_start(&label, false, false, 0);
+        }
+
+        let entry = gtk::Entry::new();
+        entry.set_activates_default(true);
+        entry.set_placeholder_text(Some(i18n("Write a reply…").as_str()));
+        content.pack_start(&entry, false, false, 0);
+
+        let app = self.ui.gtk_app.clone();
+        dialog.connect_response(move |dialog, response| {
+            let text = entry.get_text();
+            if response == gtk::ResponseType::Accept && !text.trim().is_empty() {
+                let data = glib::Variant::from(format!("{} {}", room_id, text));
+                app.activate_action("reply", Some(&data));
+            }
+            dialog.destroy();
+        });
+
+        dialog.show_all();
+        dialog.present();
+
+        None
+    }
+
     pub fn show_error(&self, msg: String) {
         self.ui.show_error(msg);
     }
@@ -80,9 +230,42 @@ fn create_notification(room_id: &str, title: &str, body: &str, avatar: &Path) ->
     });
     let data = glib::Variant::from(room_id);
     notification.set_default_action_and_target_value("app.open-room", Some(&data));
+    notification.add_button_with_target_value(
+        &i18n("Mark as Read"),
+        "app.mark-room-as-read",
+        Some(&data),
+    );
+    // Without any text, the reply action asks for it
+    notification.add_button_with_target_value(&i18n("Reply"), "app.reply", Some(&data));
     notification
 }
 
+/// Plays the sound of the theme for new messages, if it's installed
+fn play_sound() -> Option<()> {
+    let path = glib::get_system_data_dirs()
+        .into_iter()
+        .map(|dir| dir.join(MESSAGE_SOUND))
+        .find(|path| path.exists())?;
+    let uri = glib::filename_to_uri(&path, None).ok()?;
+
+    let playbin = gst::ElementFactory::make("playbin", None).ok()?;
+    playbin.set_property("uri", &uri.as_str()).ok()?;
+    let bus = playbin.get_bus()?;
+    // The watch keeps the player alive until the sound ends
+    let player = playbin.clone();
+    bus.add_watch_local(move |_, msg| match msg.view() {
+        gst::MessageView::Eos(_) | gst::MessageView::Error(_) => {
+            let _ = player.set_state(gst::State::Null);
+            glib::Continue(false)
+        }
+        _ => glib::Continue(true),
+    })
+    .ok()?;
+    playbin.set_state(gst::State::Playing).ok()?;
+
+    Some(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
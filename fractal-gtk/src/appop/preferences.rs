@@ -0,0 +1,86 @@
+use crate::app::RUNTIME;
+use crate::appop::AppOp;
+use crate::cache::{self, crypto};
+use crate::util;
+use crate::util::i18n::i18n;
+use gtk::prelude::*;
+use log::error;
+use matrix_sdk::identifiers::RoomId;
+use std::collections::HashMap;
+
+impl AppOp {
+    pub fn show_preferences(&self) {
+        self.ui.show_preferences();
+        self.update_media_cache_usage();
+    }
+
+    /// Shows the size of the media cache by room, computed out of the main loop since it
+    /// can wait for the files to be counted or removed
+    pub fn update_media_cache_usage(&self) {
+        RUNTIME.spawn(async move {
+            let usage = cache::media::usage();
+            APPOP!(set_media_cache_usage, (usage));
+        });
+    }
+
+    pub fn set_media_cache_usage(&self, usage: HashMap<Option<RoomId>, u64>) {
+        let mut usage: Vec<(String, u64)> = usage
+            .into_iter()
+            .map(|(room_id, size)| {
+                let name = match room_id {
+                    Some(room_id) => self
+                        .rooms
+                        .get(&room_id)
+                        .and_then(|room| room.name.clone())
+                        .unwrap_or_else(|| room_id.to_string()),
+                    // Translators: Media in the cache we don't know the room of
+                    None => i18n("Other"),
+                };
+                (name, size)
+            })
+            .collect();
+        usage.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        self.ui.preferences.set_media_cache_usage(&usage);
+    }
+
+    pub fn set_media_cache_size(&mut self, size: i32) {
+        util::set_media_cache_size_schema(size);
+        cache::media::set_quota(size as u64 * 1024 * 1024);
+
+        RUNTIME.spawn(async move {
+            cache::media::enforce_quota();
+            let usage = cache::media::usage();
+            APPOP!(set_media_cache_usage, (usage));
+        });
+    }
+
+    pub fn set_cache_encryption(&mut self, enabled: bool) {
+        crypto::set_enabled(enabled);
+        util::set_cache_encryption_schema(enabled);
+        // The files already in the cache would be left in the other format,
+        // so start over with an empty cache
+        self.clear_cache();
+    }
+
+    pub fn clear_cache(&mut self) {
+        // The drafts are stored in the cache too, so they are written again from the ones
+        // in memory, which have to include the text being written
+        self.update_active_draft();
+        let cleared = cache::clear(self.user_info_cache.clone());
+
+        // Keep the session and the drafts, even when only a part of the cache was removed
+        self.cache_rooms();
+        self.store_drafts();
+        self.update_media_cache_usage();
+
+        if let Err(err) = cleared {
+            error!("Can't clear the cache: {:?}", err);
+            let msg = i18n("Couldn’t clear the cache");
+            let dialog = self.ui.create_error_dialog(&msg);
+            dialog.set_transient_for(Some(&self.ui.preferences.root));
+            dialog.connect_response(|d, _| d.close());
+            dialog.show();
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use crate::backend::room;
+use crate::backend::room::{self, JoinRule};
 use crate::util::i18n::{i18n, i18n_k, ni18n_f};
 use log::{error, warn};
-use matrix_sdk::identifiers::RoomId;
+use matrix_sdk::identifiers::{RoomAliasId, RoomId, RoomIdOrAliasId, ServerName};
 use matrix_sdk::{events::EventType, identifiers::MxcUri};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fs::remove_file;
 use std::os::unix::fs;
 
@@ -13,16 +13,18 @@ use crate::app::RUNTIME;
 use crate::appop::AppOp;
 use crate::backend::HandleError;
 
+use crate::util;
 use crate::util::cache_dir_path;
 
 use crate::actions;
 use crate::actions::AppState;
 use crate::cache;
 use crate::widgets;
+use crate::widgets::RoomSortOrder;
 
 use crate::model::{
     member::Member,
-    room::{Reason, Room, RoomMembership, RoomTag},
+    room::{Reason, Room, RoomMembership},
 };
 
 use crate::util::markup_text;
@@ -39,7 +41,9 @@ pub struct Force(pub bool);
 impl AppOp {
     pub fn remove_room(&mut self, id: RoomId) {
         self.rooms.remove(&id);
-        self.unsent_messages.remove(&id);
+        if self.drafts.remove(&id).is_some() {
+            self.store_drafts();
+        }
         self.ui.roomlist.remove_room(&id);
     }
 
@@ -49,7 +53,13 @@ impl AppOp {
             self.rooms.clear();
         }
         let mut roomlist = vec![];
-        for room in rooms {
+        let mut rooms_changed = clear_room_list;
+        for mut room in rooms {
+            if let Some(draft) = room.draft.take() {
+                self.receive_synced_draft(room.id.clone(), draft);
+            }
+            room.draft = self.drafts.get(&room.id).cloned();
+
             // removing left rooms
             if let RoomMembership::Left(kicked) = room.membership.clone() {
                 if let Reason::Kicked(reason, kicker_uid) = kicked {
@@ -64,18 +74,24 @@ impl AppOp {
                 } else {
                     self.remove_room(room.id);
                 }
+                rooms_changed = true;
             } else if let Some(update_room) = self.rooms.get_mut(&room.id) {
                 // TODO: update the existing rooms
                 if room.language.is_some() {
                     update_room.language = room.language.clone();
                 };
+                if room.disable_url_previews.is_some() {
+                    update_room.disable_url_previews = room.disable_url_previews;
+                }
 
                 let typing_users: Vec<Member> = room
                     .typing_users
                     .iter()
                     .map(|u| update_room.members.get(&u.uid).unwrap_or(&u).to_owned())
                     .collect();
+                let names = typing_users.iter().map(Member::get_alias).collect();
                 update_room.typing_users = typing_users;
+                self.ui.roomlist.set_room_typing(room.id.clone(), names);
                 self.update_typing_notification();
             } else {
                 // Request all joined members for each new room
@@ -91,6 +107,19 @@ impl AppOp {
                         }
                     }
                 });
+                // The spaces are known by the type of the room
+                let session_client = login_data.session_client.clone();
+                let room_id = room.id.clone();
+                RUNTIME.spawn(async move {
+                    match room::get_room_type(session_client, room_id).await {
+                        Ok((room, room_type)) => {
+                            APPOP!(set_room_type, (room, room_type));
+                        }
+                        Err(err) => {
+                            err.handle_error();
+                        }
+                    }
+                });
                 // Download the room avatar
                 // TODO: Use the avatar url returned by sync
                 let session_client = login_data.session_client.clone();
@@ -112,6 +141,7 @@ impl AppOp {
                     self.ui.roomlist.moveup(&room.id);
                 }
                 self.rooms.insert(room.id.clone(), room);
+                rooms_changed = true;
             }
         }
 
@@ -140,26 +170,33 @@ impl AppOp {
                 }
             }
 
-            self.ui.roomlist =
-                widgets::RoomList::new(adj, Some(login_data.session_client.homeserver().clone()));
+            self.ui.roomlist = widgets::RoomList::new(
+                adj,
+                Some(login_data.session_client.homeserver().clone()),
+                util::get_room_list_tags_schema(),
+            );
+            self.ui.roomlist.set_sort_order(RoomSortOrder::from(
+                util::get_room_list_sort_schema().as_str(),
+            ));
+            self.ui
+                .roomlist
+                .set_unread_only(util::get_room_list_unread_only_schema());
+            self.ui
+                .roomlist
+                .set_compact(util::get_room_list_compact_schema());
+            if let Some(rules) = &self.push_rules {
+                self.ui.roomlist.set_muted_rooms(rules.muted_rooms());
+            }
             self.ui.roomlist.add_rooms(roomlist);
             container.add(self.ui.roomlist.widget());
+        }
 
-            self.ui.roomlist.connect_fav(move |room, tofav| {
-                let session_client = login_data.session_client.clone();
-                let uid = login_data.uid.clone();
-                RUNTIME.spawn(async move {
-                    match room::add_to_fav(session_client, &uid, room.id, tofav).await {
-                        Ok((r, tofav)) => {
-                            APPOP!(added_to_fav, (r, tofav));
-                        }
-                        Err(err) => {
-                            err.handle_error();
-                        }
-                    }
-                });
-            });
+        if rooms_changed {
+            self.update_spaces();
+            self.update_replaced_rooms();
+        }
 
+        if clear_room_list {
             // Select active room in the sidebar
             if let Some(active_room) = self.active_room.clone() {
                 self.set_active_room_by_id(active_room);
@@ -178,14 +215,23 @@ impl AppOp {
 
     pub fn set_active_room_by_id(&mut self, id: RoomId) {
         let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        // Keep what was typed in the room we are leaving
+        self.update_active_draft();
         if let Some(room) = self.rooms.get(&id) {
             if let Some(language) = room.language.clone() {
                 self.set_language(language);
             }
             if let RoomMembership::Invited(ref sender_uid) = room.membership {
-                let sender = room.members.get(sender_uid);
-                self.ui.show_inv_dialog(sender, room.name.as_deref());
+                let room = room.clone();
+                // The invite doesn't always come with the inviter's profile
+                let sender = room.members.get(sender_uid).cloned().unwrap_or(Member {
+                    uid: sender_uid.clone(),
+                    alias: None,
+                    avatar: None,
+                });
+                self.ui.show_invite_preview(&room, &sender);
                 self.invitation_roomid = Some(room.id.clone());
+                self.peek_room(id);
                 return;
             }
 
@@ -207,29 +253,6 @@ impl AppOp {
             if user_power >= 0 || room.admins.is_empty() {
                 msg_entry.set_editable(true);
                 msg_entry_stack.set_visible_child_name("Text Entry");
-
-                if let Some(buffer) = msg_entry.get_buffer() {
-                    let start = buffer.get_start_iter();
-                    let end = buffer.get_end_iter();
-
-                    if let Some(msg) = buffer.get_text(&start, &end, false) {
-                        if let Some(ref active_room) = self.active_room {
-                            if msg.len() > 0 {
-                                if let Some(mark) = buffer.get_insert() {
-                                    let iter = buffer.get_iter_at_mark(&mark);
-                                    let msg_position = iter.get_offset();
-
-                                    self.unsent_messages.insert(
-                                        active_room.clone(),
-                                        (msg.to_string(), msg_position),
-                                    );
-                                }
-                            } else {
-                                self.unsent_messages.remove(active_room);
-                            }
-                        }
-                    }
-                }
             } else {
                 msg_entry.set_editable(false);
                 msg_entry_stack.set_visible_child_name("Disabled Entry");
@@ -310,23 +333,29 @@ impl AppOp {
         };
 
         self.active_room = Some(active_room);
+        self.update_attachment_tray();
+        self.show_room_upgrade();
         self.set_state(AppState::Room);
         /* Mark the new active room as read */
         self.mark_last_message_as_read(Force(false));
         self.update_typing_notification();
     }
 
-    // FIXME: This should be a special case in a generic
-    //        function that leaves any room in any state.
     pub fn really_leave_active_room(&mut self) {
         let room_id = unwrap_or_unit_return!(self.active_room.clone());
+        self.leave_room(room_id);
+    }
+
+    pub fn leave_room(&mut self, room_id: RoomId) {
         let session_client =
             unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
 
         self.rooms.remove(&room_id);
-        self.active_room = None;
-        self.clear_tmp_msgs();
-        self.set_state(AppState::NoRoom);
+        if self.active_room.as_ref() == Some(&room_id) {
+            self.active_room = None;
+            self.clear_tmp_msgs();
+            self.set_state(AppState::NoRoom);
+        }
         self.ui.roomlist.remove_room(&room_id);
 
         RUNTIME.spawn(async move {
@@ -466,6 +495,9 @@ impl AppOp {
             self.ui
                 .roomlist
                 .set_room_avatar(room_id.clone(), r.avatar.clone());
+            if r.is_space() {
+                self.update_spaces();
+            }
         }
     }
 
@@ -492,6 +524,28 @@ impl AppOp {
         self.ui.roomlist.filter_rooms(term);
     }
 
+    pub fn set_room_list_sort(&mut self, sort: RoomSortOrder) {
+        util::set_room_list_sort_schema(sort.as_str());
+        self.ui.roomlist.set_sort_order(sort);
+    }
+
+    pub fn set_room_list_unread_only(&mut self, unread_only: bool) {
+        util::set_room_list_unread_only_schema(unread_only);
+        self.ui.roomlist.set_unread_only(unread_only);
+    }
+
+    pub fn set_room_list_compact(&mut self, compact: bool) {
+        util::set_room_list_compact_schema(compact);
+        self.ui.roomlist.set_compact(compact);
+    }
+
+    /// Shows the last message of the room in its row of the room list
+    pub fn update_room_last_message(&mut self, room_id: RoomId) -> Option<()> {
+        let msg = self.rooms.get(&room_id)?.last_message()?.clone();
+        self.ui.roomlist.set_room_last_message(room_id, msg);
+        None
+    }
+
     pub fn new_room_dialog(&self) {
         let dialog = self
             .ui
@@ -524,7 +578,6 @@ impl AppOp {
     }
 
     pub fn join_to_room(&mut self) {
-        let login_data = unwrap_or_unit_return!(self.login_data.clone());
         let entry = self
             .ui
             .builder
@@ -533,7 +586,7 @@ impl AppOp {
         let try_room_id = entry.get_text().to_string().trim().try_into();
         entry.set_text("");
 
-        let room_id = match try_room_id {
+        let room_id_or_alias: RoomIdOrAliasId = match try_room_id {
             Ok(room_id) => room_id,
             Err(err) => {
                 error!("The room ID is malformed: {}", err);
@@ -543,9 +596,143 @@ impl AppOp {
             }
         };
 
-        let session_client = login_data.session_client.clone();
+        self.join_room_by_id_or_alias(room_id_or_alias);
+    }
+
+    /// Joins the room, resolving the alias first and asking before joining the rooms
+    /// that need a knock or a membership elsewhere
+    pub fn join_room_by_id_or_alias(&self, room_id_or_alias: RoomIdOrAliasId) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
         RUNTIME.spawn(async move {
-            match room::join_room(session_client, &room_id).await {
+            let (room_id, via) = match room_id_or_alias.try_into() {
+                Ok(room_id) => (room_id, vec![]),
+                Err(alias) => match room::resolve_alias(session_client.clone(), &alias).await {
+                    Ok(resolved) => resolved,
+                    Err(err) => {
+                        err.handle_error();
+                        return;
+                    }
+                },
+            };
+
+            match room::get_join_rule(session_client.clone(), &room_id).await {
+                Some(JoinRule::Knock) => {
+                    let room_id_or_alias: RoomIdOrAliasId = room_id.into();
+                    let info = None;
+                    APPOP!(knock_room_dialog, (room_id_or_alias, via, info));
+                }
+                Some(JoinRule::Restricted(allowed)) => {
+                    APPOP!(join_restricted_room, (room_id, via, allowed));
+                }
+                _ => match room::join_room(session_client, &room_id.into(), &via).await {
+                    Ok(jtr) => {
+                        let jtr = Some(jtr);
+                        APPOP!(set_join_to_room, (jtr));
+                        APPOP!(reload_rooms);
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                },
+            }
+        });
+    }
+
+    /// Shows the servers the alias typed in the join dialog leads to
+    pub fn resolve_join_alias(&self, alias: String) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let room_alias = match RoomAliasId::try_from(alias.as_str()) {
+            Ok(room_alias) => room_alias,
+            Err(_) => {
+                self.set_join_room_servers(alias, None);
+                return;
+            }
+        };
+
+        RUNTIME.spawn(async move {
+            let servers = room::resolve_alias(session_client, &room_alias)
+                .await
+                .ok()
+                .map(|(_, servers)| servers.iter().map(|s| s.to_string()).collect());
+            APPOP!(set_join_room_servers, (alias, servers));
+        });
+    }
+
+    pub fn set_join_room_servers(&self, alias: String, servers: Option<Vec<String>>) {
+        let entry = self
+            .ui
+            .builder
+            .get_object::<gtk::Entry>("join_room_name")
+            .expect("Can't find join_room_name in ui file.");
+        let label = self
+            .ui
+            .builder
+            .get_object::<gtk::Label>("join_room_servers")
+            .expect("Can't find join_room_servers in ui file.");
+
+        // The user kept typing since
+        if entry.get_text().trim() != alias {
+            return;
+        }
+
+        match servers {
+            Some(servers) if !servers.is_empty() => {
+                label.set_text(&i18n_k(
+                    "Joining through {servers}",
+                    &[("servers", &servers.join(", "))],
+                ));
+                label.show();
+            }
+            Some(_) => {
+                label.set_text(&i18n("No server is in this room anymore"));
+                label.show();
+            }
+            None if alias.starts_with('#') => {
+                label.set_text(&i18n("No room uses this alias"));
+                label.show();
+            }
+            None => label.hide(),
+        }
+    }
+
+    /// The server only lets us in when we are in one of the allowed rooms
+    pub fn join_restricted_room(
+        &self,
+        room_id: RoomId,
+        via: Vec<Box<ServerName>>,
+        allowed: Vec<RoomId>,
+    ) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+        let can_join = allowed.iter().any(|id| {
+            self.rooms
+                .get(id)
+                .map_or(false, |r| r.membership.is_joined())
+        });
+
+        if !can_join {
+            let names: Vec<String> = allowed
+                .iter()
+                .map(|id| {
+                    self.rooms
+                        .get(id)
+                        .and_then(|r| r.name.clone())
+                        .unwrap_or_else(|| id.to_string())
+                })
+                .collect();
+            let error = i18n("Only the members of some spaces can join this room.");
+            let info = Some(i18n_k(
+                "Join one of these first: {rooms}",
+                &[("rooms", &names.join(", "))],
+            ));
+            self.show_error_with_info(error, info);
+            return;
+        }
+
+        RUNTIME.spawn(async move {
+            match room::join_room(session_client, &room_id.into(), &via).await {
                 Ok(jtr) => {
                     let jtr = Some(jtr);
                     APPOP!(set_join_to_room, (jtr));
@@ -558,6 +745,97 @@ impl AppOp {
         });
     }
 
+    /// Asks for the reason of the request to join, `info` is why we couldn't join directly
+    pub fn knock_room_dialog(
+        &self,
+        room_id_or_alias: RoomIdOrAliasId,
+        via: Vec<Box<ServerName>>,
+        info: Option<String>,
+    ) {
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.ui.main_window),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &i18n("Ask to Join?"),
+        );
+        let mut secondary = i18n(
+            "You need to be invited to join this room. You can ask its members for an invite.",
+        );
+        if let Some(info) = info {
+            secondary = format!("{}\n\n{}", info, secondary);
+        }
+        dialog.set_property_secondary_text(Some(secondary.as_str()));
+
+        let reason_entry = gtk::Entry::new();
+        reason_entry.set_placeholder_text(Some(&i18n("Reason (optional)")));
+        reason_entry.set_activates_default(true);
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.add(&reason_entry);
+        }
+
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(&i18n("Ask to Join"), gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(move |w, r| {
+            if let gtk::ResponseType::Ok = r {
+                let reason = Some(reason_entry.get_text().trim().to_string())
+                    .filter(|reason| !reason.is_empty());
+                APPOP!(knock_room, (room_id_or_alias, via, reason));
+            }
+            w.close();
+        });
+        dialog.show_all();
+    }
+
+    pub fn knock_room(
+        &self,
+        room_id_or_alias: RoomIdOrAliasId,
+        via: Vec<Box<ServerName>>,
+        reason: Option<String>,
+    ) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let base = login_data.session_client.homeserver().clone();
+
+        RUNTIME.spawn(async move {
+            let query = room::knock(
+                base,
+                login_data.access_token,
+                &room_id_or_alias,
+                &via,
+                reason,
+            )
+            .await;
+            match query {
+                Ok(_) => {
+                    APPOP!(show_knock_sent);
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn show_knock_sent(&self) {
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.ui.main_window),
+            flags,
+            gtk::MessageType::Info,
+            gtk::ButtonsType::Ok,
+            &i18n("Request Sent"),
+        );
+        dialog.set_property_secondary_text(Some(
+            i18n("The room will show up in your invites if a member lets you in.").as_str(),
+        ));
+        dialog.connect_response(|w, _| w.close());
+        dialog.show_all();
+    }
+
     pub fn new_room(&mut self, r: Room) {
         if !self.rooms.contains_key(&r.id) {
             self.rooms.insert(r.id.clone(), r.clone());
@@ -569,17 +847,6 @@ impl AppOp {
         self.set_active_room_by_id(r.id);
     }
 
-    pub fn added_to_fav(&mut self, room_id: RoomId, tofav: bool) {
-        if let Some(ref mut r) = self.rooms.get_mut(&room_id) {
-            let tag = if tofav {
-                RoomTag::Favourite
-            } else {
-                RoomTag::None
-            };
-            r.membership = RoomMembership::Joined(tag);
-        }
-    }
-
     /// This method calculate the room name when there's no room name event
     /// For this we use the members in the room. If there's only one member we'll return that
     /// member name, if there's more than one we'll return the first one and others
@@ -619,6 +886,7 @@ impl AppOp {
     pub fn room_name_change(&mut self, room_id: RoomId, name: Option<String>) {
         let r = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
         r.name = name.clone();
+        let is_space = r.is_space();
 
         if self
             .active_room
@@ -633,6 +901,9 @@ impl AppOp {
         }
 
         self.ui.roomlist.rename_room(room_id, name);
+        if is_space {
+            self.update_spaces();
+        }
     }
 
     pub fn room_topic_change(&mut self, room_id: RoomId, topic: Option<String>) {
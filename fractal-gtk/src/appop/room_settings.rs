@@ -1,5 +1,7 @@
 use crate::actions::AppState;
 use crate::appop::AppOp;
+use crate::backend::room::RoomNotify;
+use matrix_sdk::identifiers::{RoomId, RoomVersionId};
 
 impl AppOp {
     pub fn create_room_settings(&mut self) -> Option<()> {
@@ -25,7 +27,16 @@ impl AppOp {
         self.ui.show_new_room_topic()
     }
 
-    pub fn set_notifications_switch(&self, active: bool, sensitive: bool) -> Option<()> {
-        self.ui.set_notifications_switch(active, sensitive)
+    pub fn set_notifications_mode(&self, notify: RoomNotify, sensitive: bool) -> Option<()> {
+        self.ui.set_notifications_mode(notify, sensitive)
+    }
+
+    pub fn set_room_url_previews(&mut self, room_id: RoomId, disable: bool) -> Option<()> {
+        self.rooms.get_mut(&room_id)?.disable_url_previews = Some(disable);
+        None
+    }
+
+    pub fn show_room_settings_upgrade(&self, version: RoomVersionId) -> Option<()> {
+        self.ui.show_room_settings_upgrade(version)
     }
 }
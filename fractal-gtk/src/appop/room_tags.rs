@@ -0,0 +1,223 @@
+use crate::app::RUNTIME;
+use crate::appop::AppOp;
+use crate::backend::room;
+use crate::backend::HandleError;
+use crate::model::room::{RoomMembership, RoomTag};
+use crate::util;
+use crate::util::i18n::i18n;
+use gtk::prelude::*;
+use matrix_sdk::identifiers::RoomId;
+
+impl AppOp {
+    /// Adds the tag to the room. Favourites and low priority replace each other, while
+    /// the custom tags are added to the ones the room has.
+    pub fn set_room_tag(&mut self, room_id: RoomId, tag: RoomTag) {
+        let room = unwrap_or_unit_return!(self.rooms.get(&room_id));
+        let old_tag = match &room.membership {
+            RoomMembership::Joined(old_tag) if tag.is_builtin() => old_tag.clone(),
+            _ => RoomTag::None,
+        };
+
+        self.change_room_tag(room_id, old_tag, tag);
+    }
+
+    pub fn remove_room_tag(&mut self, room_id: RoomId, tag: RoomTag) {
+        self.change_room_tag(room_id, tag, RoomTag::None);
+    }
+
+    /// Replaces `old_tag` by `new_tag` in the room, either of them can be `RoomTag::None`
+    fn change_room_tag(&mut self, room_id: RoomId, old_tag: RoomTag, new_tag: RoomTag) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let room = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
+        let mut builtin = match &room.membership {
+            RoomMembership::Joined(tag) => tag.clone(),
+            _ => return,
+        };
+        let old_user_tags = room.user_tags.clone();
+        let old_tags = tag_names(&builtin, &old_user_tags);
+
+        match old_tag {
+            RoomTag::Custom(tag) => room.user_tags.retain(|t| *t != tag),
+            RoomTag::None => {}
+            tag if tag == builtin => builtin = RoomTag::None,
+            _ => {}
+        }
+        match new_tag {
+            RoomTag::Custom(tag) if !room.user_tags.contains(&tag) => room.user_tags.push(tag),
+            tag if tag.is_builtin() => builtin = tag,
+            _ => {}
+        }
+
+        let new_tags = tag_names(&builtin, &room.user_tags);
+        let removed: Vec<String> = old_tags
+            .iter()
+            .filter(|tag| !new_tags.contains(tag))
+            .cloned()
+            .collect();
+        let added: Vec<String> = new_tags
+            .iter()
+            .filter(|tag| !old_tags.contains(tag))
+            .cloned()
+            .collect();
+        if removed.is_empty() && added.is_empty() {
+            return;
+        }
+
+        let old_builtin = room.membership.clone();
+        room.membership = RoomMembership::Joined(builtin);
+        self.ui.roomlist.move_room(room);
+
+        RUNTIME.spawn(async move {
+            let query = room::set_room_tags(
+                login_data.session_client,
+                &login_data.uid,
+                &room_id,
+                &removed,
+                &added,
+            )
+            .await;
+
+            if let Err(err) = query {
+                err.handle_error();
+                // Put the room back in its groups
+                if let RoomMembership::Joined(tag) = old_builtin {
+                    let user_tags = old_user_tags;
+                    APPOP!(room_tag_changed, (room_id, tag, user_tags));
+                }
+            }
+        });
+    }
+
+    /// Moves the room to the groups of its tags, when they changed on the server
+    pub fn room_tag_changed(&mut self, room_id: RoomId, tag: RoomTag, user_tags: Vec<String>) {
+        let room = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
+        if room.membership.is_joined() {
+            room.membership = RoomMembership::Joined(tag);
+            room.user_tags = user_tags;
+            self.ui.roomlist.move_room(room);
+        }
+    }
+
+    pub fn new_room_tag_dialog(&self, room_id: Option<RoomId>) {
+        self.show_tag_name_dialog(&i18n("New Tag"), "", &i18n("Create"), move |name| {
+            let tag = format!("u.{}", name);
+            let room_id = room_id.clone();
+            APPOP!(add_room_tag, (tag, room_id));
+        });
+    }
+
+    /// Adds an empty group for the tag, and moves the room to it
+    pub fn add_room_tag(&mut self, tag: String, room_id: Option<RoomId>) {
+        self.ui.roomlist.add_tag(tag.clone());
+        util::set_room_list_tags_schema(&self.ui.roomlist.tags());
+
+        if let Some(room_id) = room_id {
+            self.set_room_tag(room_id, RoomTag::Custom(tag));
+        }
+    }
+
+    pub fn rename_room_tag_dialog(&self, tag: String) {
+        let name = RoomTag::display_name(&tag).to_string();
+        self.show_tag_name_dialog(&i18n("Rename Tag"), &name, &i18n("Rename"), move |name| {
+            let tag = tag.clone();
+            let new_tag = format!("u.{}", name);
+            APPOP!(rename_room_tag, (tag, new_tag));
+        });
+    }
+
+    /// Tags can't be renamed on the server, the rooms are tagged again with the new name
+    pub fn rename_room_tag(&mut self, tag: String, new_tag: String) {
+        if tag == new_tag {
+            return;
+        }
+        if self.ui.roomlist.tags().contains(&new_tag) {
+            self.show_error(i18n("There’s already a tag with this name."));
+            return;
+        }
+
+        self.ui.roomlist.rename_tag(&tag, new_tag.clone());
+        util::set_room_list_tags_schema(&self.ui.roomlist.tags());
+
+        for room_id in self.rooms_with_tag(&tag) {
+            let old_tag = RoomTag::Custom(tag.clone());
+            self.change_room_tag(room_id, old_tag, RoomTag::Custom(new_tag.clone()));
+        }
+    }
+
+    pub fn move_room_tag(&mut self, tag: String, up: bool) {
+        self.ui.roomlist.move_tag(&tag, up);
+        util::set_room_list_tags_schema(&self.ui.roomlist.tags());
+    }
+
+    /// Removes the tag from its rooms, they stay in the groups of their other tags
+    pub fn delete_room_tag(&mut self, tag: String) {
+        for room_id in self.rooms_with_tag(&tag) {
+            self.remove_room_tag(room_id, RoomTag::Custom(tag.clone()));
+        }
+
+        self.ui.roomlist.remove_tag(&tag);
+        util::set_room_list_tags_schema(&self.ui.roomlist.tags());
+    }
+
+    fn rooms_with_tag(&self, tag: &str) -> Vec<RoomId> {
+        self.rooms
+            .values()
+            .filter(|r| r.membership.is_joined() && r.user_tags.iter().any(|t| t == tag))
+            .map(|r| r.id.clone())
+            .collect()
+    }
+
+    fn show_tag_name_dialog<F: Fn(String) + 'static>(
+        &self,
+        title: &str,
+        name: &str,
+        accept_label: &str,
+        cb: F,
+    ) {
+        let entry = gtk::Entry::new();
+        entry.set_text(name);
+        entry.set_activates_default(true);
+
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.ui.main_window),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            title,
+        );
+        if let Some(area) = dialog.get_message_area() {
+            if let Ok(area) = area.downcast::<gtk::Box>() {
+                area.add(&entry);
+            }
+        }
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(accept_label, gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+        button.set_sensitive(!name.is_empty());
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        entry.connect_property_text_notify(move |w| {
+            button.set_sensitive(!w.get_text().trim().is_empty());
+        });
+
+        dialog.connect_response(move |w, r| {
+            let name = entry.get_text().trim().to_string();
+            if r == gtk::ResponseType::Ok && !name.is_empty() {
+                cb(name);
+            }
+            w.close();
+        });
+        dialog.show_all();
+    }
+}
+
+/// The names of the tags on the server, for the built-in tag and the custom ones
+fn tag_names(builtin: &RoomTag, user_tags: &[String]) -> Vec<String> {
+    builtin
+        .name()
+        .map(String::from)
+        .into_iter()
+        .chain(user_tags.iter().cloned())
+        .collect()
+}
@@ -0,0 +1,152 @@
+use crate::app::RUNTIME;
+use crate::appop::AppOp;
+use crate::backend::room;
+use crate::backend::HandleError;
+use crate::util::i18n::{i18n, i18n_k};
+use gtk::prelude::*;
+use matrix_sdk::identifiers::{RoomId, RoomVersionId, ServerName};
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+impl AppOp {
+    /// Hides the upgraded rooms from the room list once their successor is joined
+    pub fn update_replaced_rooms(&mut self) {
+        let replaced_rooms: HashSet<RoomId> = self
+            .rooms
+            .values()
+            .filter(|r| {
+                r.successor.as_ref().map_or(false, |successor| {
+                    self.rooms
+                        .get(successor)
+                        .map_or(false, |s| s.membership.is_joined())
+                })
+            })
+            .map(|r| r.id.clone())
+            .collect();
+
+        self.ui.roomlist.set_replaced_rooms(replaced_rooms);
+    }
+
+    pub fn set_room_successor(&mut self, room_id: RoomId, successor: RoomId) {
+        let r = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
+        r.successor = Some(successor);
+
+        self.update_replaced_rooms();
+        if self.active_room.as_ref() == Some(&room_id) {
+            self.show_room_upgrade();
+        }
+    }
+
+    /// Replaces the message entry of an upgraded room by a link to the new room, and links
+    /// the old room at the top of the new one
+    pub fn show_room_upgrade(&self) {
+        let room =
+            unwrap_or_unit_return!(self.active_room.as_ref().and_then(|id| self.rooms.get(id)));
+
+        if let Some(successor) = room.successor.as_ref() {
+            self.ui.sventry.view.set_editable(false);
+            self.ui
+                .room_upgraded_button
+                .set_action_target_value(Some(&successor.as_str().to_variant()));
+            self.ui.sventry_box.set_visible_child_name("Upgraded Room");
+        }
+
+        let revealer = self
+            .ui
+            .builder
+            .get_object::<gtk::Revealer>("room_predecessor_revealer")
+            .expect("Can't find room_predecessor_revealer in ui file.");
+        let button = self
+            .ui
+            .builder
+            .get_object::<gtk::Button>("room_predecessor_button")
+            .expect("Can't find room_predecessor_button in ui file.");
+
+        if let Some(predecessor) = room.predecessor.as_ref() {
+            button.set_action_target_value(Some(&predecessor.as_str().to_variant()));
+            button.set_action_name(Some("app.go-to-room"));
+        }
+        revealer.set_reveal_child(room.predecessor.is_some());
+    }
+
+    /// Opens the room, joining it first when needed, for the links between the upgraded rooms
+    pub fn go_to_room(&mut self, room_id: RoomId) {
+        if self
+            .rooms
+            .get(&room_id)
+            .map_or(false, |r| r.membership.is_joined())
+        {
+            self.set_active_room_by_id(room_id);
+            return;
+        }
+
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        // The room was created on the server of its ID
+        let via: Vec<Box<ServerName>> = room_id
+            .server_name()
+            .as_str()
+            .try_into()
+            .ok()
+            .into_iter()
+            .collect();
+
+        RUNTIME.spawn(async move {
+            match room::join_room(login_data.session_client, &room_id.into(), &via).await {
+                Ok(jtr) => {
+                    let jtr = Some(jtr);
+                    APPOP!(set_join_to_room, (jtr));
+                    APPOP!(reload_rooms);
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    pub fn upgrade_room_dialog(&self, room_id: RoomId, version: RoomVersionId) {
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.ui.main_window),
+            flags,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::None,
+            &i18n("Upgrade Room?"),
+        );
+        let secondary = i18n_k(
+            "A new room will be created with the version {version}. The members will have to join it, this room will only be kept for its history.",
+            &[("version", version.as_str())],
+        );
+        dialog.set_property_secondary_text(Some(secondary.as_str()));
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(&i18n("Upgrade"), gtk::ResponseType::Ok);
+        button.get_style_context().add_class("destructive-action");
+
+        dialog.connect_response(move |w, r| {
+            if let gtk::ResponseType::Ok = r {
+                APPOP!(upgrade_room, (room_id, version));
+            }
+            w.close();
+        });
+        dialog.show_all();
+    }
+
+    pub fn upgrade_room(&self, room_id: RoomId, version: RoomVersionId) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            match room::upgrade_room(session_client, &room_id, &version).await {
+                Ok(new_room_id) => {
+                    // The server already joined us to the new room
+                    let jtr = Some(new_room_id);
+                    APPOP!(set_join_to_room, (jtr));
+                    APPOP!(reload_rooms);
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+}
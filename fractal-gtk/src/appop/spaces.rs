@@ -0,0 +1,323 @@
+use crate::actions::AppState;
+use crate::app::RUNTIME;
+use crate::appop::AppOp;
+use crate::backend::room;
+use crate::backend::HandleError;
+use crate::model::room::{Room, SpaceChild};
+use crate::ui::space::SpaceRoom;
+use crate::util::i18n::i18n;
+use crate::widgets::SpaceFilter;
+use gtk::prelude::*;
+use matrix_sdk::identifiers::{RoomId, ServerName};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+impl AppOp {
+    /// Updates the space sidebar and the room list filter after the spaces changed
+    pub fn update_spaces(&mut self) {
+        let mut spaces: Vec<&Room> = self
+            .rooms
+            .values()
+            .filter(|r| r.membership.is_joined() && r.is_space())
+            .collect();
+        spaces.sort_by_key(|r| r.name.clone().unwrap_or_default().to_lowercase());
+
+        if self
+            .active_space
+            .as_ref()
+            .map_or(false, |id| !spaces.iter().any(|r| r.id == *id))
+        {
+            self.active_space = None;
+        }
+
+        self.ui.space_list.set_spaces(&spaces);
+        self.ui.space_list.select(self.active_space.as_ref());
+
+        let visible = !self.ui.space_list.is_empty();
+        for name in &["space_list_scroll", "space_list_separator"] {
+            if let Some(widget) = self.ui.builder.get_object::<gtk::Widget>(name) {
+                widget.set_visible(visible);
+            }
+        }
+
+        let space_filter = SpaceFilter {
+            rooms: self
+                .active_space
+                .as_ref()
+                .map(|space_id| self.space_rooms(space_id)),
+            spaces: spaces.iter().map(|r| r.id.clone()).collect(),
+        };
+        self.ui.roomlist.set_space_filter(space_filter);
+    }
+
+    /// Limits the room list to the space and shows its home page, or shows all the rooms
+    /// for `None`
+    pub fn select_space(&mut self, space_id: Option<RoomId>) {
+        if self.active_space == space_id {
+            return;
+        }
+
+        self.active_space = space_id;
+        self.update_spaces();
+
+        if self.active_space.is_some() {
+            self.set_state(AppState::NoRoom);
+        } else if self.state == AppState::NoRoom {
+            self.ui
+                .builder
+                .get_object::<gtk::Stack>("room_view_stack")
+                .expect("Can't find room_view_stack in ui file.")
+                .set_visible_child_name("noroom");
+        }
+    }
+
+    /// The rooms of the space and of its subspaces, the rooms saying they are in one of
+    /// them are included too
+    fn space_rooms(&self, space_id: &RoomId) -> HashSet<RoomId> {
+        let mut rooms = HashSet::new();
+        let mut spaces = vec![space_id.clone()];
+        let mut visited = HashSet::new();
+
+        while let Some(space_id) = spaces.pop() {
+            if !visited.insert(space_id.clone()) {
+                continue;
+            }
+
+            let children = self
+                .rooms
+                .get(&space_id)
+                .map(|space| space.space_children.iter().map(|c| c.room_id.clone()))
+                .into_iter()
+                .flatten();
+            let with_parent = self
+                .rooms
+                .values()
+                .filter(|r| r.space_parents.contains(&space_id))
+                .map(|r| r.id.clone());
+
+            for room_id in children.chain(with_parent).collect::<Vec<_>>() {
+                if self.rooms.get(&room_id).map_or(false, Room::is_space) {
+                    spaces.push(room_id.clone());
+                }
+                rooms.insert(room_id);
+            }
+        }
+
+        rooms
+    }
+
+    /// Whether we can send the `m.space.child` events adding and removing the rooms
+    fn can_edit_space(&self, space_id: &RoomId) -> bool {
+        match (self.login_data.as_ref(), self.rooms.get(space_id)) {
+            (Some(login_data), Some(space)) => {
+                let level = space
+                    .admins
+                    .get(&login_data.uid)
+                    .copied()
+                    .unwrap_or(space.default_power_level);
+                level >= space.space_child_power_level
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_room_type(&mut self, room_id: RoomId, room_type: Option<String>) {
+        let room = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
+        room.room_type = room_type;
+        if room.is_space() {
+            self.update_spaces();
+        }
+    }
+
+    /// Adds the room to the space or removes it, when the room changed its parents
+    pub fn set_space_parent(&mut self, room_id: RoomId, space_id: RoomId, valid: bool) {
+        let room = unwrap_or_unit_return!(self.rooms.get_mut(&room_id));
+        room.space_parents.retain(|id| *id != space_id);
+        if valid {
+            room.space_parents.push(space_id);
+        }
+
+        self.update_spaces();
+    }
+
+    pub fn set_space_child(
+        &mut self,
+        space_id: RoomId,
+        room_id: RoomId,
+        child: Option<SpaceChild>,
+    ) {
+        let space = unwrap_or_unit_return!(self.rooms.get_mut(&space_id));
+        space.space_children.retain(|c| c.room_id != room_id);
+        if let Some(child) = child {
+            space.space_children.push(child);
+            SpaceChild::sort(&mut space.space_children);
+        }
+
+        self.update_spaces();
+        if self.state == AppState::NoRoom {
+            self.show_space_home();
+        }
+    }
+
+    /// Shows the home page of the selected space and loads its rooms
+    pub fn show_space_home(&self) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        let space_id = unwrap_or_unit_return!(self.active_space.clone());
+        let space = unwrap_or_unit_return!(self.rooms.get(&space_id));
+
+        self.ui
+            .show_space_home(space, self.can_edit_space(&space_id));
+
+        let base = login_data.session_client.homeserver().clone();
+        RUNTIME.spawn(async move {
+            match room::get_space_summary(base, login_data.access_token, &space_id).await {
+                Ok(rooms) => {
+                    APPOP!(set_space_summary, (space_id, rooms));
+                }
+                Err(err) => {
+                    err.handle_error();
+                }
+            }
+        });
+    }
+
+    /// Shows the hierarchy of the space, the subspaces are followed by their rooms
+    pub fn set_space_summary(&mut self, space_id: RoomId, rooms: Vec<Room>) {
+        let login_data = unwrap_or_unit_return!(self.login_data.clone());
+        if self.active_space.as_ref() != Some(&space_id) {
+            return;
+        }
+
+        let summary: HashMap<RoomId, Room> = rooms.into_iter().map(|r| (r.id.clone(), r)).collect();
+        let mut space_rooms = vec![];
+        let mut visited = HashSet::new();
+        visited.insert(space_id.clone());
+        self.add_space_summary_rooms(&summary, &space_id, 0, &mut visited, &mut space_rooms);
+
+        self.ui
+            .set_space_rooms(space_rooms, login_data.session_client);
+    }
+
+    fn add_space_summary_rooms(
+        &self,
+        summary: &HashMap<RoomId, Room>,
+        space_id: &RoomId,
+        depth: usize,
+        visited: &mut HashSet<RoomId>,
+        space_rooms: &mut Vec<SpaceRoom>,
+    ) {
+        let space = unwrap_or_unit_return!(summary.get(space_id));
+        let can_remove = self.can_edit_space(space_id);
+
+        for child in space.space_children.iter() {
+            // The server leaves out the rooms we can't see
+            let room = match summary.get(&child.room_id) {
+                Some(room) if visited.insert(child.room_id.clone()) => room,
+                _ => continue,
+            };
+
+            space_rooms.push(SpaceRoom {
+                room: room.clone(),
+                parent: space_id.clone(),
+                depth,
+                via: child.via.clone(),
+                joined: self
+                    .rooms
+                    .get(&room.id)
+                    .map_or(false, |r| r.membership.is_joined()),
+                can_remove,
+            });
+
+            if room.is_space() {
+                self.add_space_summary_rooms(summary, &room.id, depth + 1, visited, space_rooms);
+            }
+        }
+    }
+
+    pub fn add_room_to_space_dialog(&self) {
+        let space_id = unwrap_or_unit_return!(self.active_space.clone());
+        let space = unwrap_or_unit_return!(self.rooms.get(&space_id));
+
+        let mut rooms: Vec<&Room> = self
+            .rooms
+            .values()
+            .filter(|r| {
+                r.membership.is_joined()
+                    && r.id != space_id
+                    && !space.space_children.iter().any(|c| c.room_id == r.id)
+            })
+            .collect();
+        rooms.sort_by_key(|r| r.name.clone().unwrap_or_default().to_lowercase());
+
+        let combo = gtk::ComboBoxText::new();
+        for r in rooms {
+            combo.append(Some(r.id.as_str()), r.name.as_deref().unwrap_or_default());
+        }
+        combo.set_active(Some(0));
+
+        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.ui.main_window),
+            flags,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &i18n("Add Room to Space"),
+        );
+        if let Some(area) = dialog.get_message_area() {
+            if let Ok(area) = area.downcast::<gtk::Box>() {
+                area.add(&combo);
+            }
+        }
+        dialog.add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let button = dialog.add_button(&i18n("Add"), gtk::ResponseType::Ok);
+        button.get_style_context().add_class("suggested-action");
+        button.set_sensitive(combo.get_active_id().is_some());
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(move |w, r| {
+            let room_id: Option<RoomId> = combo
+                .get_active_id()
+                .and_then(|id| id.as_str().try_into().ok());
+            if let (gtk::ResponseType::Ok, Some(room_id)) = (r, room_id) {
+                let space_id = space_id.clone();
+                APPOP!(add_room_to_space, (space_id, room_id));
+            }
+            w.close();
+        });
+        dialog.show_all();
+    }
+
+    pub fn add_room_to_space(&self, space_id: RoomId, room_id: RoomId) {
+        let login_data = unwrap_or_unit_return!(self.login_data.as_ref());
+        // The server the room was created on and ours, since we are in the room
+        let mut via: Vec<Box<ServerName>> = [room_id.server_name(), login_data.uid.server_name()]
+            .iter()
+            .filter_map(|server| server.as_str().try_into().ok())
+            .collect();
+        via.dedup();
+
+        self.set_space_child_on_server(space_id, room_id, via);
+    }
+
+    pub fn remove_room_from_space(&self, space_id: RoomId, room_id: RoomId) {
+        self.set_space_child_on_server(space_id, room_id, vec![]);
+    }
+
+    /// The space is updated by the sync, when the event comes back
+    fn set_space_child_on_server(
+        &self,
+        space_id: RoomId,
+        room_id: RoomId,
+        via: Vec<Box<ServerName>>,
+    ) {
+        let session_client =
+            unwrap_or_unit_return!(self.login_data.as_ref().map(|ld| ld.session_client.clone()));
+
+        RUNTIME.spawn(async move {
+            let query = room::set_space_child(session_client, &space_id, &room_id, &via).await;
+            if let Err(err) = query {
+                err.handle_error();
+            }
+        });
+    }
+}
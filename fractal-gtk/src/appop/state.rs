@@ -14,19 +14,28 @@ impl AppOp {
                 self.ui.set_chat_state(None);
                 self.active_room = None;
                 self.clear_tmp_msgs();
+                self.show_space_home();
             }
             AppState::Room => {
                 let msg = if let Some(active_room_id) = self.active_room.as_ref() {
-                    self.unsent_messages
+                    self.drafts
                         .get(active_room_id)
-                        .map(|(msg_text, msg_number)| (msg_text.as_str(), msg_number.clone()))
+                        .map(|draft| (draft.text.as_str(), draft.cursor))
                         .unwrap_or_default()
                 } else {
                     Default::default()
                 };
 
+                let formats = self
+                    .active_room
+                    .as_ref()
+                    .and_then(|room_id| self.drafts.get(room_id))
+                    .map(|draft| draft.formats.clone())
+                    .unwrap_or_default();
+
                 self.ui.set_stack_state("main_view");
                 self.ui.set_chat_state(Some(msg));
+                self.ui.sventry.apply_formats(&formats);
             }
             AppState::Directory => self.ui.set_deck_state(Some("directory"), state),
             AppState::Loading => self.ui.set_stack_state("loading"),
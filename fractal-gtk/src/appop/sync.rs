@@ -6,16 +6,18 @@ use crate::backend::{
 };
 use crate::model::{
     member::Member,
-    room::{Room, RoomMembership, RoomTag},
+    room::{space_parent, Room, RoomMembership, RoomTag, SpaceChild},
 };
 use crate::util::i18n::i18n;
 use matrix_sdk::deserialized_responses::{JoinedRoom, SyncResponse};
+use matrix_sdk::events::AnyBasicEvent;
 use matrix_sdk::events::AnyEphemeralRoomEventContent;
 use matrix_sdk::events::AnySyncMessageEvent;
 use matrix_sdk::events::AnySyncRoomEvent;
 use matrix_sdk::events::AnySyncStateEvent;
 use matrix_sdk::identifiers::{RoomId, UserId};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
 impl AppOp {
     pub fn initial_sync(&self, show: bool) {
@@ -60,6 +62,15 @@ impl AppOp {
                             let typing_events_as_rooms = updates.typing_events_as_rooms;
                             APPOP!(set_rooms, (typing_events_as_rooms, clear_room_list));
 
+                            // Before the counts, which are the ones from the server after that
+                            for room_id in updates.read_rooms {
+                                APPOP!(clear_room_notifications, (room_id));
+                            }
+
+                            for (room_id, tag, user_tags) in updates.room_tags {
+                                APPOP!(room_tag_changed, (room_id, tag, user_tags));
+                            }
+
                             for (room_id, unread_notifications) in updates.room_notifications {
                                 let r = room_id;
                                 let n: u64 = unread_notifications.notification_count;
@@ -86,6 +97,15 @@ impl AppOp {
                                     RoomElement::RemoveMessage(room_id, msg_id) => {
                                         APPOP!(remove_message, (room_id, msg_id));
                                     }
+                                    RoomElement::SpaceChild(space_id, room_id, child) => {
+                                        APPOP!(set_space_child, (space_id, room_id, child));
+                                    }
+                                    RoomElement::SpaceParent(room_id, space_id, valid) => {
+                                        APPOP!(set_space_parent, (room_id, space_id, valid));
+                                    }
+                                    RoomElement::Tombstone(room_id, successor) => {
+                                        APPOP!(set_room_successor, (room_id, successor));
+                                    }
                                 }
                             }
                         } else {
@@ -203,8 +223,71 @@ fn get_sync_updates(join: &BTreeMap<RoomId, JoinedRoom>, user_id: &UserId) -> Sy
                 AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomRedaction(ev)) => {
                     Some(RoomElement::RemoveMessage(room_id, ev.redacts.clone()))
                 }
+                AnySyncRoomEvent::State(AnySyncStateEvent::RoomTombstone(ev)) => Some(
+                    RoomElement::Tombstone(room_id, ev.content.replacement_room.clone()),
+                ),
+                AnySyncRoomEvent::State(AnySyncStateEvent::Custom(ev))
+                    if ev.content.event_type == "m.space.child" =>
+                {
+                    let child = SpaceChild::from_content(&ev.state_key, &ev.content.data);
+                    RoomId::try_from(ev.state_key.as_str())
+                        .ok()
+                        .map(|child_id| RoomElement::SpaceChild(room_id, child_id, child))
+                }
+                AnySyncRoomEvent::State(AnySyncStateEvent::Custom(ev))
+                    if ev.content.event_type == "m.space.parent" =>
+                {
+                    space_parent(&ev.state_key, &ev.content.data)
+                        .map(|(space_id, valid)| RoomElement::SpaceParent(room_id, space_id, valid))
+                }
                 _ => None,
             })
             .collect(),
+        read_rooms: join
+            .iter()
+            .filter(|(_, room)| {
+                let receipt = room
+                    .ephemeral
+                    .events
+                    .iter()
+                    .any(|event| match event.content() {
+                        AnyEphemeralRoomEventContent::Receipt(content) => {
+                            content.0.values().any(|receipts| {
+                                receipts
+                                    .read
+                                    .as_ref()
+                                    .map_or(false, |read| read.contains_key(user_id))
+                            })
+                        }
+                        _ => false,
+                    });
+                // Moving the read marker on another device reads the room too
+                let fully_read = room.account_data.events.iter().any(|event| {
+                    matches!(event, AnyBasicEvent::Custom(ev) if ev.content.event_type == "m.fully_read")
+                });
+                receipt || fully_read
+            })
+            .map(|(room_id, _)| room_id.clone())
+            .collect(),
+        room_tags: join
+            .iter()
+            .filter_map(|(room_id, room)| {
+                room.account_data
+                    .events
+                    .iter()
+                    .find_map(|event| match event {
+                        AnyBasicEvent::Tag(ev) => {
+                            let (tag, user_tags) = RoomTag::from_tags(
+                                ev.content
+                                    .tags
+                                    .iter()
+                                    .map(|(name, info)| (name, info.order)),
+                            );
+                            Some((room_id.clone(), tag, user_tags))
+                        }
+                        _ => None,
+                    })
+            })
+            .collect(),
     }
 }
@@ -1,20 +1,32 @@
-use matrix_sdk::identifiers::{Error as IdentifierError, ServerName};
+use matrix_sdk::identifiers::{Error as IdentifierError, RoomAliasId, RoomId, ServerName};
+use matrix_sdk::reqwest::Error as ReqwestError;
 use matrix_sdk::Client as MatrixClient;
 use matrix_sdk::Error as MatrixError;
+use serde_json::Error as ParseJsonError;
 use std::convert::TryFrom;
 use url::ParseError as UrlError;
+use url::Url;
 
 use crate::globals;
 
+use crate::api::r0::room::initial_sync::request as room_initial_sync;
+use crate::api::r0::room::initial_sync::Parameters as RoomInitialSyncParameters;
+use crate::api::r0::room::initial_sync::Response as RoomInitialSyncResponse;
+use crate::api::r0::AccessToken;
 use crate::backend::MediaError;
+use crate::backend::HTTP_CLIENT;
 use crate::util::cache_dir_path;
 
+use crate::model::message::Message;
 use crate::model::room::Room;
 use matrix_sdk::api::r0::directory::get_public_rooms_filtered::Request as PublicRoomsFilteredRequest;
 use matrix_sdk::api::r0::thirdparty::get_protocols::Request as GetProtocolsRequest;
 use matrix_sdk::assign;
 use matrix_sdk::directory::Filter as PublicRoomsFilter;
 use matrix_sdk::directory::RoomNetwork;
+use matrix_sdk::events::room::member::MembershipState;
+use matrix_sdk::events::AnyRoomEvent;
+use matrix_sdk::events::AnyStateEvent;
 use matrix_sdk::thirdparty::ProtocolInstance;
 
 use super::{dw_media, ContentType, HandleError};
@@ -123,6 +135,7 @@ pub async fn room_search(
                     avatar,
                     ContentType::Download,
                     Some(dest),
+                    None,
                 )
                 .await;
             }
@@ -131,3 +144,93 @@ pub async fn room_search(
 
     Ok((rooms, since))
 }
+
+/// What can be seen of a room before joining it
+#[derive(Clone, Debug, Default)]
+pub struct RoomPreview {
+    pub aliases: Vec<RoomAliasId>,
+    pub n_members: u64,
+    pub encrypted: bool,
+    /// The last messages of the room
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug)]
+pub enum RoomPreviewError {
+    Reqwest(ReqwestError),
+    ParseJson(ParseJsonError),
+}
+
+impl From<ReqwestError> for RoomPreviewError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<ParseJsonError> for RoomPreviewError {
+    fn from(err: ParseJsonError) -> Self {
+        Self::ParseJson(err)
+    }
+}
+
+impl HandleError for RoomPreviewError {}
+
+/// Peeks into the room, only the world readable rooms let us read their state and
+/// messages before joining
+pub async fn room_preview(
+    base: Url,
+    access_token: AccessToken,
+    room_id: &RoomId,
+) -> Result<RoomPreview, RoomPreviewError> {
+    let params = RoomInitialSyncParameters { access_token };
+    let request = room_initial_sync(base, &params, room_id)?;
+    let response_raw = HTTP_CLIENT
+        .get_client()
+        .execute(request)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let response: RoomInitialSyncResponse = serde_json::from_slice(&response_raw)?;
+
+    let mut preview = RoomPreview::default();
+    for event in response
+        .state
+        .into_iter()
+        .filter_map(|ev| serde_json::from_value(ev).ok())
+    {
+        match event {
+            AnyStateEvent::RoomCanonicalAlias(ev) => {
+                preview.aliases = ev
+                    .content
+                    .alias
+                    .into_iter()
+                    .chain(ev.content.alt_aliases)
+                    .collect();
+            }
+            AnyStateEvent::RoomMember(ev) if ev.content.membership == MembershipState::Join => {
+                preview.n_members += 1;
+            }
+            AnyStateEvent::RoomEncryption(_) => {
+                preview.encrypted = true;
+            }
+            _ => {}
+        }
+    }
+
+    let mut messages: Vec<Message> = response
+        .messages
+        .map(|messages| messages.chunk)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|ev| serde_json::from_value::<AnyRoomEvent>(ev).ok())
+        .filter_map(|ev| Message::try_from(ev).ok())
+        .filter(|msg| msg.mtype != "m.sticker")
+        .collect();
+    let skip = messages
+        .len()
+        .saturating_sub(globals::ROOM_PREVIEW_LIMIT as usize);
+    preview.messages = messages.split_off(skip);
+
+    Ok(preview)
+}
@@ -1,12 +1,23 @@
-use super::MediaError;
+use super::{get_ruma_error_kind, remove_matrix_access_token_if_present, HandleError, MediaError};
+use crate::cache::{self, crypto};
 use crate::globals;
+use crate::util::cache_dir_path;
+use log::debug;
 use matrix_sdk::identifiers::{EventId, MxcUri, RoomId};
-use matrix_sdk::{Client as MatrixClient, Error as MatrixError};
+use matrix_sdk::{
+    api::error::ErrorKind as RumaErrorKind, Client as MatrixClient, Error as MatrixError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Error as ParseJsonError;
 use std::convert::TryInto;
+use std::io::Error as IoError;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 use crate::model::message::Message;
 use matrix_sdk::api::r0::filter::{RoomEventFilter, UrlFilter};
+use matrix_sdk::api::r0::media::get_media_config::Request as GetMediaConfigRequest;
+use matrix_sdk::api::r0::media::get_media_preview::Request as GetMediaPreviewRequest;
 use matrix_sdk::api::r0::message::get_message_events::Request as GetMessagesEventsRequest;
 use matrix_sdk::assign;
 
@@ -15,18 +26,156 @@ use super::{dw_media, get_prev_batch_from, ContentType};
 pub type MediaResult = Result<PathBuf, MediaError>;
 pub type MediaList = (Vec<Message>, String);
 
-pub async fn get_thumb(session_client: MatrixClient, media: &MxcUri) -> MediaResult {
+pub async fn get_thumb(
+    session_client: MatrixClient,
+    media: &MxcUri,
+    room_id: Option<&RoomId>,
+) -> MediaResult {
     dw_media(
         session_client,
         media,
         ContentType::default_thumbnail(),
         None,
+        room_id,
     )
     .await
 }
 
-pub async fn get_media(session_client: MatrixClient, media: &MxcUri) -> MediaResult {
-    dw_media(session_client, media, ContentType::Download, None).await
+pub async fn get_media(
+    session_client: MatrixClient,
+    media: &MxcUri,
+    room_id: Option<&RoomId>,
+) -> MediaResult {
+    dw_media(session_client, media, ContentType::Download, None, room_id).await
+}
+
+#[derive(Debug)]
+pub struct GetMaxUploadSizeError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for GetMaxUploadSizeError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for GetMaxUploadSizeError {}
+
+/// Returns the size of the largest file the server accepts, in bytes
+pub async fn get_max_upload_size(
+    session_client: MatrixClient,
+) -> Result<u64, GetMaxUploadSizeError> {
+    let response = session_client
+        .send(GetMediaConfigRequest::new(), None)
+        .await?;
+
+    Ok(response.upload_size.into())
+}
+
+/// The OpenGraph data of a web page, as returned by the homeserver
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UrlPreview {
+    #[serde(rename = "og:title")]
+    pub title: Option<String>,
+    #[serde(rename = "og:description")]
+    pub description: Option<String>,
+    #[serde(rename = "og:site_name")]
+    pub site_name: Option<String>,
+    #[serde(rename = "og:image")]
+    pub image: Option<String>,
+}
+
+impl UrlPreview {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none()
+    }
+}
+
+#[derive(Debug)]
+pub enum GetUrlPreviewError {
+    Io(IoError),
+    Matrix(MatrixError),
+    Json(ParseJsonError),
+}
+
+impl From<MatrixError> for GetUrlPreviewError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<IoError> for GetUrlPreviewError {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ParseJsonError> for GetUrlPreviewError {
+    fn from(err: ParseJsonError) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl HandleError for GetUrlPreviewError {
+    // Many pages can't be previewed, so it isn't worth an error
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        debug!(
+            "Couldn’t get the URL preview: {}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+    }
+}
+
+/// Fetches the preview of `url` through the homeserver, keeping it a day in the
+/// media cache so the same link isn't requested again for every message. The pages the
+/// homeserver can't preview are kept as an empty preview.
+pub async fn get_url_preview(
+    session_client: MatrixClient,
+    url: &str,
+    room_id: Option<&RoomId>,
+) -> Result<UrlPreview, GetUrlPreviewError> {
+    let digest = format!("{:x}", md5::compute(url.as_bytes()));
+    let fname = cache_dir_path(Some("previews"), &digest)?;
+
+    let is_fname_recent = fname
+        .metadata()
+        .ok()
+        .and_then(|md| md.modified().ok())
+        .and_then(|modf| modf.elapsed().ok())
+        .map_or(false, |dur| dur.as_secs() < 60 * 60 * 24);
+
+    if fname.is_file() && is_fname_recent {
+        let data = crypto::open(&tokio::fs::read(&fname).await?)?;
+        if let Ok(preview) = serde_json::from_slice(&data) {
+            cache::media::record(&fname, room_id);
+            return Ok(preview);
+        }
+    }
+
+    let request = GetMediaPreviewRequest::new(url, SystemTime::now());
+    let preview: UrlPreview = match session_client.send(request, None).await {
+        Ok(response) => match response.data {
+            Some(data) => serde_json::from_str(data.get())?,
+            None => Default::default(),
+        },
+        // The homeserver couldn't get the page or find anything to preview in it, the other
+        // errors like the rate limit don't say anything about the page
+        Err(err)
+            if matches!(
+                get_ruma_error_kind(&err),
+                Some(RumaErrorKind::Unknown) | Some(RumaErrorKind::NotFound)
+            ) =>
+        {
+            Default::default()
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    crypto::write(&fname, &serde_json::to_vec(&preview)?).await?;
+    cache::media::record(&fname, room_id);
+
+    Ok(preview)
 }
 
 pub async fn get_media_list(
@@ -10,6 +10,7 @@ use std::fmt::Debug;
 use std::io::Error as IoError;
 use std::path::PathBuf;
 
+use crate::cache::{self, crypto};
 use crate::client::Client;
 use crate::util::cache_dir_path;
 use matrix_sdk::api::r0::context::get_context::Request as GetContextRequest;
@@ -89,6 +90,7 @@ pub async fn dw_media(
     mxc: &MxcUri,
     media_type: ContentType,
     dest: Option<PathBuf>,
+    room_id: Option<&RoomId>,
 ) -> Result<PathBuf, MediaError> {
     if !mxc.is_valid() {
         return Err(MatrixError::from(IdentifierError::InvalidMxcUri).into());
@@ -113,7 +115,10 @@ pub async fn dw_media(
         .map_or(false, |dur| dur.as_secs() < 60);
 
     if fname.is_file() && (dest.is_none() || is_fname_recent) {
-        return Ok(fname);
+        if dest.is_none() {
+            cache::media::record(&fname, room_id);
+        }
+        return Ok(crypto::readable_path(&fname)?);
     }
 
     let media = if let ContentType::Thumbnail(width, height) = media_type {
@@ -131,9 +136,13 @@ pub async fn dw_media(
         session_client.send(request, None).await?.file
     };
 
-    tokio::fs::write(&fname, media).await?;
+    crypto::write(&fname, &media).await?;
+    // Only the files in the media directories are subject to the quota
+    if dest.is_none() {
+        cache::media::record(&fname, room_id);
+    }
 
-    Ok(fname)
+    Ok(crypto::readable_path(&fname)?)
 }
 
 pub trait HandleError: Debug {
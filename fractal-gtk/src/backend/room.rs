@@ -1,55 +1,85 @@
 use log::error;
 use serde_json::json;
 
+use futures_util::stream;
+use matrix_sdk::reqwest::{Body, Error as ReqwestError, StatusCode};
 use matrix_sdk::{
     api::error::ErrorKind as RumaErrorKind,
-    identifiers::{EventId, MxcUri, RoomId, RoomIdOrAliasId, UserId},
+    identifiers::{
+        EventId, MxcUri, RoomAliasId, RoomId, RoomIdOrAliasId, RoomVersionId, ServerName, UserId,
+    },
     Client as MatrixClient, Error as MatrixError, FromHttpResponseError, HttpError, ServerError,
 };
 use serde::Serialize;
 use std::io::Error as IoError;
 use std::path::Path;
-use url::ParseError as UrlError;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use url::{ParseError as UrlError, Url};
 
 use std::convert::{TryFrom, TryInto};
 
 use crate::globals;
 
 use crate::actions::AppState;
+use crate::api::r0::media::create_content::request as create_content;
+use crate::api::r0::media::create_content::Parameters as CreateContentParameters;
+use crate::api::r0::media::create_content::Response as CreateContentStreamResponse;
+use crate::api::r0::AccessToken;
+use crate::api::unstable::knock::request as knock_request;
+use crate::api::unstable::knock::Body as KnockBody;
+use crate::api::unstable::knock::Parameters as KnockParameters;
+use crate::api::unstable::knock::Response as KnockResponse;
+use crate::api::unstable::space_summary::request as space_summary;
+use crate::api::unstable::space_summary::Body as SpaceSummaryBody;
+use crate::api::unstable::space_summary::Parameters as SpaceSummaryParameters;
+use crate::api::unstable::space_summary::Response as SpaceSummaryResponse;
 use crate::backend::MediaError;
+use crate::backend::HTTP_CLIENT;
 use crate::util::cache_dir_path;
 
 use crate::model::{
+    draft::Draft,
     member::Member,
     message::Message,
-    room::{Room, RoomMembership, RoomTag},
+    room::{Room, RoomMembership, RoomTag, SpaceChild},
 };
+use matrix_sdk::api::r0::alias::get_alias::Request as GetAliasRequest;
+use matrix_sdk::api::r0::capabilities::get_capabilities::Request as GetCapabilitiesRequest;
 use matrix_sdk::api::r0::config::get_global_account_data::Request as GetGlobalAccountDataRequest;
 use matrix_sdk::api::r0::config::set_global_account_data::Request as SetGlobalAccountDataRequest;
 use matrix_sdk::api::r0::config::set_room_account_data::Request as SetRoomAccountDataRequest;
 use matrix_sdk::api::r0::filter::RoomEventFilter;
 use matrix_sdk::api::r0::media::create_content::Request as CreateContentRequest;
 use matrix_sdk::api::r0::media::create_content::Response as CreateContentResponse;
+use matrix_sdk::api::r0::membership::forget_room::Request as ForgetRoomRequest;
 use matrix_sdk::api::r0::membership::joined_members::Request as JoinedMembersRequest;
+use matrix_sdk::api::r0::membership::leave_room::Request as LeaveRoomRequest;
 use matrix_sdk::api::r0::message::get_message_events::Request as GetMessagesEventsRequest;
 use matrix_sdk::api::r0::push::delete_pushrule::Request as DeleteRoomRulesRequest;
 use matrix_sdk::api::r0::push::get_pushrule::Request as GetRoomRulesRequest;
 use matrix_sdk::api::r0::push::set_pushrule::Request as SetRoomRulesRequest;
+use matrix_sdk::api::r0::push::set_pushrule_enabled::Request as SetRoomRulesEnabledRequest;
 use matrix_sdk::api::r0::push::RuleKind;
 use matrix_sdk::api::r0::redact::redact_event::Request as RedactEventRequest;
 use matrix_sdk::api::r0::room::create_room::Request as CreateRoomRequest;
 use matrix_sdk::api::r0::room::create_room::RoomPreset;
+use matrix_sdk::api::r0::room::upgrade_room::Request as UpgradeRoomRequest;
 use matrix_sdk::api::r0::room::Visibility;
+use matrix_sdk::api::r0::state::get_state_events::Request as GetStateEventsRequest;
 use matrix_sdk::api::r0::state::get_state_events_for_key::Request as GetStateEventForKeyRequest;
 use matrix_sdk::api::r0::state::send_state_event::Request as SendStateEventForKeyRequest;
 use matrix_sdk::api::r0::tag::create_tag::Request as CreateTagRequest;
 use matrix_sdk::api::r0::tag::delete_tag::Request as DeleteTagRequest;
 use matrix_sdk::assign;
+use matrix_sdk::events::custom::CustomEventContent;
 use matrix_sdk::events::room::avatar::AvatarEventContent;
 use matrix_sdk::events::room::history_visibility::HistoryVisibility;
 use matrix_sdk::events::room::history_visibility::HistoryVisibilityEventContent;
+use matrix_sdk::events::room::member::MemberEventContent;
 use matrix_sdk::events::room::message::MessageEventContent;
 use matrix_sdk::events::room::name::NameEventContent;
+use matrix_sdk::events::room::power_levels::PowerLevelsEventContent;
 use matrix_sdk::events::room::topic::TopicEventContent;
 use matrix_sdk::events::tag::TagInfo;
 use matrix_sdk::events::AnyBasicEventContent;
@@ -61,10 +91,12 @@ use matrix_sdk::events::EventType;
 use matrix_sdk::events::InitialStateEvent;
 use matrix_sdk::events::InvalidInput as NameRoomEventInvalidInput;
 use matrix_sdk::push::Action;
+use matrix_sdk::push::PushCondition;
 use matrix_sdk::push::Tweak;
 
 use serde_json::value::to_raw_value;
 use serde_json::Error as ParseJsonError;
+use serde_json::Value as JsonValue;
 
 use super::{
     dw_media, get_prev_batch_from, get_ruma_error_kind, remove_matrix_access_token_if_present,
@@ -123,6 +155,32 @@ pub async fn get_room_detail(
     Ok((room_id, event_type, value.unwrap_or_default()))
 }
 
+#[derive(Debug)]
+pub struct RoomTypeError(MatrixError);
+
+impl From<MatrixError> for RoomTypeError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for RoomTypeError {}
+
+/// The SDK drops the `type` of the `m.room.create` event, so it's read from the raw event
+pub async fn get_room_type(
+    session_client: MatrixClient,
+    room_id: RoomId,
+) -> Result<(RoomId, Option<String>), RoomTypeError> {
+    let request = GetStateEventForKeyRequest::new(&room_id, EventType::RoomCreate, "");
+    let response = session_client.send(request, None).await?;
+
+    let room_type = serde_json::to_value(&response.content)
+        .ok()
+        .and_then(|content| content["type"].as_str().map(String::from));
+
+    Ok((room_id, room_type))
+}
+
 #[derive(Debug)]
 pub enum RoomAvatarError {
     Matrix(MatrixError),
@@ -177,6 +235,7 @@ pub async fn get_room_avatar(
             avatar,
             ContentType::default_thumbnail(),
             dest,
+            None,
         )
         .await?;
     }
@@ -336,6 +395,14 @@ pub async fn send_msg(session_client: MatrixClient, msg: Message) -> Result<Even
         event["format"] = json!(f);
     }
 
+    if let Some(in_reply_to) = msg.in_reply_to.as_ref() {
+        event["m.relates_to"] = json!({
+            "m.in_reply_to": {
+                "event_id": in_reply_to,
+            },
+        });
+    }
+
     let extra_content_map = msg
         .extra_content
         .into_iter()
@@ -418,17 +485,17 @@ pub async fn redact_msg(
 }
 
 #[derive(Debug)]
-pub struct JoinRoomError(MatrixError);
-
-impl From<MatrixError> for JoinRoomError {
-    fn from(err: MatrixError) -> Self {
-        Self(err)
-    }
+pub struct JoinRoomError {
+    error: MatrixError,
+    room_id_or_alias: RoomIdOrAliasId,
+    via: Vec<Box<ServerName>>,
+    /// Read when the join is forbidden, the rooms with the knock rule accept requests
+    join_rule: Option<JoinRule>,
 }
 
 impl HandleError for JoinRoomError {
     fn handle_error(&self) {
-        let (err_str, info) = match &self.0 {
+        let (err_str, info) = match &self.error {
             MatrixError::Http(HttpError::FromHttpResponse(FromHttpResponseError::Http(
                 ServerError::Known(error),
             ))) => (error.message.clone(), Some(error.message.clone())),
@@ -439,21 +506,228 @@ impl HandleError for JoinRoomError {
             "{}",
             remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
         );
-        let error = i18n("Can’t join the room, try again.");
+
+        let error = match get_ruma_error_kind(&self.error) {
+            // The room accepts requests to join
+            Some(RumaErrorKind::Forbidden) if self.join_rule == Some(JoinRule::Knock) => {
+                let room_id_or_alias = self.room_id_or_alias.clone();
+                let via = self.via.clone();
+                APPOP!(knock_room_dialog, (room_id_or_alias, via, info));
+                return;
+            }
+            kind => {
+                join_error_message(kind).unwrap_or_else(|| i18n("Can’t join the room, try again."))
+            }
+        };
         let state = AppState::NoRoom;
         APPOP!(show_error_with_info, (error, info));
         APPOP!(set_state, (state));
     }
 }
 
+/// Explains why a room couldn't be joined, from the kind of error the homeserver returned
+fn join_error_message(kind: Option<&RumaErrorKind>) -> Option<String> {
+    match kind? {
+        RumaErrorKind::Forbidden => Some(i18n(
+            "You aren’t allowed to join this room. You might have been banned, or it needs an invite.",
+        )),
+        RumaErrorKind::NotFound => Some(i18n(
+            "The room couldn’t be found. Check its address, or try again later if it was just created.",
+        )),
+        RumaErrorKind::LimitExceeded { .. } => Some(i18n(
+            "Too many requests were sent, wait a moment before joining again.",
+        )),
+        _ => None,
+    }
+}
+
+/// `server_names` are the servers to join through, needed for the rooms our server isn't in
 pub async fn join_room(
     session_client: MatrixClient,
     room_id_or_alias_id: &RoomIdOrAliasId,
+    server_names: &[Box<ServerName>],
 ) -> Result<RoomId, JoinRoomError> {
-    Ok(session_client
-        .join_room_by_id_or_alias(room_id_or_alias_id, Default::default())
+    let error = match session_client
+        .join_room_by_id_or_alias(room_id_or_alias_id, server_names)
+        .await
+    {
+        Ok(response) => return Ok(response.room_id),
+        Err(error) => error,
+    };
+
+    let join_rule = if get_ruma_error_kind(&error) == Some(&RumaErrorKind::Forbidden) {
+        let room_id = match room_id_or_alias_id.clone().try_into() {
+            Ok(room_id) => Some(room_id),
+            Err(alias) => resolve_alias(session_client.clone(), &alias)
+                .await
+                .ok()
+                .map(|(room_id, _)| room_id),
+        };
+
+        match room_id {
+            Some(room_id) => get_join_rule(session_client, &room_id).await,
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    Err(JoinRoomError {
+        error,
+        room_id_or_alias: room_id_or_alias_id.clone(),
+        via: server_names.to_vec(),
+        join_rule,
+    })
+}
+
+#[derive(Debug)]
+pub struct ResolveAliasError(MatrixError);
+
+impl From<MatrixError> for ResolveAliasError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for ResolveAliasError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::NotFound) => {
+                i18n("No room uses this alias. Check it, aliases can change over time.")
+            }
+            kind => join_error_message(kind)
+                .unwrap_or_else(|| i18n("Couldn’t find the room of this alias.")),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Returns the room of the alias and the servers in it, to join through
+pub async fn resolve_alias(
+    session_client: MatrixClient,
+    alias: &RoomAliasId,
+) -> Result<(RoomId, Vec<Box<ServerName>>), ResolveAliasError> {
+    let request = GetAliasRequest::new(alias);
+    let response = session_client.send(request, None).await?;
+
+    Ok((response.room_id, response.servers))
+}
+
+/// Who can join a room, the SDK doesn't know about the knock and restricted rules yet
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoinRule {
+    Public,
+    Invite,
+    Knock,
+    /// The members of these rooms, usually spaces, can join
+    Restricted(Vec<RoomId>),
+    Private,
+}
+
+impl JoinRule {
+    fn from_content(content: &JsonValue) -> Option<Self> {
+        match content["join_rule"].as_str()? {
+            "public" => Some(JoinRule::Public),
+            "invite" => Some(JoinRule::Invite),
+            "knock" | "xyz.amorgan.knock" => Some(JoinRule::Knock),
+            "restricted" | "org.matrix.msc3083.restricted" => {
+                let allowed = content["allow"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    // Earlier versions of the proposal called it `space`
+                    .filter_map(|allow| allow["room_id"].as_str().or(allow["space"].as_str()))
+                    .filter_map(|room_id| RoomId::try_from(room_id).ok())
+                    .collect();
+                Some(JoinRule::Restricted(allowed))
+            }
+            "private" => Some(JoinRule::Private),
+            _ => None,
+        }
+    }
+}
+
+/// The join rule of the rooms we aren't in can only be read when they are world readable
+pub async fn get_join_rule(session_client: MatrixClient, room_id: &RoomId) -> Option<JoinRule> {
+    let request = GetStateEventForKeyRequest::new(room_id, EventType::RoomJoinRules, "");
+    let response = session_client.send(request, None).await.ok()?;
+    let content = serde_json::to_value(&response.content).ok()?;
+
+    JoinRule::from_content(&content)
+}
+
+#[derive(Debug)]
+pub enum KnockError {
+    Reqwest(ReqwestError),
+    ParseJson(ParseJsonError),
+}
+
+impl From<ReqwestError> for KnockError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<ParseJsonError> for KnockError {
+    fn from(err: ParseJsonError) -> Self {
+        Self::ParseJson(err)
+    }
+}
+
+impl HandleError for KnockError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let status = match self {
+            Self::Reqwest(err) => err.status(),
+            Self::ParseJson(_) => None,
+        };
+        let error = match status {
+            Some(StatusCode::FORBIDDEN) => {
+                i18n("This room doesn’t accept requests to join, you need to be invited.")
+            }
+            Some(StatusCode::NOT_FOUND) => i18n("The room couldn’t be found."),
+            Some(StatusCode::TOO_MANY_REQUESTS) => {
+                i18n("Too many requests were sent, wait a moment before asking again.")
+            }
+            _ => i18n("Couldn’t ask to join the room."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Asks the members of the room to invite us, the request is sent with an optional reason
+pub async fn knock(
+    base: Url,
+    access_token: AccessToken,
+    room_id_or_alias: &RoomIdOrAliasId,
+    via: &[Box<ServerName>],
+    reason: Option<String>,
+) -> Result<RoomId, KnockError> {
+    let params = KnockParameters { access_token };
+    let body = KnockBody { reason };
+
+    let request = knock_request(base, &params, room_id_or_alias, via, &body)?;
+    let response_raw = HTTP_CLIENT
+        .get_client()
+        .execute(request)
         .await?
-        .room_id)
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let response: KnockResponse = serde_json::from_slice(&response_raw)?;
+
+    Ok(response.room_id)
 }
 
 #[derive(Debug)]
@@ -480,6 +754,45 @@ pub async fn leave_room(
     Ok(())
 }
 
+#[derive(Debug)]
+pub struct RejectInviteError(MatrixError);
+
+impl From<MatrixError> for RejectInviteError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for RejectInviteError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t reject the invitation.");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// The SDK only leaves the joined rooms. Forgetting the room also removes it from the
+/// rooms we left.
+pub async fn reject_invite(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    forget: bool,
+) -> Result<(), RejectInviteError> {
+    let request = LeaveRoomRequest::new(room_id);
+    session_client.send(request, None).await?;
+
+    if forget {
+        let request = ForgetRoomRequest::new(room_id);
+        session_client.send(request, None).await?;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct MarkedAsReadError(MatrixError);
 
@@ -546,7 +859,23 @@ impl From<MatrixError> for SetRoomTopicError {
     }
 }
 
-impl HandleError for SetRoomTopicError {}
+impl HandleError for SetRoomTopicError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::Forbidden) => {
+                i18n("You don’t have permission to change the topic of this room.")
+            }
+            _ => i18n("Couldn’t change the topic of the room."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
 
 pub async fn set_room_topic(
     session_client: MatrixClient,
@@ -654,6 +983,124 @@ pub async fn upload_file(
     session_client.send(request, None).await.map_err(Into::into)
 }
 
+/// Size of the pieces files are read and sent in, each one updates the progress
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum UploadAttachmentError {
+    Io(IoError),
+    Reqwest(ReqwestError),
+    ParseJson(ParseJsonError),
+}
+
+impl From<IoError> for UploadAttachmentError {
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ReqwestError> for UploadAttachmentError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
+    }
+}
+
+impl From<ParseJsonError> for UploadAttachmentError {
+    fn from(err: ParseJsonError) -> Self {
+        Self::ParseJson(err)
+    }
+}
+
+impl HandleError for UploadAttachmentError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        let err_str = remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str);
+
+        match self {
+            Self::Reqwest(err) if err.status() == Some(StatusCode::PAYLOAD_TOO_LARGE) => {
+                error!("uploading: {}", err_str);
+                APPOP!(upload_rejected);
+            }
+            _ => {
+                error!("uploading {}: retrying send", err_str);
+                APPOP!(retry_send);
+            }
+        }
+    }
+}
+
+/// Uploads the file at `fname` to the media repository, reading it as it's sent instead
+/// of loading it at once. `progress` is called with the number of bytes sent so far and
+/// the size of the file.
+pub async fn upload_attachment<F>(
+    base: Url,
+    access_token: AccessToken,
+    fname: &Path,
+    progress: F,
+) -> Result<MxcUri, UploadAttachmentError>
+where
+    F: Fn(u64, u64) + Send + Sync + 'static,
+{
+    let mut file = tokio::fs::File::open(fname).await?;
+    let size = file.metadata().await?.len();
+
+    // The beginning of the file is enough to guess its type
+    let mut head = vec![0; UPLOAD_CHUNK_SIZE];
+    let read = file.read(&mut head).await?;
+    head.truncate(read);
+    let (content_type, _) = gio::content_type_guess(None, &head);
+
+    let progress = Arc::new(progress);
+    let chunks = stream::unfold((file, Some(head), 0_u64), move |(mut file, head, sent)| {
+        let progress = progress.clone();
+        async move {
+            let chunk = match head {
+                Some(head) => Ok(head),
+                None => {
+                    let mut chunk = vec![0; UPLOAD_CHUNK_SIZE];
+                    file.read(&mut chunk).await.map(|read| {
+                        chunk.truncate(read);
+                        chunk
+                    })
+                }
+            };
+
+            match chunk {
+                Ok(chunk) if chunk.is_empty() => None,
+                Ok(chunk) => {
+                    let sent = sent + chunk.len() as u64;
+                    progress(sent, size);
+                    Some((Ok(chunk), (file, None, sent)))
+                }
+                Err(err) => Some((Err(err), (file, None, sent))),
+            }
+        }
+    });
+
+    let params = CreateContentParameters {
+        access_token,
+        filename: None,
+    };
+    let request = create_content(
+        base,
+        &params,
+        &content_type,
+        size,
+        Body::wrap_stream(chunks),
+    )?;
+    let response_raw = HTTP_CLIENT
+        .get_client()
+        .execute(request)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let response: CreateContentStreamResponse = serde_json::from_slice(&response_raw)?;
+
+    Ok(response.content_uri)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum RoomType {
     Public,
@@ -808,25 +1255,41 @@ pub async fn direct_chat(
 }
 
 #[derive(Debug)]
-pub struct AddedToFavError(MatrixError);
+pub struct SetRoomTagError(MatrixError);
 
-impl From<MatrixError> for AddedToFavError {
+impl From<MatrixError> for SetRoomTagError {
     fn from(err: MatrixError) -> Self {
         Self(err)
     }
 }
 
-impl HandleError for AddedToFavError {}
+impl HandleError for SetRoomTagError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t move the room.");
+        APPOP!(show_error, (error));
+    }
+}
 
-pub async fn add_to_fav(
+/// Removes the tags named in `removed` from the room and adds the ones in `added`,
+/// the other tags of the room are kept
+pub async fn set_room_tags(
     session_client: MatrixClient,
     user_id: &UserId,
-    rid: RoomId,
-    tofav: bool,
-) -> Result<(RoomId, bool), AddedToFavError> {
-    let tag = "m.favourite";
-    let room_id = &rid;
-    if tofav {
+    room_id: &RoomId,
+    removed: &[String],
+    added: &[String],
+) -> Result<(), SetRoomTagError> {
+    for tag in removed {
+        let request = DeleteTagRequest::new(user_id, room_id, tag);
+        session_client.send(request, None).await?;
+    }
+
+    for tag in added {
         let request = CreateTagRequest::new(
             user_id,
             room_id,
@@ -836,66 +1299,471 @@ pub async fn add_to_fav(
             }),
         );
         session_client.send(request, None).await?;
-    } else {
-        let request = DeleteTagRequest::new(user_id, room_id, tag);
-        session_client.send(request, None).await?;
     }
 
-    Ok((rid, tofav))
+    Ok(())
 }
 
 #[derive(Debug)]
-pub struct InviteError(MatrixError);
+pub enum SpaceSummaryError {
+    Reqwest(ReqwestError),
+    ParseJson(ParseJsonError),
+}
 
-impl<T: Into<MatrixError>> From<T> for InviteError {
-    fn from(err: T) -> Self {
-        Self(err.into())
+impl From<ReqwestError> for SpaceSummaryError {
+    fn from(err: ReqwestError) -> Self {
+        Self::Reqwest(err)
     }
 }
 
-impl HandleError for InviteError {}
+impl From<ParseJsonError> for SpaceSummaryError {
+    fn from(err: ParseJsonError) -> Self {
+        Self::ParseJson(err)
+    }
+}
 
-pub async fn invite(
-    session_client: MatrixClient,
-    room_id: &RoomId,
-    user_id: &UserId,
-) -> Result<(), InviteError> {
-    let room = unwrap_or_notfound_return!(
-        session_client.get_joined_room(room_id),
-        format!("Could not find room: {}", room_id)
-    );
-    room.invite_user_by_id(user_id).await?;
+impl HandleError for SpaceSummaryError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t load the rooms of the space.");
+        APPOP!(show_error, (error));
+    }
+}
 
-    Ok(())
+/// Gets the rooms of the space and of its subspaces, including the ones we aren't in,
+/// with their children
+pub async fn get_space_summary(
+    base: Url,
+    access_token: AccessToken,
+    space_id: &RoomId,
+) -> Result<Vec<Room>, SpaceSummaryError> {
+    let params = SpaceSummaryParameters { access_token };
+    let body = SpaceSummaryBody::default();
+
+    let request = space_summary(base, &params, space_id, &body)?;
+    let response_raw = HTTP_CLIENT
+        .get_client()
+        .execute(request)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let response: SpaceSummaryResponse = serde_json::from_slice(&response_raw)?;
+
+    let mut rooms: Vec<Room> = response
+        .rooms
+        .into_iter()
+        .map(|room| Room {
+            alias: room.canonical_alias,
+            name: room.name,
+            avatar: room.avatar_url,
+            topic: room.topic,
+            n_members: room.num_joined_members,
+            world_readable: room.world_readable,
+            guest_can_join: room.guest_can_join,
+            room_type: room.room_type,
+            ..Room::new(room.room_id, RoomMembership::None)
+        })
+        .collect();
+
+    for event in response.events {
+        let child = SpaceChild::from_content(&event.state_key, &event.content);
+        let space = rooms.iter_mut().find(|room| room.id == event.room_id);
+        if let (Some(child), Some(space)) = (child, space) {
+            space.space_children.push(child);
+        }
+    }
+    for room in rooms.iter_mut() {
+        SpaceChild::sort(&mut room.space_children);
+    }
+
+    Ok(rooms)
 }
 
 #[derive(Debug)]
-pub struct ChangeLanguageError(MatrixError);
+pub struct SetSpaceChildError(MatrixError);
 
-impl From<MatrixError> for ChangeLanguageError {
+impl From<MatrixError> for SetSpaceChildError {
     fn from(err: MatrixError) -> Self {
         Self(err)
     }
 }
 
-impl From<ParseJsonError> for ChangeLanguageError {
-    fn from(err: ParseJsonError) -> Self {
-        Self(err.into())
-    }
-}
-
-impl HandleError for ChangeLanguageError {
+impl HandleError for SetSpaceChildError {
     fn handle_error(&self) {
         let err_str = format!("{:?}", self);
         error!(
-            "Error forming url to set room language: {}",
+            "{}",
             remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
         );
+        let error = i18n("Couldn’t change the rooms of the space.");
+        APPOP!(show_error, (error));
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
-pub struct Language {
+/// Adds the room to the space, or removes it when `via` is empty
+pub async fn set_space_child(
+    session_client: MatrixClient,
+    space_id: &RoomId,
+    room_id: &RoomId,
+    via: &[Box<ServerName>],
+) -> Result<(), SetSpaceChildError> {
+    // An empty content removes the room
+    let mut data = serde_json::Map::new();
+    if !via.is_empty() {
+        data.insert(String::from("via"), json!(via));
+    }
+    let content = &AnyStateEventContent::Custom(CustomEventContent {
+        event_type: String::from("m.space.child"),
+        data,
+    });
+    let request = SendStateEventForKeyRequest::new(space_id, room_id.as_str(), content);
+
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct RoomVersionError(MatrixError);
+
+impl From<MatrixError> for RoomVersionError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for RoomVersionError {}
+
+/// The version the server creates the new rooms with, the rooms are upgraded to it
+pub async fn get_default_room_version(
+    session_client: MatrixClient,
+) -> Result<RoomVersionId, RoomVersionError> {
+    let request = GetCapabilitiesRequest::new();
+    let response = session_client.send(request, None).await?;
+
+    Ok(response.capabilities.room_versions.default)
+}
+
+#[derive(Debug)]
+pub struct UpgradeRoomError(MatrixError);
+
+impl From<MatrixError> for UpgradeRoomError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for UpgradeRoomError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t upgrade the room.");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Returns the new room, the old one gets a tombstone pointing to it
+pub async fn upgrade_room(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    version: &RoomVersionId,
+) -> Result<RoomId, UpgradeRoomError> {
+    let request = UpgradeRoomRequest::new(room_id, version);
+    let response = session_client.send(request, None).await?;
+
+    Ok(response.replacement_room)
+}
+
+#[derive(Debug)]
+pub struct InviteError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for InviteError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for InviteError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::Forbidden) => {
+                i18n("You don’t have permission to invite users to this room.")
+            }
+            _ => i18n("Couldn’t invite the user."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+pub async fn invite(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    user_id: &UserId,
+) -> Result<(), InviteError> {
+    let room = unwrap_or_notfound_return!(
+        session_client.get_joined_room(room_id),
+        format!("Could not find room: {}", room_id)
+    );
+    room.invite_user_by_id(user_id).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct KickUserError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for KickUserError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for KickUserError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::Forbidden) => {
+                i18n("You don’t have permission to remove users from this room.")
+            }
+            _ => i18n("Couldn’t remove the user from the room."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+pub async fn kick_user(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    user_id: &UserId,
+    reason: Option<&str>,
+) -> Result<(), KickUserError> {
+    let room = unwrap_or_notfound_return!(
+        session_client.get_joined_room(room_id),
+        format!("Could not find room: {}", room_id)
+    );
+    room.kick_user(user_id, reason).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct BanUserError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for BanUserError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for BanUserError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::Forbidden) => {
+                i18n("You don’t have permission to ban users from this room.")
+            }
+            _ => i18n("Couldn’t ban the user from the room."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+pub async fn ban_user(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    user_id: &UserId,
+    reason: Option<&str>,
+) -> Result<(), BanUserError> {
+    let room = unwrap_or_notfound_return!(
+        session_client.get_joined_room(room_id),
+        format!("Could not find room: {}", room_id)
+    );
+    room.ban_user(user_id, reason).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SetRoomNickError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for SetRoomNickError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for SetRoomNickError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t change your display name in this room.");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Changes the display name of the user in the room only, keeping the rest of its membership
+pub async fn set_room_nick(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    user_id: &UserId,
+    nick: String,
+) -> Result<(), SetRoomNickError> {
+    let request = GetStateEventForKeyRequest::new(room_id, EventType::RoomMember, user_id.as_str());
+    let response = session_client.send(request, None).await?;
+
+    let mut content = serde_json::to_value(&response.content)?;
+    content["displayname"] = JsonValue::String(nick);
+    let content: MemberEventContent = serde_json::from_value(content)?;
+
+    let content = &AnyStateEventContent::RoomMember(content);
+    let request = SendStateEventForKeyRequest::new(room_id, user_id.as_str(), content);
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SetPowerLevelError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for SetPowerLevelError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for SetPowerLevelError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+
+        let error = match get_ruma_error_kind(&self.0) {
+            Some(RumaErrorKind::Forbidden) => {
+                i18n("You don’t have permission to change the power level of this user.")
+            }
+            _ => i18n("Couldn’t change the power level of the user."),
+        };
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Sets the power level of the user in the room, `None` giving them the default one back
+pub async fn set_power_level(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    user_id: &UserId,
+    level: Option<i64>,
+) -> Result<(), SetPowerLevelError> {
+    let request = GetStateEventForKeyRequest::new(room_id, EventType::RoomPowerLevels, "");
+    let response = session_client.send(request, None).await?;
+
+    let mut content = serde_json::to_value(&response.content)?;
+    if !content["users"].is_object() {
+        content["users"] = json!({});
+    }
+    if let Some(users) = content["users"].as_object_mut() {
+        match level {
+            Some(level) => users.insert(user_id.to_string(), json!(level)),
+            None => users.remove(user_id.as_str()),
+        };
+    }
+    let content: PowerLevelsEventContent = serde_json::from_value(content)?;
+
+    let content = &AnyStateEventContent::RoomPowerLevels(content);
+    let request = SendStateEventForKeyRequest::new(room_id, "", content);
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct GetRoomStateError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for GetRoomStateError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for GetRoomStateError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t get the state of the room.");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Returns the state events of the room, as indented JSON
+pub async fn get_room_state(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+) -> Result<String, GetRoomStateError> {
+    let request = GetStateEventsRequest::new(room_id);
+    let response = session_client.send(request, None).await?;
+
+    Ok(serde_json::to_string_pretty(&response.room_state)?)
+}
+
+#[derive(Debug)]
+pub struct ChangeLanguageError(MatrixError);
+
+impl From<MatrixError> for ChangeLanguageError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<ParseJsonError> for ChangeLanguageError {
+    fn from(err: ParseJsonError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for ChangeLanguageError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "Error forming url to set room language: {}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Language {
     pub input_language: String,
 }
 
@@ -918,8 +1786,82 @@ pub async fn set_language(
 }
 
 #[derive(Debug)]
+pub struct SetUrlPreviewsError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for SetUrlPreviewsError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for SetUrlPreviewsError {}
+
+#[derive(Clone, Debug, Serialize)]
+struct UrlPreviews {
+    disable: bool,
+}
+
+/// Stores whether the links of the room are previewed, in the account data
+/// other clients read too
+pub async fn set_url_previews(
+    session_client: MatrixClient,
+    user_id: &UserId,
+    room_id: &RoomId,
+    disable: bool,
+) -> Result<(), SetUrlPreviewsError> {
+    let request = SetRoomAccountDataRequest::new(
+        to_raw_value(&UrlPreviews { disable })?,
+        "org.matrix.room.preview_urls",
+        room_id,
+        user_id,
+    );
+
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SetDraftError(MatrixError);
+
+impl<T: Into<MatrixError>> From<T> for SetDraftError {
+    fn from(err: T) -> Self {
+        Self(err.into())
+    }
+}
+
+impl HandleError for SetDraftError {}
+
+/// Stores the draft in the account data of the room, an empty one removing it on the
+/// other devices. The attachments stay on this device.
+pub async fn set_draft(
+    session_client: MatrixClient,
+    user_id: &UserId,
+    room_id: &RoomId,
+    draft: Draft,
+) -> Result<(), SetDraftError> {
+    let draft = Draft {
+        attachments: vec![],
+        ..draft
+    };
+    let request = SetRoomAccountDataRequest::new(
+        to_raw_value(&draft)?,
+        "org.gnome.fractal.draft",
+        room_id,
+        user_id,
+    );
+
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RoomNotify {
     All,
+    /// Only the mentions and the keywords notify, since their rules come before the room ones
+    MentionsOnly,
+    /// Nothing notifies, it uses an override rule for the room
     DontNotify,
     NotSet,
 }
@@ -943,6 +1885,15 @@ pub async fn get_pushrules(
     session_client: MatrixClient,
     room_id: &RoomId,
 ) -> Result<RoomNotify, PushRulesError> {
+    let request = GetRoomRulesRequest::new("global", RuleKind::Override, room_id.as_str());
+
+    match session_client.send(request, None).await {
+        Ok(response) if response.rule.enabled => return Ok(RoomNotify::DontNotify),
+        Ok(_) => {}
+        Err(ref err) if get_ruma_error_kind(err) == Some(&RumaErrorKind::NotFound) => {}
+        Err(err) => return Err(err.into()),
+    }
+
     let request = GetRoomRulesRequest::new("global", RuleKind::Room, room_id.as_str());
 
     let value = match session_client.send(request, None).await {
@@ -953,7 +1904,7 @@ pub async fn get_pushrules(
                 .iter()
                 .fold(RoomNotify::NotSet, |notify_value, action| match action {
                     Action::Notify => RoomNotify::All,
-                    Action::DontNotify => RoomNotify::DontNotify,
+                    Action::DontNotify => RoomNotify::MentionsOnly,
                     _ => notify_value,
                 })
         }
@@ -967,33 +1918,148 @@ pub async fn get_pushrules(
     Ok(value)
 }
 
+/// Sets the rule matching `notify` for the room, then removes the rule of the other kind.
+/// The new rule is written over the existing one, so a failed request never leaves the
+/// room without any of them.
 pub async fn set_pushrules(
     session_client: MatrixClient,
     room_id: &RoomId,
     notify: RoomNotify,
 ) -> Result<(), PushRulesError> {
-    let actions = match notify {
+    let (kind, actions) = match notify {
         RoomNotify::NotSet => return delete_pushrules(session_client, room_id).await,
-        RoomNotify::DontNotify => vec![Action::DontNotify],
-        RoomNotify::All => vec![
-            Action::Notify,
-            Action::SetTweak(Tweak::Sound(String::from("default"))),
-        ],
+        RoomNotify::DontNotify => (RuleKind::Override, vec![Action::DontNotify]),
+        RoomNotify::MentionsOnly => (RuleKind::Room, vec![Action::DontNotify]),
+        RoomNotify::All => (
+            RuleKind::Room,
+            vec![
+                Action::Notify,
+                Action::SetTweak(Tweak::Sound(String::from("default"))),
+            ],
+        ),
     };
 
-    let request = SetRoomRulesRequest::new("global", RuleKind::Room, room_id.as_str(), &actions);
+    let conditions = [PushCondition::EventMatch {
+        key: String::from("room_id"),
+        pattern: room_id.to_string(),
+    }];
+    let is_override = notify == RoomNotify::DontNotify;
+    let request = if is_override {
+        assign!(
+            SetRoomRulesRequest::new("global", kind.clone(), room_id.as_str(), &actions),
+            { conditions: &conditions }
+        )
+    } else {
+        SetRoomRulesRequest::new("global", kind.clone(), room_id.as_str(), &actions)
+    };
+    session_client.send(request, None).await?;
 
+    // Updating a rule keeps its enabled state, and it may have been disabled elsewhere
+    let request = SetRoomRulesEnabledRequest::new("global", kind, room_id.as_str(), true);
     session_client.send(request, None).await?;
 
-    Ok(())
+    let other_kind = if is_override {
+        RuleKind::Room
+    } else {
+        RuleKind::Override
+    };
+    delete_pushrule(session_client, room_id, other_kind).await
 }
 
+/// Removes both the room rule and the override rule of the room, if they exist
 pub async fn delete_pushrules(
     session_client: MatrixClient,
     room_id: &RoomId,
 ) -> Result<(), PushRulesError> {
-    let request = DeleteRoomRulesRequest::new("global", RuleKind::Room, room_id.as_str());
-    session_client.send(request, None).await?;
+    for kind in [RuleKind::Room, RuleKind::Override].iter() {
+        delete_pushrule(session_client.clone(), room_id, kind.clone()).await?;
+    }
 
     Ok(())
 }
+
+async fn delete_pushrule(
+    session_client: MatrixClient,
+    room_id: &RoomId,
+    kind: RuleKind,
+) -> Result<(), PushRulesError> {
+    let request = DeleteRoomRulesRequest::new("global", kind, room_id.as_str());
+    match session_client.send(request, None).await {
+        Err(ref err) if get_ruma_error_kind(err) == Some(&RumaErrorKind::NotFound) => Ok(()),
+        result => result.map(|_| ()).map_err(Into::into),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_rules() {
+        let rule = |content| JoinRule::from_content(&content);
+
+        assert_eq!(
+            rule(json!({ "join_rule": "public" })),
+            Some(JoinRule::Public)
+        );
+        assert_eq!(
+            rule(json!({ "join_rule": "invite" })),
+            Some(JoinRule::Invite)
+        );
+        assert_eq!(rule(json!({ "join_rule": "knock" })), Some(JoinRule::Knock));
+        assert_eq!(
+            rule(json!({ "join_rule": "xyz.amorgan.knock" })),
+            Some(JoinRule::Knock)
+        );
+        assert_eq!(
+            rule(json!({ "join_rule": "private" })),
+            Some(JoinRule::Private)
+        );
+    }
+
+    #[test]
+    fn restricted_join_rules() {
+        let space = RoomId::try_from("!space:example.org").unwrap();
+        let other = RoomId::try_from("!other:example.org").unwrap();
+        let content = json!({
+            "join_rule": "restricted",
+            "allow": [
+                { "type": "m.room_membership", "room_id": "!space:example.org" },
+                { "type": "m.room_membership", "space": "!other:example.org" },
+                { "type": "m.room_membership", "room_id": "not a room" },
+            ],
+        });
+        assert_eq!(
+            JoinRule::from_content(&content),
+            Some(JoinRule::Restricted(vec![space, other]))
+        );
+
+        let content = json!({ "join_rule": "org.matrix.msc3083.restricted" });
+        assert_eq!(
+            JoinRule::from_content(&content),
+            Some(JoinRule::Restricted(vec![]))
+        );
+    }
+
+    #[test]
+    fn unknown_join_rules() {
+        assert_eq!(
+            JoinRule::from_content(&json!({ "join_rule": "secret" })),
+            None
+        );
+        assert_eq!(JoinRule::from_content(&json!({ "join_rule": 1 })), None);
+        assert_eq!(JoinRule::from_content(&json!({})), None);
+    }
+
+    #[test]
+    fn join_error_messages() {
+        assert!(join_error_message(Some(&RumaErrorKind::Forbidden))
+            .unwrap()
+            .starts_with("You aren’t allowed to join this room."));
+        assert!(join_error_message(Some(&RumaErrorKind::NotFound))
+            .unwrap()
+            .starts_with("The room couldn’t be found."));
+        assert_eq!(join_error_message(Some(&RumaErrorKind::Unknown)), None);
+        assert_eq!(join_error_message(None), None);
+    }
+}
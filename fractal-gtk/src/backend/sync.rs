@@ -1,5 +1,5 @@
 use crate::globals;
-use crate::model::room::Room;
+use crate::model::room::{Room, RoomTag, SpaceChild};
 use log::error;
 use matrix_sdk::api::r0::filter::Filter as EventFilter;
 use matrix_sdk::api::r0::filter::FilterDefinition;
@@ -27,6 +27,12 @@ pub enum RoomElement {
     NewAvatar(RoomId),
     MemberEvent(StateEvent<MemberEventContent>),
     RemoveMessage(RoomId, EventId),
+    /// The space, the room and its new `m.space.child` event, `None` when it was removed
+    SpaceChild(RoomId, RoomId, Option<SpaceChild>),
+    /// The room, the space of its `m.space.parent` event and whether the event is valid
+    SpaceParent(RoomId, RoomId, bool),
+    /// The upgraded room and the one replacing it
+    Tombstone(RoomId, RoomId),
 }
 
 #[derive(Debug)]
@@ -57,6 +63,10 @@ pub struct SyncUpdates {
     // TODO: Typing events should become RoomElements
     pub typing_events_as_rooms: Vec<Room>,
     pub new_events: Vec<RoomElement>,
+    /// Rooms where our read marker or receipt moved, possibly from another device
+    pub read_rooms: Vec<RoomId>,
+    /// Rooms whose tags changed, with the built-in one and the custom ones
+    pub room_tags: Vec<(RoomId, RoomTag, Vec<String>)>,
 }
 
 pub async fn sync(
@@ -67,7 +77,7 @@ pub async fn sync(
     let initial = since.is_none();
     let timeline_not_types = [String::from("m.call.*")];
     let timeline_types = [String::from("m.room.message"), String::from("m.sticker")];
-    let state_types = [String::from("m.room.*")];
+    let state_types = [String::from("m.room.*"), String::from("m.space.*")];
     let sync_settings = if !initial {
         SyncSettings::new().timeout(Duration::from_secs(30))
     } else {
@@ -33,18 +33,28 @@ use crate::api::r0::AccessToken;
 use crate::api::r0::Medium;
 use crate::api::r0::ThreePIDCredentials;
 use crate::model::member::Member;
+use crate::model::push_rules::{RuleKind, Ruleset};
 use matrix_sdk::api::r0::account::change_password::Request as ChangePasswordRequest;
 use matrix_sdk::api::r0::account::request_3pid_management_token_via_email::Request as EmailTokenRequest;
 use matrix_sdk::api::r0::account::request_3pid_management_token_via_msisdn::Request as PhoneTokenRequest;
+use matrix_sdk::api::r0::config::get_global_account_data::Request as GetGlobalAccountDataRequest;
+use matrix_sdk::api::r0::config::set_global_account_data::Request as SetGlobalAccountDataRequest;
 use matrix_sdk::api::r0::contact::get_contacts::Request as GetContactsRequest;
 use matrix_sdk::api::r0::contact::get_contacts::ThirdPartyIdentifier;
 use matrix_sdk::api::r0::profile::get_display_name::Request as GetDisplayNameRequest;
 use matrix_sdk::api::r0::profile::get_profile::Request as GetProfileRequest;
 use matrix_sdk::api::r0::profile::set_avatar_url::Request as SetAvatarUrlRequest;
 use matrix_sdk::api::r0::profile::set_display_name::Request as SetDisplayNameRequest;
+use matrix_sdk::api::r0::push::delete_pushrule::Request as DeletePushRuleRequest;
+use matrix_sdk::api::r0::push::get_pushrules_all::Request as GetPushRulesAllRequest;
+use matrix_sdk::api::r0::push::set_pushrule::Request as SetPushRuleRequest;
+use matrix_sdk::api::r0::push::set_pushrule_enabled::Request as SetPushRuleEnabledRequest;
+use matrix_sdk::api::r0::push::RuleKind as RumaRuleKind;
 use matrix_sdk::api::r0::uiaa::AuthData;
 use matrix_sdk::api::r0::user_directory::search_users::Request as UserDirectoryRequest;
 use matrix_sdk::assign;
+use matrix_sdk::events::EventType;
+use matrix_sdk::push::{Action, Tweak};
 
 use super::{dw_media, ContentType};
 
@@ -52,6 +62,8 @@ use super::{get_ruma_error_kind, remove_matrix_access_token_if_present, HandleEr
 use crate::util::i18n::i18n;
 use crate::APPOP;
 use serde_json::json;
+use serde_json::value::to_raw_value;
+use serde_json::Value as JsonValue;
 
 pub type UserInfo = (String, PathBuf);
 
@@ -544,6 +556,7 @@ pub async fn get_user_avatar(
                 &url,
                 ContentType::default_thumbnail(),
                 Some(dest),
+                None,
             )
             .await
         }
@@ -558,3 +571,146 @@ pub async fn get_user_avatar(
 
     Ok((name, img))
 }
+
+#[derive(Debug)]
+pub enum GetPushRulesError {
+    Matrix(MatrixError),
+    ParseJson(serde_json::Error),
+}
+
+impl From<MatrixError> for GetPushRulesError {
+    fn from(err: MatrixError) -> Self {
+        Self::Matrix(err)
+    }
+}
+
+impl From<serde_json::Error> for GetPushRulesError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::ParseJson(err)
+    }
+}
+
+impl HandleError for GetPushRulesError {}
+
+pub async fn get_push_rules(session_client: MatrixClient) -> Result<Ruleset, GetPushRulesError> {
+    let response = session_client
+        .send(GetPushRulesAllRequest::new(), None)
+        .await?;
+    let ruleset = serde_json::to_value(response.global)?;
+
+    Ok(serde_json::from_value(ruleset)?)
+}
+
+#[derive(Debug)]
+pub struct ChangePushRuleError(MatrixError);
+
+impl From<MatrixError> for ChangePushRuleError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for ChangePushRuleError {
+    fn handle_error(&self) {
+        error!("Changing the push rules: {}", self.0);
+        let error = i18n("Couldn’t change the notification settings.");
+        APPOP!(show_error, (error));
+    }
+}
+
+pub async fn set_push_rule_enabled(
+    session_client: MatrixClient,
+    kind: RuleKind,
+    rule_id: &str,
+    enabled: bool,
+) -> Result<(), ChangePushRuleError> {
+    let request = SetPushRuleEnabledRequest::new("global", kind.into(), rule_id, enabled);
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+/// Adds a content rule that notifies, with a sound and highlighted, about the messages
+/// containing `keyword`. The keyword is used as the id of the rule.
+pub async fn add_keyword(
+    session_client: MatrixClient,
+    keyword: &str,
+) -> Result<(), ChangePushRuleError> {
+    let actions = [
+        Action::Notify,
+        Action::SetTweak(Tweak::Sound(String::from("default"))),
+        Action::SetTweak(Tweak::Highlight(true)),
+    ];
+    let request = assign!(
+        SetPushRuleRequest::new("global", RumaRuleKind::Content, keyword, &actions),
+        { pattern: Some(keyword) }
+    );
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+pub async fn remove_keyword(
+    session_client: MatrixClient,
+    keyword: &str,
+) -> Result<(), ChangePushRuleError> {
+    let request = DeletePushRuleRequest::new("global", RumaRuleKind::Content, keyword);
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct IgnoreUserError(MatrixError);
+
+impl From<MatrixError> for IgnoreUserError {
+    fn from(err: MatrixError) -> Self {
+        Self(err)
+    }
+}
+
+impl HandleError for IgnoreUserError {
+    fn handle_error(&self) {
+        let err_str = format!("{:?}", self);
+        error!(
+            "{}",
+            remove_matrix_access_token_if_present(&err_str).unwrap_or(err_str)
+        );
+        let error = i18n("Couldn’t ignore the user.");
+        APPOP!(show_error, (error));
+    }
+}
+
+/// Adds the user to `m.ignored_user_list`, the server won't send us their invites and
+/// messages anymore
+pub async fn ignore_user(
+    session_client: MatrixClient,
+    user_id: &UserId,
+    ignored: &UserId,
+) -> Result<(), IgnoreUserError> {
+    let event_type = EventType::IgnoredUserList;
+
+    let request = GetGlobalAccountDataRequest::new(user_id, event_type.as_ref());
+    let mut ignored_users = match session_client.send(request, None).await {
+        Ok(response) => serde_json::from_str::<JsonValue>(response.account_data.json().get())
+            .ok()
+            .and_then(|data| data["content"]["ignored_users"].as_object().cloned())
+            .unwrap_or_default(),
+        Err(err) if get_ruma_error_kind(&err) == Some(&RumaErrorKind::NotFound) => {
+            Default::default()
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    ignored_users.insert(ignored.to_string(), json!({}));
+
+    let content = json!({ "ignored_users": ignored_users });
+    let request = SetGlobalAccountDataRequest::new(
+        to_raw_value(&content).expect("Can't serialize the ignored users"),
+        event_type.as_ref(),
+        user_id,
+    );
+    session_client.send(request, None).await?;
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use once_cell::sync::OnceCell;
+use std::fs::{self, File};
+use std::io::{Error as IoError, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::globals::{CACHE_PATH, DECRYPTED_CACHE_PATH};
+use crate::passwd;
+
+/// Header written at the start of every encrypted file. It lets us keep reading the files
+/// cached before the encryption was enabled.
+const MAGIC: &[u8] = b"FRACTAL-ENC1";
+const NONCE_LEN: usize = 12;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CIPHER: OnceCell<Aes256Gcm> = OnceCell::new();
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+fn cipher() -> Result<&'static Aes256Gcm, IoError> {
+    CIPHER.get_or_try_init(|| {
+        let key = passwd::get_cache_key().map_err(|err| {
+            IoError::new(
+                ErrorKind::Other,
+                format!("Can't get the cache key: {:?}", err),
+            )
+        })?;
+
+        if key.len() != 32 {
+            return Err(IoError::new(ErrorKind::InvalidData, "Malformed cache key"));
+        }
+
+        Ok(Aes256Gcm::new(GenericArray::from_slice(&key)))
+    })
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts `data` with the cache key. The result contains the header and the nonce,
+/// so it can be given as is to `open`.
+pub fn seal(data: &[u8]) -> Result<Vec<u8>, IoError> {
+    seal_with(cipher()?, data)
+}
+
+/// Decrypts data produced by `seal`. Data without the encryption header is returned untouched.
+pub fn open(data: &[u8]) -> Result<Vec<u8>, IoError> {
+    if !is_sealed(data) {
+        return Ok(data.to_vec());
+    }
+
+    open_with(cipher()?, data)
+}
+
+fn seal_with(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, IoError> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = cipher
+        .encrypt(GenericArray::from_slice(&nonce), data)
+        .map_err(|_| IoError::new(ErrorKind::Other, "Can't encrypt the cache data"))?;
+
+    let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend(ciphertext);
+
+    Ok(sealed)
+}
+
+fn open_with(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, IoError> {
+    let data = &data[MAGIC.len()..];
+    if data.len() < NONCE_LEN {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            "Truncated encrypted cache data",
+        ));
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), ciphertext)
+        .map_err(|_| IoError::new(ErrorKind::InvalidData, "Can't decrypt the cache data"))
+}
+
+/// Writes `data` to `path`, encrypting it first if the cache encryption is enabled.
+pub async fn write(path: &Path, data: &[u8]) -> Result<(), IoError> {
+    if is_enabled() {
+        tokio::fs::write(path, seal(data)?).await
+    } else {
+        tokio::fs::write(path, data).await
+    }
+}
+
+/// Where the decrypted copy of the cached file at `path` is kept.
+pub fn decrypted_path(path: &Path) -> PathBuf {
+    let relative = path.strip_prefix(&*CACHE_PATH).unwrap_or(path);
+    DECRYPTED_CACHE_PATH.join(relative.strip_prefix("/").unwrap_or(relative))
+}
+
+/// Returns a path to a plain version of the cached file at `path`, which can be handed to
+/// anything that reads files on its own (gdk-pixbuf, GStreamer, xdg-open...).
+///
+/// Files that aren't encrypted are returned as is. For encrypted ones, a decrypted copy is
+/// kept under `DECRYPTED_CACHE_PATH` and only refreshed when the cached file changes.
+pub fn readable_path(path: &Path) -> Result<PathBuf, IoError> {
+    let mut header = [0; MAGIC.len()];
+    let encrypted = File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map(|_| is_sealed(&header))
+        .unwrap_or(false);
+
+    if !encrypted {
+        return Ok(path.to_path_buf());
+    }
+
+    let plain = decrypted_path(path);
+
+    let is_fresh = match (
+        fs::metadata(&plain).and_then(|md| md.modified()),
+        fs::metadata(path).and_then(|md| md.modified()),
+    ) {
+        (Ok(plain_modified), Ok(modified)) => plain_modified >= modified,
+        _ => false,
+    };
+
+    if !is_fresh {
+        if let Some(parent) = plain.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = open(&fs::read(path)?)?;
+        fs::write(&plain, data)?;
+    }
+
+    Ok(plain)
+}
+
+/// Removes the decrypted copies made by `readable_path`. They are only needed while the
+/// session is open, so this is done when quitting and when logging out.
+pub fn purge_decrypted() -> Result<(), IoError> {
+    if DECRYPTED_CACHE_PATH.exists() {
+        fs::remove_dir_all(&*DECRYPTED_CACHE_PATH)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cipher(key: u8) -> Aes256Gcm {
+        Aes256Gcm::new(GenericArray::from_slice(&[key; 32]))
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let cipher = test_cipher(1);
+        let data = b"some cached media";
+
+        let sealed = seal_with(&cipher, data).unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(data.len()).any(|w| w == data));
+        assert_eq!(open_with(&cipher, &sealed).unwrap(), data);
+
+        // The nonce is random, so the same data never gives the same output
+        assert_ne!(seal_with(&cipher, data).unwrap(), sealed);
+    }
+
+    #[test]
+    fn open_with_wrong_key() {
+        let sealed = seal_with(&test_cipher(1), b"some cached media").unwrap();
+        let err = open_with(&test_cipher(2), &sealed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_truncated() {
+        let sealed = seal_with(&test_cipher(1), b"some cached media").unwrap();
+        let err = open_with(&test_cipher(1), &sealed[..MAGIC.len() + 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_plain_data() {
+        let data = b"cached before the encryption was enabled";
+        assert!(!is_sealed(data));
+        assert_eq!(open(data).unwrap(), data);
+    }
+}
@@ -0,0 +1,336 @@
+use lazy_static::lazy_static;
+use log::error;
+use matrix_sdk::identifiers::RoomId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::crypto;
+use crate::app::RUNTIME;
+use crate::globals::CACHE_PATH;
+
+/// The directories `backend::dw_media` downloads to and the one of the URL previews, which are
+/// the ones we keep under the quota
+const MEDIA_DIRS: &[&str] = &["thumbs", "medias", "previews"];
+const INDEX_NAME: &str = "media-index.json";
+/// How long the changes to the index are gathered before writing it
+const SAVE_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MediaEntry {
+    size: u64,
+    /// Seconds since the UNIX epoch
+    last_access: u64,
+    room_id: Option<RoomId>,
+}
+
+/// Keeps track of the size and the last access of every downloaded media file, and removes
+/// the least recently used ones when the cache grows over the quota.
+#[derive(Debug, Default)]
+struct MediaCache {
+    entries: HashMap<PathBuf, MediaEntry>,
+    /// In bytes, 0 means there's no limit
+    quota: u64,
+    loaded: bool,
+    /// Whether the index has changed since it was last written
+    dirty: bool,
+}
+
+impl MediaCache {
+    fn ensure_loaded(&mut self) {
+        if self.loaded {
+            return;
+        }
+        self.loaded = true;
+
+        let index_path = CACHE_PATH.join(INDEX_NAME);
+        if let Ok(data) = fs::read(&index_path) {
+            match crypto::open(&data).and_then(|data| Ok(serde_json::from_slice(&data)?)) {
+                Ok(entries) => self.entries = entries,
+                Err(err) => error!("Can't load the media cache index: {:?}", err),
+            }
+        }
+
+        // Forget the files removed behind our back and pick up the ones downloaded
+        // before the index existed
+        self.entries.retain(|path, _| path.is_file());
+        for dir in MEDIA_DIRS {
+            let files = fs::read_dir(CACHE_PATH.join(dir))
+                .into_iter()
+                .flatten()
+                .filter_map(Result::ok);
+            for file in files {
+                let path = file.path();
+                if self.entries.contains_key(&path) {
+                    continue;
+                }
+                if let Ok(md) = file.metadata() {
+                    let last_access = md
+                        .modified()
+                        .ok()
+                        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |dur| dur.as_secs());
+                    let entry = MediaEntry {
+                        size: md.len(),
+                        last_access,
+                        room_id: None,
+                    };
+                    self.entries.insert(path, entry);
+                }
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), IoError> {
+        let data = serde_json::to_vec(&self.entries)?;
+        let data = if crypto::is_enabled() {
+            crypto::seal(&data)?
+        } else {
+            data
+        };
+
+        fs::write(CACHE_PATH.join(INDEX_NAME), data)
+    }
+
+    /// Updates the size and the last access of the file at `path`, adding it if needed.
+    /// Returns whether the file wasn't in the index yet.
+    fn touch(&mut self, path: &Path, size: u64, room_id: Option<&RoomId>, now: u64) -> bool {
+        let is_new = !self.entries.contains_key(path);
+        let entry = self
+            .entries
+            .entry(path.to_path_buf())
+            .or_insert(MediaEntry {
+                size,
+                last_access: 0,
+                room_id: None,
+            });
+        entry.size = size;
+        entry.last_access = now;
+        if let Some(room_id) = room_id {
+            entry.room_id = Some(room_id.clone());
+        }
+
+        is_new
+    }
+
+    fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    /// Removes the least recently used files until the cache fits in the quota.
+    /// The file at `keep` is never removed, it's the one we are about to hand out.
+    fn evict(&mut self, keep: Option<&Path>) -> bool {
+        let mut total = self.total_size();
+        if self.quota == 0 || total <= self.quota {
+            return false;
+        }
+
+        let mut lru: Vec<(PathBuf, u64, u64)> = self
+            .entries
+            .iter()
+            .filter(|(path, _)| Some(path.as_path()) != keep)
+            .map(|(path, entry)| (path.clone(), entry.last_access, entry.size))
+            .collect();
+        lru.sort_by_key(|(_, last_access, _)| *last_access);
+
+        for (path, _, size) in lru {
+            if total <= self.quota {
+                break;
+            }
+
+            if let Err(err) = fs::remove_file(&path) {
+                error!("Can't remove {:?} from the cache: {:?}", path, err);
+                continue;
+            }
+            let _ = fs::remove_file(crypto::decrypted_path(&path));
+
+            self.entries.remove(&path);
+            total -= size;
+        }
+
+        true
+    }
+}
+
+lazy_static! {
+    static ref MEDIA_CACHE: Mutex<MediaCache> = Mutex::new(MediaCache::default());
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |dur| dur.as_secs())
+}
+
+/// Sets the maximum size of the media cache, in bytes. 0 disables the limit.
+pub fn set_quota(quota: u64) {
+    MEDIA_CACHE.lock().unwrap().quota = quota;
+}
+
+/// Registers an access to the cached media file at `path`, which may have been just
+/// downloaded, and makes room for it if the cache is over the quota.
+pub fn record(path: &Path, room_id: Option<&RoomId>) {
+    let size = match path.metadata() {
+        Ok(md) => md.len(),
+        Err(_) => return,
+    };
+
+    let mut cache = MEDIA_CACHE.lock().unwrap();
+    cache.ensure_loaded();
+
+    let is_new = cache.touch(path, size, room_id, now());
+    let evicted = cache.evict(Some(path));
+    if is_new || evicted {
+        schedule_save(&mut cache);
+    }
+}
+
+/// Writes the index once the records done in the next `SAVE_DELAY` are gathered, instead
+/// of after every one of them. Only the first change starts the timer.
+fn schedule_save(cache: &mut MediaCache) {
+    if cache.dirty {
+        return;
+    }
+    cache.dirty = true;
+
+    RUNTIME.spawn(async {
+        tokio::time::sleep(SAVE_DELAY).await;
+        save();
+    });
+}
+
+/// Removes files until the cache fits in the quota, useful after lowering it.
+pub fn enforce_quota() {
+    let mut cache = MEDIA_CACHE.lock().unwrap();
+    cache.ensure_loaded();
+
+    if cache.evict(None) {
+        schedule_save(&mut cache);
+    }
+}
+
+/// Writes the index to disk if it has pending changes, so the access times survive a restart.
+pub fn save() {
+    let mut cache = MEDIA_CACHE.lock().unwrap();
+    if !cache.loaded || !cache.dirty {
+        return;
+    }
+
+    cache.dirty = false;
+    if let Err(err) = cache.save() {
+        error!("Can't save the media cache index: {:?}", err);
+    }
+}
+
+/// Forgets every entry. To be used when the files have been removed from disk.
+pub fn reset() {
+    let mut cache = MEDIA_CACHE.lock().unwrap();
+    cache.entries.clear();
+    cache.dirty = false;
+}
+
+/// Returns how much space the media of each room takes in the cache. Media we don't
+/// know the room of is under `None`.
+pub fn usage() -> HashMap<Option<RoomId>, u64> {
+    let mut cache = MEDIA_CACHE.lock().unwrap();
+    cache.ensure_loaded();
+
+    cache
+        .entries
+        .values()
+        .fold(HashMap::new(), |mut usage, entry| {
+            *usage.entry(entry.room_id.clone()).or_default() += entry.size;
+            usage
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "fractal-media-cache-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn add_file(cache: &mut MediaCache, path: &Path, size: u64, last_access: u64) {
+        fs::write(path, vec![0; size as usize]).unwrap();
+        cache.touch(path, size, None, last_access);
+    }
+
+    #[test]
+    fn size_accounting() {
+        let mut cache = MediaCache::default();
+        let a = Path::new("/cache/medias/a");
+        let b = Path::new("/cache/medias/b");
+        let room_id = RoomId::try_from("!room:example.org").unwrap();
+
+        assert!(cache.touch(a, 100, None, 1));
+        assert!(cache.touch(b, 50, Some(&room_id), 2));
+        assert_eq!(cache.total_size(), 150);
+
+        // A new download of the same file replaces its size instead of adding to it
+        assert!(!cache.touch(a, 120, None, 3));
+        assert_eq!(cache.total_size(), 170);
+        assert_eq!(cache.entries[a].last_access, 3);
+
+        // The room is kept when it isn't given again
+        cache.touch(b, 50, None, 4);
+        assert_eq!(cache.entries[b].room_id, Some(room_id));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dir = test_dir("evict");
+        let (old, mid, new) = (dir.join("old"), dir.join("mid"), dir.join("new"));
+
+        let mut cache = MediaCache {
+            quota: 250,
+            loaded: true,
+            ..MediaCache::default()
+        };
+        add_file(&mut cache, &mid, 100, 20);
+        add_file(&mut cache, &old, 100, 10);
+        add_file(&mut cache, &new, 100, 30);
+
+        assert!(cache.evict(None));
+        assert!(!old.exists());
+        assert!(mid.exists() && new.exists());
+        assert_eq!(cache.total_size(), 200);
+
+        // Nothing to do once under the quota
+        assert!(!cache.evict(None));
+
+        // The kept file survives even if it's the oldest one
+        cache.quota = 100;
+        cache.touch(&mid, 100, None, 5);
+        assert!(cache.evict(Some(&mid)));
+        assert!(mid.exists());
+        assert!(!new.exists());
+        assert_eq!(cache.total_size(), 100);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn no_quota() {
+        let dir = test_dir("no-quota");
+        let mut cache = MediaCache::default();
+        add_file(&mut cache, &dir.join("a"), 1000, 1);
+
+        assert!(!cache.evict(None));
+        assert_eq!(cache.total_size(), 1000);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
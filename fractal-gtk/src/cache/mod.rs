@@ -1,11 +1,13 @@
 use crate::app::RUNTIME;
 use crate::appop::UserInfoCache;
 use crate::backend::user;
+use crate::globals::{CACHE_PATH, DECRYPTED_CACHE_PATH};
 use crate::util::cache_dir_path;
 use matrix_sdk::Client as MatrixClient;
 
+use crate::model::draft::Draft;
 use anyhow::Error;
-use matrix_sdk::identifiers::{DeviceId, UserId};
+use matrix_sdk::identifiers::{DeviceId, RoomId, UserId};
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::time::{Duration, Instant};
@@ -14,9 +16,12 @@ use crate::widgets::AvatarData;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+pub mod crypto;
+pub mod media;
 mod state;
 pub use self::state::get;
 pub use self::state::AppState;
+use self::state::Drafts;
 pub use self::state::FCache;
 
 // user info cache, uid -> (name, avatar)
@@ -59,6 +64,10 @@ impl<K: Clone + Eq + Hash, V: Clone> CacheMap<K, V> {
     pub fn remove(&mut self, k: &K) -> Option<V> {
         self.map.remove(k).map(|v| v.1)
     }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
 }
 
 pub struct CacheData {
@@ -97,6 +106,26 @@ pub fn load() -> Result<CacheData, Error> {
     })
 }
 
+pub fn store_drafts(uid: UserId, drafts: HashMap<RoomId, Draft>) -> Result<(), Error> {
+    get().save_drafts(Drafts { uid, drafts })
+}
+
+/// The drafts saved for the account, without the attachments that were removed since
+pub fn load_drafts(uid: &UserId) -> Result<HashMap<RoomId, Draft>, Error> {
+    let mut drafts = get().get_drafts(uid)?;
+    prune_drafts(&mut drafts);
+
+    Ok(drafts)
+}
+
+/// Drops the attachments whose file is gone, and the drafts left empty
+fn prune_drafts(drafts: &mut HashMap<RoomId, Draft>) {
+    for draft in drafts.values_mut() {
+        draft.attachments.retain(|path| path.is_file());
+    }
+    drafts.retain(|_, draft| !draft.is_empty());
+}
+
 pub fn remove_from_cache(user_info_cache: UserInfoCache, user_id: &UserId) {
     user_info_cache.lock().unwrap().remove(&user_id);
     if let Ok(dest) = cache_dir_path(None, &user_id.to_string()) {
@@ -104,6 +133,24 @@ pub fn remove_from_cache(user_info_cache: UserInfoCache, user_id: &UserId) {
     }
 }
 
+/// Removes everything we have in the local cache: the state database, the downloaded
+/// media and the decrypted copies of the encrypted files. The session and the drafts are in
+/// the state database, they have to be stored again afterwards.
+pub fn clear(user_info_cache: UserInfoCache) -> Result<(), Error> {
+    user_info_cache.lock().unwrap().clear();
+    // The database may not exist yet, so there's nothing to report if this fails
+    let _ = get().destroy();
+
+    for path in [&*CACHE_PATH, &*DECRYPTED_CACHE_PATH].iter() {
+        if path.exists() {
+            std::fs::remove_dir_all(path)?;
+        }
+    }
+    media::reset();
+
+    Ok(())
+}
+
 /// this downloads a avatar and stores it in the cache folder
 pub fn download_to_cache(
     session_client: MatrixClient,
@@ -119,3 +166,60 @@ pub fn download_to_cache(
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn prune_drafts_attachments() {
+        let dir = std::env::temp_dir().join("fractal-test-prune-drafts");
+        std::fs::create_dir_all(&dir).unwrap();
+        let kept = dir.join("kept.txt");
+        std::fs::write(&kept, b"data").unwrap();
+        let missing = dir.join("missing.txt");
+
+        let text_room = RoomId::try_from("!text:example.org").unwrap();
+        let files_room = RoomId::try_from("!files:example.org").unwrap();
+        let gone_room = RoomId::try_from("!gone:example.org").unwrap();
+        let empty_room = RoomId::try_from("!empty:example.org").unwrap();
+
+        let mut drafts = HashMap::new();
+        drafts.insert(
+            text_room.clone(),
+            Draft {
+                text: String::from("Hello"),
+                attachments: vec![missing.clone()],
+                ..Default::default()
+            },
+        );
+        drafts.insert(
+            files_room.clone(),
+            Draft {
+                attachments: vec![kept.clone(), missing.clone()],
+                ..Default::default()
+            },
+        );
+        drafts.insert(
+            gone_room.clone(),
+            Draft {
+                text: String::from("  "),
+                attachments: vec![missing],
+                ..Default::default()
+            },
+        );
+        drafts.insert(empty_room.clone(), Draft::default());
+
+        prune_drafts(&mut drafts);
+
+        assert_eq!(drafts.len(), 2);
+        assert_eq!(drafts[&text_room].text, "Hello");
+        assert!(drafts[&text_room].attachments.is_empty());
+        assert_eq!(drafts[&files_room].attachments, vec![kept]);
+        assert!(!drafts.contains_key(&gone_room));
+        assert!(!drafts.contains_key(&empty_room));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
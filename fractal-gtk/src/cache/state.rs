@@ -5,11 +5,14 @@ use serde::{Deserialize, Serialize};
 
 use anyhow::{anyhow, Error};
 
+use std::collections::HashMap;
 use std::fs::remove_dir_all;
 use std::sync::{Arc, Mutex, MutexGuard};
 
+use super::crypto;
+use crate::model::draft::Draft;
 use crate::util::cache_dir_path;
-use matrix_sdk::identifiers::{DeviceId, UserId};
+use matrix_sdk::identifiers::{DeviceId, RoomId, UserId};
 
 // Models
 
@@ -30,6 +33,45 @@ impl Model for AppState {
     }
 }
 
+/// The `AppState` serialized and encrypted with the cache key, used
+/// instead of the plain one when the cache encryption is enabled
+#[derive(Serialize, Deserialize)]
+pub struct SealedAppState {
+    pub data: Vec<u8>,
+}
+
+impl Model for SealedAppState {
+    fn key(&self) -> String {
+        "sealed-state".to_string()
+    }
+}
+
+/// The unsent messages of the rooms of an account
+#[derive(Serialize, Deserialize)]
+pub struct Drafts {
+    pub uid: UserId,
+    pub drafts: HashMap<RoomId, Draft>,
+}
+
+impl Model for Drafts {
+    fn key(&self) -> String {
+        format!("drafts:{}", self.uid)
+    }
+}
+
+/// The `Drafts` encrypted with the cache key
+#[derive(Serialize, Deserialize)]
+pub struct SealedDrafts {
+    pub uid: UserId,
+    pub data: Vec<u8>,
+}
+
+impl Model for SealedDrafts {
+    fn key(&self) -> String {
+        format!("sealed-drafts:{}", self.uid)
+    }
+}
+
 // Cache
 #[derive(Clone)]
 pub struct FCache {
@@ -63,12 +105,49 @@ impl FCache {
 
     pub fn get_st(&self) -> Result<AppState, Error> {
         let cache = self.get_store();
-        AppState::get(cache.as_ref().unwrap(), "state")
+        if !crypto::is_enabled() {
+            return AppState::get(cache.as_ref().unwrap(), "state");
+        }
+
+        let sealed = SealedAppState::get(cache.as_ref().unwrap(), "sealed-state")?;
+        let data = crypto::open(&sealed.data)?;
+        Ok(serde_json::from_slice(&data)?)
     }
 
     pub fn save_st(&self, st: AppState) -> Result<(), Error> {
         let cache = self.get_store();
-        st.store(cache.as_ref().unwrap())?;
+        if crypto::is_enabled() {
+            let data = crypto::seal(&serde_json::to_vec(&st)?)?;
+            SealedAppState { data }.store(cache.as_ref().unwrap())?;
+        } else {
+            st.store(cache.as_ref().unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_drafts(&self, uid: &UserId) -> Result<HashMap<RoomId, Draft>, Error> {
+        let cache = self.get_store();
+        if !crypto::is_enabled() {
+            let drafts = Drafts::get(cache.as_ref().unwrap(), &format!("drafts:{}", uid))?;
+            return Ok(drafts.drafts);
+        }
+
+        let key = format!("sealed-drafts:{}", uid);
+        let sealed = SealedDrafts::get(cache.as_ref().unwrap(), &key)?;
+        let data = crypto::open(&sealed.data)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn save_drafts(&self, drafts: Drafts) -> Result<(), Error> {
+        let cache = self.get_store();
+        if crypto::is_enabled() {
+            let data = crypto::seal(&serde_json::to_vec(&drafts.drafts)?)?;
+            let uid = drafts.uid;
+            SealedDrafts { uid, data }.store(cache.as_ref().unwrap())?;
+        } else {
+            drafts.store(cache.as_ref().unwrap())?;
+        }
 
         Ok(())
     }
@@ -9,6 +9,7 @@ use url::Url;
 pub const TIMEOUT: Duration = Duration::from_secs(80);
 pub const PAGE_LIMIT: u32 = 40;
 pub const ROOM_DIRECTORY_LIMIT: u32 = 20;
+pub const ROOM_PREVIEW_LIMIT: u32 = 10;
 pub const DEVICE_NAME: &str = "Fractal";
 
 pub const MSG_ICON_SIZE: i32 = 40;
@@ -17,9 +18,11 @@ pub const PILL_ICON_SIZE: i32 = 18;
 pub const MINUTES_TO_SPLIT_MSGS: i64 = 30;
 pub const PLACEHOLDER_TEXT: &str = "Matrix username, email or phone number";
 pub const ELEMENT_REGISTER_URL: &str = "https://app.element.io/#/register";
+pub const MATRIX_TO_URL: &str = "https://matrix.to/#/";
 
 pub const MAX_IMAGE_SIZE: (i32, i32) = (600, 400);
 pub const MAX_STICKER_SIZE: (i32, i32) = (200, 130);
+pub const URL_PREVIEW_IMAGE_SIZE: (i32, i32) = (80, 80);
 
 lazy_static! {
     pub static ref DEFAULT_HOMESERVER: Url =
@@ -36,4 +39,9 @@ lazy_static! {
         .map(ProjectDirs::cache_dir)
         .map(Into::into)
         .unwrap_or_else(|| std::env::temp_dir().join("fractal"));
+    /// Where the decrypted copies of the cached files live when the cache is encrypted.
+    /// The runtime dir is usually a tmpfs, so the plain files never hit the disk.
+    pub static ref DECRYPTED_CACHE_PATH: PathBuf = glib::get_user_runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("fractal");
 }
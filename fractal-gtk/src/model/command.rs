@@ -0,0 +1,202 @@
+use crate::util::i18n::{i18n, i18n_f};
+
+/// A command typed at the start of a message, like `/me waves`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Me,
+    Shrug,
+    Topic,
+    Nick,
+    MyRoomNick,
+    Invite,
+    Kick,
+    Ban,
+    Join,
+    Part,
+    Op,
+    Deop,
+    Plain,
+    Html,
+    Markdown,
+    Spoiler,
+    Notice,
+    Devtools,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    MissingArgs(Command),
+    InvalidArgs(Command),
+}
+
+impl CommandError {
+    pub fn message(&self) -> String {
+        match self {
+            Self::Unknown(name) => i18n_f(
+                "“/{}” isn’t a command. Start the message with “//” to send it as it is.",
+                &[name],
+            ),
+            Self::MissingArgs(command) | Self::InvalidArgs(command) => {
+                i18n_f("Usage: /{} {}", &[command.name(), &command.args()])
+            }
+        }
+    }
+}
+
+impl Command {
+    pub const ALL: &'static [Command] = &[
+        Command::Me,
+        Command::Shrug,
+        Command::Topic,
+        Command::Nick,
+        Command::MyRoomNick,
+        Command::Invite,
+        Command::Kick,
+        Command::Ban,
+        Command::Join,
+        Command::Part,
+        Command::Op,
+        Command::Deop,
+        Command::Plain,
+        Command::Html,
+        Command::Markdown,
+        Command::Spoiler,
+        Command::Notice,
+        Command::Devtools,
+    ];
+
+    /// The name typed after the slash
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Me => "me",
+            Command::Shrug => "shrug",
+            Command::Topic => "topic",
+            Command::Nick => "nick",
+            Command::MyRoomNick => "myroomnick",
+            Command::Invite => "invite",
+            Command::Kick => "kick",
+            Command::Ban => "ban",
+            Command::Join => "join",
+            Command::Part => "part",
+            Command::Op => "op",
+            Command::Deop => "deop",
+            Command::Plain => "plain",
+            Command::Html => "html",
+            Command::Markdown => "markdown",
+            Command::Spoiler => "spoiler",
+            Command::Notice => "notice",
+            Command::Devtools => "devtools",
+        }
+    }
+
+    /// The arguments of the command, as shown in its help. The optional ones are in brackets.
+    pub fn args(self) -> String {
+        match self {
+            Command::Me
+            | Command::Plain
+            | Command::Html
+            | Command::Markdown
+            | Command::Spoiler
+            | Command::Notice => i18n("<message>"),
+            Command::Shrug => i18n("[message]"),
+            Command::Topic => i18n("<topic>"),
+            Command::Nick | Command::MyRoomNick => i18n("<display name>"),
+            Command::Invite | Command::Deop => i18n("<user ID>"),
+            Command::Kick | Command::Ban => i18n("<user ID> [reason]"),
+            Command::Join => i18n("<room address>"),
+            Command::Part => i18n("[room address]"),
+            Command::Op => i18n("<user ID> [power level]"),
+            Command::Devtools => String::new(),
+        }
+    }
+
+    /// Whether the command can't be run without arguments
+    fn needs_args(self) -> bool {
+        !matches!(self, Command::Shrug | Command::Part | Command::Devtools)
+    }
+
+    pub fn description(self) -> String {
+        match self {
+            Command::Me => i18n("Send the message as an action"),
+            Command::Shrug => i18n("Send the message prefixed with ¯\\_(ツ)_/¯"),
+            Command::Topic => i18n("Set the topic of the room"),
+            Command::Nick => i18n("Change your display name"),
+            Command::MyRoomNick => i18n("Change your display name in this room only"),
+            Command::Invite => i18n("Invite the user to the room"),
+            Command::Kick => i18n("Remove the user from the room"),
+            Command::Ban => i18n("Ban the user from the room"),
+            Command::Join => i18n("Join the room with this ID or alias"),
+            Command::Part => i18n("Leave this room, or the given one"),
+            Command::Op => i18n("Set the power level of the user, moderator by default"),
+            Command::Deop => i18n("Reset the power level of the user"),
+            Command::Plain => i18n("Send the message without formatting it"),
+            Command::Html => i18n("Send the message as HTML"),
+            Command::Markdown => i18n("Send the message formatted with Markdown"),
+            Command::Spoiler => i18n("Send the message hidden as a spoiler"),
+            Command::Notice => i18n("Send the message as a notice, like bots do"),
+            Command::Devtools => i18n("Show the state of the room"),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|command| command.name() == name)
+    }
+
+    /// Splits a message starting with a command into the command and its arguments, which
+    /// are the end of the message. The messages without command, including the ones starting
+    /// with `//` to escape the slash, give `None`.
+    pub fn parse(msg: &str) -> Option<Result<(Self, &str), CommandError>> {
+        let text = msg.strip_prefix('/')?;
+        if text.starts_with('/') {
+            return None;
+        }
+
+        let (name, args) = match text.find(char::is_whitespace) {
+            Some(pos) => (&text[..pos], text[pos..].trim_start()),
+            None => (text, ""),
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let command = match Self::from_name(name) {
+            Some(command) => command,
+            None => return Some(Err(CommandError::Unknown(name.to_string()))),
+        };
+        if args.trim().is_empty() && command.needs_args() {
+            return Some(Err(CommandError::MissingArgs(command)));
+        }
+
+        Some(Ok((command, args)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("hello"), None);
+        assert_eq!(Command::parse("//me hello"), None);
+        assert_eq!(Command::parse("/ hello"), None);
+
+        assert_eq!(
+            Command::parse("/me  waves"),
+            Some(Ok((Command::Me, "waves")))
+        );
+        assert_eq!(Command::parse("/SHRUG"), Some(Ok((Command::Shrug, ""))));
+        assert_eq!(
+            Command::parse("/topic"),
+            Some(Err(CommandError::MissingArgs(Command::Topic)))
+        );
+        assert_eq!(
+            Command::parse("/unknown command"),
+            Some(Err(CommandError::Unknown(String::from("unknown"))))
+        );
+    }
+}
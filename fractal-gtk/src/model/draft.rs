@@ -0,0 +1,107 @@
+use crate::util::i18n::{i18n_f, ni18n_f};
+use matrix_sdk::identifiers::EventId;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A message being written in a room, kept until it's sent
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Draft {
+    pub text: String,
+    /// The position of the cursor in the text, in characters
+    pub cursor: i32,
+    /// The message this one replies to
+    pub reply_to: Option<EventId>,
+    /// Files picked or dropped in the room, waiting in the tray to be sent
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<PathBuf>,
+    /// The formats picked in the formatting toolbar
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<TextFormat>,
+}
+
+/// A format of the formatting toolbar applied to a part of the draft, from `start` to `end`
+/// in characters of the text
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextFormat {
+    pub name: String,
+    pub start: i32,
+    pub end: i32,
+}
+
+impl Draft {
+    pub fn is_empty(&self) -> bool {
+        self.text.trim().is_empty() && self.attachments.is_empty()
+    }
+
+    /// The beginning of the draft, as shown in the room list
+    pub fn snippet(&self) -> Option<String> {
+        if let Some(line) = self.text.lines().map(str::trim).find(|l| !l.is_empty()) {
+            return Some(i18n_f("Draft: {}", &[line]));
+        }
+
+        let n = self.attachments.len();
+        if n > 0 {
+            let count = n.to_string();
+            return Some(ni18n_f(
+                "Draft: {} attachment",
+                "Draft: {} attachments",
+                n as u32,
+                &[&count],
+            ));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_empty() {
+        assert!(Draft::default().is_empty());
+
+        let draft = Draft {
+            text: String::from("  \n\t "),
+            ..Default::default()
+        };
+        assert!(draft.is_empty());
+
+        let draft = Draft {
+            text: String::from("  \n\t "),
+            attachments: vec![PathBuf::from("/tmp/image.png")],
+            ..Default::default()
+        };
+        assert!(!draft.is_empty());
+    }
+
+    #[test]
+    fn snippet_text() {
+        let draft = Draft {
+            text: String::from("\n   \n  Hello there  \nsecond line"),
+            attachments: vec![PathBuf::from("/tmp/image.png")],
+            ..Default::default()
+        };
+        assert_eq!(draft.snippet().as_deref(), Some("Draft: Hello there"));
+    }
+
+    #[test]
+    fn snippet_attachments() {
+        let mut draft = Draft {
+            text: String::from(" "),
+            attachments: vec![PathBuf::from("/tmp/image.png")],
+            ..Default::default()
+        };
+        assert_eq!(draft.snippet().as_deref(), Some("Draft: 1 attachment"));
+
+        draft.attachments.push(PathBuf::from("/tmp/notes.txt"));
+        assert_eq!(draft.snippet().as_deref(), Some("Draft: 2 attachments"));
+    }
+
+    #[test]
+    fn snippet_empty() {
+        assert_eq!(Draft::default().snippet(), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::util::i18n::i18n;
 use chrono::prelude::*;
 use chrono::DateTime;
 use matrix_sdk::{
@@ -431,6 +432,27 @@ impl Message {
         }
     }
 
+    /// A single line summing up the message, as shown in the room list
+    pub fn snippet(&self) -> String {
+        match self.mtype.as_str() {
+            "m.audio" => i18n("Audio"),
+            "m.image" => i18n("Image"),
+            "m.video" => i18n("Video"),
+            "m.file" => i18n("File"),
+            "m.sticker" => i18n("Sticker"),
+            _ => self
+                .body
+                .lines()
+                // The replies start by quoting the message they reply to
+                .skip_while(|line| {
+                    self.in_reply_to.is_some() && (line.starts_with('>') || line.is_empty())
+                })
+                .flat_map(str::split_whitespace)
+                .collect::<Vec<&str>>()
+                .join(" "),
+        }
+    }
+
     /// Returns all event IDs this message relates to.
     pub fn relations(&self) -> Vec<EventId> {
         vec![self.in_reply_to.as_ref(), self.replace.as_ref()]
@@ -456,3 +478,46 @@ impl Message {
         self.receipt = receipt;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(mtype: &str, body: &str) -> Message {
+        Message::new(
+            RoomId::try_from("!room:example.org").unwrap(),
+            UserId::try_from("@alice:example.org").unwrap(),
+            body.to_string(),
+            mtype.to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn snippet_text() {
+        let msg = message("m.text", "  Hello\n\n  there,   you ");
+        assert_eq!(msg.snippet(), "Hello there, you");
+    }
+
+    #[test]
+    fn snippet_reply() {
+        let mut msg = message(
+            "m.text",
+            "> <@bob:example.org> Hi\n> second line\n\nHello\n> not quoted",
+        );
+        assert_eq!(
+            msg.snippet(),
+            "> <@bob:example.org> Hi > second line Hello > not quoted"
+        );
+
+        msg.in_reply_to = Some(EventId::try_from("$reply:example.org").unwrap());
+        assert_eq!(msg.snippet(), "Hello > not quoted");
+    }
+
+    #[test]
+    fn snippet_media() {
+        assert_eq!(message("m.image", "cat.png").snippet(), "Image");
+        assert_eq!(message("m.file", "report.pdf").snippet(), "File");
+        assert_eq!(message("m.sticker", "A cat").snippet(), "Sticker");
+    }
+}
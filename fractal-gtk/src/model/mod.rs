@@ -1,5 +1,7 @@
-pub mod fileinfo;
+pub mod command;
+pub mod draft;
 pub mod member;
 pub mod message;
 pub mod message_list;
+pub mod push_rules;
 pub mod room;
@@ -0,0 +1,530 @@
+use matrix_sdk::api::r0::push::RuleKind as RumaRuleKind;
+use matrix_sdk::identifiers::RoomId;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use crate::model::message::Message;
+use crate::model::room::Room;
+
+/// The push rules of the account, in the format of the `/pushrules/` endpoints.
+/// Only the `global` scope is used.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Ruleset {
+    #[serde(rename = "override", default)]
+    pub override_rules: Vec<PushRule>,
+    #[serde(default)]
+    pub content: Vec<PushRule>,
+    #[serde(default)]
+    pub room: Vec<PushRule>,
+    #[serde(default)]
+    pub sender: Vec<PushRule>,
+    #[serde(default)]
+    pub underride: Vec<PushRule>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuleKind {
+    Override,
+    Content,
+    Room,
+    Sender,
+    Underride,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PushRule {
+    pub rule_id: String,
+    #[serde(default)]
+    pub default: bool,
+    pub enabled: bool,
+    #[serde(default)]
+    pub actions: Vec<JsonValue>,
+    #[serde(default)]
+    pub conditions: Vec<PushCondition>,
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PushCondition {
+    EventMatch {
+        key: String,
+        pattern: String,
+    },
+    ContainsDisplayName,
+    RoomMemberCount {
+        is: String,
+    },
+    SenderNotificationPermission {
+        key: String,
+    },
+    // Conditions we don't know about never match, as the spec asks
+    #[serde(other)]
+    Unknown,
+}
+
+/// What the rules are checked against
+pub struct PushContext<'a> {
+    pub event: JsonValue,
+    pub display_name: Option<&'a str>,
+    pub member_count: usize,
+    pub sender_power_level: i64,
+    /// The level needed to send notifications for the whole room
+    pub room_notification_level: i64,
+}
+
+/// What should be done about an event, from the actions of the rule it matched
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PushActions {
+    pub notify: bool,
+    pub highlight: bool,
+    pub sound: bool,
+}
+
+impl<'a> PushContext<'a> {
+    pub fn new(msg: &Message, room: Option<&Room>, display_name: Option<&'a str>) -> Self {
+        let mut event = msg
+            .source
+            .as_ref()
+            .and_then(|source| serde_json::from_str(source).ok())
+            .filter(JsonValue::is_object)
+            .unwrap_or_else(|| {
+                json!({
+                    "type": "m.room.message",
+                    "sender": msg.sender.as_str(),
+                    "content": {
+                        "msgtype": msg.mtype,
+                        "body": msg.body,
+                    },
+                })
+            });
+        // The events from the sync don't have it
+        event["room_id"] = json!(msg.room.as_str());
+
+        let sender_power_level = room.map_or(0, |r| {
+            r.admins
+                .get(&msg.sender)
+                .copied()
+                .unwrap_or_else(|| r.default_power_level.max(0))
+        });
+
+        Self {
+            event,
+            display_name,
+            member_count: room.map_or(0, |r| r.members.len()),
+            sender_power_level,
+            room_notification_level: room.map_or(50, |r| r.notifications_power_level),
+        }
+    }
+}
+
+impl From<&[JsonValue]> for PushActions {
+    fn from(actions: &[JsonValue]) -> Self {
+        let mut push_actions = Self::default();
+
+        for action in actions {
+            if action == "notify" {
+                push_actions.notify = true;
+            }
+
+            match action["set_tweak"].as_str() {
+                // Without a value, the highlight tweak means `true`
+                Some("highlight") => {
+                    push_actions.highlight = action["value"].as_bool().unwrap_or(true);
+                }
+                Some("sound") => push_actions.sound = action["value"].is_string(),
+                _ => {}
+            }
+        }
+
+        push_actions
+    }
+}
+
+impl From<RuleKind> for RumaRuleKind {
+    fn from(kind: RuleKind) -> Self {
+        match kind {
+            RuleKind::Override => Self::Override,
+            RuleKind::Content => Self::Content,
+            RuleKind::Room => Self::Room,
+            RuleKind::Sender => Self::Sender,
+            RuleKind::Underride => Self::Underride,
+        }
+    }
+}
+
+impl Ruleset {
+    pub fn rules(&self, kind: RuleKind) -> &[PushRule] {
+        match kind {
+            RuleKind::Override => &self.override_rules,
+            RuleKind::Content => &self.content,
+            RuleKind::Room => &self.room,
+            RuleKind::Sender => &self.sender,
+            RuleKind::Underride => &self.underride,
+        }
+    }
+
+    pub fn get_rule(&self, kind: RuleKind, rule_id: &str) -> Option<&PushRule> {
+        self.rules(kind).iter().find(|rule| rule.rule_id == rule_id)
+    }
+
+    /// The rooms muted with an override rule, the way the room settings do it
+    pub fn muted_rooms(&self) -> HashSet<RoomId> {
+        self.override_rules
+            .iter()
+            .filter(|rule| rule.enabled && !PushActions::from(rule.actions.as_slice()).notify)
+            .filter_map(|rule| RoomId::try_from(rule.rule_id.as_str()).ok())
+            .collect()
+    }
+
+    /// The keywords added by the user, the content rules that aren't server defaults
+    pub fn keywords(&self) -> Vec<&PushRule> {
+        self.content.iter().filter(|rule| !rule.default).collect()
+    }
+
+    /// What to do about the event, the tweaks are only taken into account
+    /// when the event notifies
+    pub fn evaluate(&self, ctx: &PushContext) -> PushActions {
+        let actions = PushActions::from(self.get_actions(ctx));
+        if actions.notify {
+            actions
+        } else {
+            PushActions::default()
+        }
+    }
+
+    /// The actions of the first enabled rule matching the event, going through
+    /// the kinds in their order of priority
+    fn get_actions(&self, ctx: &PushContext) -> &[JsonValue] {
+        let kinds = [
+            RuleKind::Override,
+            RuleKind::Content,
+            RuleKind::Room,
+            RuleKind::Sender,
+            RuleKind::Underride,
+        ];
+
+        kinds
+            .iter()
+            .flat_map(|kind| self.rules(*kind).iter().map(move |rule| (*kind, rule)))
+            .find(|(kind, rule)| rule.enabled && rule.matches(*kind, ctx))
+            .map_or(&[][..], |(_, rule)| rule.actions.as_slice())
+    }
+}
+
+impl PushRule {
+    pub fn notifies(&self) -> bool {
+        PushActions::from(self.actions.as_slice()).notify
+    }
+
+    fn matches(&self, kind: RuleKind, ctx: &PushContext) -> bool {
+        match kind {
+            RuleKind::Override | RuleKind::Underride => {
+                self.conditions.iter().all(|cond| cond.matches(ctx))
+            }
+            RuleKind::Content => self.pattern.as_ref().map_or(false, |pattern| {
+                event_match(&ctx.event, "content.body", pattern)
+            }),
+            RuleKind::Room => ctx.event["room_id"].as_str() == Some(self.rule_id.as_str()),
+            RuleKind::Sender => ctx.event["sender"].as_str() == Some(self.rule_id.as_str()),
+        }
+    }
+}
+
+impl PushCondition {
+    fn matches(&self, ctx: &PushContext) -> bool {
+        match self {
+            Self::EventMatch { key, pattern } => event_match(&ctx.event, key, pattern),
+            Self::ContainsDisplayName => match (ctx.display_name, body(&ctx.event)) {
+                (Some(name), Some(body)) if !name.is_empty() => {
+                    word_regex(&regex::escape(name)).map_or(false, |re| re.is_match(body))
+                }
+                _ => false,
+            },
+            Self::RoomMemberCount { is } => member_count_matches(is, ctx.member_count),
+            // Only the `room` key, for `@room`, is defined by the spec
+            Self::SenderNotificationPermission { key } => {
+                key == "room" && ctx.sender_power_level >= ctx.room_notification_level
+            }
+            Self::Unknown => false,
+        }
+    }
+}
+
+fn body(event: &JsonValue) -> Option<&str> {
+    event["content"]["body"].as_str()
+}
+
+fn event_match(event: &JsonValue, key: &str, pattern: &str) -> bool {
+    let value = key
+        .split('.')
+        .try_fold(event, |value, field| value.get(field))
+        .and_then(JsonValue::as_str);
+    let value = match value {
+        Some(value) => value,
+        None => return false,
+    };
+
+    let glob = glob_to_regex(pattern);
+    // The body matches when any of its words does, everything else has to match whole
+    let re = if key == "content.body" {
+        word_regex(&glob)
+    } else {
+        Regex::new(&format!("(?i)^{}$", glob)).ok()
+    };
+
+    re.map_or(false, |re| re.is_match(value))
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => String::from(".*?"),
+            '?' => String::from("."),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+fn word_regex(re: &str) -> Option<Regex> {
+    Regex::new(&format!(r"(?i)(^|\W){}(\W|$)", re)).ok()
+}
+
+/// Checks `count` against conditions like `2`, `==2`, `<10` or `>=3`
+fn member_count_matches(is: &str, count: usize) -> bool {
+    let split = is.find(|c: char| c.is_ascii_digit()).unwrap_or(is.len());
+    let (op, number) = is.split_at(split);
+    let number = match number.parse::<usize>() {
+        Ok(number) => number,
+        Err(_) => return false,
+    };
+
+    match op {
+        "" | "==" => count == number,
+        "<" => count < number,
+        ">" => count > number,
+        "<=" => count <= number,
+        ">=" => count >= number,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(body: &str) -> PushContext<'static> {
+        PushContext {
+            event: json!({
+                "type": "m.room.message",
+                "room_id": "!room:example.org",
+                "sender": "@alice:example.org",
+                "content": { "msgtype": "m.text", "body": body },
+            }),
+            display_name: Some("Bob"),
+            member_count: 5,
+            sender_power_level: 0,
+            room_notification_level: 50,
+        }
+    }
+
+    fn ruleset(rules: JsonValue) -> Ruleset {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    fn condition(cond: JsonValue) -> PushCondition {
+        serde_json::from_value(cond).unwrap()
+    }
+
+    #[test]
+    fn event_match_body() {
+        let event = ctx("").event;
+        let matches = |body: &str, pattern: &str| {
+            let mut event = event.clone();
+            event["content"]["body"] = json!(body);
+            event_match(&event, "content.body", pattern)
+        };
+
+        // Any word of the body can match, whatever the case
+        assert!(matches("hello fractal", "Fractal"));
+        assert!(matches("fractal, hello", "fractal"));
+        assert!(!matches("fractals", "fractal"));
+        assert!(!matches("unfractal", "fractal"));
+
+        assert!(matches("fractals", "fractal*"));
+        assert!(matches("some fractal-gtk", "fractal*"));
+        assert!(matches("cat", "c?t"));
+        assert!(!matches("coat", "c?t"));
+        // The pattern isn't taken as a regex
+        assert!(!matches("fractal", "fr.ctal"));
+    }
+
+    #[test]
+    fn event_match_other_keys() {
+        let event = ctx("hello").event;
+
+        // Other keys have to match the whole value
+        assert!(event_match(&event, "type", "m.room.message"));
+        assert!(event_match(&event, "type", "m.room.*"));
+        assert!(!event_match(&event, "type", "m.room"));
+        assert!(event_match(&event, "content.msgtype", "m.text"));
+        assert!(!event_match(&event, "content.missing", "*"));
+    }
+
+    #[test]
+    fn contains_display_name() {
+        let cond = condition(json!({ "kind": "contains_display_name" }));
+
+        assert!(cond.matches(&ctx("hi bob!")));
+        assert!(cond.matches(&ctx("Bob: hi")));
+        assert!(!cond.matches(&ctx("hi bobby")));
+
+        let mut no_name = ctx("hi bob");
+        no_name.display_name = None;
+        assert!(!cond.matches(&no_name));
+        no_name.display_name = Some("");
+        assert!(!cond.matches(&no_name));
+    }
+
+    #[test]
+    fn room_member_count() {
+        assert!(member_count_matches("2", 2));
+        assert!(member_count_matches("==2", 2));
+        assert!(!member_count_matches("==2", 3));
+        assert!(member_count_matches("<10", 9));
+        assert!(!member_count_matches("<10", 10));
+        assert!(member_count_matches(">1", 2));
+        assert!(member_count_matches("<=10", 10));
+        assert!(member_count_matches(">=3", 3));
+        assert!(!member_count_matches(">=3", 2));
+        assert!(!member_count_matches("!=2", 3));
+        assert!(!member_count_matches("two", 2));
+
+        let cond = condition(json!({ "kind": "room_member_count", "is": "<10" }));
+        assert!(cond.matches(&ctx("hello")));
+    }
+
+    #[test]
+    fn sender_notification_permission() {
+        let cond = condition(json!({ "kind": "sender_notification_permission", "key": "room" }));
+
+        let mut ctx = ctx("@room hello");
+        assert!(!cond.matches(&ctx));
+        ctx.sender_power_level = 50;
+        assert!(cond.matches(&ctx));
+
+        let other = condition(json!({ "kind": "sender_notification_permission", "key": "user" }));
+        assert!(!other.matches(&ctx));
+    }
+
+    #[test]
+    fn unknown_condition() {
+        let cond = condition(json!({ "kind": "something_new", "value": 1 }));
+        assert!(!cond.matches(&ctx("hello")));
+    }
+
+    #[test]
+    fn priority_order() {
+        let notify = json!(["notify"]);
+        let dont_notify = json!(["dont_notify"]);
+        let highlight = json!(["notify", { "set_tweak": "highlight" }]);
+        let sound = json!(["notify", { "set_tweak": "sound", "value": "default" }]);
+        let rules = ruleset(json!({
+            "override": [{
+                "rule_id": ".m.rule.muted",
+                "enabled": true,
+                "actions": dont_notify,
+                "conditions": [{ "kind": "event_match", "key": "content.body", "pattern": "mute" }],
+            }],
+            "content": [{ "rule_id": "kw", "enabled": true, "actions": highlight, "pattern": "keyword" }],
+            "room": [{ "rule_id": "!room:example.org", "enabled": true, "actions": dont_notify }],
+            "sender": [{ "rule_id": "@alice:example.org", "enabled": true, "actions": sound }],
+            "underride": [{ "rule_id": ".m.rule.message", "enabled": true, "actions": notify, "conditions": [] }],
+        }));
+
+        // Override comes before content
+        assert_eq!(rules.evaluate(&ctx("mute keyword")), PushActions::default());
+        // Content comes before room
+        let actions = rules.evaluate(&ctx("a keyword"));
+        assert!(actions.notify && actions.highlight);
+        // Room comes before sender and underride
+        assert_eq!(rules.evaluate(&ctx("hello")), PushActions::default());
+
+        let mut rules = rules;
+        rules.room.clear();
+        // Sender comes before underride
+        let actions = rules.evaluate(&ctx("hello"));
+        assert!(actions.notify && actions.sound && !actions.highlight);
+
+        rules.sender.clear();
+        let actions = rules.evaluate(&ctx("hello"));
+        assert!(actions.notify && !actions.sound);
+
+        rules.underride.clear();
+        assert_eq!(rules.evaluate(&ctx("hello")), PushActions::default());
+    }
+
+    #[test]
+    fn disabled_rules_are_skipped() {
+        let rules = ruleset(json!({
+            "override": [{
+                "rule_id": ".m.rule.master",
+                "enabled": false,
+                "actions": ["dont_notify"],
+                "conditions": [],
+            }],
+            "content": [{
+                "rule_id": "kw",
+                "enabled": false,
+                "actions": ["notify", { "set_tweak": "highlight" }],
+                "pattern": "keyword",
+            }],
+            "underride": [{ "rule_id": ".m.rule.message", "enabled": true, "actions": ["notify"] }],
+        }));
+
+        let actions = rules.evaluate(&ctx("a keyword"));
+        assert!(actions.notify);
+        assert!(!actions.highlight);
+    }
+
+    #[test]
+    fn tweaks_without_notify() {
+        let rules = ruleset(json!({
+            "underride": [{
+                "rule_id": ".m.rule.message",
+                "enabled": true,
+                "actions": ["dont_notify", { "set_tweak": "highlight" }],
+            }],
+        }));
+
+        assert_eq!(rules.evaluate(&ctx("hello")), PushActions::default());
+    }
+
+    #[test]
+    fn muted_rooms() {
+        let rules = ruleset(json!({
+            "override": [
+                {
+                    "rule_id": "!muted:example.org",
+                    "enabled": true,
+                    "actions": ["dont_notify"],
+                    "conditions": [{ "kind": "event_match", "key": "room_id", "pattern": "!muted:example.org" }],
+                },
+                { "rule_id": "!disabled:example.org", "enabled": false, "actions": ["dont_notify"] },
+                { "rule_id": "!loud:example.org", "enabled": true, "actions": ["notify"] },
+                { "rule_id": ".m.rule.suppress_notices", "enabled": true, "actions": ["dont_notify"] },
+            ],
+            "room": [{ "rule_id": "!mentions:example.org", "enabled": true, "actions": ["dont_notify"] }],
+        }));
+
+        let muted: HashSet<RoomId> = vec![RoomId::try_from("!muted:example.org").unwrap()]
+            .into_iter()
+            .collect();
+        assert_eq!(rules.muted_rooms(), muted);
+    }
+}
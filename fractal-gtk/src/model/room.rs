@@ -1,3 +1,4 @@
+use crate::model::draft::Draft;
 use crate::model::member::Member;
 use crate::model::member::MemberList;
 use crate::model::message::Message;
@@ -11,12 +12,13 @@ use matrix_sdk::directory::PublicRoomsChunk;
 use matrix_sdk::events::{
     room::member::{MemberEventContent, MembershipState},
     AnyBasicEvent, AnyBasicEventContent, AnyMessageEventContent, AnyStrippedStateEvent,
-    AnySyncEphemeralRoomEvent, AnySyncRoomEvent, AnySyncStateEvent, SyncStateEvent,
+    AnySyncEphemeralRoomEvent, AnySyncRoomEvent, AnySyncStateEvent, EventType, SyncStateEvent,
 };
-use matrix_sdk::identifiers::{EventId, RoomAliasId, RoomId, UserId};
+use matrix_sdk::identifiers::{EventId, RoomAliasId, RoomId, RoomVersionId, ServerName, UserId};
 use matrix_sdk::{deserialized_responses::SyncResponse, identifiers::MxcUri};
 use serde::{Deserialize, Serialize};
 use serde_json::value::Value;
+use serde_json::Map as JsonObject;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 
@@ -56,14 +58,6 @@ impl RoomMembership {
             false
         }
     }
-
-    pub fn match_joined_tag(&self, tag: RoomTag) -> bool {
-        if let RoomMembership::Joined(this_tag) = self {
-            this_tag == &tag
-        } else {
-            false
-        }
-    }
 }
 
 impl Default for RoomMembership {
@@ -78,16 +72,143 @@ pub enum Reason {
     Kicked(String, UserId),
 }
 
-// This needs to opt-out of the lint to keep consistency
-#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum RoomTag {
     None,
     Favourite,
     LowPriority,
+    /// The full name of the tag, with its `u.` prefix
     Custom(String),
 }
 
+impl RoomTag {
+    /// Splits the tags of the room into the built-in one it's grouped by, favourites going
+    /// before low priority, and the custom ones, ordered by their `order` and then by name
+    pub fn from_tags<'a>(
+        tags: impl Iterator<Item = (&'a String, Option<f64>)>,
+    ) -> (Self, Vec<String>) {
+        let mut tag = RoomTag::None;
+        let mut custom = vec![];
+
+        for (name, order) in tags {
+            match name.as_str() {
+                "m.favourite" => tag = RoomTag::Favourite,
+                "m.lowpriority" if tag == RoomTag::None => tag = RoomTag::LowPriority,
+                name if RoomTag::is_custom(name) => {
+                    custom.push((order.unwrap_or(f64::MAX), name.to_string()))
+                }
+                _ => {}
+            }
+        }
+
+        custom.sort_by(|(a_order, a_name), (b_order, b_name)| {
+            a_order
+                .partial_cmp(b_order)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a_name.cmp(b_name))
+        });
+
+        (tag, custom.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Favourites and low priority are the sections of the room list, a room is in one
+    /// of them at most
+    pub fn is_builtin(&self) -> bool {
+        matches!(self, RoomTag::Favourite | RoomTag::LowPriority)
+    }
+
+    /// Only the tags created by users, the other `m.` ones are reserved by the spec
+    pub fn is_custom(name: &str) -> bool {
+        name.starts_with("u.") && name.len() > 2
+    }
+
+    /// The name of the tag sent to the server
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            RoomTag::None => None,
+            RoomTag::Favourite => Some("m.favourite"),
+            RoomTag::LowPriority => Some("m.lowpriority"),
+            RoomTag::Custom(name) => Some(name.as_str()),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "m.favourite" => RoomTag::Favourite,
+            "m.lowpriority" => RoomTag::LowPriority,
+            name if RoomTag::is_custom(name) => RoomTag::Custom(name.to_string()),
+            _ => RoomTag::None,
+        }
+    }
+
+    /// The name shown to the user for a custom tag
+    pub fn display_name(name: &str) -> &str {
+        name.strip_prefix("u.").unwrap_or(name)
+    }
+}
+
+/// The space of a `m.space.parent` event, and whether the event is valid: the events
+/// without servers remove the room from the space
+pub fn space_parent(
+    state_key: &str,
+    content: &JsonObject<String, Value>,
+) -> Option<(RoomId, bool)> {
+    let parent_id = RoomId::try_from(state_key).ok()?;
+    let valid = content
+        .get("via")
+        .and_then(Value::as_array)
+        .map_or(false, |via| !via.is_empty());
+
+    Some((parent_id, valid))
+}
+
+/// A room in a space, from the `m.space.child` event of the space
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaceChild {
+    pub room_id: RoomId,
+    /// The servers to join the room through
+    pub via: Vec<Box<ServerName>>,
+    pub order: Option<String>,
+}
+
+impl SpaceChild {
+    /// Returns `None` for the events without servers, they remove the room from the space
+    pub fn from_content(state_key: &str, content: &JsonObject<String, Value>) -> Option<Self> {
+        let room_id = RoomId::try_from(state_key).ok()?;
+        let via: Vec<Box<ServerName>> = content
+            .get("via")?
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .filter_map(|server| server.try_into().ok())
+            .collect();
+        if via.is_empty() {
+            return None;
+        }
+
+        let order = content
+            .get("order")
+            .and_then(Value::as_str)
+            .map(String::from);
+
+        Some(Self {
+            room_id,
+            via,
+            order,
+        })
+    }
+
+    /// The children with an order go first, the others are sorted by their ID
+    pub fn sort(children: &mut Vec<Self>) {
+        children.sort_by(|a, b| match (&a.order, &b.order) {
+            (Some(a_order), Some(b_order)) => a_order.cmp(b_order),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.room_id.as_str().cmp(b.room_id.as_str()),
+        });
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(try_from = "&str")]
 struct DirectType;
@@ -124,16 +245,45 @@ pub struct Room {
     pub notifications: u64,
     pub highlight: u64,
     pub messages: MessageList,
+    /// The room list section of the joined rooms is in their membership
     pub membership: RoomMembership,
+    /// The custom tags of the room, in their order
+    pub user_tags: Vec<String>,
     pub direct: bool,
+    pub encrypted: bool,
     pub prev_batch: Option<String>,
     pub typing_users: Vec<Member>,
     pub language: Option<String>,
+    /// The unsent message of the room, as synced from the other devices
+    /// or kept here to be shown in the room list
+    pub draft: Option<Draft>,
+    /// Whether the links of the messages shouldn't be previewed, as set in
+    /// the `org.matrix.room.preview_urls` account data of the room
+    pub disable_url_previews: Option<bool>,
 
     /// Hashmap with the room users power levels
     /// the key will be the userid and the value will be the level
     pub admins: HashMap<UserId, i64>,
     pub default_power_level: i64,
+    /// The level needed to notify the whole room with `@room`
+    pub notifications_power_level: i64,
+    /// The level needed to send the `m.room.tombstone` event upgrading the room
+    pub upgrade_power_level: i64,
+    /// The level needed to add rooms to the space
+    pub space_child_power_level: i64,
+
+    pub version: Option<RoomVersionId>,
+    /// The `type` of the `m.room.create` event, `m.space` for the spaces
+    pub room_type: Option<String>,
+    /// The room this one was upgraded from
+    pub predecessor: Option<RoomId>,
+    /// The room replacing this one, once it has been upgraded
+    pub successor: Option<RoomId>,
+
+    /// The rooms in the space, when the room is one
+    pub space_children: Vec<SpaceChild>,
+    /// The spaces the room says it belongs to
+    pub space_parents: Vec<RoomId>,
 }
 
 impl Room {
@@ -152,15 +302,32 @@ impl Room {
             notifications: Default::default(),
             highlight: Default::default(),
             messages: Default::default(),
+            user_tags: Default::default(),
             direct: Default::default(),
+            encrypted: Default::default(),
             prev_batch: Default::default(),
             typing_users: Default::default(),
             language: Default::default(),
+            draft: Default::default(),
+            disable_url_previews: Default::default(),
             admins: Default::default(),
             default_power_level: -1,
+            notifications_power_level: 50,
+            upgrade_power_level: 100,
+            space_child_power_level: 50,
+            version: Default::default(),
+            room_type: Default::default(),
+            predecessor: Default::default(),
+            successor: Default::default(),
+            space_children: Default::default(),
+            space_parents: Default::default(),
         }
     }
 
+    pub fn is_space(&self) -> bool {
+        self.room_type.as_deref() == Some("m.space")
+    }
+
     pub fn from_sync_response(response: &SyncResponse, user_id: UserId) -> Vec<Self> {
         // getting the list of direct rooms
         let direct: HashSet<RoomId> = response
@@ -180,14 +347,18 @@ impl Room {
         let joined_rooms = response.rooms.join.iter().map(|(k, room)| {
             let stevents: Vec<_> = room.state.events.iter().collect();
             let dataevs: Vec<_> = room.account_data.events.iter().collect();
-            let room_tag = dataevs
+            let (room_tag, user_tags) = dataevs
                 .iter()
                 .find_map(|event| match event {
-                    AnyBasicEvent::Tag(ev) => ev.content.tags.get("m.favourite"),
+                    AnyBasicEvent::Tag(ev) => Some(RoomTag::from_tags(
+                        ev.content
+                            .tags
+                            .iter()
+                            .map(|(name, info)| (name, info.order)),
+                    )),
                     _ => None,
                 })
-                .and(Some(RoomTag::Favourite))
-                .unwrap_or(RoomTag::None);
+                .unwrap_or((RoomTag::None, vec![]));
 
             let members: MemberList = stevents
                 .iter()
@@ -270,6 +441,80 @@ impl Room {
                     })
                     .last()
                     .unwrap_or(-1),
+                notifications_power_level: stevents
+                    .iter()
+                    .filter_map(|event| match event {
+                        AnySyncStateEvent::RoomPowerLevels(ev) => {
+                            Some(ev.content.notifications.room.into())
+                        }
+                        _ => None,
+                    })
+                    .last()
+                    .unwrap_or(50),
+                upgrade_power_level: stevents
+                    .iter()
+                    .filter_map(|event| match event {
+                        AnySyncStateEvent::RoomPowerLevels(ev) => Some(
+                            ev.content
+                                .events
+                                .get(&EventType::RoomTombstone)
+                                .copied()
+                                .unwrap_or(ev.content.state_default)
+                                .into(),
+                        ),
+                        _ => None,
+                    })
+                    .last()
+                    .unwrap_or(100),
+                space_child_power_level: stevents
+                    .iter()
+                    .filter_map(|event| match event {
+                        AnySyncStateEvent::RoomPowerLevels(ev) => Some(
+                            ev.content
+                                .events
+                                .get(&EventType::from("m.space.child"))
+                                .copied()
+                                .unwrap_or(ev.content.state_default)
+                                .into(),
+                        ),
+                        _ => None,
+                    })
+                    .last()
+                    .unwrap_or(50),
+                version: stevents.iter().find_map(|event| match event {
+                    AnySyncStateEvent::RoomCreate(ev) => Some(ev.content.room_version.clone()),
+                    _ => None,
+                }),
+                predecessor: stevents
+                    .iter()
+                    .find_map(|event| match event {
+                        AnySyncStateEvent::RoomCreate(ev) => ev.content.predecessor.as_ref(),
+                        _ => None,
+                    })
+                    .map(|predecessor| predecessor.room_id.clone()),
+                successor: stevents.iter().find_map(|event| match event {
+                    AnySyncStateEvent::RoomTombstone(ev) => {
+                        Some(ev.content.replacement_room.clone())
+                    }
+                    _ => None,
+                }),
+                encrypted: stevents
+                    .iter()
+                    .any(|event| matches!(event, AnySyncStateEvent::RoomEncryption(_))),
+                space_children: space_children(&stevents),
+                space_parents: stevents
+                    .iter()
+                    .filter_map(|event| match event {
+                        AnySyncStateEvent::Custom(ev)
+                            if ev.content.event_type == "m.space.parent" =>
+                        {
+                            space_parent(&ev.state_key, &ev.content.data)
+                                .filter(|(_, valid)| *valid)
+                                .map(|(parent_id, _)| parent_id)
+                        }
+                        _ => None,
+                    })
+                    .collect(),
                 members,
                 language: dataevs
                     .iter()
@@ -286,6 +531,25 @@ impl Room {
                         _ => None,
                     })
                     .map(String::from),
+                draft: dataevs.iter().find_map(|event| match event {
+                    AnyBasicEvent::Custom(ev)
+                        if ev.content.event_type == "org.gnome.fractal.draft" =>
+                    {
+                        serde_json::to_value(&ev.content.data)
+                            .and_then(serde_json::from_value)
+                            .ok()
+                    }
+                    _ => None,
+                }),
+                disable_url_previews: dataevs.iter().find_map(|event| match event {
+                    AnyBasicEvent::Custom(ev)
+                        if ev.content.event_type == "org.matrix.room.preview_urls" =>
+                    {
+                        ev.content.data.get("disable").and_then(|v| v.as_bool())
+                    }
+                    _ => None,
+                }),
+                user_tags,
                 ..Self::new(k.clone(), RoomMembership::Joined(room_tag))
             };
 
@@ -339,10 +603,22 @@ impl Room {
                 r.take_new_message(msg);
             }
 
-            if let Some(event_id) = room.ephemeral.events.iter().find_map(|event| match event {
-                AnySyncEphemeralRoomEvent::FullyRead(ev) => Some(ev.content.event_id.clone()),
-                _ => None,
-            }) {
+            // The read marker is in the account data of the room, the SDK doesn't know it
+            // belongs there so it's left as a custom event
+            if let Some(event_id) = room
+                .account_data
+                .events
+                .iter()
+                .find_map(|event| match event {
+                    AnyBasicEvent::Custom(ev) if ev.content.event_type == "m.fully_read" => ev
+                        .content
+                        .data
+                        .get("event_id")
+                        .and_then(|id| id.as_str())
+                        .and_then(|id| EventId::try_from(id).ok()),
+                    _ => None,
+                })
+            {
                 if let Some(mut msg) = r.messages.get(&event_id).cloned() {
                     msg.receipt.insert(user_id.clone(), 0);
                     r.take_new_message(msg);
@@ -434,6 +710,26 @@ impl Room {
                         AnyStrippedStateEvent::RoomTopic(ev) => Some(ev.content.topic.clone()),
                         _ => None,
                     }),
+                    encrypted: stevents
+                        .iter()
+                        .any(|event| matches!(event, AnyStrippedStateEvent::RoomEncryption(_))),
+                    // Only the members sent with the invite, usually the inviter
+                    members: stevents
+                        .iter()
+                        .filter_map(|event| match event {
+                            AnyStrippedStateEvent::RoomMember(ev)
+                                if ev.content.membership == MembershipState::Join =>
+                            {
+                                Some(Member {
+                                    uid: ev.sender.clone(),
+                                    alias: ev.content.displayname.clone(),
+                                    avatar: ev.content.avatar_url.clone().map(Either::Left),
+                                })
+                            }
+                            _ => None,
+                        })
+                        .map(|m| (m.uid.clone(), m))
+                        .collect(),
                     direct: direct.contains(&k),
                     ..Self::new(k.clone(), RoomMembership::Invited(inv_sender))
                 })
@@ -448,6 +744,14 @@ impl Room {
             .collect()
     }
 
+    /// The last message shown in the history, the edits and the deleted ones aside
+    pub fn last_message(&self) -> Option<&Message> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|msg| msg.replace.is_none() && !msg.redacted)
+    }
+
     /// Inserts the given message into the room.
     pub fn take_new_message(&mut self, msg: Message) {
         self.messages.add(msg);
@@ -503,6 +807,20 @@ fn member_from_stripped_event<'a>(
     }
 }
 
+fn space_children(events: &[&AnySyncStateEvent]) -> Vec<SpaceChild> {
+    let mut children: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            AnySyncStateEvent::Custom(ev) if ev.content.event_type == "m.space.child" => {
+                SpaceChild::from_content(&ev.state_key, &ev.content.data)
+            }
+            _ => None,
+        })
+        .collect();
+    SpaceChild::sort(&mut children);
+    children
+}
+
 fn room_name_from_members(members: &[&str]) -> Option<String> {
     match members.len() {
         0 => None,
@@ -523,3 +841,44 @@ fn parse_room_member(msg: &SyncStateEvent<MemberEventContent>) -> Option<Member>
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn message(room: &Room, id: &str, minute: u32) -> Message {
+        let mut msg = Message::new(
+            room.id.clone(),
+            UserId::try_from("@alice:example.org").unwrap(),
+            id.to_string(),
+            String::from("m.text"),
+            Some(EventId::try_from(format!("${}:example.org", id)).unwrap()),
+        );
+        msg.date = Local.ymd(2021, 3, 10).and_hms(12, minute, 0);
+        msg
+    }
+
+    #[test]
+    fn last_message() {
+        let room_id = RoomId::try_from("!room:example.org").unwrap();
+        let mut room = Room::new(room_id, RoomMembership::Joined(RoomTag::None));
+        assert!(room.last_message().is_none());
+
+        let first = message(&room, "first", 1);
+        room.take_new_message(message(&room, "second", 2));
+        room.take_new_message(first);
+        assert_eq!(room.last_message().map(|m| m.body.as_str()), Some("second"));
+
+        // The edits aren't shown in the history, they change the message they replace
+        let mut edit = message(&room, "edit", 3);
+        edit.replace = Some(EventId::try_from("$second:example.org").unwrap());
+        room.take_new_message(edit);
+        assert_eq!(room.last_message().map(|m| m.body.as_str()), Some("second"));
+
+        let mut redacted = message(&room, "second", 2);
+        redacted.redacted = true;
+        room.take_new_message(redacted);
+        assert_eq!(room.last_message().map(|m| m.body.as_str()), Some("first"));
+    }
+}
@@ -56,6 +56,12 @@ pub trait PasswordStorage {
     }
 }
 
+/// Returns the key used to encrypt the local cache, generating and storing a new one in the
+/// Secret Service if there isn't one yet.
+pub fn get_cache_key() -> Result<Vec<u8>, Error> {
+    ss_storage::get_cache_key()
+}
+
 mod ss_storage {
     use std::collections::HashMap;
     use std::convert::{TryFrom, TryInto};
@@ -139,6 +145,33 @@ mod ss_storage {
         Ok((token, uid))
     }
 
+    pub fn get_cache_key() -> Result<Vec<u8>, Error> {
+        let collection = get_default_collection_unlocked()?;
+        let allpass = collection.get_all_items()?;
+        let key = "fractal-cache-key";
+
+        let stored = allpass
+            .iter()
+            .find(|x| x.get_label().unwrap_or_default() == key);
+
+        if let Some(p) = stored {
+            p.unlock()?;
+            return Ok(p.get_secret()?);
+        }
+
+        // create new item
+        let secret: [u8; 32] = rand::random();
+        collection.create_item(
+            key,                        // label
+            HashMap::new(),             // properties
+            &secret,                    // secret
+            true,                       // replace item with same attributes
+            "application/octet-stream", // secret content type
+        )?;
+
+        Ok(secret.to_vec())
+    }
+
     pub fn store_pass(
         username: String,
         password: String,
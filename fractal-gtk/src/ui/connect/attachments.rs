@@ -0,0 +1,29 @@
+use gio::prelude::*;
+use gtk::prelude::*;
+use std::path::PathBuf;
+
+use crate::app::AppRuntime;
+use crate::ui::UI;
+
+pub fn connect(ui: &UI, app_runtime: AppRuntime) {
+    let room_view = ui
+        .builder
+        .get_object::<gtk::Box>("focused_room")
+        .expect("Can't find focused_room in ui file.");
+
+    // Files dropped on the room view go to the attachment tray
+    room_view.drag_dest_set(gtk::DestDefaults::ALL, &[], gdk::DragAction::COPY);
+    room_view.drag_dest_add_uri_targets();
+
+    room_view.connect_drag_data_received(move |_, _, _, _, data, _, _| {
+        let paths: Vec<PathBuf> = data
+            .get_uris()
+            .iter()
+            .filter_map(|uri| gio::File::new_for_uri(uri).get_path())
+            .collect();
+
+        if !paths.is_empty() {
+            app_runtime.update_state_with(move |state| state.add_attachments(paths));
+        }
+    });
+}
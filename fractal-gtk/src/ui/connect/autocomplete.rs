@@ -25,6 +25,7 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
         ui.sventry.view.clone(),
         popover,
         listbox,
+        ui.sventry.mentions.clone(),
     )
     .connect();
 }
@@ -1,7 +1,5 @@
 use glib::clone;
 
-use crate::util::i18n::i18n;
-
 use gtk::prelude::*;
 use libhandy::prelude::*;
 
@@ -45,45 +43,25 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
     ui.builder
         .expose_object::<libhandy::Clamp>("directory_clamp", &clamp);
 
-    let directory_choice_label = ui
-        .builder
-        .get_object::<gtk::Label>("directory_choice_label")
-        .expect("Can't find directory_choice_label in ui file.");
-
-    let default_matrix_server_radio = ui
-        .builder
-        .get_object::<gtk::RadioButton>("default_matrix_server_radio")
-        .expect("Can't find default_matrix_server_radio in ui file.");
-
-    let other_protocol_radio = ui
-        .builder
-        .get_object::<gtk::RadioButton>("other_protocol_radio")
-        .expect("Can't find other_protocol_radio in ui file.");
-
-    let protocol_combo = ui
+    let server_entry = ui
         .builder
-        .get_object::<gtk::ComboBox>("protocol_combo")
-        .expect("Can't find protocol_combo in ui file.");
+        .get_object::<gtk::Entry>("directory_server_entry")
+        .expect("Can't find directory_server_entry in ui file.");
 
-    let protocol_model = ui
+    let add_server_button = ui
         .builder
-        .get_object::<gtk::ListStore>("protocol_model")
-        .expect("Can't find protocol_model in ui file.");
+        .get_object::<gtk::Button>("directory_add_server_button")
+        .expect("Can't find directory_add_server_button in ui file.");
 
-    let other_homeserver_radio = ui
+    let network_list = ui
         .builder
-        .get_object::<gtk::RadioButton>("other_homeserver_radio")
-        .expect("Can't find other_homeserver_radio in ui file.");
+        .get_object::<gtk::ListBox>("directory_network_list")
+        .expect("Can't find directory_network_list in ui file.");
 
-    let other_homeserver_url_entry = ui
+    let popover = ui
         .builder
-        .get_object::<gtk::Entry>("other_homeserver_url_entry")
-        .expect("Can't find other_homeserver_url_entry in ui file.");
-
-    let other_homeserver_url = ui
-        .builder
-        .get_object::<gtk::EntryBuffer>("other_homeserver_url")
-        .expect("Can't find other_homeserver_url in ui file.");
+        .get_object::<gtk::Popover>("server_chooser_popover")
+        .expect("Can't find server_chooser_popover in ui file.");
 
     let scroll = ui
         .builder
@@ -96,83 +74,30 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
         }
     }));
 
-    q.connect_activate(move |_| {
+    q.connect_activate(clone!(@strong app_runtime => move |_| {
         app_runtime.update_state_with(|state| {
             state.directory_pagination = RoomSearchPagination::Initial;
             state.search_rooms();
         });
-    });
-
-    default_matrix_server_radio.connect_toggled(clone!(
-    @strong directory_choice_label,
-    @strong default_matrix_server_radio,
-    @strong protocol_combo,
-    @strong other_homeserver_url_entry
-    => move |_| {
-        if default_matrix_server_radio.get_active() {
-            protocol_combo.set_sensitive(false);
-            other_homeserver_url_entry.set_sensitive(false);
-        }
-
-        directory_choice_label.set_text(&i18n("Default Matrix Server"));
     }));
 
-    other_protocol_radio.connect_toggled(clone!(
-    @strong directory_choice_label,
-    @strong other_protocol_radio,
-    @strong protocol_combo,
-    @strong protocol_model,
-    @strong other_homeserver_url_entry
-    => move |_| {
-        if other_protocol_radio.get_active() {
-            protocol_combo.set_sensitive(true);
-            other_homeserver_url_entry.set_sensitive(false);
-        }
-
-        let active = protocol_combo.get_active().map_or(-1, |uint| uint as i32);
-        let protocol: String = match protocol_model.iter_nth_child(None, active) {
-            Some(it) => {
-                let v = protocol_model.get_value(&it, 0);
-                v.get().unwrap().unwrap()
-            }
-            None => String::new(),
-        };
+    network_list.connect_row_activated(move |_, _| popover.popdown());
 
-        directory_choice_label.set_text(&protocol);
+    server_entry.connect_changed(clone!(@strong add_server_button => move |entry| {
+        add_server_button.set_sensitive(!entry.get_text().trim().is_empty());
     }));
 
-    protocol_combo.connect_changed(clone!(
-    @strong directory_choice_label,
-    @strong protocol_combo,
-    @strong protocol_model
-    => move |_| {
-        let active = protocol_combo.get_active().map_or(-1, |uint| uint as i32);
-        let protocol: String = match protocol_model.iter_nth_child(None, active) {
-            Some(it) => {
-                let v = protocol_model.get_value(&it, 0);
-                v.get().unwrap().unwrap()
-            }
-            None => String::new(),
-        };
-
-        directory_choice_label.set_text(&protocol);
+    server_entry.connect_activate(clone!(@strong add_server_button => move |_| {
+        let _ = add_server_button.emit("clicked", &[]);
     }));
 
-    other_homeserver_radio.connect_toggled(clone!(
-    @strong other_homeserver_radio,
-    @strong protocol_combo,
-    @strong other_homeserver_url_entry
-    => move |_| {
-        if other_homeserver_radio.get_active() {
-            protocol_combo.set_sensitive(false);
-            other_homeserver_url_entry.set_sensitive(true);
+    add_server_button.connect_clicked(clone!(@strong server_entry => move |_| {
+        let server = server_entry.get_text().trim().to_string();
+        if server.is_empty() {
+            return;
         }
-    }));
 
-    other_homeserver_url_entry.connect_changed(clone!(
-    @strong directory_choice_label,
-    @strong other_homeserver_url
-    => move |_| {
-        directory_choice_label.set_text(&other_homeserver_url.get_text());
+        server_entry.set_text("");
+        app_runtime.update_state_with(move |state| state.add_directory_server(server));
     }));
 }
@@ -7,36 +7,6 @@ use std::sync::{Arc, Mutex};
 use crate::app::AppRuntime;
 use crate::ui::UI;
 
-pub fn connect_dialog(ui: &UI, app_runtime: AppRuntime) {
-    let dialog = ui
-        .builder
-        .get_object::<gtk::MessageDialog>("invite_dialog")
-        .expect("Can't find invite_dialog in ui file.");
-    let accept = ui
-        .builder
-        .get_object::<gtk::Button>("invite_accept")
-        .expect("Can't find invite_accept in ui file.");
-    let reject = ui
-        .builder
-        .get_object::<gtk::Button>("invite_reject")
-        .expect("Can't find invite_reject in ui file.");
-
-    reject.connect_clicked(clone!(@strong dialog, @strong app_runtime => move |_| {
-        app_runtime.update_state_with(|state| state.accept_inv(false));
-        dialog.hide();
-    }));
-    dialog.connect_delete_event(clone!(@strong dialog, @strong app_runtime => move |_, _| {
-        app_runtime.update_state_with(|state| state.accept_inv(false));
-        dialog.hide();
-        glib::signal::Inhibit(true)
-    }));
-
-    accept.connect_clicked(clone!(@strong dialog => move |_| {
-        app_runtime.update_state_with(|state| state.accept_inv(true));
-        dialog.hide();
-    }));
-}
-
 pub fn connect_user(ui: &UI, app_runtime: AppRuntime) {
     let cancel = ui
         .builder
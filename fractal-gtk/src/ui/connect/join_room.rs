@@ -1,5 +1,7 @@
 use glib::clone;
+use glib::source::Continue;
 use gtk::prelude::*;
+use std::sync::{Arc, Mutex};
 
 use crate::app::AppRuntime;
 use crate::ui::UI;
@@ -21,6 +23,10 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
         .builder
         .get_object::<gtk::Entry>("join_room_name")
         .expect("Can't find join_room_name in ui file.");
+    let servers_label = ui
+        .builder
+        .get_object::<gtk::Label>("join_room_servers")
+        .expect("Can't find join_room_servers in ui file.");
 
     cancel.connect_clicked(clone!(@strong entry, @strong dialog => move |_| {
         dialog.hide();
@@ -37,11 +43,35 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
             app_runtime.update_state_with(|state| state.join_to_room());
     }));
 
-    entry.connect_activate(clone!(@strong dialog => move |_| {
+    entry.connect_activate(clone!(@strong dialog, @strong app_runtime => move |_| {
         dialog.hide();
         app_runtime.update_state_with(|state| state.join_to_room());
     }));
-    entry.connect_changed(clone!(@strong confirm => move |entry| {
-            confirm.set_sensitive(entry.get_buffer().get_length() > 0);
-    }));
+
+    // Resolves the alias once the user stops typing for 500ms
+    let source_id: Arc<Mutex<Option<glib::source::SourceId>>> = Arc::new(Mutex::new(None));
+    entry.connect_changed(
+        clone!(@strong confirm, @strong app_runtime => move |entry| {
+                confirm.set_sensitive(entry.get_buffer().get_length() > 0);
+                servers_label.hide();
+
+                if let Some(sid) = source_id.lock().unwrap().take() {
+                    glib::source::source_remove(sid);
+                }
+
+                let sid = glib::timeout_add_local(
+                    500,
+                    clone!(@strong entry, @strong source_id, @strong app_runtime => move || {
+                        let alias = entry.get_text().trim().to_string();
+                        if !alias.is_empty() {
+                            app_runtime.update_state_with(move |state| state.resolve_join_alias(alias));
+                        }
+
+                        *(source_id.lock().unwrap()) = None;
+                        Continue(false)
+                    }),
+                );
+                *(source_id.lock().unwrap()) = Some(sid);
+        }),
+    );
 }
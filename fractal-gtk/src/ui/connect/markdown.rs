@@ -22,6 +22,11 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
         .get_object("markdown_switch")
         .expect("Couldn't find markdown_switch in ui file.");
 
+    let toolbar_switch: gtk::Switch = ui
+        .builder
+        .get_object("formatting_toolbar_switch")
+        .expect("Couldn't find formatting_toolbar_switch in ui file.");
+
     let txt: gtk::Grid = ui
         .builder
         .get_object("tutorial_text_box")
@@ -32,6 +37,16 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
 
     md_popover_btn.set_popover(Some(&popover));
 
+    let toolbar = ui.sventry.toolbar.revealer.clone();
+    let toolbar_shown = util::get_formatting_toolbar_schema();
+    toolbar_switch.set_active(toolbar_shown);
+    toolbar.set_reveal_child(toolbar_shown);
+    toolbar_switch.connect_property_active_notify(move |switch| {
+        let shown = switch.get_active();
+        toolbar.set_reveal_child(shown);
+        util::set_formatting_toolbar_schema(shown);
+    });
+
     let md_active = util::get_markdown_schema();
     if md_active {
         app_runtime.update_state_with(|state| {
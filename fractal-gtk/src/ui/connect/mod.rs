@@ -1,3 +1,4 @@
+mod attachments;
 mod autocomplete;
 mod directory;
 mod headerbar;
@@ -20,6 +21,7 @@ impl UI {
         send::connect(self, app_runtime.clone());
         markdown::connect(self, app_runtime.clone());
         autocomplete::connect(self, app_runtime.clone());
+        attachments::connect(self, app_runtime.clone());
         language::connect(self, app_runtime.clone());
         directory::connect(self, app_runtime.clone());
         leave_room::connect(self, app_runtime.clone());
@@ -27,9 +29,9 @@ impl UI {
         join_room::connect(self, app_runtime.clone());
         self.account_settings
             .connect(&self.builder, &self.main_window, app_runtime.clone());
-        invite::connect_dialog(self, app_runtime.clone());
         invite::connect_user(self, app_runtime.clone());
         self.direct_chat_dialog.connect(app_runtime.clone());
+        self.preferences.connect(app_runtime.clone());
         roomlist_search::connect(self, app_runtime);
         swipeable_widgets::connect(self);
     }
@@ -50,6 +50,10 @@ pub fn connect(ui: &UI, app_runtime: AppRuntime) {
         Inhibit(false)
     }));
 
+    buffer.connect_changed(clone!(@strong app_runtime => move |_| {
+        app_runtime.update_state_with(|state| state.update_active_draft());
+    }));
+
     msg_entry.connect_paste_clipboard(move |_| {
         app_runtime.update_state_with(|state| state.ui.paste());
     });
@@ -1,16 +1,13 @@
+use super::room_preview::join_room;
 use super::UI;
-use crate::app::RUNTIME;
-use crate::appop::RoomSearchPagination;
-use crate::backend::room;
-use crate::backend::HandleError;
+use crate::appop::{DirectoryNetwork, RoomSearchPagination};
 use crate::model::room::Room;
 use crate::util::i18n::i18n;
 use crate::util::markup_text;
 use crate::widgets::{self, AvatarExt};
-use crate::APPOP;
 use gtk::prelude::*;
 use gtk::WidgetExt;
-use matrix_sdk::identifiers::{RoomAliasId, RoomIdOrAliasId};
+use matrix_sdk::identifiers::{RoomAliasId, ServerName};
 use matrix_sdk::thirdparty::ProtocolInstance;
 use matrix_sdk::Client as MatrixClient;
 
@@ -18,63 +15,55 @@ const AVATAR_SIZE: i32 = 60;
 const JOIN_BUTTON_WIDTH: i32 = 84;
 
 impl UI {
-    pub fn set_protocols(&self, protocols: Vec<ProtocolInstance>) {
-        let combo = self
+    /// Fills the network picker: our homeserver, the servers added by the user and the
+    /// networks bridged to our homeserver
+    pub fn set_directory_networks(
+        &self,
+        homeserver: &str,
+        servers: &[String],
+        protocols: &[ProtocolInstance],
+        selected: &DirectoryNetwork,
+    ) {
+        let network_list = self
+            .builder
+            .get_object::<gtk::ListBox>("directory_network_list")
+            .expect("Can't find directory_network_list in ui file.");
+        let directory_choice_label = self
             .builder
-            .get_object::<gtk::ListStore>("protocol_model")
-            .expect("Can't find protocol_model in ui file.");
-        combo.clear();
+            .get_object::<gtk::Label>("directory_choice_label")
+            .expect("Can't find directory_choice_label in ui file.");
 
-        for p in protocols {
-            combo.insert_with_values(None, &[0, 1], &[&p.desc, &p.network_id]);
+        for ch in network_list.get_children() {
+            network_list.remove(&ch);
+        }
+
+        let networks = std::iter::once((DirectoryNetwork::Homeserver, homeserver.to_string()))
+            .chain(
+                servers
+                    .iter()
+                    .map(|s| (DirectoryNetwork::Server(s.clone()), s.clone())),
+            )
+            .chain(protocols.iter().map(|p| {
+                (
+                    DirectoryNetwork::Protocol(p.network_id.clone()),
+                    p.desc.clone(),
+                )
+            }));
+
+        for (network, name) in networks {
+            let is_selected = &network == selected;
+            if is_selected {
+                directory_choice_label.set_text(&name);
+            }
+            network_list.add(&build_network_row(&network, &name, is_selected));
         }
     }
 
+    /// Clears the room list for a new search, returns the search term
     pub fn get_search_rooms_query(
         &self,
         directory_pagination: RoomSearchPagination,
-    ) -> (Option<String>, Option<String>, Option<String>) {
-        let other_protocol_radio = self
-            .builder
-            .get_object::<gtk::RadioButton>("other_protocol_radio")
-            .expect("Can't find other_protocol_radio in ui file.");
-
-        let protocol: Option<String> = if other_protocol_radio.get_active() {
-            let protocol_combo = self
-                .builder
-                .get_object::<gtk::ComboBox>("protocol_combo")
-                .expect("Can't find protocol_combo in ui file.");
-
-            let protocol_model = self
-                .builder
-                .get_object::<gtk::ListStore>("protocol_model")
-                .expect("Can't find protocol_model in ui file.");
-
-            let active = protocol_combo.get_active().map_or(-1, |uint| uint as i32);
-
-            protocol_model
-                .iter_nth_child(None, active)
-                .and_then(|it| protocol_model.get_value(&it, 1).get().ok()?)
-        } else {
-            None
-        };
-
-        let other_homeserver_radio = self
-            .builder
-            .get_object::<gtk::RadioButton>("other_homeserver_radio")
-            .expect("Can't find other_homeserver_radio in ui file.");
-
-        let other_homeserver_url = self
-            .builder
-            .get_object::<gtk::EntryBuffer>("other_homeserver_url")
-            .expect("Can't find other_homeserver_url in ui file.");
-
-        let homeserver = if other_homeserver_radio.get_active() {
-            Some(other_homeserver_url.get_text())
-        } else {
-            None
-        };
-
+    ) -> Option<String> {
         let q = self
             .builder
             .get_object::<gtk::Entry>("directory_search_entry")
@@ -102,12 +91,15 @@ impl UI {
             q.set_sensitive(false);
         }
 
-        let search_term = Some(q.get_text().to_string()).filter(|s| !s.is_empty());
-
-        (protocol, homeserver, search_term)
+        Some(q.get_text().to_string()).filter(|s| !s.is_empty())
     }
 
-    pub fn append_directory_rooms(&mut self, rooms: Vec<Room>, session_client: MatrixClient) {
+    pub fn append_directory_rooms(
+        &mut self,
+        rooms: Vec<Room>,
+        session_client: MatrixClient,
+        via: Vec<Box<ServerName>>,
+    ) {
         let directory = self
             .builder
             .get_object::<gtk::ListBox>("directory_room_list")
@@ -125,7 +117,7 @@ impl UI {
         directory_stack.set_visible_child(&directory_clamp);
 
         for r in rooms.iter() {
-            let room_widget = build_room_box_widget(&r, session_client.clone());
+            let room_widget = build_room_box_widget(&r, session_client.clone(), via.clone());
             directory.add(&room_widget);
         }
 
@@ -155,18 +147,28 @@ impl UI {
     }
 }
 
-fn build_room_box_widget(room: &Room, session_client: MatrixClient) -> gtk::ListBoxRow {
+fn build_room_box_widget(
+    room: &Room,
+    session_client: MatrixClient,
+    via: Vec<Box<ServerName>>,
+) -> gtk::ListBoxRow {
     let row = gtk::ListBoxRow::new();
-    let room_box = build_room_box(room, session_client);
+    let room_box = build_room_box(room, session_client, via);
 
     row.set_selectable(false);
+    row.set_action_target_value(Some(&room.id.as_str().to_variant()));
+    row.set_action_name(Some("app.directory-preview"));
     row.add(&room_box);
     row.show_all();
 
     row
 }
 
-fn build_room_box(room: &Room, session_client: MatrixClient) -> gtk::Box {
+fn build_room_box(
+    room: &Room,
+    session_client: MatrixClient,
+    via: Vec<Box<ServerName>>,
+) -> gtk::Box {
     let widget_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
 
     let avatar = widgets::Avatar::avatar_new(Some(AVATAR_SIZE));
@@ -243,20 +245,9 @@ fn build_room_box(room: &Room, session_client: MatrixClient) -> gtk::Box {
     members_count.get_style_context().add_class("dim-label");
 
     let join_button = gtk::Button::with_label(i18n("Join").as_str());
-    let room_id_or_alias: RoomIdOrAliasId = room.id.clone().into();
+    let room_id = room.id.clone();
     join_button.connect_clicked(move |_| {
-        let session_client = session_client.clone();
-        let room_id_or_alias = room_id_or_alias.clone();
-        RUNTIME.spawn(async move {
-            match room::join_room(session_client, &room_id_or_alias).await {
-                Ok(jtr) => {
-                    let jtr = Some(jtr);
-                    APPOP!(set_join_to_room, (jtr));
-                    APPOP!(reload_rooms);
-                }
-                Err(err) => err.handle_error(),
-            }
-        });
+        join_room(session_client.clone(), room_id.clone(), via.clone());
     });
     join_button.set_property_width_request(JOIN_BUTTON_WIDTH);
 
@@ -272,3 +263,45 @@ fn build_room_box(room: &Room, session_client: MatrixClient) -> gtk::Box {
 
     widget_box
 }
+
+fn build_network_row(network: &DirectoryNetwork, name: &str, selected: bool) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    row_box.set_margin_top(6);
+    row_box.set_margin_bottom(6);
+    row_box.set_margin_start(12);
+    row_box.set_margin_end(6);
+
+    let icon_name = match network {
+        DirectoryNetwork::Protocol(_) => "network-workgroup-symbolic",
+        _ => "network-server-symbolic",
+    };
+    let icon = gtk::Image::from_icon_name(Some(icon_name), gtk::IconSize::Menu);
+    row_box.pack_start(&icon, false, false, 0);
+
+    let label = gtk::Label::new(Some(name));
+    label.set_ellipsize(pango::EllipsizeMode::End);
+    label.set_xalign(0.0);
+    row_box.pack_start(&label, true, true, 0);
+
+    let check = gtk::Image::from_icon_name(Some("object-select-symbolic"), gtk::IconSize::Menu);
+    check.set_opacity(if selected { 1.0 } else { 0.0 });
+    row_box.pack_end(&check, false, false, 0);
+
+    if let DirectoryNetwork::Server(server) = network {
+        let remove_button =
+            gtk::Button::from_icon_name(Some("list-remove-symbolic"), gtk::IconSize::Menu);
+        remove_button.set_relief(gtk::ReliefStyle::None);
+        remove_button.set_tooltip_text(Some(&i18n("Remove Server")));
+        remove_button.set_action_target_value(Some(&server.to_variant()));
+        remove_button.set_action_name(Some("app.remove-directory-server"));
+        row_box.pack_end(&remove_button, false, false, 0);
+    }
+
+    row.add(&row_box);
+    row.set_action_target_value(Some(&network.to_target().to_variant()));
+    row.set_action_name(Some("app.directory-network"));
+    row.show_all();
+
+    row
+}
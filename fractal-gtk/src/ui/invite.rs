@@ -1,11 +1,10 @@
+use super::member::build_memberbox_pill;
 use super::UI;
 use crate::appop::member::SearchType;
 use crate::appop::UserInfoCache;
-use crate::cache::download_to_cache;
 use crate::globals;
 use crate::model::member::Member;
 use crate::util::i18n::{i18n, i18n_k};
-use crate::widgets::{self, AvatarExt};
 use gtk::prelude::*;
 use matrix_sdk::identifiers::UserId;
 use matrix_sdk::Client as MatrixClient;
@@ -172,35 +171,6 @@ impl UI {
         dialog.resize(300, 200);
     }
 
-    pub fn show_inv_dialog(&self, sender: Option<&Member>, room_name: Option<&str>) {
-        let dialog = self
-            .builder
-            .get_object::<gtk::MessageDialog>("invite_dialog")
-            .expect("Can't find invite_dialog in ui file.");
-
-        let empty = String::new();
-        let room_name = room_name.unwrap_or(&empty);
-        let title = i18n_k("Join {room_name}?", &[("room_name", &room_name)]);
-        let secondary = if let Some(ref sender) = sender {
-            let sender_name = sender.get_alias();
-            i18n_k(
-                "You’ve been invited to join <b>{room_name}</b> room by <b>{sender_name}</b>",
-                &[("room_name", &room_name), ("sender_name", &sender_name)],
-            )
-        } else {
-            i18n_k(
-                "You’ve been invited to join <b>{room_name}</b>",
-                &[("room_name", &room_name)],
-            )
-        };
-
-        dialog.set_property_text(Some(title.as_str()));
-        dialog.set_property_secondary_use_markup(true);
-        dialog.set_property_secondary_text(Some(secondary.as_str()));
-
-        dialog.present();
-    }
-
     pub fn set_invite_user_dialog_placeholder(&mut self, search_type: SearchType) {
         let invite_entry = self.get_invite_entry(search_type);
 
@@ -241,35 +211,3 @@ impl UI {
         }
     }
 }
-
-fn build_memberbox_pill(
-    session_client: MatrixClient,
-    user_info_cache: UserInfoCache,
-    member: Member,
-) -> gtk::Box {
-    let pill = gtk::Box::new(gtk::Orientation::Horizontal, 3);
-
-    let username = gtk::Label::new(None);
-
-    username.set_text(&member.get_alias());
-    username.set_margin_end(3);
-    username.get_style_context().add_class("msg-highlighted");
-
-    let avatar = widgets::Avatar::avatar_new(Some(globals::PILL_ICON_SIZE));
-    let data = avatar.circle(
-        member.uid.to_string(),
-        Some(member.get_alias()),
-        globals::PILL_ICON_SIZE,
-        None,
-        None,
-    );
-
-    download_to_cache(session_client, user_info_cache, member.uid, data);
-
-    avatar.set_margin_start(3);
-
-    pill.pack_start(&avatar, true, true, 0);
-    pill.pack_start(&username, true, true, 0);
-    pill.show_all();
-    pill
-}
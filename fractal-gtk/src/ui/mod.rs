@@ -13,7 +13,10 @@ pub mod invite;
 pub mod media_viewer;
 pub mod member;
 pub mod notify;
+pub mod preferences;
+pub mod room_preview;
 pub mod room_settings;
+pub mod space;
 pub mod start_chat;
 pub mod state;
 pub mod user;
@@ -23,11 +26,15 @@ pub struct UI {
     pub gtk_app: gtk::Application,
     pub main_window: libhandy::ApplicationWindow,
     pub sventry: SVEntry,
+    pub attachment_tray: widgets::AttachmentTray,
     pub sventry_box: Box<gtk::Stack>,
+    pub room_upgraded_button: gtk::Button,
     pub subview_stack: gtk::Stack,
     pub room_settings: Option<room_settings::RoomSettings>,
+    pub room_preview: Option<room_preview::RoomPreviewDialog>,
     pub history: Option<widgets::RoomHistory>,
     pub roomlist: widgets::RoomList,
+    pub space_list: widgets::SpaceList,
     pub media_viewer: Option<widgets::MediaViewer>,
     pub room_back_history: Vec<AppState>,
     pub invite_list: Vec<(Member, gtk::TextChildAnchor)>,
@@ -35,6 +42,7 @@ pub struct UI {
     pub deck: libhandy::Deck,
     pub account_settings: account::AccountSettings,
     pub direct_chat_dialog: start_chat::DirectChatDialog,
+    pub preferences: preferences::PreferencesWindow,
 }
 
 impl UI {
@@ -81,9 +89,27 @@ impl UI {
         sventry_disabled.set_line_wrap(true);
         sventry_disabled.set_line_wrap_mode(pango::WrapMode::WordChar);
         sventry_stack.add_named(&sventry_disabled, "Disabled Entry");
+        // Shown in the rooms with a tombstone, the button opens the new room
+        let room_upgraded_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        let room_upgraded_label = gtk::Label::new(Some(&i18n(
+            "This room has been replaced and is no longer active",
+        )));
+        room_upgraded_label
+            .get_style_context()
+            .add_class("dim-label");
+        room_upgraded_label.set_line_wrap(true);
+        room_upgraded_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+        let room_upgraded_button = gtk::Button::with_label(&i18n("Go to the New Room"));
+        room_upgraded_button.set_halign(gtk::Align::Center);
+        room_upgraded_button.set_action_name(Some("app.go-to-room"));
+        room_upgraded_box.add(&room_upgraded_label);
+        room_upgraded_box.add(&room_upgraded_button);
+        sventry_stack.add_named(&room_upgraded_box, "Upgraded Room");
 
         let sventry_box = Box::new(sventry_stack.clone());
+        let attachment_tray = widgets::AttachmentTray::default();
         let parent: gtk::Box = builder.get_object("room_parent").unwrap();
+        parent.add(&attachment_tray.container);
         parent.add(&sventry_stack);
 
         let subview_stack = builder
@@ -92,9 +118,6 @@ impl UI {
 
         // Depends on main_window
         // These are all dialogs transient for main_window
-        builder
-            .add_from_resource("/org/gnome/Fractal/ui/invite.ui")
-            .expect("Can't load ui file: invite.ui");
         builder
             .add_from_resource("/org/gnome/Fractal/ui/invite_user.ui")
             .expect("Can't load ui file: invite_user.ui");
@@ -125,18 +148,29 @@ impl UI {
             .get_object::<libhandy::Deck>("main_deck")
             .expect("Couldn't find main_deck in ui file");
 
+        let space_list = widgets::SpaceList::default();
+        builder
+            .get_object::<gtk::ScrolledWindow>("space_list_scroll")
+            .expect("Couldn't find space_list_scroll in ui file")
+            .add(&space_list.widget);
+
         let direct_chat_dialog = start_chat::DirectChatDialog::new(&main_window);
+        let preferences = preferences::PreferencesWindow::new(&main_window);
 
         UI {
             builder,
             gtk_app,
             main_window,
             sventry,
+            attachment_tray,
             sventry_box,
+            room_upgraded_button,
             subview_stack,
             room_settings: None,
+            room_preview: None,
             history: None,
-            roomlist: widgets::RoomList::new(None, None),
+            roomlist: widgets::RoomList::new(None, None, vec![]),
+            space_list,
             media_viewer: None,
             room_back_history: vec![],
             invite_list: vec![],
@@ -144,6 +178,7 @@ impl UI {
             deck,
             account_settings,
             direct_chat_dialog,
+            preferences,
         }
     }
 
@@ -173,6 +208,8 @@ pub struct MessageContent {
     pub highlights: Vec<String>,
     pub redactable: bool,
     pub last_viewed: bool,
+    /// Whether a preview of the first link of the body is shown below it
+    pub preview_urls: bool,
     pub widget: Option<widgets::MessageBox>,
 }
 
@@ -0,0 +1,303 @@
+use super::UI;
+use crate::app::AppRuntime;
+use crate::model::push_rules::{PushRule, RuleKind, Ruleset};
+use crate::util;
+use crate::util::i18n::{i18n, i18n_k};
+use glib::clone;
+use gtk::prelude::*;
+use libhandy::prelude::*;
+
+pub struct PreferencesWindow {
+    pub root: libhandy::PreferencesWindow,
+    pub room_list_compact_switch: gtk::Switch,
+    pub strip_image_metadata_switch: gtk::Switch,
+    pub url_previews_switch: gtk::Switch,
+    pub url_previews_encrypted_switch: gtk::Switch,
+    pub sync_drafts_switch: gtk::Switch,
+    pub cache_encryption_switch: gtk::Switch,
+    pub media_cache_size_spin: gtk::SpinButton,
+    pub clear_cache_button: gtk::Button,
+    pub media_cache_usage_list: gtk::ListBox,
+    pub notification_rules_list: gtk::ListBox,
+    pub keywords_list: gtk::ListBox,
+    pub keyword_entry: gtk::Entry,
+    pub add_keyword_button: gtk::Button,
+}
+
+impl PreferencesWindow {
+    pub fn new(parent: &libhandy::ApplicationWindow) -> Self {
+        let builder = gtk::Builder::from_resource("/org/gnome/Fractal/ui/preferences.ui");
+        let root: libhandy::PreferencesWindow = builder
+            .get_object("preferences_window")
+            .expect("Can't find preferences_window in ui file.");
+        root.set_transient_for(Some(parent));
+
+        Self {
+            root,
+            room_list_compact_switch: builder
+                .get_object("room_list_compact_switch")
+                .expect("Can't find room_list_compact_switch in ui file."),
+            strip_image_metadata_switch: builder
+                .get_object("strip_image_metadata_switch")
+                .expect("Can't find strip_image_metadata_switch in ui file."),
+            url_previews_switch: builder
+                .get_object("url_previews_switch")
+                .expect("Can't find url_previews_switch in ui file."),
+            url_previews_encrypted_switch: builder
+                .get_object("url_previews_encrypted_switch")
+                .expect("Can't find url_previews_encrypted_switch in ui file."),
+            sync_drafts_switch: builder
+                .get_object("sync_drafts_switch")
+                .expect("Can't find sync_drafts_switch in ui file."),
+            cache_encryption_switch: builder
+                .get_object("cache_encryption_switch")
+                .expect("Can't find cache_encryption_switch in ui file."),
+            media_cache_size_spin: builder
+                .get_object("media_cache_size_spin")
+                .expect("Can't find media_cache_size_spin in ui file."),
+            clear_cache_button: builder
+                .get_object("clear_cache_button")
+                .expect("Can't find clear_cache_button in ui file."),
+            media_cache_usage_list: builder
+                .get_object("media_cache_usage_list")
+                .expect("Can't find media_cache_usage_list in ui file."),
+            notification_rules_list: builder
+                .get_object("notification_rules_list")
+                .expect("Can't find notification_rules_list in ui file."),
+            keywords_list: builder
+                .get_object("keywords_list")
+                .expect("Can't find keywords_list in ui file."),
+            keyword_entry: builder
+                .get_object("keyword_entry")
+                .expect("Can't find keyword_entry in ui file."),
+            add_keyword_button: builder
+                .get_object("add_keyword_button")
+                .expect("Can't find add_keyword_button in ui file."),
+        }
+    }
+
+    pub fn connect(&self, app_runtime: AppRuntime) {
+        // The window is reused, so only hide it when it's closed
+        self.root.connect_delete_event(|w, _| w.hide_on_delete());
+
+        self.room_list_compact_switch
+            .connect_property_active_notify(clone!(@strong app_runtime => move |switch| {
+                let active = switch.get_active();
+                if active != util::get_room_list_compact_schema() {
+                    app_runtime.update_state_with(move |state| state.set_room_list_compact(active));
+                }
+            }));
+
+        self.strip_image_metadata_switch
+            .connect_property_active_notify(|switch| {
+                util::set_strip_image_metadata_schema(switch.get_active());
+            });
+
+        self.url_previews_switch
+            .bind_property("active", &self.url_previews_encrypted_switch, "sensitive")
+            .flags(glib::BindingFlags::SYNC_CREATE)
+            .build();
+        self.url_previews_switch
+            .connect_property_active_notify(|switch| {
+                util::set_url_previews_schema(switch.get_active());
+            });
+
+        self.url_previews_encrypted_switch
+            .connect_property_active_notify(|switch| {
+                util::set_url_previews_encrypted_schema(switch.get_active());
+            });
+
+        self.sync_drafts_switch
+            .connect_property_active_notify(|switch| {
+                util::set_sync_drafts_schema(switch.get_active());
+            });
+
+        self.cache_encryption_switch.connect_property_active_notify(
+            clone!(@strong app_runtime => move |switch| {
+                let active = switch.get_active();
+                if active != util::get_cache_encryption_schema() {
+                    app_runtime.update_state_with(move |state| state.set_cache_encryption(active));
+                }
+            }),
+        );
+
+        self.media_cache_size_spin.connect_value_changed(
+            clone!(@strong app_runtime => move |spin| {
+                let size = spin.get_value_as_int();
+                if size != util::get_media_cache_size_schema() {
+                    app_runtime.update_state_with(move |state| state.set_media_cache_size(size));
+                }
+            }),
+        );
+
+        self.clear_cache_button
+            .connect_clicked(clone!(@strong app_runtime => move |_| {
+                app_runtime.update_state_with(|state| state.clear_cache());
+            }));
+
+        let button = self.add_keyword_button.clone();
+        self.keyword_entry
+            .connect_property_text_notify(move |entry| {
+                button.set_sensitive(!entry.get_text().trim().is_empty());
+            });
+
+        let entry = self.keyword_entry.clone();
+        let add_keyword = move || {
+            let keyword = entry.get_text().trim().to_string();
+            if !keyword.is_empty() {
+                entry.set_text("");
+                app_runtime.update_state_with(move |state| state.add_keyword(keyword));
+            }
+        };
+        let add = add_keyword.clone();
+        self.add_keyword_button.connect_clicked(move |_| add());
+        self.keyword_entry.connect_activate(move |_| add_keyword());
+    }
+
+    pub fn show(&self) {
+        self.room_list_compact_switch
+            .set_active(util::get_room_list_compact_schema());
+        self.strip_image_metadata_switch
+            .set_active(util::get_strip_image_metadata_schema());
+        self.url_previews_switch
+            .set_active(util::get_url_previews_schema());
+        self.url_previews_encrypted_switch
+            .set_active(util::get_url_previews_encrypted_schema());
+        self.sync_drafts_switch
+            .set_active(util::get_sync_drafts_schema());
+        self.cache_encryption_switch
+            .set_active(util::get_cache_encryption_schema());
+        self.media_cache_size_spin
+            .set_value(util::get_media_cache_size_schema().into());
+        self.root.present();
+    }
+
+    /// Fills the usage list with the space taken by each room, given as the room name
+    /// and the size in bytes.
+    pub fn set_media_cache_usage(&self, usage: &[(String, u64)]) {
+        for row in self.media_cache_usage_list.get_children() {
+            self.media_cache_usage_list.remove(&row);
+        }
+
+        let total: (String, u64) = (i18n("Total"), usage.iter().map(|(_, size)| size).sum());
+        for (name, size) in usage.iter().chain(std::iter::once(&total)) {
+            let row = libhandy::ActionRow::new();
+            row.set_title(Some(name));
+            let size_label = gtk::Label::new(glib::format_size(*size).as_deref());
+            size_label.get_style_context().add_class("dim-label");
+            row.add(&size_label);
+            row.show_all();
+            self.media_cache_usage_list.add(&row);
+        }
+    }
+
+    /// Fills the notification page with the default rules we know how to describe
+    /// and the keywords of the user
+    pub fn set_push_rules(&self, rules: &Ruleset, app_runtime: &AppRuntime) {
+        for row in self.notification_rules_list.get_children() {
+            self.notification_rules_list.remove(&row);
+        }
+        for row in self.keywords_list.get_children() {
+            self.keywords_list.remove(&row);
+        }
+
+        let kinds = [RuleKind::Override, RuleKind::Content, RuleKind::Underride];
+        for kind in kinds.iter() {
+            for rule in rules.rules(*kind).iter().filter(|rule| rule.default) {
+                if let Some(title) = rule_title(&rule.rule_id) {
+                    let row = create_rule_row(*kind, rule, &title, app_runtime);
+                    self.notification_rules_list.add(&row);
+                }
+            }
+        }
+
+        let keywords = rules.keywords();
+        for rule in keywords.iter() {
+            let row = create_keyword_row(rule, app_runtime);
+            self.keywords_list.add(&row);
+        }
+        self.keywords_list.set_visible(!keywords.is_empty());
+    }
+}
+
+fn create_rule_row(
+    kind: RuleKind,
+    rule: &PushRule,
+    title: &str,
+    app_runtime: &AppRuntime,
+) -> libhandy::ActionRow {
+    // Some rules are there to silence events, for those the switch shows whether
+    // the rule is off, so it always means "notify me about this"
+    let inverted = !rule.notifies();
+
+    let row = libhandy::ActionRow::new();
+    row.set_title(Some(title));
+    let switch = gtk::Switch::new();
+    switch.set_valign(gtk::Align::Center);
+    switch.set_active(rule.enabled != inverted);
+    row.add(&switch);
+    row.set_activatable_widget(Some(&switch));
+
+    let rule_id = rule.rule_id.clone();
+    switch.connect_property_active_notify(clone!(@strong app_runtime => move |switch| {
+        let enabled = switch.get_active() != inverted;
+        let rule_id = rule_id.clone();
+        app_runtime.update_state_with(move |state| {
+            state.set_push_rule_enabled(kind, rule_id, enabled)
+        });
+    }));
+
+    row.show_all();
+    row
+}
+
+fn create_keyword_row(rule: &PushRule, app_runtime: &AppRuntime) -> libhandy::ActionRow {
+    let keyword = rule.pattern.clone().unwrap_or_else(|| rule.rule_id.clone());
+
+    let row = libhandy::ActionRow::new();
+    row.set_title(Some(&keyword));
+    let remove = gtk::Button::from_icon_name(Some("user-trash-symbolic"), gtk::IconSize::Button);
+    remove.set_valign(gtk::Align::Center);
+    remove.set_tooltip_text(Some(
+        i18n_k("Remove “{keyword}”", &[("keyword", &keyword)]).as_str(),
+    ));
+    row.add(&remove);
+
+    let rule_id = rule.rule_id.clone();
+    remove.connect_clicked(clone!(@strong app_runtime => move |button| {
+        button.set_sensitive(false);
+        let rule_id = rule_id.clone();
+        app_runtime.update_state_with(move |state| state.remove_keyword(rule_id));
+    }));
+
+    row.show_all();
+    row
+}
+
+/// Describes the server default rules shown in the preferences, the others are left out
+fn rule_title(rule_id: &str) -> Option<String> {
+    let title = match rule_id {
+        ".m.rule.master" => i18n("Anything at All"),
+        ".m.rule.suppress_notices" => i18n("Messages Sent by Bots"),
+        ".m.rule.invite_for_me" => i18n("Invitations"),
+        ".m.rule.member_event" => i18n("People Joining or Leaving"),
+        ".m.rule.contains_display_name" => i18n("Messages Containing My Display Name"),
+        ".m.rule.contains_user_name" => i18n("Messages Containing My Username"),
+        ".m.rule.roomnotif" => i18n("Messages Mentioning the Whole Room"),
+        ".m.rule.tombstone" => i18n("Room Upgrades"),
+        ".m.rule.call" => i18n("Calls"),
+        ".m.rule.room_one_to_one" => i18n("Direct Messages"),
+        ".m.rule.encrypted_room_one_to_one" => i18n("Encrypted Direct Messages"),
+        ".m.rule.message" => i18n("Messages in Group Rooms"),
+        ".m.rule.encrypted" => i18n("Encrypted Messages in Group Rooms"),
+        _ => return None,
+    };
+
+    Some(title)
+}
+
+impl UI {
+    pub fn show_preferences(&self) {
+        self.preferences.show();
+    }
+}
@@ -0,0 +1,392 @@
+use super::UI;
+use crate::app::RUNTIME;
+use crate::backend::directory::RoomPreview;
+use crate::backend::room;
+use crate::backend::HandleError;
+use crate::model::member::Member;
+use crate::model::message::Message;
+use crate::model::room::Room;
+use crate::util::i18n::{i18n, i18n_k, ni18n_f};
+use crate::util::markup_text;
+use crate::widgets::{self, AvatarExt};
+use crate::APPOP;
+use gio::prelude::*;
+use glib::clone;
+use gtk::prelude::*;
+use matrix_sdk::identifiers::{RoomAliasId, RoomId, ServerName};
+use matrix_sdk::Client as MatrixClient;
+
+const AVATAR_SIZE: i32 = 80;
+const INVITER_AVATAR_SIZE: i32 = 24;
+
+/// The details of a room we aren't in, from the directory or an invite, with its last
+/// messages when it's world readable
+pub struct RoomPreviewDialog {
+    pub room_id: RoomId,
+    pub root: gtk::Dialog,
+    members_label: gtk::Label,
+    encryption_label: gtk::Label,
+    aliases_label: gtk::Label,
+    messages_stack: gtk::Stack,
+    messages_list: gtk::ListBox,
+}
+
+impl RoomPreviewDialog {
+    fn new(parent: &libhandy::ApplicationWindow, room: &Room, inviter: Option<&Member>) -> Self {
+        let root = gtk::Dialog::new();
+        root.set_transient_for(Some(parent));
+        root.set_modal(true);
+        root.set_destroy_with_parent(true);
+        root.set_default_size(480, 560);
+
+        let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        content.set_margin_top(18);
+        content.set_margin_bottom(18);
+        content.set_margin_start(18);
+        content.set_margin_end(18);
+
+        let avatar = widgets::Avatar::avatar_new(Some(AVATAR_SIZE));
+        avatar.circle(
+            room.id.to_string(),
+            room.name.clone(),
+            AVATAR_SIZE,
+            None,
+            None,
+        );
+        content.add(&avatar);
+
+        let name = room
+            .name
+            .as_ref()
+            .filter(|n| !n.is_empty())
+            .map(String::as_str)
+            .or(room.alias.as_ref().map(RoomAliasId::as_str))
+            .unwrap_or_else(|| room.id.as_str());
+        let name_label = gtk::Label::new(None);
+        name_label.set_line_wrap(true);
+        name_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+        name_label.set_justify(gtk::Justification::Center);
+        name_label.set_markup(&format!("<big><b>{}</b></big>", markup_text(name)));
+        content.add(&name_label);
+
+        if let Some(inviter) = inviter {
+            content.add(&build_inviter_box(inviter));
+        }
+
+        let topic = room.topic.clone().unwrap_or_default();
+        if !topic.is_empty() {
+            let topic_label = gtk::Label::new(None);
+            topic_label.set_line_wrap(true);
+            topic_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+            topic_label.set_justify(gtk::Justification::Center);
+            topic_label.set_selectable(true);
+            topic_label.set_markup(&markup_text(&topic));
+            content.add(&topic_label);
+        }
+
+        let members_label = gtk::Label::new(None);
+        members_label.get_style_context().add_class("dim-label");
+        content.add(&members_label);
+
+        let encryption_label = gtk::Label::new(Some(&i18n("Messages are end-to-end encrypted")));
+        encryption_label.get_style_context().add_class("dim-label");
+        encryption_label.set_no_show_all(true);
+        content.add(&encryption_label);
+
+        let aliases_label = gtk::Label::new(room.alias.as_ref().map(RoomAliasId::as_str));
+        aliases_label.set_line_wrap(true);
+        aliases_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+        aliases_label.set_justify(gtk::Justification::Center);
+        aliases_label.set_selectable(true);
+        aliases_label.get_style_context().add_class("dim-label");
+        content.add(&aliases_label);
+
+        let messages_stack = gtk::Stack::new();
+        messages_stack.set_vexpand(true);
+
+        let spinner = gtk::Spinner::new();
+        spinner.start();
+        messages_stack.add_named(&spinner, "loading");
+
+        let hidden_label = gtk::Label::new(Some(&i18n(
+            "Only the members of this room can read its messages.",
+        )));
+        hidden_label.set_line_wrap(true);
+        hidden_label.set_justify(gtk::Justification::Center);
+        hidden_label.get_style_context().add_class("dim-label");
+        messages_stack.add_named(&hidden_label, "hidden");
+
+        let messages_list = gtk::ListBox::new();
+        messages_list.set_selection_mode(gtk::SelectionMode::None);
+        let scroll = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+        scroll.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scroll.set_shadow_type(gtk::ShadowType::In);
+        scroll.add(&messages_list);
+        messages_stack.add_named(&scroll, "messages");
+
+        content.add(&messages_stack);
+        root.get_content_area().add(&content);
+
+        let dialog = Self {
+            room_id: room.id.clone(),
+            root,
+            members_label,
+            encryption_label,
+            aliases_label,
+            messages_stack,
+            messages_list,
+        };
+        dialog.set_members(room.n_members);
+        dialog.set_encrypted(room.encrypted);
+
+        dialog
+    }
+
+    /// The preview of a room from the directory, joined through `via`
+    fn for_directory(
+        parent: &libhandy::ApplicationWindow,
+        room: &Room,
+        session_client: MatrixClient,
+        via: Vec<Box<ServerName>>,
+    ) -> Self {
+        let dialog = Self::new(parent, room, None);
+        dialog.root.set_title(&i18n("Room Preview"));
+        dialog
+            .root
+            .add_button(&i18n("Cancel"), gtk::ResponseType::Cancel);
+        let join_button = dialog.root.add_button(&i18n("Join"), gtk::ResponseType::Ok);
+        join_button
+            .get_style_context()
+            .add_class("suggested-action");
+
+        let room_id = room.id.clone();
+        dialog.root.connect_response(move |w, r| {
+            if let gtk::ResponseType::Ok = r {
+                join_room(session_client.clone(), room_id.clone(), via.clone());
+            }
+            w.close();
+        });
+
+        dialog.root.show_all();
+        dialog
+            .messages_stack
+            .set_visible_child_name(if room.world_readable {
+                "loading"
+            } else {
+                "hidden"
+            });
+
+        dialog
+    }
+
+    /// The details of an invite, closing it without answering keeps the invite
+    fn for_invite(parent: &libhandy::ApplicationWindow, room: &Room, inviter: &Member) -> Self {
+        let dialog = Self::new(parent, room, Some(inviter));
+        dialog.root.set_title(&i18n("Invitation"));
+
+        let menu = gio::Menu::new();
+        menu.append(
+            Some(&i18n("Reject and Ignore")),
+            Some("invite.reject-and-forget"),
+        );
+        menu.append(Some(&i18n("Block Inviter")), Some("invite.block-inviter"));
+        let menu_button = gtk::MenuButton::new();
+        menu_button.set_menu_model(Some(&menu));
+        menu_button.add(&gtk::Image::from_icon_name(
+            Some("view-more-symbolic"),
+            gtk::IconSize::Button,
+        ));
+        menu_button.set_tooltip_text(Some(&i18n("More Options")));
+        dialog
+            .root
+            .add_action_widget(&menu_button, gtk::ResponseType::None);
+
+        dialog
+            .root
+            .add_button(&i18n("Reject"), gtk::ResponseType::Reject);
+        let accept_button = dialog
+            .root
+            .add_button(&i18n("Accept"), gtk::ResponseType::Accept);
+        accept_button
+            .get_style_context()
+            .add_class("suggested-action");
+
+        let actions = gio::SimpleActionGroup::new();
+        let reject_and_forget = gio::SimpleAction::new("reject-and-forget", None);
+        let block_inviter = gio::SimpleAction::new("block-inviter", None);
+        actions.add_action(&reject_and_forget);
+        actions.add_action(&block_inviter);
+        dialog.root.insert_action_group("invite", Some(&actions));
+
+        let root = &dialog.root;
+        reject_and_forget.connect_activate(clone!(@weak root => move |_, _| {
+            APPOP!(reject_and_forget_inv);
+            root.close();
+        }));
+        block_inviter.connect_activate(clone!(@weak root => move |_, _| {
+            APPOP!(block_inviter);
+            root.close();
+        }));
+
+        dialog.root.connect_response(|w, r| {
+            match r {
+                gtk::ResponseType::Accept => {
+                    let accept = true;
+                    APPOP!(accept_inv, (accept));
+                }
+                gtk::ResponseType::Reject => {
+                    let accept = false;
+                    APPOP!(accept_inv, (accept));
+                }
+                _ => {}
+            }
+            w.close();
+        });
+
+        dialog.root.show_all();
+        dialog.messages_stack.set_visible_child_name("loading");
+
+        dialog
+    }
+
+    fn set_members(&self, n_members: u64) {
+        self.members_label.set_text(&ni18n_f(
+            "{} member",
+            "{} members",
+            n_members as u32,
+            &[&n_members.to_string()],
+        ));
+        // The invites don't tell the number of members
+        self.members_label.set_visible(n_members > 0);
+    }
+
+    fn set_encrypted(&self, encrypted: bool) {
+        self.encryption_label.set_visible(encrypted);
+    }
+
+    /// Shows what we could read of the room, `None` when it can't be read without joining
+    pub fn set_preview(&self, preview: Option<RoomPreview>) {
+        let preview = match preview {
+            Some(preview) => preview,
+            None => {
+                self.messages_stack.set_visible_child_name("hidden");
+                return;
+            }
+        };
+
+        if !preview.aliases.is_empty() {
+            let aliases: Vec<&str> = preview.aliases.iter().map(RoomAliasId::as_str).collect();
+            self.aliases_label.set_text(&aliases.join("\n"));
+        }
+        if preview.n_members > 0 {
+            self.set_members(preview.n_members);
+        }
+        if preview.encrypted {
+            self.set_encrypted(true);
+        }
+
+        for msg in preview.messages.iter() {
+            self.messages_list.add(&build_preview_message(msg));
+        }
+        self.messages_list.show_all();
+
+        if preview.messages.is_empty() {
+            self.messages_stack.set_visible_child_name("hidden");
+        } else {
+            self.messages_stack.set_visible_child_name("messages");
+        }
+    }
+}
+
+impl UI {
+    pub fn show_room_preview(
+        &mut self,
+        room: &Room,
+        session_client: MatrixClient,
+        via: Vec<Box<ServerName>>,
+    ) {
+        let dialog = RoomPreviewDialog::for_directory(&self.main_window, room, session_client, via);
+        self.room_preview = Some(dialog);
+    }
+
+    pub fn show_invite_preview(&mut self, room: &Room, inviter: &Member) {
+        let dialog = RoomPreviewDialog::for_invite(&self.main_window, room, inviter);
+        self.room_preview = Some(dialog);
+    }
+
+    pub fn set_room_preview(&self, room_id: &RoomId, preview: Option<RoomPreview>) {
+        if let Some(dialog) = self.room_preview.as_ref().filter(|d| &d.room_id == room_id) {
+            dialog.set_preview(preview);
+        }
+    }
+}
+
+pub fn join_room(session_client: MatrixClient, room_id: RoomId, via: Vec<Box<ServerName>>) {
+    RUNTIME.spawn(async move {
+        match room::join_room(session_client, &room_id.into(), &via).await {
+            Ok(jtr) => {
+                let jtr = Some(jtr);
+                APPOP!(set_join_to_room, (jtr));
+                APPOP!(reload_rooms);
+            }
+            Err(err) => err.handle_error(),
+        }
+    });
+}
+
+fn build_inviter_box(inviter: &Member) -> gtk::Box {
+    let inviter_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    inviter_box.set_halign(gtk::Align::Center);
+
+    let avatar = widgets::Avatar::avatar_new(Some(INVITER_AVATAR_SIZE));
+    avatar.circle(
+        inviter.uid.to_string(),
+        inviter.alias.clone(),
+        INVITER_AVATAR_SIZE,
+        None,
+        None,
+    );
+    inviter_box.add(&avatar);
+
+    let label = gtk::Label::new(None);
+    label.set_line_wrap(true);
+    label.set_line_wrap_mode(pango::WrapMode::WordChar);
+    label.set_markup(&i18n_k(
+        "Invited by <b>{sender_name}</b> ({sender_id})",
+        &[
+            ("sender_name", &markup_text(&inviter.get_alias())),
+            ("sender_id", &markup_text(inviter.uid.as_str())),
+        ],
+    ));
+    inviter_box.add(&label);
+
+    inviter_box
+}
+
+fn build_preview_message(msg: &Message) -> gtk::Box {
+    let msg_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    msg_box.set_margin_top(6);
+    msg_box.set_margin_bottom(6);
+    msg_box.set_margin_start(12);
+    msg_box.set_margin_end(12);
+
+    let sender_label = gtk::Label::new(None);
+    sender_label.set_markup(&format!(
+        "<b>{}</b>  <span alpha=\"60%\">{}</span>",
+        markup_text(msg.sender.as_str()),
+        msg.date.format("%x %R"),
+    ));
+    sender_label.set_ellipsize(pango::EllipsizeMode::End);
+    sender_label.set_xalign(0.0);
+    msg_box.add(&sender_label);
+
+    let body_label = gtk::Label::new(Some(&msg.body));
+    body_label.set_line_wrap(true);
+    body_label.set_line_wrap_mode(pango::WrapMode::WordChar);
+    body_label.set_lines(4);
+    body_label.set_ellipsize(pango::EllipsizeMode::End);
+    body_label.set_xalign(0.0);
+    msg_box.add(&body_label);
+
+    msg_box
+}
@@ -13,7 +13,7 @@ use crate::APPOP;
 use gio::prelude::*;
 use glib::clone;
 use gtk::prelude::*;
-use matrix_sdk::identifiers::UserId;
+use matrix_sdk::identifiers::{RoomVersionId, UserId};
 use matrix_sdk::Client as MatrixClient;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -58,10 +58,15 @@ impl UI {
         None
     }
 
-    pub fn set_notifications_switch(&self, active: bool, sensitive: bool) -> Option<()> {
+    pub fn set_notifications_mode(&self, notify: room::RoomNotify, sensitive: bool) -> Option<()> {
         self.room_settings
             .as_ref()?
-            .set_notifications_switch(active, sensitive);
+            .set_notifications_mode(notify, sensitive);
+        None
+    }
+
+    pub fn show_room_settings_upgrade(&self, version: RoomVersionId) -> Option<()> {
+        self.room_settings.as_ref()?.show_upgrade(version);
         None
     }
 }
@@ -73,7 +78,7 @@ pub struct RoomSettings {
     uid: UserId,
     builder: gtk::Builder,
     members_list: Option<MembersList>,
-    switch_handler: Option<Rc<glib::SignalHandlerId>>,
+    notify_handler: Option<Rc<glib::SignalHandlerId>>,
 }
 
 impl RoomSettings {
@@ -102,7 +107,7 @@ impl RoomSettings {
             uid,
             builder,
             members_list: None,
-            switch_handler: None,
+            notify_handler: None,
         }
     }
 
@@ -154,10 +159,14 @@ impl RoomSettings {
             .builder
             .get_object::<gtk::Button>("room_settings_avatar_button")
             .expect("Can't find room_settings_avatar_button in ui file.");
-        let switch = self
+        let notification_combo = self
+            .builder
+            .get_object::<gtk::ComboBoxText>("room_settings_notification_combo")
+            .expect("Can't find room_settings_notification_combo in ui file.");
+        let url_previews_switch = self
             .builder
-            .get_object::<gtk::Switch>("room_settings_notification_switch")
-            .expect("Can't find room_settings_notification_switch in ui file.");
+            .get_object::<gtk::Switch>("room_settings_url_previews_switch")
+            .expect("Can't find room_settings_url_previews_switch in ui file.");
 
         let this: Rc<RefCell<RoomSettings>> = Rc::new(RefCell::new(self.clone()));
 
@@ -218,12 +227,33 @@ impl RoomSettings {
             );
         }
 
-        let switch_handler = switch.connect_property_active_notify(
-            clone!(@strong this => move |switch| {
-                let active = switch.get_active();
-                let notify = if active { room::RoomNotify::All } else { room::RoomNotify::DontNotify };
+        let uid = self.uid.clone();
+        url_previews_switch.connect_property_active_notify(
+            clone!(@strong this, @strong session_client => move |switch| {
                 let room_id = this.borrow().room.id.clone();
-                switch.set_sensitive(false);
+                let disable = !switch.get_active();
+                let session_client = session_client.clone();
+                let uid = uid.clone();
+
+                RUNTIME.spawn(async move {
+                    match room::set_url_previews(session_client, &uid, &room_id, disable).await {
+                        Ok(_) => APPOP!(set_room_url_previews, (room_id, disable)),
+                        Err(err) => err.handle_error(),
+                    }
+                });
+            }),
+        );
+
+        let notify_handler =
+            notification_combo.connect_changed(clone!(@strong this => move |combo| {
+                let notify = match combo.get_active_id().as_deref() {
+                    Some("all") => room::RoomNotify::All,
+                    Some("mentions") => room::RoomNotify::MentionsOnly,
+                    Some("mute") => room::RoomNotify::DontNotify,
+                    _ => room::RoomNotify::NotSet,
+                };
+                let room_id = this.borrow().room.id.clone();
+                combo.set_sensitive(false);
                 let session_client = session_client.clone();
 
                 RUNTIME.spawn(async move {
@@ -231,11 +261,13 @@ impl RoomSettings {
                         err.handle_error();
                     }
                     let sensitive = true;
-                    APPOP!(set_notifications_switch, (active, sensitive));
+                    APPOP!(set_notifications_mode, (notify, sensitive));
+                    // The room rules are part of the ruleset used for the notifications
+                    APPOP!(update_push_rules);
                 });
             }));
 
-        self.switch_handler = Some(Rc::new(switch_handler));
+        self.notify_handler = Some(Rc::new(notify_handler));
     }
 
     fn init_room_settings(&mut self, session_client: MatrixClient) {
@@ -273,7 +305,9 @@ impl RoomSettings {
         self.room_settings_show_room_topic(topic, is_room, edit);
         self.room_settings_show_room_type(description);
         self.room_settings_show_members(members);
-        self.room_settings_show_notifications(session_client);
+        self.room_settings_show_notifications(session_client.clone());
+        self.room_settings_show_url_previews();
+        self.room_settings_show_version(session_client, power >= self.room.upgrade_power_level);
 
         /* admin parts */
         self.room_settings_show_group_room(is_room || is_group);
@@ -678,6 +712,56 @@ impl RoomSettings {
         None
     }
 
+    /// The upgrade button is only shown once the server tells it has a newer version
+    fn room_settings_show_version(&self, session_client: MatrixClient, can_upgrade: bool) {
+        let row = self
+            .builder
+            .get_object::<gtk::ListBoxRow>("room_settings_version_row")
+            .expect("Can't find room_settings_version_row in ui file.");
+        let label = self
+            .builder
+            .get_object::<gtk::Label>("room_settings_version")
+            .expect("Can't find room_settings_version in ui file.");
+
+        match self.room.version.as_ref() {
+            Some(version) => label.set_text(version.as_str()),
+            None => {
+                row.hide();
+                return;
+            }
+        }
+
+        // An upgraded room can't be upgraded again
+        if can_upgrade && self.room.successor.is_none() {
+            RUNTIME.spawn(async move {
+                match room::get_default_room_version(session_client).await {
+                    Ok(version) => {
+                        APPOP!(show_room_settings_upgrade, (version));
+                    }
+                    Err(err) => {
+                        err.handle_error();
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn show_upgrade(&self, version: RoomVersionId) {
+        let button = self
+            .builder
+            .get_object::<gtk::Button>("room_settings_upgrade_button")
+            .expect("Can't find room_settings_upgrade_button in ui file.");
+
+        if self.room.version.as_ref() == Some(&version) {
+            return;
+        }
+
+        let target = format!("{} {}", self.room.id, version);
+        button.set_action_target_value(Some(&target.to_variant()));
+        button.set_action_name(Some("room-settings.upgrade-room"));
+        button.show();
+    }
+
     fn room_settings_show_members(&mut self, members: Vec<Member>) -> Option<()> {
         let entry = self
             .builder
@@ -711,48 +795,57 @@ impl RoomSettings {
     }
 
     fn room_settings_show_notifications(&mut self, session_client: MatrixClient) {
-        let switch = self
+        let combo = self
             .builder
-            .get_object::<gtk::Switch>("room_settings_notification_switch")
-            .expect("Can't find room_settings_notification_switch in ui file.");
+            .get_object::<gtk::ComboBoxText>("room_settings_notification_combo")
+            .expect("Can't find room_settings_notification_combo in ui file.");
 
-        switch.set_sensitive(false);
+        combo.set_sensitive(false);
 
         let room_id = self.room.id.clone();
 
         RUNTIME.spawn(async move {
-            let mut active = true;
-            let mut sensitive = true;
-            match room::get_pushrules(session_client, &room_id).await {
-                Ok(room::RoomNotify::DontNotify) => {
-                    active = false;
-                }
+            let (notify, sensitive) = match room::get_pushrules(session_client, &room_id).await {
+                Ok(notify) => (notify, true),
                 Err(err) => {
                     err.handle_error();
-                    active = false;
-                    sensitive = false;
+                    (room::RoomNotify::NotSet, false)
                 }
-                _ => {}
             };
-            APPOP!(set_notifications_switch, (active, sensitive));
+            APPOP!(set_notifications_mode, (notify, sensitive));
         });
     }
 
-    pub fn set_notifications_switch(&self, active: bool, sensitive: bool) {
+    fn room_settings_show_url_previews(&self) {
         let switch = self
             .builder
-            .get_object::<gtk::Switch>("room_settings_notification_switch")
-            .expect("Can't find room_settings_notification_switch in ui file.");
+            .get_object::<gtk::Switch>("room_settings_url_previews_switch")
+            .expect("Can't find room_settings_url_previews_switch in ui file.");
 
-        if let Some(handler) = &self.switch_handler {
-            switch.block_signal(&handler);
+        switch.set_active(self.room.disable_url_previews != Some(true));
+    }
+
+    pub fn set_notifications_mode(&self, notify: room::RoomNotify, sensitive: bool) {
+        let combo = self
+            .builder
+            .get_object::<gtk::ComboBoxText>("room_settings_notification_combo")
+            .expect("Can't find room_settings_notification_combo in ui file.");
+
+        if let Some(handler) = &self.notify_handler {
+            combo.block_signal(&handler);
         }
 
-        switch.set_active(active);
-        switch.set_sensitive(sensitive);
+        let id = match notify {
+            room::RoomNotify::All => "all",
+            room::RoomNotify::MentionsOnly => "mentions",
+            room::RoomNotify::DontNotify => "mute",
+            room::RoomNotify::NotSet => "default",
+        };
+        combo.set_active_id(Some(id));
+        combo.set_sensitive(sensitive);
 
-        if let Some(handler) = &self.switch_handler {
-            switch.unblock_signal(&handler);
+        if let Some(handler) = &self.notify_handler {
+            combo.unblock_signal(&handler);
         }
     }
 }
@@ -0,0 +1,226 @@
+use super::UI;
+use crate::app::RUNTIME;
+use crate::backend::room;
+use crate::backend::HandleError;
+use crate::model::room::Room;
+use crate::util::i18n::{i18n, ni18n_f};
+use crate::util::markup_text;
+use crate::widgets::{self, AvatarExt};
+use crate::APPOP;
+use gtk::prelude::*;
+use matrix_sdk::identifiers::{RoomAliasId, RoomId, ServerName};
+use matrix_sdk::Client as MatrixClient;
+
+const HEADER_AVATAR_SIZE: i32 = 72;
+const AVATAR_SIZE: i32 = 40;
+/// The indentation of the rooms for each level of subspaces
+const DEPTH_MARGIN: i32 = 24;
+
+/// A room of the space hierarchy, as shown in the space home page
+pub struct SpaceRoom {
+    pub room: Room,
+    /// The space it's a child of, it can only be removed from that one
+    pub parent: RoomId,
+    /// 0 for the rooms of the space, 1 for the ones of its subspaces, and so on
+    pub depth: usize,
+    /// The servers to join the room through
+    pub via: Vec<Box<ServerName>>,
+    pub joined: bool,
+    pub can_remove: bool,
+}
+
+impl UI {
+    pub fn show_space_home(&self, space: &Room, can_edit: bool) {
+        let header = self
+            .builder
+            .get_object::<gtk::Box>("space_home_header")
+            .expect("Can't find space_home_header in ui file.");
+        let name = self
+            .builder
+            .get_object::<gtk::Label>("space_home_name")
+            .expect("Can't find space_home_name in ui file.");
+        let topic = self
+            .builder
+            .get_object::<gtk::Label>("space_home_topic")
+            .expect("Can't find space_home_topic in ui file.");
+        let add_room_button = self
+            .builder
+            .get_object::<gtk::Button>("space_add_room_button")
+            .expect("Can't find space_add_room_button in ui file.");
+
+        // Replace the avatar of the previous space
+        for ch in header.get_children().iter() {
+            if ch.is::<gtk::Overlay>() {
+                header.remove(ch);
+            }
+        }
+        let avatar = widgets::Avatar::avatar_new(Some(HEADER_AVATAR_SIZE));
+        avatar.circle(
+            space.id.to_string(),
+            space.name.clone(),
+            HEADER_AVATAR_SIZE,
+            None,
+            None,
+        );
+        header.pack_start(&avatar, false, false, 0);
+        header.reorder_child(&avatar, 0);
+
+        name.set_text(space.name.as_deref().unwrap_or_default());
+        let topic_text = space.topic.clone().unwrap_or_default();
+        topic.set_markup(&markup_text(&topic_text));
+        topic.set_visible(!topic_text.is_empty());
+        add_room_button.set_visible(can_edit);
+
+        self.set_space_rooms_state("loading");
+        self.builder
+            .get_object::<gtk::Stack>("room_view_stack")
+            .expect("Can't find room_view_stack in ui file.")
+            .set_visible_child_name("space");
+    }
+
+    pub fn set_space_rooms(&self, rooms: Vec<SpaceRoom>, session_client: MatrixClient) {
+        let list = self
+            .builder
+            .get_object::<gtk::ListBox>("space_rooms_list")
+            .expect("Can't find space_rooms_list in ui file.");
+        for ch in list.get_children().iter() {
+            list.remove(ch);
+        }
+
+        for space_room in rooms.iter() {
+            list.add(&build_space_room_row(space_room, session_client.clone()));
+        }
+
+        if rooms.is_empty() {
+            self.set_space_rooms_state("empty");
+        } else {
+            self.set_space_rooms_state("rooms");
+        }
+    }
+
+    fn set_space_rooms_state(&self, state: &str) {
+        self.builder
+            .get_object::<gtk::Stack>("space_rooms_stack")
+            .expect("Can't find space_rooms_stack in ui file.")
+            .set_visible_child_name(state);
+    }
+}
+
+fn build_space_room_row(space_room: &SpaceRoom, session_client: MatrixClient) -> gtk::ListBoxRow {
+    let room = &space_room.room;
+    let row = gtk::ListBoxRow::new();
+    row.set_activatable(false);
+
+    let widget_box = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    widget_box.set_margin_start(space_room.depth as i32 * DEPTH_MARGIN);
+
+    let avatar = widgets::Avatar::avatar_new(Some(AVATAR_SIZE));
+    avatar.circle(
+        room.id.to_string(),
+        room.name.clone(),
+        AVATAR_SIZE,
+        None,
+        None,
+    );
+    widget_box.pack_start(&avatar, false, false, 0);
+
+    let details_box = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    details_box.set_valign(gtk::Align::Center);
+
+    let name = room
+        .name
+        .as_ref()
+        .filter(|n| !n.is_empty())
+        .map(String::as_str)
+        .or_else(|| room.alias.as_ref().map(RoomAliasId::as_str))
+        .unwrap_or_else(|| room.id.as_str());
+    let name_label = gtk::Label::new(None);
+    name_label.set_markup(&format!("<b>{}</b>", markup_text(name)));
+    name_label.set_ellipsize(pango::EllipsizeMode::End);
+    name_label.set_xalign(0.0);
+    details_box.add(&name_label);
+
+    if let Some(topic) = room.topic.as_ref().filter(|topic| !topic.is_empty()) {
+        let topic_label = gtk::Label::new(None);
+        topic_label.set_markup(&markup_text(topic));
+        topic_label.set_ellipsize(pango::EllipsizeMode::End);
+        topic_label.set_xalign(0.0);
+        topic_label.get_style_context().add_class("dim-label");
+        details_box.add(&topic_label);
+    }
+
+    let members = if room.is_space() {
+        ni18n_f(
+            "Space · {} member",
+            "Space · {} members",
+            room.n_members as u32,
+            &[&room.n_members.to_string()],
+        )
+    } else {
+        ni18n_f(
+            "{} member",
+            "{} members",
+            room.n_members as u32,
+            &[&room.n_members.to_string()],
+        )
+    };
+    let members_label = gtk::Label::new(Some(&members));
+    members_label.set_xalign(0.0);
+    members_label.get_style_context().add_class("dim-label");
+    details_box.add(&members_label);
+
+    widget_box.pack_start(&details_box, true, true, 0);
+
+    let button = if space_room.joined {
+        let button = gtk::Button::with_label(&i18n("Open"));
+        // The joined subspaces are opened in the room list
+        let action = if room.is_space() {
+            "app.select-space"
+        } else {
+            "app.open-room"
+        };
+        button.set_action_target_value(Some(&room.id.as_str().to_variant()));
+        button.set_action_name(Some(action));
+        button
+    } else {
+        let button = gtk::Button::with_label(&i18n("Join"));
+        button.get_style_context().add_class("suggested-action");
+        let room_id = room.id.clone();
+        let via = space_room.via.clone();
+        button.connect_clicked(move |button| {
+            button.set_sensitive(false);
+            let session_client = session_client.clone();
+            let room_id = room_id.clone();
+            let via = via.clone();
+            RUNTIME.spawn(async move {
+                match room::join_room(session_client, &room_id.into(), &via).await {
+                    Ok(jtr) => {
+                        let jtr = Some(jtr);
+                        APPOP!(set_join_to_room, (jtr));
+                        APPOP!(reload_rooms);
+                    }
+                    Err(err) => err.handle_error(),
+                }
+            });
+        });
+        button
+    };
+    button.set_valign(gtk::Align::Center);
+    widget_box.pack_end(&button, false, false, 0);
+
+    if space_room.can_remove {
+        let remove_button =
+            gtk::Button::from_icon_name(Some("list-remove-symbolic"), gtk::IconSize::Button);
+        remove_button.set_tooltip_text(Some(&i18n("Remove From Space")));
+        remove_button.set_valign(gtk::Align::Center);
+        let target = format!("{} {}", space_room.parent, room.id);
+        remove_button.set_action_target_value(Some(&target.to_variant()));
+        remove_button.set_action_name(Some("app.remove-room-from-space"));
+        widget_box.pack_end(&remove_button, false, false, 0);
+    }
+
+    row.add(&widget_box);
+    row.show_all();
+
+    row
+}
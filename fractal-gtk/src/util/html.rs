@@ -0,0 +1,375 @@
+//! The parts of the HTML of messages html2pango doesn't render, picked out to be rendered
+//! by Fractal itself
+
+use crate::util::math;
+use html2pango::html_escape;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Starts an inline format in the text given to html2pango, followed by the format and
+/// `FORMAT_END`. Private use characters go through html2pango untouched.
+const FORMAT_START: char = '\u{E000}';
+const FORMAT_END: char = '\u{E001}';
+/// Closes the last inline format
+const FORMAT_CLOSE: char = '\u{E002}';
+
+const SPOILER_URI: &str = "spoiler:";
+
+lazy_static! {
+    static ref PART_REGEX: Regex = Regex::new(
+        r#"(?is)<pre\b[^>]*>.*?</pre>|<table\b[^>]*>.*?</table>|<details\b[^>]*>.*?</details>|<img\b[^>]*>|<div\b[^>]*\sdata-mx-maths="[^"]*"[^>]*>.*?</div>"#
+    )
+    .unwrap();
+    static ref CONTAINER_REGEX: Regex =
+        Regex::new(r"(?i)<(/?)(?:blockquote|ul|ol|details|table)\b").unwrap();
+    static ref CODE_REGEX: Regex =
+        Regex::new(r#"(?is)^<pre\b[^>]*>\s*(?:<code\b([^>]*)>)?(.*?)(?:</code>\s*)?</pre>$"#)
+            .unwrap();
+    static ref LANGUAGE_REGEX: Regex =
+        Regex::new(r#"(?i)class="(?:[^"]*\s)?language-([\w+#.-]+)"#).unwrap();
+    static ref ROW_REGEX: Regex = Regex::new(r"(?is)<tr\b[^>]*>(.*?)</tr>").unwrap();
+    static ref CELL_REGEX: Regex =
+        Regex::new(r"(?is)<t([hd])\b[^>]*>(.*?)</t[hd]>").unwrap();
+    static ref DETAILS_REGEX: Regex = Regex::new(
+        r"(?is)^<details\b[^>]*>\s*(?:<summary\b[^>]*>(.*?)</summary>)?(.*)</details>$"
+    )
+    .unwrap();
+    static ref MATH_SPAN_REGEX: Regex =
+        Regex::new(r#"(?is)<span\b[^>]*\sdata-mx-maths="([^"]*)"[^>]*>.*?</span>"#).unwrap();
+    static ref SPAN_REGEX: Regex = Regex::new(r"(?i)<(/?)(span|font)\b([^>]*)>").unwrap();
+    static ref BREAK_REGEX: Regex = Regex::new(r"(?i)<br\s*/?>|</p>").unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"<[^>]*>").unwrap();
+    static ref COLOR_REGEX: Regex = Regex::new(r"^#[0-9a-fA-F]{6}$").unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"\s([\w:-]+)\s*=\s*"([^"]*)""#).unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtmlPart {
+    /// HTML left to html2pango, with its inline formats marked by `mark_inline_formats`
+    Html(String),
+    Code {
+        lang: Option<String>,
+        text: String,
+    },
+    Table(Vec<Vec<TableCell>>),
+    Details {
+        summary: String,
+        content: String,
+    },
+    Image {
+        src: String,
+        alt: String,
+        size: Option<(i32, i32)>,
+    },
+    /// The LaTeX source of some math shown as a block
+    Math(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableCell {
+    pub header: bool,
+    pub html: String,
+}
+
+/// Splits the HTML of a message into the parts rendered by html2pango and the ones rendered
+/// apart. The parts nested in a quote or a list are left to html2pango.
+pub fn split(html: &str) -> Vec<HtmlPart> {
+    let mut parts = vec![];
+    let mut last = 0;
+
+    for m in PART_REGEX.find_iter(html) {
+        if m.start() < last || container_depth(&html[..m.start()]) > 0 {
+            continue;
+        }
+        let part = match parse_part(m.as_str()) {
+            Some(part) => part,
+            None => continue,
+        };
+
+        push_html(&mut parts, &html[last..m.start()]);
+        parts.push(part);
+        last = m.end();
+    }
+    push_html(&mut parts, &html[last..]);
+
+    parts
+}
+
+fn push_html(parts: &mut Vec<HtmlPart>, html: &str) {
+    if !html.trim().is_empty() {
+        parts.push(HtmlPart::Html(mark_inline_formats(html)));
+    }
+}
+
+/// How many blocks holding other blocks are left open at the end of `html`
+fn container_depth(html: &str) -> i32 {
+    CONTAINER_REGEX
+        .captures_iter(html)
+        .map(|c| if c[1].is_empty() { 1 } else { -1 })
+        .sum()
+}
+
+fn parse_part(html: &str) -> Option<HtmlPart> {
+    let lower = html.get(..4)?.to_lowercase();
+    if lower.starts_with("<pre") {
+        let captures = CODE_REGEX.captures(html)?;
+        let lang = captures
+            .get(1)
+            .and_then(|attrs| LANGUAGE_REGEX.captures(attrs.as_str()))
+            .map(|c| c[1].to_lowercase());
+        let text = unescape(&TAG_REGEX.replace_all(&captures[2], ""));
+        Some(HtmlPart::Code { lang, text })
+    } else if lower.starts_with("<tab") {
+        let rows: Vec<Vec<TableCell>> = ROW_REGEX
+            .captures_iter(html)
+            .map(|row| {
+                CELL_REGEX
+                    .captures_iter(&row[1])
+                    .map(|cell| TableCell {
+                        header: cell[1].eq_ignore_ascii_case("h"),
+                        html: cell[2].trim().to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+        if rows.is_empty() {
+            return None;
+        }
+        Some(HtmlPart::Table(rows))
+    } else if lower.starts_with("<det") {
+        let captures = DETAILS_REGEX.captures(html)?;
+        let summary = captures
+            .get(1)
+            .map(|s| to_plain(s.as_str()))
+            .unwrap_or_default();
+        Some(HtmlPart::Details {
+            summary,
+            content: captures[2].to_string(),
+        })
+    } else if lower.starts_with("<div") {
+        attribute(html, "data-mx-maths").map(HtmlPart::Math)
+    } else {
+        let src = attribute(html, "src")?;
+        let alt = attribute(html, "alt")
+            .or_else(|| attribute(html, "title"))
+            .unwrap_or_default();
+        let dimension = |name| attribute(html, name).and_then(|v| v.parse().ok());
+        let size = dimension("width").zip(dimension("height"));
+        Some(HtmlPart::Image { src, alt, size })
+    }
+}
+
+/// Gets the unescaped value of an attribute of an HTML tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    ATTRIBUTE_REGEX
+        .captures_iter(tag)
+        .find(|c| c[1].eq_ignore_ascii_case(name))
+        .map(|c| unescape(&c[2]))
+}
+
+/// Replaces the colours and spoilers of the HTML by marks html2pango keeps in its markup,
+/// to be turned into Pango markup by `apply_inline_formats`
+pub fn mark_inline_formats(html: &str) -> String {
+    // The LaTeX is hex encoded so nothing in it is taken for HTML
+    let html = MATH_SPAN_REGEX.replace_all(html, |c: &regex::Captures| {
+        let tex = unescape(&c[1]);
+        let hex: String = tex.bytes().map(|b| format!("{:02x}", b)).collect();
+        format!("{}m{}{}", FORMAT_START, hex, FORMAT_END)
+    });
+
+    // Whether each open span was replaced by a mark
+    let mut open: Vec<bool> = vec![];
+
+    SPAN_REGEX
+        .replace_all(&html, |c: &regex::Captures| {
+            let tag = &c[0];
+            if !c[1].is_empty() {
+                return match open.pop() {
+                    Some(true) => FORMAT_CLOSE.to_string(),
+                    _ => tag.to_string(),
+                };
+            }
+
+            let attrs = &c[3];
+            let format = if attrs.to_lowercase().contains("data-mx-spoiler") {
+                Some(String::from("s"))
+            } else {
+                let color = attribute(attrs, "data-mx-color").or_else(|| {
+                    Some(&c[2])
+                        .filter(|t| t.eq_ignore_ascii_case("font"))
+                        .and_then(|_| attribute(attrs, "color"))
+                });
+                let bg_color = attribute(attrs, "data-mx-bg-color");
+                let color = color.filter(|c| COLOR_REGEX.is_match(c));
+                let bg_color = bg_color.filter(|c| COLOR_REGEX.is_match(c));
+                if color.is_some() || bg_color.is_some() {
+                    Some(format!(
+                        "c{};{}",
+                        color.unwrap_or_default(),
+                        bg_color.unwrap_or_default()
+                    ))
+                } else {
+                    None
+                }
+            };
+
+            open.push(format.is_some());
+            match format {
+                Some(format) => format!("{}{}{}", FORMAT_START, format, FORMAT_END),
+                None => tag.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Turns the marks left by `mark_inline_formats` in Pango markup into Pango markup. The
+/// spoilers are hidden behind links, unless `revealed` says otherwise for their index. Math
+/// that can't be rendered is shown as LaTeX.
+pub fn apply_inline_formats(markup: &str, revealed: impl Fn(usize) -> bool) -> String {
+    let mut result = String::with_capacity(markup.len());
+    let mut closing: Vec<&str> = vec![];
+    let mut spoilers = 0;
+    let mut chars = markup.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            FORMAT_START => {
+                let format: String = chars.by_ref().take_while(|c| *c != FORMAT_END).collect();
+                if let Some(hex) = format.strip_prefix('m') {
+                    let tex = decode_hex(hex).unwrap_or_default();
+                    match math::to_markup(&tex) {
+                        Some(markup) => result.push_str(&markup),
+                        None => result.push_str(&format!("<tt>{}</tt>", html_escape(&tex))),
+                    }
+                } else if format == "s" {
+                    if revealed(spoilers) {
+                        result.push_str("<span>");
+                        closing.push("</span>");
+                    } else {
+                        result.push_str(&format!(
+                            "<a href=\"{}{}\"><span foreground=\"#7f7f7f\" background=\"#7f7f7f\">",
+                            SPOILER_URI, spoilers
+                        ));
+                        closing.push("</span></a>");
+                    }
+                    spoilers += 1;
+                } else {
+                    let mut colors = format.get(1..).unwrap_or_default().split(';');
+                    let mut span = String::from("<span");
+                    if let Some(color) = colors.next().filter(|c| !c.is_empty()) {
+                        span.push_str(&format!(" foreground=\"{}\"", color));
+                    }
+                    if let Some(color) = colors.next().filter(|c| !c.is_empty()) {
+                        span.push_str(&format!(" background=\"{}\"", color));
+                    }
+                    span.push('>');
+                    result.push_str(&span);
+                    closing.push("</span>");
+                }
+            }
+            FORMAT_CLOSE => result.push_str(closing.pop().unwrap_or_default()),
+            c => result.push(c),
+        }
+    }
+    for close in closing.into_iter().rev() {
+        result.push_str(close);
+    }
+
+    result
+}
+
+pub fn has_spoilers(markup: &str) -> bool {
+    markup.contains(&format!("{}s{}", FORMAT_START, FORMAT_END))
+}
+
+/// Gets the index of the spoiler a link of the markup made by `apply_inline_formats` reveals
+pub fn spoiler_index(uri: &str) -> Option<usize> {
+    uri.strip_prefix(SPOILER_URI)?.parse().ok()
+}
+
+/// The text of some HTML, with its line breaks kept
+pub fn to_plain(html: &str) -> String {
+    let text = BREAK_REGEX.replace_all(html, "\n");
+    unescape(TAG_REGEX.replace_all(&text, "").trim())
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let html = "<p>Look:</p><pre><code class=\"language-rust\">let a = &amp;b;\n</code></pre>\
+            <blockquote><pre><code>quoted</code></pre></blockquote>";
+        let parts = split(html);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], HtmlPart::Html(String::from("<p>Look:</p>")));
+        assert_eq!(
+            parts[1],
+            HtmlPart::Code {
+                lang: Some(String::from("rust")),
+                text: String::from("let a = &b;\n"),
+            }
+        );
+        assert_eq!(
+            parts[2],
+            HtmlPart::Html(String::from(
+                "<blockquote><pre><code>quoted</code></pre></blockquote>"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_inline_formats() {
+        let html = "<span data-mx-color=\"#ff0000\">red</span> <span data-mx-spoiler>secret</span>";
+        let marked = mark_inline_formats(html);
+        assert!(has_spoilers(&marked));
+
+        let hidden = apply_inline_formats(&marked, |_| false);
+        assert_eq!(
+            hidden,
+            "<span foreground=\"#ff0000\">red</span> <a href=\"spoiler:0\">\
+             <span foreground=\"#7f7f7f\" background=\"#7f7f7f\">secret</span></a>"
+        );
+        let shown = apply_inline_formats(&marked, |i| i == 0);
+        assert_eq!(
+            shown,
+            "<span foreground=\"#ff0000\">red</span> <span>secret</span>"
+        );
+        assert_eq!(spoiler_index("spoiler:0"), Some(0));
+
+        let marked = mark_inline_formats("<span data-mx-maths=\"x^2\"><code>x^2</code></span>");
+        assert_eq!(
+            apply_inline_formats(&marked, |_| false),
+            "<i>x</i><sup>2</sup>"
+        );
+    }
+
+    #[test]
+    fn test_attribute() {
+        let tag = r#"<img alt="a title=&quot;b&quot;" TITLE="c" src="mxc://example.org/id">"#;
+        assert_eq!(attribute(tag, "alt").as_deref(), Some(r#"a title="b""#));
+        assert_eq!(attribute(tag, "title").as_deref(), Some("c"));
+        assert_eq!(
+            attribute(tag, "src").as_deref(),
+            Some("mxc://example.org/id")
+        );
+        assert_eq!(attribute(tag, "width"), None);
+    }
+}
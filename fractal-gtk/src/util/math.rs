@@ -0,0 +1,491 @@
+//! Renders the LaTeX of the math in messages (MSC2191) as Pango markup, using Unicode symbols,
+//! superscripts and subscripts. Only the common part of LaTeX is understood, anything else
+//! makes the rendering fail so the source can be shown instead.
+
+use html2pango::html_escape;
+
+const GREEK: &[(&str, char)] = &[
+    ("alpha", 'α'),
+    ("beta", 'β'),
+    ("gamma", 'γ'),
+    ("delta", 'δ'),
+    ("epsilon", 'ϵ'),
+    ("varepsilon", 'ε'),
+    ("zeta", 'ζ'),
+    ("eta", 'η'),
+    ("theta", 'θ'),
+    ("vartheta", 'ϑ'),
+    ("iota", 'ι'),
+    ("kappa", 'κ'),
+    ("lambda", 'λ'),
+    ("mu", 'μ'),
+    ("nu", 'ν'),
+    ("xi", 'ξ'),
+    ("pi", 'π'),
+    ("varpi", 'ϖ'),
+    ("rho", 'ρ'),
+    ("varrho", 'ϱ'),
+    ("sigma", 'σ'),
+    ("varsigma", 'ς'),
+    ("tau", 'τ'),
+    ("upsilon", 'υ'),
+    ("phi", 'ϕ'),
+    ("varphi", 'φ'),
+    ("chi", 'χ'),
+    ("psi", 'ψ'),
+    ("omega", 'ω'),
+    ("Gamma", 'Γ'),
+    ("Delta", 'Δ'),
+    ("Theta", 'Θ'),
+    ("Lambda", 'Λ'),
+    ("Xi", 'Ξ'),
+    ("Pi", 'Π'),
+    ("Sigma", 'Σ'),
+    ("Upsilon", 'Υ'),
+    ("Phi", 'Φ'),
+    ("Psi", 'Ψ'),
+    ("Omega", 'Ω'),
+];
+
+const SYMBOLS: &[(&str, &str)] = &[
+    ("infty", "∞"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("cdot", "⋅"),
+    ("ast", "∗"),
+    ("star", "⋆"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("cong", "≅"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("propto", "∝"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("nexists", "∄"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("int", "∫"),
+    ("iint", "∬"),
+    ("iiint", "∭"),
+    ("oint", "∮"),
+    ("angle", "∠"),
+    ("perp", "⊥"),
+    ("parallel", "∥"),
+    ("mid", "∣"),
+    ("wedge", "∧"),
+    ("land", "∧"),
+    ("vee", "∨"),
+    ("lor", "∨"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("Re", "ℜ"),
+    ("Im", "ℑ"),
+    ("aleph", "ℵ"),
+    ("prime", "′"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("vert", "|"),
+    ("Vert", "‖"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("quad", "\u{2003}"),
+    ("qquad", "\u{2003}\u{2003}"),
+    (",", "\u{2009}"),
+    (":", "\u{205F}"),
+    (";", "\u{2004}"),
+    (" ", " "),
+    ("!", ""),
+    ("{", "{"),
+    ("}", "}"),
+    ("%", "%"),
+    ("$", "$"),
+    ("_", "_"),
+    ("&", "&"),
+    ("#", "#"),
+    ("\\", "\n"),
+];
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "dim",
+    "ker", "deg", "arg", "gcd", "hom", "Pr", "mod", "bmod",
+];
+
+/// The combining characters the accents are drawn with
+const ACCENTS: &[(&str, char)] = &[
+    ("hat", '\u{0302}'),
+    ("widehat", '\u{0302}'),
+    ("bar", '\u{0305}'),
+    ("overline", '\u{0305}'),
+    ("vec", '\u{20D7}'),
+    ("dot", '\u{0307}'),
+    ("ddot", '\u{0308}'),
+    ("tilde", '\u{0303}'),
+    ("widetilde", '\u{0303}'),
+];
+
+/// The sizes of delimiters, which are only shown at their usual size
+const SIZES: &[&str] = &[
+    "left",
+    "right",
+    "middle",
+    "big",
+    "Big",
+    "bigg",
+    "Bigg",
+    "bigl",
+    "bigr",
+    "Bigl",
+    "Bigr",
+    "displaystyle",
+    "textstyle",
+    "limits",
+    "nolimits",
+];
+
+/// The deepest nesting of groups and scripts rendered, the LaTeX being shown beyond it
+const MAX_DEPTH: usize = 32;
+
+/// Renders LaTeX math as Pango markup, or returns nothing when it uses unsupported commands
+/// or nests too deeply
+pub fn to_markup(tex: &str) -> Option<String> {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    parser.sequence(None)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The number of atoms being read, each one nested in the previous one
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads atoms with their scripts until `end` or the end of the text
+    fn sequence(&mut self, end: Option<char>) -> Option<String> {
+        let mut markup = String::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None if end.is_none() => return Some(markup),
+                None => return None,
+                Some(c) if Some(c) == end => {
+                    self.pos += 1;
+                    return Some(markup);
+                }
+                Some('}') => return None,
+                _ => {}
+            }
+
+            let atom = self.atom()?;
+            markup.push_str(&atom);
+            markup.push_str(&self.scripts()?);
+        }
+    }
+
+    /// Reads the superscripts and subscripts following an atom
+    fn scripts(&mut self) -> Option<String> {
+        let mut markup = String::new();
+        loop {
+            self.skip_spaces();
+            let tag = match self.peek() {
+                Some('^') => "sup",
+                Some('_') => "sub",
+                Some('\'') => {
+                    self.pos += 1;
+                    markup.push('′');
+                    continue;
+                }
+                _ => return Some(markup),
+            };
+            self.pos += 1;
+            self.skip_spaces();
+            let script = self.atom()?;
+            markup.push_str(&format!("<{}>{}</{}>", tag, script, tag));
+        }
+    }
+
+    /// Reads a group, a command or a character
+    fn atom(&mut self) -> Option<String> {
+        if self.depth >= MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let atom = self.nested_atom();
+        self.depth -= 1;
+        atom
+    }
+
+    fn nested_atom(&mut self) -> Option<String> {
+        match self.next()? {
+            '{' => self.sequence(Some('}')),
+            '\\' => self.command(),
+            '^' | '_' | '}' | '&' => None,
+            c if c.is_ascii_alphabetic() => Some(format!("<i>{}</i>", c)),
+            '-' => Some(String::from("\u{2009}−\u{2009}")),
+            c @ '+' | c @ '=' | c @ '<' | c @ '>' => {
+                Some(format!("\u{2009}{}\u{2009}", escape_char(c)))
+            }
+            c => Some(escape_char(c)),
+        }
+    }
+
+    /// Reads the argument of a command, returning its markup and whether it's a single
+    /// letter or number
+    fn argument(&mut self) -> Option<(String, bool)> {
+        self.skip_spaces();
+        let start = self.pos;
+        let markup = self.atom()?;
+        let source: String = self.chars[start..self.pos]
+            .iter()
+            .filter(|c| **c != '{' && **c != '}')
+            .collect();
+        let simple = !source.is_empty() && source.chars().all(char::is_alphanumeric);
+        Some((markup, simple))
+    }
+
+    /// Reads the text of a group as it is, for `\text` and the like. The text isn't escaped.
+    fn text_argument(&mut self) -> Option<String> {
+        self.skip_spaces();
+        if self.next()? != '{' {
+            return None;
+        }
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '}' => return Some(text),
+                '\\' => text.push(self.next()?),
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn command(&mut self) -> Option<String> {
+        let mut name: String = self
+            .chars
+            .get(self.pos..)?
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        if name.is_empty() {
+            name.push(self.peek()?);
+        }
+        self.pos += name.chars().count();
+
+        if let Some((_, c)) = GREEK.iter().find(|(n, _)| *n == name) {
+            return Some(c.to_string());
+        }
+        if let Some((_, s)) = SYMBOLS.iter().find(|(n, _)| *n == name) {
+            return Some(html_escape(s));
+        }
+        if FUNCTIONS.contains(&name.as_str()) {
+            return Some(format!("{}\u{2009}", name));
+        }
+        if let Some((_, accent)) = ACCENTS.iter().find(|(n, _)| *n == name) {
+            let (markup, _) = self.argument()?;
+            return Some(format!("{}{}", markup, accent));
+        }
+        if SIZES.contains(&name.as_str()) {
+            self.skip_spaces();
+            // `\left.` stands for no delimiter
+            if self.peek() == Some('.') {
+                self.pos += 1;
+            }
+            return Some(String::new());
+        }
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let (num, num_simple) = self.argument()?;
+                let (den, den_simple) = self.argument()?;
+                let wrap = |markup: String, simple| {
+                    if simple {
+                        markup
+                    } else {
+                        format!("({})", markup)
+                    }
+                };
+                Some(format!(
+                    "{}/{}",
+                    wrap(num, num_simple),
+                    wrap(den, den_simple)
+                ))
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                let index = if self.peek() == Some('[') {
+                    self.pos += 1;
+                    Some(self.sequence(Some(']'))?)
+                } else {
+                    None
+                };
+                let (radicand, simple) = self.argument()?;
+                let root = if simple {
+                    format!("√{}", radicand)
+                } else {
+                    format!("√({})", radicand)
+                };
+                Some(match index {
+                    Some(index) => format!("<sup>{}</sup>{}", index, root),
+                    None => root,
+                })
+            }
+            "text" | "textrm" | "mathrm" | "operatorname" | "mbox" => {
+                self.text_argument().map(|text| html_escape(&text))
+            }
+            "mathbf" | "textbf" | "boldsymbol" => {
+                let (markup, _) = self.argument()?;
+                Some(format!("<b>{}</b>", markup))
+            }
+            "mathit" | "textit" => {
+                let (markup, _) = self.argument()?;
+                Some(format!("<i>{}</i>", markup))
+            }
+            "mathbb" => self.text_argument().map(|text| {
+                let text: String = text.chars().map(double_struck).collect();
+                html_escape(&text)
+            }),
+            "mathcal" | "mathscr" | "mathsf" | "mathtt" => self.argument().map(|(m, _)| m),
+            _ => None,
+        }
+    }
+}
+
+fn escape_char(c: char) -> String {
+    let mut buf = [0; 4];
+    html_escape(c.encode_utf8(&mut buf))
+}
+
+fn double_struck(c: char) -> char {
+    let special = match c {
+        'C' => Some('ℂ'),
+        'H' => Some('ℍ'),
+        'N' => Some('ℕ'),
+        'P' => Some('ℙ'),
+        'Q' => Some('ℚ'),
+        'R' => Some('ℝ'),
+        'Z' => Some('ℤ'),
+        _ => None,
+    };
+    let offset = match c {
+        'A'..='Z' => Some(0x1D538 + c as u32 - 'A' as u32),
+        'a'..='z' => Some(0x1D552 + c as u32 - 'a' as u32),
+        '0'..='9' => Some(0x1D7D8 + c as u32 - '0' as u32),
+        _ => None,
+    };
+    special
+        .or_else(|| offset.and_then(std::char::from_u32))
+        .unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markup() {
+        assert_eq!(
+            to_markup("e^{i\\pi} + 1 = 0"),
+            Some(String::from(
+                "<i>e</i><sup><i>i</i>π</sup>\u{2009}+\u{2009}1\u{2009}=\u{2009}0"
+            ))
+        );
+        assert_eq!(
+            to_markup("\\frac{1}{n+1}"),
+            Some(String::from("1/(<i>n</i>\u{2009}+\u{2009}1)"))
+        );
+        assert_eq!(
+            to_markup("x_1 \\in \\mathbb{R}"),
+            Some(String::from("<i>x</i><sub>1</sub>∈ℝ"))
+        );
+        assert_eq!(to_markup("\\begin{matrix}"), None);
+        assert_eq!(to_markup("{x"), None);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |n| format!("{}x{}", "{".repeat(n), "}".repeat(n));
+        assert_eq!(
+            to_markup(&nested(MAX_DEPTH - 1)),
+            Some(String::from("<i>x</i>"))
+        );
+        assert_eq!(to_markup(&nested(MAX_DEPTH)), None);
+        assert_eq!(to_markup(&nested(100_000)), None);
+        assert_eq!(to_markup(&"x^".repeat(100_000)), None);
+        assert_eq!(to_markup(&"\\sqrt[".repeat(100_000)), None);
+    }
+}
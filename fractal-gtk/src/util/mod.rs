@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use crate::globals::CACHE_PATH;
+use crate::globals::{CACHE_PATH, MATRIX_TO_URL};
 use anyhow::anyhow;
 use anyhow::Error as AnyhowError;
 use gdk::prelude::*;
@@ -9,12 +9,18 @@ use gio::{Settings, SettingsExt, SettingsSchemaSource};
 use gtk::StyleContextExt;
 use html2pango::{html_escape, markup_links};
 use log::error;
+use matrix_sdk::identifiers::UserId;
+use std::convert::TryFrom;
 use std::fs::create_dir_all;
 use std::io::Error as IoError;
 use std::path::PathBuf;
 use std::sync::mpsc::SendError;
 
+pub mod html;
 pub mod i18n;
+pub mod math;
+pub mod time;
+pub mod upload;
 
 pub fn cache_dir_path(dir: Option<&str>, name: &str) -> Result<PathBuf, IoError> {
     let path = CACHE_PATH.join(dir.unwrap_or_default());
@@ -43,6 +49,21 @@ pub fn markup_text(s: &str) -> String {
     markup_links(&html_escape(s))
 }
 
+/// The matrix.to permalink used to mention a user
+pub fn mention_link(uid: &UserId) -> String {
+    format!("{}{}", MATRIX_TO_URL, uid)
+}
+
+/// Gets the user a matrix.to permalink points to, if any
+pub fn mention_user(href: &str) -> Option<UserId> {
+    let id = href.strip_prefix(MATRIX_TO_URL)?.split('?').next()?;
+    let id = id
+        .replace("%40", "@")
+        .replace("%3A", ":")
+        .replace("%3a", ":");
+    UserId::try_from(id.as_str()).ok()
+}
+
 pub fn get_markdown_schema() -> bool {
     SettingsSchemaSource::get_default()
         .and_then(|s| s.lookup("org.gnome.Fractal", true))
@@ -65,6 +86,286 @@ pub fn set_markdown_schema(md: bool) {
     }
 }
 
+pub fn get_cache_encryption_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("cache-encryption"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_cache_encryption_schema(enabled: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("cache-encryption", enabled) {
+            error!("Can't save cache encryption state: {:?}", err);
+        }
+    }
+}
+
+pub fn get_media_cache_size_schema() -> i32 {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_int("media-cache-size"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_media_cache_size_schema(size: i32) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_int("media-cache-size", size) {
+            error!("Can't save media cache size: {:?}", err);
+        }
+    }
+}
+
+pub fn get_strip_image_metadata_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("strip-image-metadata"))
+        })
+        .unwrap_or(true)
+}
+
+pub fn set_strip_image_metadata_schema(enabled: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("strip-image-metadata", enabled) {
+            error!("Can't save image metadata preference: {:?}", err);
+        }
+    }
+}
+
+pub fn get_formatting_toolbar_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("formatting-toolbar"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_formatting_toolbar_schema(shown: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("formatting-toolbar", shown) {
+            error!("Can't save formatting toolbar state: {:?}", err);
+        }
+    }
+}
+
+pub fn get_room_list_compact_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("room-list-compact"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_room_list_compact_schema(compact: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("room-list-compact", compact) {
+            error!("Can't save room list compact mode preference: {:?}", err);
+        }
+    }
+}
+
+pub fn get_url_previews_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("url-previews"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_url_previews_schema(enabled: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("url-previews", enabled) {
+            error!("Can't save URL previews preference: {:?}", err);
+        }
+    }
+}
+
+pub fn get_url_previews_encrypted_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("url-previews-encrypted"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_url_previews_encrypted_schema(enabled: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("url-previews-encrypted", enabled) {
+            error!(
+                "Can't save URL previews preference for encrypted rooms: {:?}",
+                err
+            );
+        }
+    }
+}
+
+pub fn get_sync_drafts_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("sync-drafts"))
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_sync_drafts_schema(enabled: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("sync-drafts", enabled) {
+            error!("Can't save draft sync preference: {:?}", err);
+        }
+    }
+}
+
+pub fn get_room_list_tags_schema() -> Vec<String> {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .map(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            settings
+                .get_strv("room-list-tags")
+                .into_iter()
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_room_list_tags_schema(tags: &[String]) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+        if let Err(err) = settings.set_strv("room-list-tags", &tags) {
+            error!("Can't save the room list tags: {:?}", err);
+        }
+    }
+}
+
+pub fn get_room_list_sort_schema() -> String {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .map(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            settings
+                .get_string("room-list-sort")
+                .map(String::from)
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_room_list_sort_schema(sort: &str) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_string("room-list-sort", sort) {
+            error!("Can't save the room list order: {:?}", err);
+        }
+    }
+}
+
+pub fn get_room_list_unread_only_schema() -> bool {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .and_then(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            Some(settings.get_boolean("room-list-unread-only"))
+        })
+        .unwrap_or(false)
+}
+
+pub fn set_room_list_unread_only_schema(unread_only: bool) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        if let Err(err) = settings.set_boolean("room-list-unread-only", unread_only) {
+            error!("Can't save the unread rooms filter: {:?}", err);
+        }
+    }
+}
+
+pub fn get_directory_servers_schema() -> Vec<String> {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .map(|_| {
+            let settings: Settings = Settings::new("org.gnome.Fractal");
+            settings
+                .get_strv("directory-servers")
+                .into_iter()
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn set_directory_servers_schema(servers: &[String]) {
+    if SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup("org.gnome.Fractal", true))
+        .is_some()
+    {
+        let settings: Settings = Settings::new("org.gnome.Fractal");
+        let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+        if let Err(err) = settings.set_strv("directory-servers", &servers) {
+            error!("Can't save the servers of the room directory: {:?}", err);
+        }
+    }
+}
+
 pub fn get_border_radius(ctx: &gtk::StyleContext) -> i32 {
     let state = ctx.get_state();
     gtk::StyleContextExt::get_property(ctx, "border-radius", state)
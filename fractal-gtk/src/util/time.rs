@@ -0,0 +1,123 @@
+use crate::util::i18n::{i18n, i18n_k, ni18n_f};
+use chrono::prelude::*;
+use gio::{Settings, SettingsExt, SettingsSchemaSource};
+
+const INTERFACE_SCHEMA: &str = "org.gnome.desktop.interface";
+
+/// The desktop settings holding the clock format, if their schema is installed
+pub fn interface_settings() -> Option<Settings> {
+    SettingsSchemaSource::get_default()
+        .and_then(|s| s.lookup(INTERFACE_SCHEMA, true))
+        .map(|_| Settings::new(INTERFACE_SCHEMA))
+}
+
+/// Whether the times are shown with AM/PM, as chosen in the desktop settings
+pub fn use_12h_clock() -> bool {
+    interface_settings()
+        .and_then(|settings| settings.get_string("clock-format"))
+        .map_or(false, |format| format.as_str() == "12h")
+}
+
+/// Formats the date with glib, which translates the names of the months and days
+pub fn format_local(date: &DateTime<Local>, format: &str) -> String {
+    let gdate = glib::DateTime::new_local(
+        date.year(),
+        date.month() as i32,
+        date.day() as i32,
+        date.hour() as i32,
+        date.minute() as i32,
+        date.second() as f64,
+    );
+
+    if let Some(gstr) = gdate.format(format) {
+        gstr.to_string()
+    } else {
+        // Fallback to a non glib time string
+        date.format(format).to_string()
+    }
+}
+
+/// The time of the day, in the clock format of the desktop
+pub fn time(date: &DateTime<Local>) -> String {
+    let format = if use_12h_clock() {
+        // Use 12h time format (AM/PM)
+        i18n("%l∶%M %p")
+    } else {
+        // Use 24 time format
+        i18n("%R")
+    };
+
+    date.format(&format).to_string().trim().to_string()
+}
+
+/// The date along with the time, for the tooltips of the timestamps
+pub fn full(date: &DateTime<Local>) -> String {
+    // Translators: This is a date format in the tooltip of the timestamps
+    let day = format_local(date, &i18n("%A, %B %e, %Y"));
+    i18n_k(
+        "{date} at {time}",
+        &[("date", day.trim()), ("time", &time(date))],
+    )
+}
+
+/// The day of the date, as shown between the messages of different days
+pub fn day(date: &DateTime<Local>, now: &DateTime<Local>) -> String {
+    let days = now.date().signed_duration_since(date.date()).num_days();
+    if days == 0 {
+        i18n("Today")
+    } else if days == 1 {
+        i18n("Yesterday")
+    } else if date.year() == now.year() {
+        // Translators: This is a date format in the day divider without the year
+        format_local(date, &i18n("%B %e"))
+    } else {
+        // Translators: This is a date format in the day divider with the year
+        format_local(date, &i18n("%B %e, %Y"))
+    }
+}
+
+/// How long ago the date was, roughly
+pub fn relative(date: &DateTime<Local>, now: &DateTime<Local>) -> String {
+    let elapsed = now.signed_duration_since(*date);
+    let days = now.date().signed_duration_since(date.date()).num_days();
+
+    if elapsed.num_minutes() < 1 {
+        i18n("Now")
+    } else if elapsed.num_hours() < 1 {
+        let n = elapsed.num_minutes();
+        ni18n_f("{} min ago", "{} min ago", n as u32, &[&n.to_string()])
+    } else if elapsed.num_days() < 1 {
+        let n = elapsed.num_hours();
+        ni18n_f("{} h ago", "{} h ago", n as u32, &[&n.to_string()])
+    } else if days == 1 {
+        i18n("Yesterday")
+    } else if days < 7 {
+        // Translators: This is the format of the weekday of the last activity of a room
+        format_local(date, &i18n("%A"))
+    } else if date.year() == now.year() {
+        // Translators: This is a date format of the last activity of a room without the year
+        format_local(date, &i18n("%b %e"))
+    } else {
+        // Translators: This is a date format of the last activity of a room with the year
+        format_local(date, &i18n("%b %e, %Y"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative() {
+        let now = Local.ymd(2021, 3, 10).and_hms(12, 0, 0);
+
+        let out = relative(&Local.ymd(2021, 3, 10).and_hms(11, 59, 30), &now);
+        assert_eq!(out, "Now");
+        let out = relative(&Local.ymd(2021, 3, 10).and_hms(11, 55, 0), &now);
+        assert_eq!(out, "5 min ago");
+        let out = relative(&Local.ymd(2021, 3, 10).and_hms(9, 30, 0), &now);
+        assert_eq!(out, "2 h ago");
+        let out = relative(&Local.ymd(2021, 3, 9).and_hms(8, 0, 0), &now);
+        assert_eq!(out, "Yesterday");
+    }
+}
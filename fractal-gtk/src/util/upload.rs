@@ -0,0 +1,415 @@
+use anyhow::Error;
+use gdk_pixbuf::{InterpType, Pixbuf};
+use gst::prelude::*;
+use log::error;
+use serde_json::json;
+use serde_json::Value as JsonValue;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Photos are scaled down to fit in a square of this side when they're sent compressed
+const COMPRESSED_SIDE: i32 = 2048;
+/// Photos over this size, in bytes, are offered to be sent compressed
+const LARGE_PHOTO_SIZE: u64 = 2 * 1024 * 1024;
+const COMPRESSED_QUALITY: &str = "85";
+/// Used when the photo has to be encoded again to keep how it looks without the metadata
+const REENCODED_QUALITY: &str = "95";
+
+const THUMBNAIL_WIDTH: i32 = 800;
+const THUMBNAIL_HEIGHT: i32 = 600;
+const THUMBNAIL_QUALITY: &str = "80";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub struct Thumbnail {
+    pub path: PathBuf,
+    pub width: i32,
+    pub height: i32,
+    pub size: u64,
+    pub mimetype: &'static str,
+}
+
+impl Thumbnail {
+    /// The `thumbnail_info` of the message
+    pub fn info(&self) -> JsonValue {
+        json!({
+            "w": self.width,
+            "h": self.height,
+            "size": self.size,
+            "mimetype": self.mimetype,
+        })
+    }
+}
+
+pub struct PreparedImage {
+    /// The file to upload, the original one if it didn't need any change
+    pub path: PathBuf,
+    pub mimetype: String,
+    pub width: i32,
+    pub height: i32,
+    pub size: u64,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+/// Whether the photo at `path` is big enough to be worth sending compressed
+pub fn is_large_photo(path: &Path) -> bool {
+    let size = fs::metadata(path).map(|md| md.len()).unwrap_or_default();
+    let is_photo = Pixbuf::get_file_info(path)
+        .and_then(|(format, _, _)| format.get_name())
+        .map_or(false, |name| name == "jpeg" || name == "png");
+
+    is_photo && size > LARGE_PHOTO_SIZE
+}
+
+/// Gets the image at `path` ready to be sent, with its thumbnail. When `strip_metadata`
+/// is set, the EXIF data and the text chunks are removed, and when `compress` is set,
+/// large photos are scaled down and encoded again.
+///
+/// Only JPEG and PNG files are changed, other formats are sent as they are.
+pub fn prepare_image(
+    path: &Path,
+    mimetype: &str,
+    strip_metadata: bool,
+    compress: bool,
+) -> Result<PreparedImage, Error> {
+    let original = Pixbuf::from_file(path)?;
+    let rotated = original
+        .get_option("orientation")
+        .map_or(false, |orientation| orientation != "1");
+    let image = original
+        .apply_embedded_orientation()
+        .unwrap_or_else(|| original.clone());
+
+    let is_jpeg = mimetype == "image/jpeg";
+    let is_png = mimetype == "image/png";
+
+    let mut prepared = PreparedImage {
+        path: path.to_path_buf(),
+        mimetype: mimetype.to_string(),
+        width: image.get_width(),
+        height: image.get_height(),
+        size: fs::metadata(path)?.len(),
+        thumbnail: None,
+    };
+
+    if (is_jpeg || is_png) && compress && is_large_photo(path) {
+        let scaled = scale_to_fit(&image, COMPRESSED_SIDE, COMPRESSED_SIDE);
+        // Photos with transparency stay in PNG, everything else is better off as JPEG
+        let (data, mimetype) = encode(&scaled, COMPRESSED_QUALITY)?;
+        prepared.path = write_temp_file(path, mimetype, &data)?;
+        prepared.mimetype = mimetype.to_string();
+        prepared.width = scaled.get_width();
+        prepared.height = scaled.get_height();
+        prepared.size = data.len() as u64;
+    } else if (is_jpeg || is_png) && strip_metadata {
+        // Without the EXIF data the orientation would be lost, so those
+        // have to be rotated and encoded again
+        let stripped = if rotated {
+            None
+        } else {
+            let data = fs::read(path)?;
+            let stripped = if is_jpeg {
+                strip_jpeg_metadata(&data)
+            } else {
+                strip_png_metadata(&data)
+            };
+            stripped.map(|stripped| (stripped.len() != data.len(), stripped))
+        };
+
+        let new_data = match stripped {
+            Some((true, stripped)) => Some((stripped, mimetype)),
+            // There was nothing to remove
+            Some((false, _)) => None,
+            None if is_png => Some((image.save_to_bufferv("png", &[])?, "image/png")),
+            None => Some((
+                image.save_to_bufferv("jpeg", &[("quality", REENCODED_QUALITY)])?,
+                "image/jpeg",
+            )),
+        };
+
+        if let Some((data, mimetype)) = new_data {
+            prepared.path = write_temp_file(path, mimetype, &data)?;
+            prepared.size = data.len() as u64;
+        }
+    }
+
+    prepared.thumbnail = create_thumbnail(&image);
+
+    Ok(prepared)
+}
+
+/// Grabs a frame of the video at `uri` to use as its thumbnail, the one a second in if
+/// the video is long enough, since the first one is often black.
+pub fn create_video_thumbnail(uri: &Url) -> Option<Thumbnail> {
+    let pipeline = gst::parse_launch(&format!(
+        "uridecodebin uri={} ! videoconvert ! videoscale ! gdkpixbufsink name=sink",
+        uri
+    ))
+    .ok()?
+    .downcast::<gst::Pipeline>()
+    .ok()?;
+    let sink = pipeline.get_by_name("sink")?;
+
+    let frame = grab_frame(&pipeline, &sink);
+    let _ = pipeline.set_state(gst::State::Null);
+
+    create_thumbnail(&frame?)
+}
+
+fn grab_frame(pipeline: &gst::Pipeline, sink: &gst::Element) -> Option<Pixbuf> {
+    let timeout = gst::ClockTime::from_seconds(5);
+
+    pipeline.set_state(gst::State::Paused).ok()?;
+    pipeline.get_state(timeout).0.ok()?;
+
+    let flags = gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT;
+    if pipeline
+        .seek_simple(flags, gst::ClockTime::from_seconds(1))
+        .is_ok()
+    {
+        pipeline.get_state(timeout).0.ok()?;
+    }
+
+    sink.get_property("last-pixbuf")
+        .ok()?
+        .get::<Pixbuf>()
+        .ok()?
+}
+
+fn create_thumbnail(image: &Pixbuf) -> Option<Thumbnail> {
+    let thumb = scale_to_fit(image, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT);
+    let (data, mimetype) = encode(&thumb, THUMBNAIL_QUALITY).ok()?;
+    let path = write_temp_file(Path::new("thumbnail"), mimetype, &data).ok()?;
+
+    Some(Thumbnail {
+        path,
+        width: thumb.get_width(),
+        height: thumb.get_height(),
+        size: data.len() as u64,
+        mimetype,
+    })
+}
+
+fn scale_to_fit(image: &Pixbuf, width: i32, height: i32) -> Pixbuf {
+    let (w, h) = (image.get_width(), image.get_height());
+    if w <= width && h <= height {
+        return image.clone();
+    }
+
+    let scale = f64::min(width as f64 / w as f64, height as f64 / h as f64);
+    let new_width = ((w as f64 * scale) as i32).max(1);
+    let new_height = ((h as f64 * scale) as i32).max(1);
+
+    image
+        .scale_simple(new_width, new_height, InterpType::Bilinear)
+        .unwrap_or_else(|| image.clone())
+}
+
+fn encode(image: &Pixbuf, quality: &str) -> Result<(Vec<u8>, &'static str), Error> {
+    if image.get_has_alpha() {
+        Ok((image.save_to_bufferv("png", &[])?, "image/png"))
+    } else {
+        let data = image.save_to_bufferv("jpeg", &[("quality", quality)])?;
+        Ok((data, "image/jpeg"))
+    }
+}
+
+/// Where the files made to be sent are kept, every one of them in its own directory
+fn temp_root() -> PathBuf {
+    glib::get_tmp_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("fractal-uploads")
+}
+
+/// Creates a new directory for a file made to be sent, so it can keep a nice name
+pub fn create_temp_dir() -> Result<PathBuf, Error> {
+    let path = temp_root().join(rand::random::<u32>().to_string());
+    fs::create_dir_all(&path)?;
+
+    Ok(path)
+}
+
+/// Removes the file at `path` along with its directory if it's one we made to be sent,
+/// the files chosen by the user are left alone
+pub fn remove_temp_file(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if dir.parent() == Some(temp_root().as_path()) => dir,
+        _ => return,
+    };
+
+    if let Err(err) = fs::remove_dir_all(dir) {
+        error!("Can't remove {}: {}", dir.display(), err);
+    }
+}
+
+/// Writes `data` to a file in its own temporary directory, so it can keep the name
+/// of `original`, with the extension of `mimetype`
+fn write_temp_file(original: &Path, mimetype: &str, data: &[u8]) -> Result<PathBuf, Error> {
+    let mut path = create_temp_dir()?;
+
+    let extension = if mimetype == "image/png" {
+        "png"
+    } else {
+        "jpg"
+    };
+    let name = original.file_stem().unwrap_or_else(|| "image".as_ref());
+    path.push(name);
+    path.set_extension(extension);
+    fs::write(&path, data)?;
+
+    Ok(path)
+}
+
+/// Removes the APP1 (EXIF and XMP) and APP13 (IPTC) segments of a JPEG file.
+/// Returns `None` if the file can't be parsed.
+fn strip_jpeg_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut stripped = Vec::with_capacity(data.len());
+    stripped.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    loop {
+        let marker = data.get(pos..pos + 2)?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+
+        // Start of scan, the image data and everything after it are kept as they are
+        if marker[1] == 0xDA {
+            stripped.extend_from_slice(&data[pos..]);
+            return Some(stripped);
+        }
+
+        let len = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        let segment = data.get(pos..pos + 2 + len)?;
+        if marker[1] != 0xE1 && marker[1] != 0xED {
+            stripped.extend_from_slice(segment);
+        }
+        pos += segment.len();
+    }
+}
+
+/// Removes the eXIf and text chunks of a PNG file.
+/// Returns `None` if the file can't be parsed.
+fn strip_png_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC
+        let chunk = data.get(pos..pos + 12 + len)?;
+        match &chunk[4..8] {
+            b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" => {}
+            _ => stripped.extend_from_slice(chunk),
+        }
+        pos += chunk.len();
+    }
+
+    Some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gdk_pixbuf::{Colorspace, PixbufLoader, PixbufLoaderExt};
+
+    fn test_image() -> Pixbuf {
+        let image = Pixbuf::new(Colorspace::Rgb, false, 8, 4, 3).unwrap();
+        image.fill(0x3366_99ff);
+        image
+    }
+
+    fn decode(data: &[u8]) -> Pixbuf {
+        let loader = PixbufLoader::new();
+        loader.write(data).unwrap();
+        loader.close().unwrap();
+        loader.get_pixbuf().unwrap()
+    }
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn png_chunk(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(payload);
+        // The CRC isn't checked, those chunks are gone before decoding the image
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn strip_jpeg() {
+        let original = test_image().save_to_bufferv("jpeg", &[]).unwrap();
+        let mut data = original[..2].to_vec();
+        data.extend(jpeg_segment(0xE1, b"Exif\0\0GPS position"));
+        data.extend(jpeg_segment(0xED, b"Photoshop 3.0\0IPTC caption"));
+        data.extend_from_slice(&original[2..]);
+
+        let stripped = strip_jpeg_metadata(&data).unwrap();
+        assert!(!contains(&stripped, b"GPS position"));
+        assert!(!contains(&stripped, b"IPTC caption"));
+        assert_eq!(stripped.len(), original.len());
+
+        let image = decode(&stripped);
+        assert_eq!((image.get_width(), image.get_height()), (4, 3));
+    }
+
+    #[test]
+    fn strip_jpeg_keeps_jfif() {
+        let original = test_image().save_to_bufferv("jpeg", &[]).unwrap();
+        // The header of the JFIF files is an APP0 segment, which isn't metadata
+        assert!(original[2..4] == [0xFF, 0xE0]);
+        assert_eq!(strip_jpeg_metadata(&original).unwrap(), original);
+    }
+
+    #[test]
+    fn strip_png() {
+        let original = test_image().save_to_bufferv("png", &[]).unwrap();
+        // The signature and the IHDR chunk come first
+        let ihdr_end = PNG_SIGNATURE.len() + 12 + 13;
+        let mut data = original[..ihdr_end].to_vec();
+        data.extend(png_chunk(b"tEXt", b"Comment\0Taken at home"));
+        data.extend(png_chunk(b"eXIf", b"MM\0*GPS position"));
+        data.extend(png_chunk(b"iTXt", b"Author\0\0\0\0\0Someone"));
+        data.extend_from_slice(&original[ihdr_end..]);
+
+        let stripped = strip_png_metadata(&data).unwrap();
+        assert!(!contains(&stripped, b"Taken at home"));
+        assert!(!contains(&stripped, b"GPS position"));
+        assert!(!contains(&stripped, b"Someone"));
+        assert!(!contains(&stripped, b"tEXt"));
+        assert!(!contains(&stripped, b"eXIf"));
+
+        let image = decode(&stripped);
+        assert_eq!((image.get_width(), image.get_height()), (4, 3));
+    }
+
+    #[test]
+    fn strip_invalid_data() {
+        assert_eq!(strip_jpeg_metadata(b"not a jpeg"), None);
+        assert_eq!(strip_png_metadata(b"not a png"), None);
+
+        // Truncated in the middle of a segment or a chunk
+        let jpeg = test_image().save_to_bufferv("jpeg", &[]).unwrap();
+        assert_eq!(strip_jpeg_metadata(&jpeg[..6]), None);
+        let png = test_image().save_to_bufferv("png", &[]).unwrap();
+        assert_eq!(strip_png_metadata(&png[..PNG_SIGNATURE.len() + 10]), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::util::i18n::i18n;
+use crate::util::upload::is_large_photo;
+use gdk_pixbuf::Pixbuf;
+use gio::prelude::*;
+use gtk::prelude::*;
+use std::path::{Path, PathBuf};
+
+const THUMBNAIL_SIZE: i32 = 64;
+
+/// Row of the files waiting to be sent in the active room, shown above the message entry.
+/// Every file has a button to remove it from the list, which activates
+/// `app.remove-attachment` with the path of the file.
+#[derive(Debug, Clone)]
+pub struct AttachmentTray {
+    pub container: gtk::Revealer,
+    list: gtk::Box,
+    compress: gtk::CheckButton,
+}
+
+impl Default for AttachmentTray {
+    fn default() -> Self {
+        let container = gtk::Revealer::new();
+        container.set_transition_type(gtk::RevealerTransitionType::SlideUp);
+
+        let tray = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        tray.get_style_context().add_class("attachment-tray");
+
+        let hadjust: Option<&gtk::Adjustment> = None;
+        let vadjust: Option<&gtk::Adjustment> = None;
+        let scroll = gtk::ScrolledWindow::new(hadjust, vadjust);
+        scroll.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
+
+        let list = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        scroll.add(&list);
+        tray.pack_start(&scroll, false, false, 0);
+
+        let hint = gtk::Label::new(Some(
+            i18n("Any text in the message entry is sent as a caption after the files").as_str(),
+        ));
+        hint.set_xalign(0.0);
+        hint.set_line_wrap(true);
+        hint.get_style_context().add_class("dim-label");
+        tray.pack_start(&hint, false, false, 0);
+
+        let compress = gtk::CheckButton::with_label(i18n("Send large photos compressed").as_str());
+        compress.set_active(true);
+        tray.pack_start(&compress, false, false, 0);
+
+        container.add(&tray);
+        container.show_all();
+        compress.hide();
+        container.set_reveal_child(false);
+
+        Self {
+            container,
+            list,
+            compress,
+        }
+    }
+}
+
+impl AttachmentTray {
+    pub fn set_attachments(&self, attachments: &[PathBuf]) {
+        for child in self.list.get_children() {
+            self.list.remove(&child);
+        }
+
+        for path in attachments {
+            self.list.add(&create_item(path));
+        }
+
+        self.list.show_all();
+        self.container.set_reveal_child(!attachments.is_empty());
+
+        if attachments.is_empty() {
+            self.compress.set_active(true);
+        }
+        // The choice is only offered when it makes a difference
+        self.compress
+            .set_visible(attachments.iter().any(|path| is_large_photo(path)));
+    }
+
+    /// Whether the large photos should be scaled down before sending them,
+    /// instead of sending the original files
+    pub fn compress_photos(&self) -> bool {
+        self.compress.get_active()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.get_children().is_empty()
+    }
+}
+
+fn create_item(path: &Path) -> gtk::Box {
+    let item = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    item.get_style_context().add_class("attachment");
+
+    // The icon of the file is shown until the thumbnail is loaded, or if it's not an image
+    let preview = file_icon(path);
+    preview.set_size_request(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let file = gio::File::new_for_path(path);
+    let image = preview.clone();
+    glib::MainContext::default().spawn_local(async move {
+        if let Some(pb) = load_thumbnail(file).await {
+            image.set_from_pixbuf(Some(&pb));
+        }
+    });
+
+    let remove = gtk::Button::from_icon_name(Some("window-close-symbolic"), gtk::IconSize::Button);
+    remove.set_tooltip_text(Some(i18n("Remove").as_str()));
+    remove.set_halign(gtk::Align::End);
+    remove.set_valign(gtk::Align::Start);
+    remove.get_style_context().add_class("osd");
+    remove.get_style_context().add_class("circular");
+    let data = glib::Variant::from(path.to_string_lossy().as_ref());
+    remove.set_action_target_value(Some(&data));
+    remove.set_action_name(Some("app.remove-attachment"));
+
+    let overlay = gtk::Overlay::new();
+    overlay.add(&preview);
+    overlay.add_overlay(&remove);
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let label = gtk::Label::new(Some(&name));
+    label.set_ellipsize(pango::EllipsizeMode::Middle);
+    label.set_max_width_chars(12);
+    label.set_tooltip_text(Some(&name));
+
+    item.pack_start(&overlay, false, false, 0);
+    item.pack_start(&label, false, false, 0);
+
+    item
+}
+
+async fn load_thumbnail(file: gio::File) -> Option<Pixbuf> {
+    let stream = file.read_async_future(glib::PRIORITY_LOW).await.ok()?;
+    Pixbuf::from_stream_at_scale_async_future(&stream, THUMBNAIL_SIZE, THUMBNAIL_SIZE, true)
+        .await
+        .ok()?
+        .apply_embedded_orientation()
+}
+
+fn file_icon(path: &Path) -> gtk::Image {
+    let icon = gio::File::new_for_path(path)
+        .query_info(
+            &gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+            gio::FileQueryInfoFlags::NONE,
+            gio::NONE_CANCELLABLE,
+        )
+        .ok()
+        .and_then(|info| info.get_content_type())
+        .and_then(|mime| gio::content_type_get_icon(&mime));
+
+    match icon {
+        Some(icon) => gtk::Image::from_gicon(&icon, gtk::IconSize::Dialog),
+        None => gtk::Image::from_icon_name(Some("text-x-generic"), gtk::IconSize::Dialog),
+    }
+}
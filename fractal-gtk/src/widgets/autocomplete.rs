@@ -1,22 +1,102 @@
 use crate::app::AppRuntime;
-use crate::appop::{member::member_level, AppOp};
+use crate::appop::{member::member_level, AppOp, UserInfoCache};
+use crate::globals;
+use crate::model::command::Command;
 use crate::model::member::Member;
-use crate::ui::member::build_memberbox_widget;
+use crate::ui::member::{build_memberbox_pill, build_memberbox_widget};
+use crate::widgets;
+use crate::widgets::AvatarExt;
 use glib::clone;
 use gtk::prelude::*;
-use gtk::TextTag;
 use log::info;
+use matrix_sdk::identifiers::{RoomAliasId, RoomId};
+use matrix_sdk::Client as MatrixClient;
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
+mod commands;
+mod emoji;
+mod emoji_data;
+mod members;
+mod rooms;
+
+/// The most items shown in the popover
+const MAX_COMPLETIONS: usize = 5;
+
+/// An item that can replace the word being completed
+#[derive(Clone, Debug)]
+pub enum Completion {
+    Member(Member),
+    Room {
+        id: RoomId,
+        alias: RoomAliasId,
+        name: Option<String>,
+    },
+    Emoji {
+        shortcode: &'static str,
+        emoji: &'static str,
+    },
+    Command(Command),
+}
+
+/// A source of completions for the words starting with a given character
+pub trait Provider {
+    /// The character starting the words this provider completes
+    fn trigger(&self) -> char;
+
+    /// Whether the words are only completed at the start of the message
+    fn only_at_start(&self) -> bool {
+        false
+    }
+
+    /// The length the query must have before any completion is shown
+    fn min_query_len(&self) -> usize {
+        0
+    }
+
+    /// The completions for the query typed after the trigger, the best ones first
+    fn complete(&self, query: &str, op: &AppOp) -> Vec<Completion>;
+}
+
+/// Scores how well `query` matches `candidate`, ignoring the case.
+/// Prefixes rank over the start of a word, then any substring, then the other
+/// candidates containing all the characters of the query in order.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let candidate = candidate.to_lowercase();
+
+    if candidate.starts_with(&query) {
+        return Some(3);
+    }
+
+    if let Some(pos) = candidate.find(&query) {
+        let word_start = candidate[..pos]
+            .chars()
+            .last()
+            .map_or(true, |c| !c.is_alphanumeric());
+        return Some(if word_start { 2 } else { 1 });
+    }
+
+    let mut candidate_chars = candidate.chars();
+    if query
+        .chars()
+        .all(|q| candidate_chars.by_ref().any(|c| c == q))
+    {
+        Some(0)
+    } else {
+        None
+    }
+}
+
 pub struct Autocomplete {
     app_runtime: AppRuntime,
     entry: sourceview4::View,
     listbox: gtk::ListBox,
     popover: gtk::Popover,
     window: gtk::Window,
-    highlighted_entry: Vec<String>,
+    mentions: Rc<RefCell<Vec<(Member, gtk::TextChildAnchor)>>>,
+    /// The first provider also completes the words without trigger, with Tab
+    providers: Vec<Box<dyn Provider>>,
     popover_position: Option<i32>,
     popover_search: Option<String>,
     popover_closing: bool,
@@ -29,6 +109,7 @@ impl Autocomplete {
         entry: sourceview4::View,
         popover: gtk::Popover,
         listbox: gtk::ListBox,
+        mentions: Rc<RefCell<Vec<(Member, gtk::TextChildAnchor)>>>,
     ) -> Autocomplete {
         Autocomplete {
             app_runtime,
@@ -36,7 +117,13 @@ impl Autocomplete {
             listbox,
             popover,
             window,
-            highlighted_entry: vec![],
+            mentions,
+            providers: vec![
+                Box::new(members::MemberProvider),
+                Box::new(rooms::RoomProvider),
+                Box::new(emoji::EmojiProvider),
+                Box::new(commands::CommandProvider),
+            ],
             popover_position: None,
             popover_search: None,
             popover_closing: false,
@@ -47,25 +134,6 @@ impl Autocomplete {
         let app_runtime = self.app_runtime.clone();
         let this: Rc<RefCell<Autocomplete>> = Rc::new(RefCell::new(self));
 
-        let context = this.borrow().entry.get_style_context();
-        if let Some(fg) = context.lookup_color("theme_selected_bg_color") {
-            let color = gdk::RGBA {
-                red: fg.red,
-                green: fg.green,
-                blue: fg.blue,
-                alpha: 1.0,
-            };
-
-            let tag = TextTag::new(Some("alias-highlight"));
-            tag.set_property_foreground_rgba(Some(&color));
-
-            if let Some(buffer) = this.borrow().entry.get_buffer() {
-                if let Some(tag_table) = buffer.get_tag_table() {
-                    tag_table.add(&tag);
-                }
-            }
-        }
-
         let window = &this.borrow().window;
         this.borrow()
             .popover
@@ -88,34 +156,6 @@ impl Autocomplete {
                 }
             });
 
-        let own = this.clone();
-        if let Some(buffer) = this.borrow().entry.get_buffer() {
-            buffer.connect_property_cursor_position_notify(move |buffer| {
-                if let Ok(item) = own.try_borrow() {
-                    let start_iter = buffer.get_start_iter();
-                    let end_iter = buffer.get_end_iter();
-
-                    if let Some(input) = buffer.get_text(&start_iter, &end_iter, false) {
-                        item.add_highlight(input.to_string());
-                    }
-                }
-            });
-        }
-
-        let own = this.clone();
-        if let Some(buffer) = this.borrow().entry.get_buffer() {
-            buffer.connect_changed(move |buffer| {
-                if let Ok(item) = own.try_borrow() {
-                    let start_iter = buffer.get_start_iter();
-                    let end_iter = buffer.get_end_iter();
-
-                    if let Some(input) = buffer.get_text(&start_iter, &end_iter, false) {
-                        item.add_highlight(input.to_string());
-                    }
-                }
-            });
-        }
-
         let own = this.clone();
         if let Some(buffer) = this.borrow().entry.get_buffer() {
             buffer.connect_delete_range(move |_, start_iter, end_iter| {
@@ -216,8 +256,10 @@ impl Autocomplete {
 
                 let start = buffer.get_start_iter();
                 let end = buffer.get_end_iter();
+                // The slice keeps a placeholder for the mention pills, so the
+                // character offsets match the ones of the buffer
                 let text = buffer
-                    .get_text(&start, &end, false)
+                    .get_slice(&start, &end, false)
                     .map(|gstr| gstr.to_string());
 
                 /* when closing popover with tab */
@@ -258,16 +300,21 @@ impl Autocomplete {
                             let (p1, _) = graphs.split_at(pos as usize);
                             let first = p1.iter().collect::<String>();
                             if own.borrow().popover_position.is_none() {
-                                if !is_tab {
-                                    if let Some(at_pos) = first.rfind('@') {
-                                        own.borrow_mut().popover_position = Some(at_pos as i32);
-                                    }
-                                } else if let Some(space_pos) =
-                                    first.rfind(|c: char| c.is_whitespace())
+                                let (word_start, word) = match first
+                                    .char_indices()
+                                    .rev()
+                                    .find(|(_, c)| c.is_whitespace())
                                 {
-                                    own.borrow_mut().popover_position = Some(space_pos as i32 + 1);
-                                } else {
-                                    own.borrow_mut().popover_position = Some(0);
+                                    Some((i, c)) => (
+                                        first[..i].chars().count() + 1,
+                                        &first[i + c.len_utf8()..],
+                                    ),
+                                    None => (0, first.as_str()),
+                                };
+
+                                if is_tab || own.borrow().get_provider(word, word_start).is_some()
+                                {
+                                    own.borrow_mut().popover_position = Some(word_start as i32);
                                 }
                             }
                         }
@@ -280,12 +327,23 @@ impl Autocomplete {
                                 let widget_list = own
                                     .borrow_mut()
                                     .autocomplete_show_popover(list, state);
-                                for (alias, widget) in widget_list.iter() {
+                                let session_client = unwrap_or_unit_return!(state
+                                    .login_data
+                                    .as_ref()
+                                    .map(|ld| ld.session_client.clone()));
+                                let user_info_cache = state.user_info_cache.clone();
+                                for (completion, widget) in widget_list.iter() {
                                     widget.connect_key_press_event(clone!(
                                     @strong own,
-                                    @strong alias
+                                    @strong completion,
+                                    @strong session_client,
+                                    @strong user_info_cache
                                     => move |_, ev| {
-                                        own.borrow_mut().autocomplete_insert(alias.clone());
+                                        own.borrow_mut().autocomplete_insert(
+                                            completion.clone(),
+                                            session_client.clone(),
+                                            user_info_cache.clone(),
+                                        );
                                         let ev = ev
                                             .downcast_ref::<gdk::EventKey>()
                                             .unwrap();
@@ -300,9 +358,15 @@ impl Autocomplete {
 
                                     widget.connect_button_press_event(clone!(
                                     @strong own,
-                                    @strong alias
+                                    @strong completion,
+                                    @strong session_client,
+                                    @strong user_info_cache
                                     => move |_, _| {
-                                        own.borrow_mut().autocomplete_insert(alias.clone());
+                                        own.borrow_mut().autocomplete_insert(
+                                            completion.clone(),
+                                            session_client.clone(),
+                                            user_info_cache.clone(),
+                                        );
                                         own.borrow_mut().autocomplete_enter();
                                         Inhibit(true)
                                     }));
@@ -317,44 +381,54 @@ impl Autocomplete {
         });
     }
 
-    pub fn autocomplete_insert(&mut self, alias: String) {
+    /// Replaces the text being completed, inserting members as pills
+    pub fn autocomplete_insert(
+        &mut self,
+        completion: Completion,
+        session_client: MatrixClient,
+        user_info_cache: UserInfoCache,
+    ) {
         if let Some(start_pos) = self.popover_position {
             if let Some(buffer) = self.entry.get_buffer() {
                 if let Some(mark) = buffer.get_insert() {
                     let mut start_iter = buffer.get_iter_at_offset(start_pos as i32);
                     let mut end_iter = buffer.get_iter_at_mark(&mark);
                     buffer.delete(&mut start_iter, &mut end_iter);
-                    buffer.insert(&mut start_iter, &alias);
-                    buffer.place_cursor(&start_iter);
-                }
-            }
-
-            /* highlight member inside the entry */
-            /* we need to set the highlight here the first time
-             * because the ui changes from others are blocked as long we hold the look */
-            if let Some(buffer) = self.entry.get_buffer() {
-                self.highlighted_entry.push(alias);
 
-                let start_iter = buffer.get_start_iter();
-                let end_iter = buffer.get_end_iter();
+                    match completion {
+                        Completion::Member(member) => {
+                            if let Some(anchor) = buffer.create_child_anchor(&mut start_iter) {
+                                let pill = build_memberbox_pill(
+                                    session_client,
+                                    user_info_cache,
+                                    member.clone(),
+                                );
+                                self.entry.add_child_at_anchor(&pill, &anchor);
+
+                                let mut mentions = self.mentions.borrow_mut();
+                                mentions.retain(|(_, anchor)| !anchor.get_deleted());
+                                mentions.push((member, anchor));
+                            }
+                            buffer.insert(&mut start_iter, " ");
+                        }
+                        Completion::Room { alias, .. } => {
+                            buffer.insert(&mut start_iter, &format!("{} ", alias));
+                        }
+                        Completion::Emoji { emoji, .. } => {
+                            buffer.insert(&mut start_iter, emoji);
+                        }
+                        Completion::Command(command) => {
+                            buffer.insert(&mut start_iter, &format!("/{} ", command.name()));
+                        }
+                    }
 
-                if let Some(input) = buffer.get_text(&start_iter, &end_iter, false) {
-                    self.add_highlight(input.to_string());
+                    buffer.place_cursor(&start_iter);
                 }
             }
         }
     }
 
     pub fn autocomplete_enter(&mut self) -> bool {
-        if let Some(buffer) = self.entry.get_buffer() {
-            let start_iter = buffer.get_start_iter();
-            let end_iter = buffer.get_end_iter();
-
-            if let Some(input) = buffer.get_text(&start_iter, &end_iter, false) {
-                self.add_highlight(input.to_string());
-            }
-        }
-
         self.popover_position = None;
         self.popover_search = None;
         let visible = self.popover.is_visible();
@@ -363,29 +437,6 @@ impl Autocomplete {
         visible
     }
 
-    pub fn add_highlight(&self, input: String) {
-        let input = input.to_lowercase();
-
-        if let Some(buffer) = self.entry.get_buffer() {
-            let start_iter = buffer.get_start_iter();
-            let end_iter = buffer.get_end_iter();
-            buffer.remove_tag_by_name("alias-highlight", &start_iter, &end_iter);
-
-            for alias in self
-                .highlighted_entry
-                .iter()
-                .map(|alias| alias.to_lowercase())
-            {
-                for (index, text) in input.match_indices(&alias) {
-                    let start_iter = buffer.get_iter_at_offset(index as i32);
-                    let end_iter = buffer.get_iter_at_offset((index + text.len()) as i32);
-
-                    buffer.apply_tag_by_name("alias-highlight", &start_iter, &end_iter);
-                }
-            }
-        }
-    }
-
     pub fn autocomplete_arrow(&mut self, direction: i32) -> Option<gtk::Widget> {
         let mut result = None;
         if let Some(row) = self.listbox.get_selected_row() {
@@ -419,9 +470,9 @@ impl Autocomplete {
 
     pub fn autocomplete_show_popover(
         &mut self,
-        list: Vec<Member>,
+        list: Vec<Completion>,
         op: &AppOp,
-    ) -> HashMap<String, gtk::EventBox> {
+    ) -> Vec<(Completion, gtk::EventBox)> {
         let session_client = op
             .login_data
             .as_ref()
@@ -433,32 +484,23 @@ impl Autocomplete {
             self.listbox.remove(ch);
         }
 
-        let widget_list: HashMap<String, gtk::EventBox> = list
+        let widget_list: Vec<(Completion, gtk::EventBox)> = list
             .into_iter()
-            .map(|member| {
-                let alias = member
-                    .alias
-                    .clone()
-                    .unwrap_or_default()
-                    .trim_end_matches(" (IRC)")
-                    .to_owned();
-                let member_level = member_level(op.active_room.as_ref(), &op.rooms, &member.uid);
-                let widget = build_memberbox_widget(
+            .map(|completion| {
+                let widget = build_completion_widget(
+                    &completion,
                     session_client.clone(),
                     user_info_cache.clone(),
-                    member,
-                    member_level,
-                    true,
+                    op,
                 );
-
-                (alias, widget)
+                (completion, widget)
             })
             .collect();
 
         if !widget_list.is_empty() {
             widget_list
-                .values()
-                .for_each(|widget| self.listbox.add(widget));
+                .iter()
+                .for_each(|(_, widget)| self.listbox.add(widget));
 
             self.popover.set_relative_to(Some(&self.entry));
             self.popover
@@ -481,49 +523,163 @@ impl Autocomplete {
         widget_list
     }
 
-    pub fn autocomplete(&self, text: Option<String>, pos: i32, op: &AppOp) -> Vec<Member> {
-        let mut list: Vec<Member> = vec![];
-        let rooms = &op.rooms;
-        match text {
-            None => {}
-            Some(txt) => {
-                if let Some(at_pos) = self.popover_position {
-                    let last = {
-                        let start = at_pos as usize;
-                        let end = pos as usize;
-                        txt.get(start..end)
-                    };
-                    if let Some(last) = last {
-                        info!("Matching string '{}'", last);
-                        /*remove @ from string*/
-                        let w = if last.starts_with('@') {
-                            last[1..].to_lowercase()
-                        } else {
-                            last.to_lowercase()
-                        };
+    /// The provider completing `word`, found at the character offset `word_start`
+    fn get_provider(&self, word: &str, word_start: usize) -> Option<&dyn Provider> {
+        let trigger = word.chars().next()?;
+        self.providers
+            .iter()
+            .find(|provider| {
+                provider.trigger() == trigger && (!provider.only_at_start() || word_start == 0)
+            })
+            .map(AsRef::as_ref)
+    }
 
-                        /* Search for the 5 most recent active users */
-                        if let Some(aroom) = op.active_room.clone() {
-                            if let Some(r) = rooms.get(&aroom) {
-                                let mut count = 0;
-                                for (_, m) in r.members.iter() {
-                                    let alias = &m.alias.clone().unwrap_or_default().to_lowercase();
-                                    let uid = m.uid.localpart().to_lowercase();
-                                    if alias.starts_with(&w) || uid.starts_with(&w) {
-                                        list.push(m.clone());
-                                        count += 1;
-                                        /* Search only for 5 matching users */
-                                        if count > 4 {
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+    pub fn autocomplete(&self, text: Option<String>, pos: i32, op: &AppOp) -> Vec<Completion> {
+        let (txt, at_pos) = match (text, self.popover_position) {
+            (Some(txt), Some(at_pos)) => (txt, at_pos as usize),
+            _ => return vec![],
+        };
+
+        let chars: Vec<char> = txt.chars().collect();
+        let word: String = match chars.get(at_pos..pos as usize) {
+            Some(word) => word.iter().collect(),
+            None => return vec![],
+        };
+        info!("Matching string '{}'", word);
+
+        match self.get_provider(&word, at_pos) {
+            Some(provider) => {
+                let query = &word[provider.trigger().len_utf8()..];
+                if query.chars().count() < provider.min_query_len() {
+                    return vec![];
                 }
+                provider.complete(query, op)
             }
-        };
-        list
+            None => self
+                .providers
+                .first()
+                .map(|provider| provider.complete(&word, op))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn build_completion_widget(
+    completion: &Completion,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
+    op: &AppOp,
+) -> gtk::EventBox {
+    let (icon, title, detail) = match completion {
+        Completion::Member(member) => {
+            let member_level = member_level(op.active_room.as_ref(), &op.rooms, &member.uid);
+            return build_memberbox_widget(
+                session_client,
+                user_info_cache,
+                member.clone(),
+                member_level,
+                true,
+            );
+        }
+        Completion::Room { id, alias, name } => {
+            let avatar = widgets::Avatar::avatar_new(Some(globals::USERLIST_ICON_SIZE));
+            avatar.circle(
+                id.to_string(),
+                name.clone(),
+                globals::USERLIST_ICON_SIZE,
+                None,
+                None,
+            );
+            let title = name.clone().unwrap_or_else(|| alias.to_string());
+            (avatar.upcast::<gtk::Widget>(), title, alias.to_string())
+        }
+        Completion::Emoji { shortcode, emoji } => {
+            let label = gtk::Label::new(Some(emoji));
+            label.set_size_request(globals::USERLIST_ICON_SIZE, globals::USERLIST_ICON_SIZE);
+            label.get_style_context().add_class("completion-emoji");
+            let title = format!(":{}:", shortcode);
+            (label.upcast::<gtk::Widget>(), title, String::new())
+        }
+        Completion::Command(command) => {
+            let image = gtk::Image::from_icon_name(
+                Some("utilities-terminal-symbolic"),
+                gtk::IconSize::Button,
+            );
+            image.set_size_request(globals::USERLIST_ICON_SIZE, globals::USERLIST_ICON_SIZE);
+            let title = format!("/{} {}", command.name(), command.args())
+                .trim_end()
+                .to_string();
+            (image.upcast::<gtk::Widget>(), title, command.description())
+        }
+    };
+
+    let event_box = gtk::EventBox::new();
+    let w = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let v = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+    let title_label = gtk::Label::new(Some(&title));
+    title_label.set_ellipsize(pango::EllipsizeMode::End);
+    title_label.set_halign(gtk::Align::Start);
+    title_label.get_style_context().add_class("member");
+    v.pack_start(&title_label, true, true, 0);
+
+    if !detail.is_empty() {
+        let detail_label = gtk::Label::new(Some(&detail));
+        detail_label.set_ellipsize(pango::EllipsizeMode::End);
+        detail_label.set_halign(gtk::Align::Start);
+        detail_label.get_style_context().add_class("member-uid");
+        v.pack_start(&detail_label, true, true, 0);
+    }
+
+    icon.set_margin_start(3);
+    icon.set_valign(gtk::Align::Center);
+    v.set_margin_start(3);
+    v.set_valign(gtk::Align::Center);
+
+    w.add(&icon);
+    w.add(&v);
+    event_box.add(&w);
+    event_box.show_all();
+
+    event_box
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_ranking() {
+        let prefix = fuzzy_score("al", "Alice");
+        let word_start = fuzzy_score("al", "Big Alice");
+        let substring = fuzzy_score("al", "Kalle");
+        let subsequence = fuzzy_score("al", "Adele");
+
+        assert_eq!(prefix, Some(3));
+        assert_eq!(word_start, Some(2));
+        assert_eq!(substring, Some(1));
+        assert_eq!(subsequence, Some(0));
+        assert!(prefix > word_start && word_start > substring && substring > subsequence);
+    }
+
+    #[test]
+    fn fuzzy_score_ignores_case() {
+        assert_eq!(fuzzy_score("ALI", "alice"), Some(3));
+        assert_eq!(fuzzy_score("ali", "ALICE"), Some(3));
+    }
+
+    #[test]
+    fn fuzzy_score_non_matches() {
+        assert_eq!(fuzzy_score("bob", "Alice"), None);
+        // All the characters are there, but not in order
+        assert_eq!(fuzzy_score("la", "Alice"), None);
+        // The query is longer than the candidate
+        assert_eq!(fuzzy_score("alicia", "Alice"), None);
+        assert_eq!(fuzzy_score("a", ""), None);
+    }
+
+    #[test]
+    fn fuzzy_score_empty_query() {
+        assert_eq!(fuzzy_score("", "Alice"), Some(3));
     }
 }
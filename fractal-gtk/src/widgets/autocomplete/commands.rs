@@ -0,0 +1,34 @@
+use super::{fuzzy_score, Completion, Provider};
+use crate::appop::AppOp;
+use crate::model::command::Command;
+use std::cmp::Reverse;
+
+/// Completes `/command` at the start of a message, showing the arguments it takes
+pub struct CommandProvider;
+
+impl Provider for CommandProvider {
+    fn trigger(&self) -> char {
+        '/'
+    }
+
+    fn only_at_start(&self) -> bool {
+        true
+    }
+
+    fn complete(&self, query: &str, _op: &AppOp) -> Vec<Completion> {
+        let mut commands: Vec<_> = Command::ALL
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &command)| {
+                let score = fuzzy_score(query, command.name())?;
+                Some((Reverse(score), i, command))
+            })
+            .collect();
+        commands.sort_by_key(|(score, i, _)| (*score, *i));
+
+        commands
+            .into_iter()
+            .map(|(.., command)| Completion::Command(command))
+            .collect()
+    }
+}
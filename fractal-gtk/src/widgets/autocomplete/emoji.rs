@@ -0,0 +1,41 @@
+use super::emoji_data::EMOJI;
+use super::{fuzzy_score, Completion, Provider, MAX_COMPLETIONS};
+use crate::appop::AppOp;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+/// Completes `:shortcode:` with the emoji it stands for
+pub struct EmojiProvider;
+
+impl Provider for EmojiProvider {
+    fn trigger(&self) -> char {
+        ':'
+    }
+
+    // A single character would open the popover for every `:)`
+    fn min_query_len(&self) -> usize {
+        2
+    }
+
+    fn complete(&self, query: &str, _op: &AppOp) -> Vec<Completion> {
+        let query = query.trim_end_matches(':');
+        let mut emoji: Vec<_> = EMOJI
+            .iter()
+            .filter_map(|&(shortcode, emoji)| {
+                let score = fuzzy_score(query, shortcode)?;
+                Some((Reverse(score), shortcode.len(), shortcode, emoji))
+            })
+            .collect();
+        emoji.sort();
+
+        // Keep only the best shortcode of each emoji
+        let mut seen = HashSet::new();
+        emoji.retain(|(.., emoji)| seen.insert(*emoji));
+
+        emoji
+            .into_iter()
+            .take(MAX_COMPLETIONS)
+            .map(|(_, _, shortcode, emoji)| Completion::Emoji { shortcode, emoji })
+            .collect()
+    }
+}
@@ -0,0 +1,55 @@
+use super::{fuzzy_score, Completion, Provider, MAX_COMPLETIONS};
+use crate::appop::AppOp;
+use matrix_sdk::identifiers::UserId;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Completes `@name` with the members of the active room
+pub struct MemberProvider;
+
+impl Provider for MemberProvider {
+    fn trigger(&self) -> char {
+        '@'
+    }
+
+    fn complete(&self, query: &str, op: &AppOp) -> Vec<Completion> {
+        let room = match op.active_room.as_ref().and_then(|id| op.rooms.get(id)) {
+            Some(room) => room,
+            None => return vec![],
+        };
+
+        // The members who talked last come first
+        let mut activity: HashMap<&UserId, usize> = HashMap::new();
+        for msg in room.messages.iter().rev() {
+            let rank = activity.len();
+            activity.entry(&msg.sender).or_insert(rank);
+        }
+
+        let mut members: Vec<_> = room
+            .members
+            .values()
+            .filter_map(|member| {
+                let alias = member.alias.as_deref().unwrap_or_default();
+                let score =
+                    fuzzy_score(query, alias).max(fuzzy_score(query, member.uid.localpart()))?;
+                let rank = activity.get(&member.uid).copied().unwrap_or(usize::MAX);
+                Some((Reverse(score), rank, member))
+            })
+            .collect();
+        members.sort_by_key(|(score, rank, member)| {
+            (*score, *rank, member.get_alias().to_lowercase())
+        });
+
+        members
+            .into_iter()
+            .take(MAX_COMPLETIONS)
+            .map(|(_, _, member)| {
+                let mut member = member.clone();
+                member.alias = member
+                    .alias
+                    .map(|alias| alias.trim_end_matches(" (IRC)").to_owned());
+                Completion::Member(member)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,38 @@
+use super::{fuzzy_score, Completion, Provider, MAX_COMPLETIONS};
+use crate::appop::AppOp;
+use std::cmp::Reverse;
+
+/// Completes `#alias` with the aliases of the joined rooms
+pub struct RoomProvider;
+
+impl Provider for RoomProvider {
+    fn trigger(&self) -> char {
+        '#'
+    }
+
+    fn complete(&self, query: &str, op: &AppOp) -> Vec<Completion> {
+        let mut rooms: Vec<_> = op
+            .rooms
+            .values()
+            .filter(|room| room.membership.is_joined())
+            .filter_map(|room| {
+                let alias = room.alias.as_ref()?;
+                let name = room.name.as_deref().unwrap_or_default();
+                let score = fuzzy_score(query, alias.alias()).max(fuzzy_score(query, name))?;
+                let last_message = room.messages.iter().last().map(|msg| msg.date);
+                Some((Reverse(score), Reverse(last_message), alias, room))
+            })
+            .collect();
+        rooms.sort_by_key(|(score, last_message, alias, _)| (*score, *last_message, *alias));
+
+        rooms
+            .into_iter()
+            .take(MAX_COMPLETIONS)
+            .map(|(_, _, alias, room)| Completion::Room {
+                id: room.id.clone(),
+                alias: alias.clone(),
+                name: room.name.clone(),
+            })
+            .collect()
+    }
+}
@@ -3,6 +3,7 @@ use std::cell::RefCell;
 use std::path::Path;
 use std::rc::Rc;
 
+use crate::cache::crypto;
 use crate::util::cache_dir_path;
 use gdk_pixbuf::Pixbuf;
 use gtk::prelude::*;
@@ -29,7 +30,9 @@ impl AvatarData {
         }
         // Ensure that we reload the avatar
         self.widget.set_image_load_func(Some(Box::new(move |sz| {
-            let path = cache_dir_path(None, &id).unwrap_or_default();
+            let path = cache_dir_path(None, &id)
+                .and_then(|path| crypto::readable_path(&path))
+                .unwrap_or_default();
             load_pixbuf(&path, sz)
         })));
     }
@@ -124,7 +127,9 @@ impl AvatarExt for gtk::Overlay {
         let avatar_cache: Rc<RefCell<AvatarData>> = Rc::new(RefCell::new(data));
 
         avatar.set_image_load_func(Some(Box::new(move |sz| {
-            let path = cache_dir_path(None, &id).unwrap_or_default();
+            let path = cache_dir_path(None, &id)
+                .and_then(|path| crypto::readable_path(&path))
+                .unwrap_or_default();
             load_pixbuf(&path, sz)
         })));
 
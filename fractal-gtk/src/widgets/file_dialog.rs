@@ -24,6 +24,31 @@ pub fn save(parent: &gtk::Window, title: &str, filter: &[gtk::FileFilter]) -> Op
     None
 }
 
+pub fn open_multiple(
+    parent: &gtk::Window,
+    title: &str,
+    filter: &[gtk::FileFilter],
+) -> Vec<PathBuf> {
+    let file_chooser = gtk::FileChooserNative::new(
+        Some(title),
+        Some(parent),
+        gtk::FileChooserAction::Open,
+        Some(i18n("_Select").as_str()),
+        Some(i18n("_Cancel").as_str()),
+    );
+    file_chooser.set_select_multiple(true);
+
+    for f in filter {
+        file_chooser.add_filter(f);
+    }
+
+    let response = file_chooser.run();
+    if response == gtk::ResponseType::Accept {
+        return file_chooser.get_filenames();
+    }
+    vec![]
+}
+
 pub fn open(parent: &gtk::Window, title: &str, filter: &[gtk::FileFilter]) -> Option<PathBuf> {
     let file_chooser = gtk::FileChooserNative::new(
         Some(title),
@@ -0,0 +1,1097 @@
+use crate::model::member::Member;
+use crate::util;
+use crate::util::i18n::i18n;
+use gdk::ModifierType;
+use glib::clone;
+use gtk::prelude::*;
+use html2pango::html_escape;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+const BOLD: &str = "bold";
+const ITALIC: &str = "italic";
+const STRIKETHROUGH: &str = "strikethrough";
+const CODE: &str = "code";
+const QUOTE: &str = "quote";
+const BULLET_LIST: &str = "bullet-list";
+const NUMBERED_LIST: &str = "numbered-list";
+/// Followed by the language of the block, which can be empty
+const CODE_BLOCK_PREFIX: &str = "code-block:";
+/// Followed by the URL the text links to
+const LINK_PREFIX: &str = "link:";
+
+const CODE_BACKGROUND: &str = "rgba(127, 127, 127, 0.2)";
+const LINK_COLOR: &str = "#3584e4";
+
+/// The inline formats, in the order their HTML elements are nested
+const INLINE_TAGS: [&str; 4] = [BOLD, ITALIC, STRIKETHROUGH, CODE];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Paragraph,
+    Quote,
+    BulletList,
+    NumberedList,
+    Code(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inline {
+    Link(String),
+    Tag(&'static str),
+}
+
+/// Buttons formatting the text of the message entry. The formats are kept as tags of the
+/// buffer, and turned into HTML when the message is sent.
+#[derive(Debug, Clone)]
+pub struct FormattingToolbar {
+    pub revealer: gtk::Revealer,
+    buffer: gtk::TextBuffer,
+    inline_buttons: Vec<(&'static str, gtk::ToggleButton)>,
+    block_buttons: Vec<(&'static str, gtk::ToggleButton)>,
+    code_block: gtk::MenuButton,
+    link: gtk::MenuButton,
+    /// The inline formats of the text typed at the cursor
+    typing: Rc<RefCell<Vec<&'static str>>>,
+    /// Set while the buttons are being synced with the text at the cursor
+    updating: Rc<Cell<bool>>,
+}
+
+impl FormattingToolbar {
+    pub fn new(view: &gtk::TextView, buffer: &gtk::TextBuffer) -> Self {
+        create_tags(buffer);
+
+        let toolbar = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        toolbar.get_style_context().add_class("formatting-toolbar");
+
+        let inline_buttons = vec![
+            (BOLD, "format-text-bold-symbolic", i18n("Bold")),
+            (ITALIC, "format-text-italic-symbolic", i18n("Italic")),
+            (
+                STRIKETHROUGH,
+                "format-text-strikethrough-symbolic",
+                i18n("Strikethrough"),
+            ),
+            (CODE, "utilities-terminal-symbolic", i18n("Inline Code")),
+        ];
+        let block_buttons = vec![
+            (QUOTE, "format-indent-more-symbolic", i18n("Quote")),
+            (
+                BULLET_LIST,
+                "view-list-bullet-symbolic",
+                i18n("Bulleted List"),
+            ),
+            (
+                NUMBERED_LIST,
+                "view-list-ordered-symbolic",
+                i18n("Numbered List"),
+            ),
+        ];
+
+        let toggle_button = |icon: &str, tooltip: &str| {
+            let button = gtk::ToggleButton::new();
+            let image = gtk::Image::from_icon_name(Some(icon), gtk::IconSize::Button);
+            button.set_image(Some(&image));
+            button.set_tooltip_text(Some(tooltip));
+            button.set_relief(gtk::ReliefStyle::None);
+            // Keeps the selection in the entry
+            button.set_focus_on_click(false);
+            toolbar.add(&button);
+            button
+        };
+
+        let inline_buttons: Vec<_> = inline_buttons
+            .into_iter()
+            .map(|(name, icon, tooltip)| (name, toggle_button(icon, &tooltip)))
+            .collect();
+        toolbar.add(&gtk::Separator::new(gtk::Orientation::Vertical));
+        let block_buttons: Vec<_> = block_buttons
+            .into_iter()
+            .map(|(name, icon, tooltip)| (name, toggle_button(icon, &tooltip)))
+            .collect();
+
+        let (code_block, language_entry, apply_code_block, remove_code_block) = input_button(
+            "format-justify-fill-symbolic",
+            &i18n("Code Block"),
+            &i18n("Language (optional)"),
+        );
+        toolbar.add(&code_block);
+        toolbar.add(&gtk::Separator::new(gtk::Orientation::Vertical));
+        let (link, url_entry, apply_link, remove_link) =
+            input_button("insert-link-symbolic", &i18n("Link"), "https://example.com");
+        toolbar.add(&link);
+
+        let revealer = gtk::Revealer::new();
+        revealer.add(&toolbar);
+        revealer.show_all();
+
+        let this = FormattingToolbar {
+            revealer,
+            buffer: buffer.clone(),
+            inline_buttons,
+            block_buttons,
+            code_block,
+            link,
+            typing: Default::default(),
+            updating: Default::default(),
+        };
+
+        for (name, button) in this.inline_buttons.iter() {
+            let name = *name;
+            button.connect_toggled(clone!(@strong this => move |_| {
+                if !this.updating.get() {
+                    this.toggle_inline(name);
+                }
+            }));
+        }
+        for (name, button) in this.block_buttons.iter() {
+            let name = *name;
+            button.connect_toggled(clone!(@strong this => move |_| {
+                if !this.updating.get() {
+                    let tag = lookup_tag(&this.buffer, name);
+                    this.toggle_block(&tag);
+                }
+            }));
+        }
+
+        this.code_block.connect_toggled(
+            clone!(@strong this, @strong language_entry, @strong remove_code_block => move |button| {
+                if !button.get_active() {
+                    return;
+                }
+                let lang = this.block_at_cursor().and_then(|tag| {
+                    tag_name(&tag)?.strip_prefix(CODE_BLOCK_PREFIX).map(str::to_string)
+                });
+                remove_code_block.set_visible(lang.is_some());
+                language_entry.set_text(lang.as_deref().unwrap_or_default());
+            }),
+        );
+        apply_code_block.connect_clicked(clone!(@strong this, @strong language_entry => move |_| {
+            let lang = language_entry.get_text().trim().to_string();
+            let tag = code_block_tag(&this.buffer, &lang);
+            this.set_block(Some(&tag));
+            this.code_block.set_active(false);
+        }));
+        remove_code_block.connect_clicked(clone!(@strong this => move |_| {
+            this.set_block(None);
+            this.code_block.set_active(false);
+        }));
+
+        this.link.connect_toggled(
+            clone!(@strong this, @strong url_entry, @strong remove_link => move |button| {
+                if !button.get_active() {
+                    return;
+                }
+                let url = this.link_at_cursor();
+                remove_link.set_visible(url.is_some());
+                url_entry.set_text(url.as_deref().unwrap_or_default());
+            }),
+        );
+        apply_link.connect_clicked(clone!(@strong this, @strong url_entry => move |_| {
+            let url = url_entry.get_text().trim().to_string();
+            if !url.is_empty() {
+                this.set_link(Some(&url));
+            }
+            this.link.set_active(false);
+        }));
+        remove_link.connect_clicked(clone!(@strong this => move |_| {
+            this.set_link(None);
+            this.link.set_active(false);
+        }));
+        url_entry.connect_activate(move |_| apply_link.clicked());
+        language_entry.connect_activate(move |_| apply_code_block.clicked());
+
+        this.connect_buffer();
+
+        view.connect_key_press_event(clone!(@strong this => move |_, ev| {
+            if !this.revealer.get_reveal_child()
+                || !ev.get_state().contains(ModifierType::CONTROL_MASK)
+            {
+                return Inhibit(false);
+            }
+
+            let name = match ev.get_keyval() {
+                gdk::keys::constants::b => BOLD,
+                gdk::keys::constants::i => ITALIC,
+                _ => return Inhibit(false),
+            };
+            if let Some((_, button)) = this.inline_buttons.iter().find(|(n, _)| *n == name) {
+                button.set_active(!button.get_active());
+            }
+            Inhibit(true)
+        }));
+
+        this
+    }
+
+    /// Formats the text typed at the cursor, and keeps the buttons in sync with the
+    /// formats of the text at the cursor
+    fn connect_buffer(&self) {
+        // The ranges of text inserted by the user, applied the typing formats once the
+        // insertion is over so the iters of the other handlers stay valid
+        let inserted: Rc<RefCell<Vec<(i32, i32)>>> = Default::default();
+        let in_user_action: Rc<Cell<bool>> = Default::default();
+
+        self.buffer
+            .connect_begin_user_action(clone!(@strong in_user_action => move |_| {
+                in_user_action.set(true);
+            }));
+
+        self.buffer.connect_local(
+            "insert-text",
+            false,
+            clone!(@strong inserted, @strong in_user_action => move |values| {
+                if in_user_action.get() {
+                    let location = values[1].get::<gtk::TextIter>().ok().flatten();
+                    let text = values[2].get::<String>().ok().flatten();
+                    if let (Some(location), Some(text)) = (location, text) {
+                        let start = location.get_offset();
+                        inserted.borrow_mut().push((start, start + text.chars().count() as i32));
+                    }
+                }
+                None
+            }),
+        ).expect("Couldn't connect to insert-text");
+
+        let this = self.clone();
+        self.buffer
+            .connect_end_user_action(clone!(@strong in_user_action => move |buffer| {
+                in_user_action.set(false);
+                for (start, end) in inserted.borrow_mut().drain(..) {
+                    let start = buffer.get_iter_at_offset(start);
+                    let end = buffer.get_iter_at_offset(end);
+                    this.format_inserted(&start, &end);
+                }
+                this.sync_with_cursor();
+            }));
+
+        // The formats are synced once the text inserted by the user is formatted
+        let this = self.clone();
+        self.buffer.connect_mark_set(move |buffer, _, mark| {
+            let moved_cursor = buffer.get_insert().map_or(false, |insert| &insert == mark);
+            if moved_cursor && !in_user_action.get() {
+                this.sync_with_cursor();
+            }
+        });
+    }
+
+    /// Gives the typing formats to the text just inserted, and makes it continue the block
+    /// it was inserted in
+    fn format_inserted(&self, start: &gtk::TextIter, end: &gtk::TextIter) {
+        for name in self.typing.borrow().iter() {
+            let tag = lookup_tag(&self.buffer, name);
+            self.buffer.apply_tag(&tag, start, end);
+        }
+
+        // The character after the insertion is on the same line as its end
+        let mut neighbour = end.clone();
+        if neighbour.is_end() {
+            neighbour = start.clone();
+            if !neighbour.backward_char() {
+                return;
+            }
+        }
+        if let Some(tag) = block_tag_at(&neighbour) {
+            self.buffer.apply_tag(&tag, start, end);
+        }
+    }
+
+    /// Takes the typing formats from the text before the cursor, and updates the buttons
+    fn sync_with_cursor(&self) {
+        let cursor = self.cursor();
+        let mut before = cursor.clone();
+        let typing = if before.backward_char() {
+            INLINE_TAGS
+                .iter()
+                .copied()
+                .filter(|name| before.has_tag(&lookup_tag(&self.buffer, name)))
+                .collect()
+        } else {
+            vec![]
+        };
+        *self.typing.borrow_mut() = typing;
+        self.update_buttons();
+    }
+
+    fn update_buttons(&self) {
+        let block = self.block_at_cursor().and_then(|tag| tag_name(&tag));
+        let typing = self.typing.borrow();
+
+        self.updating.set(true);
+        for (name, button) in self.inline_buttons.iter() {
+            button.set_active(typing.contains(name));
+        }
+        for (name, button) in self.block_buttons.iter() {
+            button.set_active(block.as_deref() == Some(*name));
+        }
+        self.updating.set(false);
+    }
+
+    fn cursor(&self) -> gtk::TextIter {
+        match self.buffer.get_insert() {
+            Some(mark) => self.buffer.get_iter_at_mark(&mark),
+            None => self.buffer.get_end_iter(),
+        }
+    }
+
+    fn block_at_cursor(&self) -> Option<gtk::TextTag> {
+        let line = self.cursor().get_line();
+        block_tag_at(&self.buffer.get_iter_at_line(line))
+    }
+
+    fn link_at_cursor(&self) -> Option<String> {
+        let cursor = match self.buffer.get_selection_bounds() {
+            Some((start, _)) => start,
+            None => self.cursor(),
+        };
+        cursor
+            .get_tags()
+            .iter()
+            .find_map(|tag| tag_name(tag)?.strip_prefix(LINK_PREFIX).map(str::to_string))
+    }
+
+    /// Formats the selected text, or the text typed next when nothing is selected
+    fn toggle_inline(&self, name: &'static str) {
+        let tag = lookup_tag(&self.buffer, name);
+        if let Some((start, end)) = self.buffer.get_selection_bounds() {
+            if range_has_tag(&start, &end, &tag) {
+                self.buffer.remove_tag(&tag, &start, &end);
+            } else {
+                self.buffer.apply_tag(&tag, &start, &end);
+            }
+        } else {
+            let mut typing = self.typing.borrow_mut();
+            if let Some(pos) = typing.iter().position(|n| *n == name) {
+                typing.remove(pos);
+            } else {
+                typing.push(name);
+            }
+        }
+    }
+
+    /// Turns the lines of the selection into the block, or back into paragraphs when all of
+    /// them already are
+    fn toggle_block(&self, tag: &gtk::TextTag) {
+        let (start, end) = self.selected_lines();
+        let mut line = start.clone();
+        let mut all_tagged = true;
+        while line < end {
+            all_tagged &= line.has_tag(tag);
+            if !line.forward_line() {
+                break;
+            }
+        }
+
+        self.set_block(if all_tagged { None } else { Some(tag) });
+    }
+
+    fn set_block(&self, tag: Option<&gtk::TextTag>) {
+        let (start, end) = self.selected_lines();
+        let mut line = start.clone();
+        while line < end {
+            if let Some(tag) = block_tag_at(&line) {
+                self.buffer.remove_tag(&tag, &start, &end);
+            }
+            if !line.forward_line() {
+                break;
+            }
+        }
+
+        if let Some(tag) = tag {
+            self.buffer.apply_tag(tag, &start, &end);
+        }
+        self.update_buttons();
+    }
+
+    /// The whole lines the selection or the cursor is in, including their line break
+    fn selected_lines(&self) -> (gtk::TextIter, gtk::TextIter) {
+        let (start, mut end) = self
+            .buffer
+            .get_selection_bounds()
+            .unwrap_or_else(|| (self.cursor(), self.cursor()));
+        let start = self.buffer.get_iter_at_line(start.get_line());
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        end.forward_char();
+        (start, end)
+    }
+
+    /// Makes the selected text link to `url`, or inserts the URL as a link when nothing is
+    /// selected. The link is removed when there's no URL.
+    fn set_link(&self, url: Option<&str>) {
+        let (start, end) = match (self.buffer.get_selection_bounds(), url) {
+            (Some(bounds), _) => bounds,
+            (None, Some(url)) => {
+                let mut cursor = self.cursor();
+                let offset = cursor.get_offset();
+                self.buffer.insert(&mut cursor, url);
+                (self.buffer.get_iter_at_offset(offset), cursor)
+            }
+            (None, None) => match self.link_range_at_cursor() {
+                Some(bounds) => bounds,
+                None => return,
+            },
+        };
+
+        let mut iter = start.clone();
+        while iter < end {
+            for tag in iter.get_tags() {
+                if tag_name(&tag).map_or(false, |name| name.starts_with(LINK_PREFIX)) {
+                    self.buffer.remove_tag(&tag, &start, &end);
+                }
+            }
+            if !iter.forward_char() {
+                break;
+            }
+        }
+
+        if let Some(url) = url {
+            let tag = link_tag(&self.buffer, url);
+            self.buffer.apply_tag(&tag, &start, &end);
+        }
+    }
+
+    /// The formats of the text, as the names of their tags and the character offsets of
+    /// the ranges they apply to
+    pub fn formats(&self) -> Vec<(String, i32, i32)> {
+        let mut tags = vec![];
+        if let Some(table) = self.buffer.get_tag_table() {
+            table.foreach(|tag| tags.push(tag.clone()));
+        }
+
+        let mut formats = vec![];
+        for tag in tags {
+            let name = match tag_name(&tag).filter(|name| is_format(name)) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let mut iter = self.buffer.get_start_iter();
+            if !iter.has_tag(&tag) && !iter.forward_to_tag_toggle(Some(&tag)) {
+                continue;
+            }
+            loop {
+                let start = iter.get_offset();
+                iter.forward_to_tag_toggle(Some(&tag));
+                formats.push((name.clone(), start, iter.get_offset()));
+                if !iter.forward_to_tag_toggle(Some(&tag)) {
+                    break;
+                }
+            }
+        }
+
+        formats.sort_by_key(|(_, start, _)| *start);
+        formats
+    }
+
+    /// Formats the text again with the formats saved in the draft
+    pub fn apply_formats(&self, formats: &[(String, i32, i32)]) {
+        for (name, start, end) in formats {
+            let tag = if let Some(url) = name.strip_prefix(LINK_PREFIX) {
+                link_tag(&self.buffer, url)
+            } else if let Some(lang) = name.strip_prefix(CODE_BLOCK_PREFIX) {
+                code_block_tag(&self.buffer, lang)
+            } else if is_format(name) {
+                lookup_tag(&self.buffer, name)
+            } else {
+                continue;
+            };
+
+            let start = self.buffer.get_iter_at_offset(*start);
+            let end = self.buffer.get_iter_at_offset(*end);
+            self.buffer.apply_tag(&tag, &start, &end);
+        }
+        self.sync_with_cursor();
+    }
+
+    fn link_range_at_cursor(&self) -> Option<(gtk::TextIter, gtk::TextIter)> {
+        let cursor = self.cursor();
+        let tag = cursor
+            .get_tags()
+            .into_iter()
+            .find(|tag| tag_name(tag).map_or(false, |name| name.starts_with(LINK_PREFIX)))?;
+
+        let mut start = cursor.clone();
+        if !start.begins_tag(Some(&tag)) {
+            start.backward_to_tag_toggle(Some(&tag));
+        }
+        let mut end = cursor;
+        end.forward_to_tag_toggle(Some(&tag));
+        Some((start, end))
+    }
+}
+
+/// Makes a button opening a popover with an entry, to apply or remove a format that needs
+/// some input
+fn input_button(
+    icon: &str,
+    tooltip: &str,
+    placeholder: &str,
+) -> (gtk::MenuButton, gtk::Entry, gtk::Button, gtk::Button) {
+    let button = gtk::MenuButton::new();
+    let image = gtk::Image::from_icon_name(Some(icon), gtk::IconSize::Button);
+    button.set_image(Some(&image));
+    button.set_tooltip_text(Some(tooltip));
+    button.set_relief(gtk::ReliefStyle::None);
+    button.set_focus_on_click(false);
+
+    let entry = gtk::Entry::new();
+    entry.set_placeholder_text(Some(placeholder));
+    let remove = gtk::Button::with_label(&i18n("Remove"));
+    let apply = gtk::Button::with_label(&i18n("Apply"));
+    apply.get_style_context().add_class("suggested-action");
+
+    let content = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+    content.set_border_width(6);
+    content.pack_start(&entry, true, true, 0);
+    content.pack_start(&remove, false, false, 0);
+    content.pack_start(&apply, false, false, 0);
+    content.show_all();
+
+    let popover = gtk::Popover::new(Some(&button));
+    popover.add(&content);
+    button.set_popover(Some(&popover));
+
+    (button, entry, apply, remove)
+}
+
+fn create_tags(buffer: &gtk::TextBuffer) {
+    buffer.create_tag(Some(BOLD), &[("weight", &700)]);
+    buffer.create_tag(Some(ITALIC), &[("style", &pango::Style::Italic)]);
+    buffer.create_tag(Some(STRIKETHROUGH), &[("strikethrough", &true)]);
+    buffer.create_tag(
+        Some(CODE),
+        &[("family", &"monospace"), ("background", &CODE_BACKGROUND)],
+    );
+    buffer.create_tag(
+        Some(QUOTE),
+        &[("left-margin", &16), ("foreground", &"grey")],
+    );
+    buffer.create_tag(Some(BULLET_LIST), &[("left-margin", &24)]);
+    buffer.create_tag(Some(NUMBERED_LIST), &[("left-margin", &24)]);
+}
+
+fn lookup_tag(buffer: &gtk::TextBuffer, name: &str) -> gtk::TextTag {
+    buffer
+        .get_tag_table()
+        .and_then(|table| table.lookup(name))
+        .unwrap_or_else(|| panic!("The {} tag is created along with the toolbar", name))
+}
+
+fn code_block_tag(buffer: &gtk::TextBuffer, lang: &str) -> gtk::TextTag {
+    let name = format!("{}{}", CODE_BLOCK_PREFIX, lang);
+    buffer
+        .get_tag_table()
+        .and_then(|table| table.lookup(&name))
+        .or_else(|| {
+            buffer.create_tag(
+                Some(&name),
+                &[
+                    ("family", &"monospace"),
+                    ("paragraph-background", &CODE_BACKGROUND),
+                ],
+            )
+        })
+        .expect("Couldn't create code block tag")
+}
+
+fn link_tag(buffer: &gtk::TextBuffer, url: &str) -> gtk::TextTag {
+    let name = format!("{}{}", LINK_PREFIX, url);
+    buffer
+        .get_tag_table()
+        .and_then(|table| table.lookup(&name))
+        .or_else(|| {
+            buffer.create_tag(
+                Some(&name),
+                &[
+                    ("underline", &pango::Underline::Single),
+                    ("foreground", &LINK_COLOR),
+                ],
+            )
+        })
+        .expect("Couldn't create link tag")
+}
+
+fn tag_name(tag: &gtk::TextTag) -> Option<String> {
+    tag.get_property_name().map(|name| name.to_string())
+}
+
+fn is_block(name: &str) -> bool {
+    [QUOTE, BULLET_LIST, NUMBERED_LIST].contains(&name) || name.starts_with(CODE_BLOCK_PREFIX)
+}
+
+/// Whether the tag is one of the formats of the toolbar
+fn is_format(name: &str) -> bool {
+    INLINE_TAGS.contains(&name) || is_block(name) || name.starts_with(LINK_PREFIX)
+}
+
+fn block_tag_at(iter: &gtk::TextIter) -> Option<gtk::TextTag> {
+    iter.get_tags()
+        .into_iter()
+        .find(|tag| tag_name(tag).map_or(false, |name| is_block(&name)))
+}
+
+/// Whether all the text between `start` and `end` has the tag
+fn range_has_tag(start: &gtk::TextIter, end: &gtk::TextIter, tag: &gtk::TextTag) -> bool {
+    let mut iter = start.clone();
+    while iter < *end {
+        if !iter.has_tag(tag) {
+            return false;
+        }
+        iter.forward_to_tag_toggle(Some(tag));
+    }
+    true
+}
+
+fn block_of(iter: &gtk::TextIter) -> Block {
+    let name = match block_tag_at(iter).and_then(|tag| tag_name(&tag)) {
+        Some(name) => name,
+        None => return Block::Paragraph,
+    };
+    match name.as_str() {
+        QUOTE => Block::Quote,
+        BULLET_LIST => Block::BulletList,
+        NUMBERED_LIST => Block::NumberedList,
+        name => Block::Code(
+            name.strip_prefix(CODE_BLOCK_PREFIX)
+                .unwrap_or_default()
+                .to_string(),
+        ),
+    }
+}
+
+fn inline_formats(iter: &gtk::TextIter) -> Vec<Inline> {
+    let tags = iter.get_tags();
+    let names: Vec<_> = tags.iter().filter_map(tag_name).collect();
+
+    let link = names
+        .iter()
+        .find_map(|name| name.strip_prefix(LINK_PREFIX))
+        .map(|url| Inline::Link(url.to_string()));
+    let inline = INLINE_TAGS
+        .iter()
+        .filter(|tag| names.iter().any(|name| name == *tag))
+        .map(|tag| Inline::Tag(tag));
+    link.into_iter().chain(inline).collect()
+}
+
+fn open_html(format: &Inline) -> String {
+    match format {
+        Inline::Link(url) => format!("<a href=\"{}\">", html_escape(url)),
+        Inline::Tag(BOLD) => String::from("<strong>"),
+        Inline::Tag(ITALIC) => String::from("<em>"),
+        Inline::Tag(STRIKETHROUGH) => String::from("<del>"),
+        Inline::Tag(_) => String::from("<code>"),
+    }
+}
+
+fn close_html(format: &Inline) -> &'static str {
+    match format {
+        Inline::Link(_) => "</a>",
+        Inline::Tag(BOLD) => "</strong>",
+        Inline::Tag(ITALIC) => "</em>",
+        Inline::Tag(STRIKETHROUGH) => "</del>",
+        Inline::Tag(_) => "</code>",
+    }
+}
+
+/// A character of a line, or the pill of a mention
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Char(char),
+    Mention { name: String, link: String },
+}
+
+/// A line of the buffer, with the inline formats of each of its pieces
+#[derive(Debug, Clone, PartialEq)]
+struct Line {
+    block: Block,
+    pieces: Vec<(Vec<Inline>, Piece)>,
+}
+
+/// Turns the text of the buffer into a plain text body and an HTML formatted body. Nothing is
+/// returned when the text has no format, for it to be sent as usual.
+pub fn serialize(
+    buffer: &gtk::TextBuffer,
+    mentions: &[(Member, gtk::TextChildAnchor)],
+) -> Option<(String, String)> {
+    let mut lines: Vec<Line> = vec![];
+
+    for n in 0..buffer.get_line_count() {
+        let mut iter = buffer.get_iter_at_line(n);
+        let mut line = Line {
+            block: block_of(&iter),
+            pieces: vec![],
+        };
+
+        while !iter.ends_line() {
+            let member = iter.get_child_anchor().and_then(|anchor| {
+                mentions
+                    .iter()
+                    .find(|(_, a)| *a == anchor)
+                    .map(|(member, _)| member)
+            });
+            let c = iter.get_char();
+            let piece = match member {
+                Some(member) => Some(Piece::Mention {
+                    name: member.get_alias(),
+                    link: util::mention_link(&member.uid),
+                }),
+                None if c != '\u{FFFC}' => Some(Piece::Char(c)),
+                None => None,
+            };
+            if let Some(piece) = piece {
+                line.pieces.push((inline_formats(&iter), piece));
+            }
+
+            if !iter.forward_char() {
+                break;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    to_bodies(&lines)
+}
+
+/// The HTML and the plain text of the inline content of a line, the links are followed by
+/// their URL in the plain text
+fn line_bodies(line: &Line) -> (String, String) {
+    let mut html = String::new();
+    let mut plain = String::new();
+    let mut open: Vec<Inline> = vec![];
+    let mut link: Option<&str> = None;
+
+    for (formats, piece) in line.pieces.iter() {
+        let wanted: &[Inline] = match line.block {
+            // Code blocks are already verbatim
+            Block::Code(_) => &[],
+            _ => formats,
+        };
+
+        // Keeps the formats shared with the previous character open
+        let kept = open.iter().zip(wanted).take_while(|(a, b)| a == b).count();
+        for format in open.drain(kept..).rev() {
+            html.push_str(close_html(&format));
+        }
+        for format in wanted[kept..].iter() {
+            html.push_str(&open_html(format));
+        }
+        open = wanted.to_vec();
+
+        let url = wanted.iter().find_map(|format| match format {
+            Inline::Link(url) => Some(url.as_str()),
+            _ => None,
+        });
+        if link != url {
+            if let Some(link) = link.take() {
+                plain.push_str(&format!(" ({})", link));
+            }
+            link = url;
+        }
+
+        match piece {
+            Piece::Mention { name, link } => {
+                html.push_str(&format!("<a href=\"{}\">{}</a>", link, html_escape(name)));
+                plain.push_str(name);
+            }
+            Piece::Char(c) => {
+                let mut buf = [0; 4];
+                html.push_str(&html_escape(c.encode_utf8(&mut buf)));
+                plain.push(*c);
+            }
+        }
+    }
+    for format in open.iter().rev() {
+        html.push_str(close_html(format));
+    }
+    if let Some(link) = link {
+        plain.push_str(&format!(" ({})", link));
+    }
+
+    (html, plain)
+}
+
+/// Turns the lines into a plain text body and an HTML body, `None` when none of them is
+/// formatted
+fn to_bodies(lines: &[Line]) -> Option<(String, String)> {
+    let formatted = lines.iter().any(|line| {
+        line.block != Block::Paragraph || line.pieces.iter().any(|(formats, _)| !formats.is_empty())
+    });
+    if !formatted {
+        return None;
+    }
+
+    let mut lines: Vec<(&Block, String, String)> = lines
+        .iter()
+        .map(|line| {
+            let (html, plain) = line_bodies(line);
+            (&line.block, html, plain)
+        })
+        .collect();
+
+    // Leaves out the empty lines at the end of the text
+    while lines
+        .last()
+        .map_or(false, |(_, _, plain)| plain.trim().is_empty())
+    {
+        lines.pop();
+    }
+
+    let mut html = String::new();
+    let mut plain: Vec<String> = vec![];
+    let mut number = 0;
+    for (i, (block, line_html, line_plain)) in lines.iter().enumerate() {
+        let block = *block;
+        let prev = i.checked_sub(1).map(|i| lines[i].0);
+        let next = lines.get(i + 1).map(|line| line.0);
+        let starts = prev != Some(block);
+        let ends = next != Some(block);
+
+        match block {
+            Block::Paragraph => {
+                html.push_str(line_html);
+                if !ends {
+                    html.push_str("<br>");
+                }
+                plain.push(line_plain.clone());
+            }
+            Block::Quote => {
+                if starts {
+                    html.push_str("<blockquote>");
+                }
+                html.push_str(line_html);
+                html.push_str(if ends { "</blockquote>" } else { "<br>" });
+                plain.push(format!("> {}", line_plain));
+            }
+            Block::BulletList | Block::NumberedList => {
+                let (list, prefix) = if *block == Block::BulletList {
+                    ("ul", String::from("- "))
+                } else {
+                    number = if starts { 1 } else { number + 1 };
+                    ("ol", format!("{}. ", number))
+                };
+                if starts {
+                    html.push_str(&format!("<{}>", list));
+                }
+                html.push_str(&format!("<li>{}</li>", line_html));
+                if ends {
+                    html.push_str(&format!("</{}>", list));
+                }
+                plain.push(format!("{}{}", prefix, line_plain));
+            }
+            Block::Code(lang) => {
+                if starts {
+                    if lang.is_empty() {
+                        html.push_str("<pre><code>");
+                    } else {
+                        html.push_str(&format!(
+                            "<pre><code class=\"language-{}\">",
+                            html_escape(lang)
+                        ));
+                    }
+                }
+                html.push_str(line_html);
+                html.push_str(if ends { "</code></pre>" } else { "\n" });
+                plain.push(line_plain.clone());
+            }
+        }
+
+        // Blocks are separated from the paragraph following them
+        if ends && *block != Block::Paragraph && next == Some(&Block::Paragraph) {
+            plain.push(String::new());
+        }
+    }
+
+    Some((plain.join("\n"), html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(block: Block, pieces: &[(&[Inline], &str)]) -> Line {
+        let pieces = pieces
+            .iter()
+            .flat_map(|(formats, text)| {
+                text.chars()
+                    .map(move |c| (formats.to_vec(), Piece::Char(c)))
+            })
+            .collect();
+        Line { block, pieces }
+    }
+
+    fn text(block: Block, text: &str) -> Line {
+        line(block, &[(&[], text)])
+    }
+
+    #[test]
+    fn plain_text_is_not_formatted() {
+        let lines = vec![
+            text(Block::Paragraph, "Hello"),
+            text(Block::Paragraph, "world"),
+        ];
+        assert_eq!(to_bodies(&lines), None);
+    }
+
+    #[test]
+    fn nested_inline_formats() {
+        let bold = Inline::Tag(BOLD);
+        let italic = Inline::Tag(ITALIC);
+        let lines = vec![line(
+            Block::Paragraph,
+            &[
+                (&[], "a "),
+                (&[bold.clone()], "b"),
+                (&[bold.clone(), italic.clone()], "c"),
+                (&[bold], "d"),
+                (&[italic], "e"),
+                (&[Inline::Tag(CODE)], "<f>"),
+            ],
+        )];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "a bcde<f>");
+        assert_eq!(
+            html,
+            "a <strong>b<em>c</em>d</strong><em>e</em><code>&lt;f&gt;</code>"
+        );
+    }
+
+    #[test]
+    fn links() {
+        let link = Inline::Link(String::from("https://example.com/?a=1&b=2"));
+        let lines = vec![line(
+            Block::Paragraph,
+            &[
+                (&[link.clone()], "the "),
+                (&[link, Inline::Tag(BOLD)], "site"),
+                (&[], "!"),
+            ],
+        )];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "the site (https://example.com/?a=1&b=2)!");
+        assert_eq!(
+            html,
+            "<a href=\"https://example.com/?a=1&amp;b=2\">the <strong>site</strong></a>!"
+        );
+    }
+
+    #[test]
+    fn link_at_the_end() {
+        let link = Inline::Link(String::from("https://example.com"));
+        let lines = vec![line(Block::Paragraph, &[(&[], "see "), (&[link], "here")])];
+
+        let (plain, _) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "see here (https://example.com)");
+    }
+
+    #[test]
+    fn mentions() {
+        let mut lines = vec![line(Block::Paragraph, &[(&[Inline::Tag(BOLD)], "hi ")])];
+        lines[0].pieces.push((
+            vec![Inline::Tag(BOLD)],
+            Piece::Mention {
+                name: String::from("Bob <3"),
+                link: String::from("https://matrix.to/#/@bob:example.org"),
+            },
+        ));
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "hi Bob <3");
+        assert_eq!(
+            html,
+            "<strong>hi <a href=\"https://matrix.to/#/@bob:example.org\">Bob &lt;3</a></strong>"
+        );
+    }
+
+    #[test]
+    fn bullet_list() {
+        let lines = vec![
+            text(Block::Paragraph, "List:"),
+            text(Block::BulletList, "one"),
+            text(Block::BulletList, "two"),
+            text(Block::Paragraph, "end"),
+        ];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "List:\n- one\n- two\n\nend");
+        assert_eq!(html, "List:<ul><li>one</li><li>two</li></ul>end");
+    }
+
+    #[test]
+    fn numbered_lists_start_again() {
+        let lines = vec![
+            text(Block::NumberedList, "one"),
+            text(Block::NumberedList, "two"),
+            text(Block::Paragraph, "break"),
+            text(Block::NumberedList, "first"),
+        ];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "1. one\n2. two\n\nbreak\n1. first");
+        assert_eq!(
+            html,
+            "<ol><li>one</li><li>two</li></ol>break<ol><li>first</li></ol>"
+        );
+    }
+
+    #[test]
+    fn quote() {
+        let lines = vec![
+            text(Block::Quote, "to be"),
+            line(Block::Quote, &[(&[Inline::Tag(ITALIC)], "or not")]),
+            text(Block::Paragraph, "indeed"),
+        ];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "> to be\n> or not\n\nindeed");
+        assert_eq!(
+            html,
+            "<blockquote>to be<br><em>or not</em></blockquote>indeed"
+        );
+    }
+
+    #[test]
+    fn code_block_with_language() {
+        let rust = Block::Code(String::from("rust"));
+        let lines = vec![
+            text(rust.clone(), "fn main() {"),
+            // The inline formats don't apply in code blocks
+            line(rust.clone(), &[(&[Inline::Tag(BOLD)], "    a < b")]),
+            text(rust, "}"),
+        ];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "fn main() {\n    a < b\n}");
+        assert_eq!(
+            html,
+            "<pre><code class=\"language-rust\">fn main() {\n    a &lt; b\n}</code></pre>"
+        );
+    }
+
+    #[test]
+    fn code_block_without_language() {
+        let lines = vec![text(Block::Code(String::new()), "ls")];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "ls");
+        assert_eq!(html, "<pre><code>ls</code></pre>");
+    }
+
+    #[test]
+    fn trailing_empty_lines() {
+        let lines = vec![
+            text(Block::Quote, "quoted"),
+            text(Block::Paragraph, "  "),
+            text(Block::Paragraph, ""),
+        ];
+
+        let (plain, html) = to_bodies(&lines).unwrap();
+        assert_eq!(plain, "> quoted");
+        assert_eq!(html, "<blockquote>quoted</blockquote>");
+    }
+}
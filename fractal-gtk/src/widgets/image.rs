@@ -11,13 +11,18 @@ use glib::source::Continue;
 use gtk::prelude::*;
 use gtk::DrawingArea;
 use log::error;
-use matrix_sdk::{identifiers::MxcUri, Client as MatrixClient};
+use matrix_sdk::{
+    identifiers::{MxcUri, RoomId},
+    Client as MatrixClient,
+};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 #[derive(Clone, Debug)]
 pub struct Image {
     pub path: Either<MxcUri, PathBuf>,
+    /// The room the image was sent to, to account for it in the media cache
+    pub room_id: Option<RoomId>,
     pub local_path: Arc<Mutex<Option<PathBuf>>>,
     pub max_size: Option<(i32, i32)>,
     pub widget: DrawingArea,
@@ -62,6 +67,7 @@ impl Image {
 
         Image {
             path,
+            room_id: None,
             local_path: Arc::new(Mutex::new(None)),
             max_size: None,
             widget: da,
@@ -90,6 +96,11 @@ impl Image {
         self
     }
 
+    pub fn room(mut self, room_id: RoomId) -> Image {
+        self.room_id = Some(room_id);
+        self
+    }
+
     pub fn size(mut self, size: Option<(i32, i32)>) -> Image {
         self.max_size = size;
         self
@@ -267,11 +278,16 @@ impl Image {
         match self.path.as_ref() {
             Either::Left(mxc) => {
                 let mxc = mxc.clone();
+                let room_id = self.room_id.clone();
                 // asyn load
                 let response = if self.thumb {
-                    RUNTIME.spawn(async move { media::get_thumb(session_client, &mxc).await })
+                    RUNTIME.spawn(async move {
+                        media::get_thumb(session_client, &mxc, room_id.as_ref()).await
+                    })
                 } else {
-                    RUNTIME.spawn(async move { media::get_media(session_client, &mxc).await })
+                    RUNTIME.spawn(async move {
+                        media::get_media(session_client, &mxc, room_id.as_ref()).await
+                    })
                 };
                 let local_path = self.local_path.clone();
                 let pix = self.pixbuf.clone();
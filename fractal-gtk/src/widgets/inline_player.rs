@@ -40,7 +40,10 @@ use std::ops::Deref;
 use std::path::PathBuf;
 use std::rc::Rc;
 
-use matrix_sdk::{identifiers::MxcUri, Client as MatrixClient};
+use matrix_sdk::{
+    identifiers::{MxcUri, RoomId},
+    Client as MatrixClient,
+};
 use url::Url;
 
 use crate::app::RUNTIME;
@@ -54,6 +57,7 @@ pub trait PlayerExt {
         player: Rc<Self>,
         session_client: MatrixClient,
         media_url: MxcUri,
+        room_id: RoomId,
         bx: gtk::Box,
         start_playing: bool,
     );
@@ -500,11 +504,13 @@ impl<T: MediaPlayer + 'static> PlayerExt for T {
         player: Rc<Self>,
         session_client: MatrixClient,
         media_url: MxcUri,
+        room_id: RoomId,
         bx: gtk::Box,
         start_playing: bool,
     ) {
-        let response =
-            RUNTIME.spawn(async move { media::get_media(session_client, &media_url).await });
+        let response = RUNTIME.spawn(async move {
+            media::get_media(session_client, &media_url, Some(&room_id)).await
+        });
         bx.set_opacity(0.3);
         let local_path = player.get_local_path_access();
         glib::MainContext::default().spawn_local(async move {
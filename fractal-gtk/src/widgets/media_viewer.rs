@@ -15,7 +15,7 @@ use glib::source::Continue;
 use gtk::prelude::*;
 use gtk::Overlay;
 use libhandy::HeaderBarExt;
-use matrix_sdk::identifiers::{MxcUri, UserId};
+use matrix_sdk::identifiers::{MxcUri, RoomId, UserId};
 use matrix_sdk::Client as MatrixClient;
 
 use crate::model::{message::Message, room::Room};
@@ -322,6 +322,7 @@ impl Data {
         match msg.mtype.as_ref() {
             "m.image" => {
                 let image = image::Image::new(Either::Left(url))
+                    .room(msg.room.clone())
                     .shrink_to_fit(true)
                     .center(true)
                     .build(session_client);
@@ -330,7 +331,7 @@ impl Data {
                 self.widget = Widget::Image(image);
             }
             "m.video" => {
-                let widget = self.create_video_widget(session_client, url);
+                let widget = self.create_video_widget(session_client, url, msg.room.clone());
                 media_container.add(&widget.outer_box);
                 self.widget = Widget::Video(widget);
                 media_container.show_all();
@@ -342,7 +343,12 @@ impl Data {
         self.set_nav_btn_visibility();
     }
 
-    fn create_video_widget(&self, session_client: MatrixClient, url: MxcUri) -> VideoWidget {
+    fn create_video_widget(
+        &self,
+        session_client: MatrixClient,
+        url: MxcUri,
+        room_id: RoomId,
+    ) -> VideoWidget {
         let with_controls = true;
         let player = VideoPlayerWidget::new(with_controls);
         let bx = gtk::Box::new(gtk::Orientation::Vertical, 0);
@@ -351,6 +357,7 @@ impl Data {
             player.clone(),
             session_client,
             url,
+            room_id,
             bx.clone(),
             start_playing,
         );
@@ -673,6 +680,7 @@ impl MediaViewer {
         match media_msg.mtype.as_ref() {
             "m.image" => {
                 let image = image::Image::new(Either::Left(url))
+                    .room(media_msg.room.clone())
                     .shrink_to_fit(true)
                     .center(true)
                     .build(session_client);
@@ -683,7 +691,11 @@ impl MediaViewer {
                 self.data.borrow_mut().widget = Widget::Image(image);
             }
             "m.video" => {
-                let video_widget = self.data.borrow().create_video_widget(session_client, url);
+                let video_widget = self.data.borrow().create_video_widget(
+                    session_client,
+                    url,
+                    media_msg.room.clone(),
+                );
                 media_container.add(&video_widget.outer_box);
                 media_container.show_all();
 
@@ -1,8 +1,14 @@
+use crate::app::RUNTIME;
 use crate::appop::UserInfoCache;
+use crate::backend::{media, HandleError};
 use crate::cache::download_to_cache;
 use crate::globals;
+use crate::model::member::Member;
+use crate::ui::member::build_memberbox_pill;
 use crate::ui::MessageContent as Message;
 use crate::ui::RowType;
+use crate::util;
+use crate::util::html::HtmlPart;
 use crate::util::i18n::i18n;
 use crate::util::markup_text;
 use crate::widgets;
@@ -16,12 +22,28 @@ use either::Either;
 use glib::clone;
 use gtk::{prelude::*, ButtonExt, ContainerExt, LabelExt, Overlay, WidgetExt};
 use html2pango::block::{markup_html, HtmlBlock};
+use html2pango::html_escape;
 use itertools::Itertools;
+use lazy_static::lazy_static;
+use matrix_sdk::identifiers::{MxcUri, RoomId};
 use matrix_sdk::Client as MatrixClient;
+use regex::Regex;
 use sourceview4::BufferExt;
+use sourceview4::LanguageManagerExt;
+use std::cell::RefCell;
 use std::cmp::max;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::rc::Rc;
 
+lazy_static! {
+    /// The links to users in the markup of a message, capturing their permalink and their name
+    static ref MENTION_REGEX: Regex =
+        Regex::new(r#"(?s)<a href="(https://matrix\.to/#/(?:@|%40)[^"]+)">(.*?)</a>"#).unwrap();
+    static ref LINK_REGEX: Regex = Regex::new(r#"(?s)<a href="([^"]*)">(.*?)</a>"#).unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r#"https?://[^\s<>"]+"#).unwrap();
+}
+
 // A message row in the room history
 #[derive(Clone, Debug)]
 pub struct MessageBox {
@@ -48,13 +70,13 @@ impl MessageBox {
             RowType::File if is_temp => MessageBoxMsg::tmpwidget("Uploading file."),
             RowType::Emote => {
                 container.root.set_margin_top(12);
-                MessageBoxMsg::small_widget(&container, session_client, msg)
+                MessageBoxMsg::small_widget(&container, session_client, user_info_cache, msg)
             }
             _ if has_header => {
                 container.root.set_margin_top(12);
                 MessageBoxMsg::widget(&container, session_client, user_info_cache, msg)
             }
-            _ => MessageBoxMsg::small_widget(&container, session_client, msg),
+            _ => MessageBoxMsg::small_widget(&container, session_client, user_info_cache, msg),
         };
 
         if is_temp {
@@ -93,7 +115,7 @@ impl MessageBox {
             if let RowType::Emote = msg.mtype {
                 self.container.root.set_margin_top(12);
             }
-            MessageBoxMsg::small_widget(&self.container, session_client, &msg)
+            MessageBoxMsg::small_widget(&self.container, session_client, user_info_cache, &msg)
         };
         if let Some(eb) = self.container.eventbox.get_child() {
             self.container.eventbox.remove(&eb);
@@ -119,6 +141,27 @@ impl MessageBox {
         }
     }
 
+    /// The bar showing how much of the file of a temporary media message has been uploaded
+    pub fn get_progress_bar(&self) -> Option<&gtk::ProgressBar> {
+        match &self.msg_widget {
+            MessageBoxMsg::Temp { progress, .. } => Some(progress),
+            _ => None,
+        }
+    }
+
+    /// Shows the time of the header again, after the clock format changed
+    pub fn refresh_timestamp(&self) {
+        if let MessageBoxMsg::Final {
+            content: MessageBoxContent {
+                info: Some(info), ..
+            },
+            ..
+        } = &self.msg_widget
+        {
+            set_room_msg_date(&info.date, &info.time);
+        }
+    }
+
     pub fn has_header(&self) -> bool {
         match &self.msg_widget {
             MessageBoxMsg::Final { content, .. } => content.info.is_some(),
@@ -181,10 +224,21 @@ impl MessageBoxContainer {
             self.eventbox.upcast_ref::<gtk::Widget>()
         };
 
+        // Files still being uploaded can only be cancelled
+        let upload = msg
+            .msg
+            .local_path
+            .clone()
+            .filter(|_| msg.msg.id.is_none() && msg.msg.url.is_none());
+
         let id = msg.msg.id.clone();
+        let upload_path = upload.clone();
         widget.connect_button_press_event(move |w, e| {
             if e.triggers_context_menu() {
                 let menu = MessageMenu::new(id.as_ref(), &mtype, &redactable, Some(w));
+                if let Some(ref path) = upload_path {
+                    menu.set_upload(path);
+                }
                 let coords = e.get_position();
                 menu.show_at_coords(w, coords);
                 Inhibit(true)
@@ -197,6 +251,9 @@ impl MessageBoxContainer {
         self.gesture
             .connect_pressed(clone!(@weak widget => move |_, x, y| {
                 let menu = MessageMenu::new(id.as_ref(), &mtype, &redactable, Some(&widget));
+                if let Some(ref path) = upload {
+                    menu.set_upload(path);
+                }
                 menu.show_at_coords(&widget, (x, y));
             }));
         None
@@ -205,7 +262,10 @@ impl MessageBoxContainer {
 
 #[derive(Clone, Debug)]
 enum MessageBoxMsg {
-    Temp(gtk::Box),
+    Temp {
+        root: gtk::Box,
+        progress: gtk::ProgressBar,
+    },
     Final {
         root: gtk::Box,
         avatar: Option<widgets::Avatar>,
@@ -215,10 +275,19 @@ enum MessageBoxMsg {
 
 impl MessageBoxMsg {
     fn tmpwidget(label_content: &str) -> Self {
-        let upload_attachment_msg = gtk::Box::new(gtk::Orientation::Horizontal, 10);
-        upload_attachment_msg.add(&gtk::Label::new(Some(i18n(label_content).as_str())));
-
-        Self::Temp(upload_attachment_msg)
+        let upload_attachment_msg = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        let label = gtk::Label::new(Some(i18n(label_content).as_str()));
+        label.set_xalign(0.0);
+        upload_attachment_msg.add(&label);
+
+        let progress = gtk::ProgressBar::new();
+        progress.set_valign(gtk::Align::Center);
+        upload_attachment_msg.add(&progress);
+
+        Self::Temp {
+            root: upload_attachment_msg,
+            progress,
+        }
     }
 
     fn widget(
@@ -232,7 +301,13 @@ impl MessageBoxMsg {
         // | avatar | content |
         // +--------+---------+
         let msg_widget = gtk::Box::new(gtk::Orientation::Horizontal, 10);
-        let content = MessageBoxContent::build(container, session_client.clone(), msg, true);
+        let content = MessageBoxContent::build(
+            container,
+            session_client.clone(),
+            user_info_cache.clone(),
+            msg,
+            true,
+        );
         // TODO: make build_room_msg_avatar() faster (currently ~1ms)
         let avatar = build_room_msg_avatar(session_client, user_info_cache, msg);
 
@@ -249,6 +324,7 @@ impl MessageBoxMsg {
     fn small_widget(
         container: &MessageBoxContainer,
         session_client: MatrixClient,
+        user_info_cache: UserInfoCache,
         msg: &Message,
     ) -> Self {
         // msg
@@ -256,7 +332,8 @@ impl MessageBoxMsg {
         // |        | content |
         // +--------+---------+
         let msg_widget = gtk::Box::new(gtk::Orientation::Horizontal, 5);
-        let content = MessageBoxContent::build(container, session_client, msg, false);
+        let content =
+            MessageBoxContent::build(container, session_client, user_info_cache, msg, false);
         content.root.set_margin_start(50);
 
         msg_widget.pack_start(&content.root, true, true, 0);
@@ -270,7 +347,7 @@ impl MessageBoxMsg {
 
     fn root(&self) -> &gtk::Box {
         match self {
-            Self::Temp(root) => root,
+            Self::Temp { root, .. } => root,
             Self::Final { root, .. } => root,
         }
     }
@@ -287,6 +364,7 @@ impl MessageBoxContent {
     fn build(
         container: &MessageBoxContainer,
         session_client: MatrixClient,
+        user_info_cache: UserInfoCache,
         msg: &Message,
         info_header: bool,
     ) -> Self {
@@ -309,7 +387,7 @@ impl MessageBoxContent {
             None
         };
 
-        let body_bx = MessageBodyBox::build(&container, session_client, msg);
+        let body_bx = MessageBodyBox::build(&container, session_client, user_info_cache, msg);
         content.pack_start(&body_bx.root, true, true, 0);
 
         Self {
@@ -451,14 +529,19 @@ struct MessageBodyBox {
 }
 
 impl MessageBodyBox {
-    fn build(container: &MessageBoxContainer, session_client: MatrixClient, msg: &Message) -> Self {
+    fn build(
+        container: &MessageBoxContainer,
+        session_client: MatrixClient,
+        user_info_cache: UserInfoCache,
+        msg: &Message,
+    ) -> Self {
         // body_bx
         // +------+-----------+
         // | body | edit_mark |
         // +------+-----------+
         let body_bx = gtk::Box::new(gtk::Orientation::Horizontal, 0);
 
-        let (body, type_extras) = build_room_msg(container, session_client, msg);
+        let (body, type_extras) = build_room_msg(container, session_client, user_info_cache, msg);
 
         body_bx.pack_start(&body, true, true, 0);
 
@@ -492,6 +575,7 @@ type BodyAndType = (gtk::Box, MessageBodyType);
 fn build_room_msg(
     container: &MessageBoxContainer,
     session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
     msg: &Message,
 ) -> BodyAndType {
     let (body, type_extras) = match msg.mtype {
@@ -501,7 +585,7 @@ fn build_room_msg(
         RowType::Video => build_room_video_player(session_client, msg),
         RowType::Emote => build_room_msg_emote(msg),
         RowType::File => build_room_msg_file(msg),
-        _ => build_room_msg_body(container, msg),
+        _ => build_room_msg_body(container, session_client, user_info_cache, msg),
     };
 
     match type_extras {
@@ -522,6 +606,8 @@ fn build_room_msg(
 
 fn build_room_msg_body_html(
     container: &MessageBoxContainer,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
     msg: &Message,
 ) -> anyhow::Result<gtk::Box> {
     let raw = msg.msg.formatted_body.clone().unwrap_or_default();
@@ -530,18 +616,219 @@ fn build_room_msg_body_html(
         anyhow::bail!("Empty message omited: <!-- raw HTML omitted -->, using plain text instead.");
     }
 
-    let blocks =
-        markup_html(&raw).with_context(|| format!("Could not render message: {}", &raw))?;
+    render_html(container, session_client, user_info_cache, msg, &raw)
+}
+
+/// Renders some HTML with html2pango, apart from the parts it can't render
+fn render_html(
+    container: &MessageBoxContainer,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
+    msg: &Message,
+    html: &str,
+) -> anyhow::Result<gtk::Box> {
     let bx = gtk::Box::new(gtk::Orientation::Vertical, 6);
-    for b in blocks {
-        let widget = render_html_block(container, msg, &b);
-        bx.add(&widget);
+    for part in util::html::split(html) {
+        match part {
+            HtmlPart::Html(html) => {
+                let blocks = markup_html(&html)
+                    .with_context(|| format!("Could not render message: {}", &html))?;
+                for b in blocks {
+                    let widget = render_html_block(
+                        container,
+                        session_client.clone(),
+                        user_info_cache.clone(),
+                        msg,
+                        &b,
+                    );
+                    bx.add(&widget);
+                }
+            }
+            HtmlPart::Code { lang, text } => {
+                bx.add(&build_code_block(container, msg, &text, lang.as_deref()));
+            }
+            HtmlPart::Table(rows) => {
+                let grid = gtk::Grid::new();
+                grid.set_row_spacing(6);
+                grid.set_column_spacing(12);
+                grid.get_style_context().add_class("html-table");
+                for (i, row) in rows.iter().enumerate() {
+                    for (j, cell) in row.iter().enumerate() {
+                        let w = render_html(
+                            container,
+                            session_client.clone(),
+                            user_info_cache.clone(),
+                            msg,
+                            &cell.html,
+                        )?;
+                        if cell.header {
+                            w.get_style_context().add_class("table-header");
+                        }
+                        grid.attach(&w, j as i32, i as i32, 1, 1);
+                    }
+                }
+                let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+                scrolled.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
+                scrolled.add(&grid);
+                bx.add(&scrolled);
+            }
+            HtmlPart::Details { summary, content } => {
+                let summary = Some(summary)
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or_else(|| i18n("Details"));
+                let expander = gtk::Expander::new(Some(summary.as_str()));
+                let w = render_html(
+                    container,
+                    session_client.clone(),
+                    user_info_cache.clone(),
+                    msg,
+                    &content,
+                )?;
+                w.set_margin_start(18);
+                expander.add(&w);
+                bx.add(&expander);
+            }
+            HtmlPart::Math(tex) => match util::math::to_markup(&tex) {
+                Some(markup) => {
+                    let w = gtk::Label::new(None);
+                    set_label_styles(&w);
+                    w.set_markup(&markup);
+                    w.set_xalign(0.5);
+                    w.set_justify(gtk::Justification::Center);
+                    w.get_style_context().add_class("math-display");
+                    container.connect_right_click_menu(msg, Some(w.upcast_ref::<gtk::Widget>()));
+                    bx.add(&w);
+                }
+                None => bx.add(&build_code_block(container, msg, &tex, Some("latex"))),
+            },
+            HtmlPart::Image { src, alt, size } => {
+                bx.add(&build_html_image(
+                    session_client.clone(),
+                    msg,
+                    &src,
+                    &alt,
+                    size,
+                ));
+            }
+        }
     }
     Ok(bx)
 }
 
+/// Renders a code block, highlighted when its language is known
+fn build_code_block(
+    container: &MessageBoxContainer,
+    msg: &Message,
+    text: &str,
+    lang: Option<&str>,
+) -> gtk::Widget {
+    let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
+    scrolled.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
+    let buffer = sourceview4::Buffer::new::<gtk::TextTagTable>(None);
+    buffer.set_highlight_matching_brackets(false);
+    buffer.set_text(text.trim_end_matches('\n'));
+
+    // Messages use the names of Markdown code blocks, not always the IDs of sourceview
+    let language = lang
+        .map(|lang| match lang {
+            "bash" | "shell" | "zsh" => "sh",
+            "c++" => "cpp",
+            "javascript" => "js",
+            "py" => "python",
+            "rs" => "rust",
+            "yml" => "yaml",
+            lang => lang,
+        })
+        .and_then(|lang| sourceview4::LanguageManager::get_default()?.get_language(lang));
+    if let Some(language) = language {
+        buffer.set_language(Some(&language));
+        buffer.set_highlight_syntax(true);
+    }
+
+    let view = sourceview4::View::with_buffer(&buffer);
+    view.set_editable(false);
+    view.get_style_context().add_class("codeview");
+    container.connect_right_click_menu(msg, Some(&view.upcast_ref::<gtk::Widget>()));
+    scrolled.add(&view);
+
+    let copy = gtk::Button::from_icon_name(Some("edit-copy-symbolic"), gtk::IconSize::Button);
+    copy.set_tooltip_text(Some(i18n("Copy").as_str()));
+    copy.set_halign(gtk::Align::End);
+    copy.set_valign(gtk::Align::Start);
+    copy.get_style_context().add_class("code-copy");
+    copy.connect_clicked(clone!(@strong buffer => move |_| {
+        let (start, end) = buffer.get_bounds();
+        if let Some(text) = buffer.get_text(&start, &end, false) {
+            let atom = gdk::Atom::intern("CLIPBOARD");
+            gtk::Clipboard::get(&atom).set_text(&text);
+        }
+    }));
+
+    let overlay = gtk::Overlay::new();
+    overlay.add(&scrolled);
+    overlay.add_overlay(&copy);
+    overlay.upcast::<gtk::Widget>()
+}
+
+/// Renders an image of the HTML. Only images from the homeserver are loaded, the others are
+/// replaced by their description.
+fn build_html_image(
+    session_client: MatrixClient,
+    msg: &Message,
+    src: &str,
+    alt: &str,
+    size: Option<(i32, i32)>,
+) -> gtk::Widget {
+    let url = match MxcUri::try_from(src) {
+        Ok(url) => url,
+        Err(_) => {
+            let w = gtk::Label::new(Some(alt));
+            set_label_styles(&w);
+            return w.upcast::<gtk::Widget>();
+        }
+    };
+
+    let (max_width, max_height) = globals::MAX_IMAGE_SIZE;
+    let size = size
+        .map(|(width, height)| (width.min(max_width), height.min(max_height)))
+        .unwrap_or(globals::MAX_IMAGE_SIZE);
+    let image = widgets::image::Image::new(Either::Left(url))
+        .room(msg.msg.room.clone())
+        .size(Some(size))
+        .build(session_client);
+    if !alt.is_empty() {
+        image.widget.set_tooltip_text(Some(alt));
+    }
+    image.widget.set_halign(gtk::Align::Start);
+    image.widget.upcast::<gtk::Widget>()
+}
+
+/// Sets the markup of a label rendering HTML, with the spoilers revealed on click
+fn set_html_markup(w: &gtk::Label, markup: &str) {
+    w.set_markup(&util::html::apply_inline_formats(markup, |_| false));
+    if !util::html::has_spoilers(markup) {
+        return;
+    }
+
+    let markup = markup.to_string();
+    let revealed: RefCell<HashSet<usize>> = Default::default();
+    w.connect_activate_link(move |w, uri| match util::html::spoiler_index(uri) {
+        Some(n) => {
+            revealed.borrow_mut().insert(n);
+            let revealed = revealed.borrow();
+            w.set_markup(&util::html::apply_inline_formats(&markup, |i| {
+                revealed.contains(&i)
+            }));
+            Inhibit(true)
+        }
+        None => Inhibit(false),
+    });
+}
+
 fn render_html_block(
     container: &MessageBoxContainer,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
     msg: &Message,
     block: &HtmlBlock,
 ) -> gtk::Widget {
@@ -549,7 +836,7 @@ fn render_html_block(
         HtmlBlock::Heading(n, s) => {
             let w = gtk::Label::new(None);
             set_label_styles(&w);
-            w.set_markup(&s);
+            set_html_markup(&w, &s);
             w.get_style_context().add_class(&format!("h{}", n));
             container.connect_right_click_menu(msg, Some(&w.upcast_ref::<gtk::Widget>()));
             w.upcast::<gtk::Widget>()
@@ -567,7 +854,7 @@ fn render_html_block(
                 set_label_styles(&w);
                 h_box.add(&bullet);
                 h_box.add(&w);
-                w.set_markup(&li);
+                set_html_markup(&w, &li);
                 container.connect_right_click_menu(msg, Some(&w.upcast_ref::<gtk::Widget>()));
                 bx.add(&h_box);
             }
@@ -587,39 +874,38 @@ fn render_html_block(
                 set_label_styles(&w);
                 h_box.add(&bullet);
                 h_box.add(&w);
-                w.set_markup(&ol);
+                set_html_markup(&w, &ol);
                 bx.add(&h_box);
                 container.connect_right_click_menu(msg, Some(&w.upcast_ref::<gtk::Widget>()));
             }
 
             bx.upcast::<gtk::Widget>()
         }
-        HtmlBlock::Code(s) => {
-            let scrolled = gtk::ScrolledWindow::new(gtk::NONE_ADJUSTMENT, gtk::NONE_ADJUSTMENT);
-            scrolled.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
-            let buffer = sourceview4::Buffer::new::<gtk::TextTagTable>(None);
-            buffer.set_highlight_matching_brackets(false);
-            buffer.set_text(&s);
-            let view = sourceview4::View::with_buffer(&buffer);
-            view.set_editable(false);
-            view.get_style_context().add_class("codeview");
-            container.connect_right_click_menu(msg, Some(&view.upcast_ref::<gtk::Widget>()));
-            scrolled.add(&view);
-            scrolled.upcast::<gtk::Widget>()
-        }
+        HtmlBlock::Code(s) => build_code_block(container, msg, &s, None),
         HtmlBlock::Quote(blocks) => {
             let bx = gtk::Box::new(gtk::Orientation::Vertical, 6);
             bx.get_style_context().add_class("quote");
             for b in blocks.iter() {
-                let w = render_html_block(container, msg, &b);
+                let w = render_html_block(
+                    container,
+                    session_client.clone(),
+                    user_info_cache.clone(),
+                    msg,
+                    &b,
+                );
                 bx.add(&w);
             }
             bx.upcast::<gtk::Widget>()
         }
+        HtmlBlock::Text(s) if MENTION_REGEX.is_match(s) => {
+            let w = build_mentions_text(session_client, user_info_cache, s);
+            container.connect_right_click_menu(msg, Some(&w.upcast_ref::<gtk::Widget>()));
+            w.upcast::<gtk::Widget>()
+        }
         HtmlBlock::Text(s) => {
             let w = gtk::Label::new(None);
             set_label_styles(&w);
-            w.set_markup(&s);
+            set_html_markup(&w, &s);
             container.connect_right_click_menu(msg, Some(&w.upcast_ref::<gtk::Widget>()));
             w.upcast::<gtk::Widget>()
         }
@@ -627,10 +913,136 @@ fn render_html_block(
     widget
 }
 
+/// Renders a paragraph with the mentioned users shown as pills, with the spoilers revealed
+/// on click
+fn build_mentions_text(
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
+    markup: &str,
+) -> gtk::TextView {
+    let view = gtk::TextView::new();
+    view.set_editable(false);
+    view.set_cursor_visible(false);
+    view.set_wrap_mode(gtk::WrapMode::WordChar);
+    view.set_hexpand(true);
+    view.get_style_context().add_class("mentions-text");
+
+    fill_mentions_text(
+        &view,
+        session_client.clone(),
+        user_info_cache.clone(),
+        &util::html::apply_inline_formats(markup, |_| false),
+    );
+    if !util::html::has_spoilers(markup) {
+        return view;
+    }
+
+    let markup = markup.to_string();
+    let revealed: RefCell<HashSet<usize>> = Default::default();
+    view.connect_button_release_event(move |view, ev| {
+        let (x, y) = ev.get_position();
+        let (x, y) = view.window_to_buffer_coords(gtk::TextWindowType::Widget, x as i32, y as i32);
+        let spoiler = view.get_iter_at_location(x, y).and_then(|iter| {
+            iter.get_tags().iter().find_map(|tag| {
+                tag.get_property_name()
+                    .and_then(|name| util::html::spoiler_index(&name))
+            })
+        });
+
+        match spoiler {
+            Some(n) if revealed.borrow_mut().insert(n) => {
+                let revealed = revealed.borrow();
+                fill_mentions_text(
+                    view,
+                    session_client.clone(),
+                    user_info_cache.clone(),
+                    &util::html::apply_inline_formats(&markup, |i| revealed.contains(&i)),
+                );
+                Inhibit(true)
+            }
+            _ => Inhibit(false),
+        }
+    });
+
+    view
+}
+
+/// Replaces the text of the view by the markup, with pills for the mentions. The hidden
+/// spoilers are tagged with their link to be found on click.
+fn fill_mentions_text(
+    view: &gtk::TextView,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
+    markup: &str,
+) {
+    let buffer = view
+        .get_buffer()
+        .expect("A new text view always has a buffer");
+    buffer.set_text("");
+
+    // Text buffers don't understand the links of labels, so the ones that aren't
+    // mentions are only underlined
+    let insert_markup = |iter: &mut gtk::TextIter, markup: &str| {
+        let mut last = 0;
+        for captures in LINK_REGEX.captures_iter(markup) {
+            let link = captures.get(0).expect("The whole match is always captured");
+            buffer.insert_markup(iter, &markup[last..link.start()]);
+            last = link.end();
+
+            if util::html::spoiler_index(&captures[1]).is_none() {
+                let underlined = format!(r#"<span underline="single">{}</span>"#, &captures[2]);
+                buffer.insert_markup(iter, &underlined);
+                continue;
+            }
+
+            let start = iter.get_offset();
+            buffer.insert_markup(iter, &captures[2]);
+            let tag = buffer
+                .get_tag_table()
+                .and_then(|table| table.lookup(&captures[1]))
+                .or_else(|| buffer.create_tag(Some(&captures[1]), &[]));
+            if let Some(tag) = tag {
+                buffer.apply_tag(&tag, &buffer.get_iter_at_offset(start), iter);
+            }
+        }
+        buffer.insert_markup(iter, &markup[last..]);
+    };
+
+    let mut iter = buffer.get_end_iter();
+    let mut last = 0;
+    for captures in MENTION_REGEX.captures_iter(markup) {
+        let uid = match util::mention_user(&captures[1]) {
+            Some(uid) => uid,
+            None => continue,
+        };
+        let link = captures.get(0).expect("The whole match is always captured");
+        insert_markup(&mut iter, &markup[last..link.start()]);
+        last = link.end();
+
+        let anchor = match buffer.create_child_anchor(&mut iter) {
+            Some(anchor) => anchor,
+            None => continue,
+        };
+        let name = pango::parse_markup(&captures[2], '\0')
+            .map(|(_, text, _)| text.to_string())
+            .ok()
+            .filter(|name| !name.is_empty());
+        let member = Member {
+            uid,
+            alias: name,
+            avatar: None,
+        };
+        let pill = build_memberbox_pill(session_client.clone(), user_info_cache.clone(), member);
+        view.add_child_at_anchor(&pill, &anchor);
+    }
+    insert_markup(&mut iter, &markup[last..]);
+}
+
 fn build_room_msg_sticker(session_client: MatrixClient, msg: &Message) -> BodyAndType {
     let bx = gtk::Box::new(gtk::Orientation::Horizontal, 0);
     if let Some(url) = msg.msg.url.clone() {
         let image = widgets::image::Image::new(Either::Left(url))
+            .room(msg.msg.room.clone())
             .size(Some(globals::MAX_STICKER_SIZE))
             .build(session_client);
         image.widget.set_tooltip_text(Some(&msg.msg.body[..]));
@@ -651,6 +1063,7 @@ fn build_room_audio_player(session_client: MatrixClient, msg: &Message) -> BodyA
             player.clone(),
             session_client,
             url,
+            msg.msg.room.clone(),
             bx.clone(),
             start_playing,
         );
@@ -702,6 +1115,7 @@ fn build_room_msg_image(session_client: MatrixClient, msg: &Message) -> BodyAndT
 
     let image = if let Some(img_path) = img {
         let image = widgets::image::Image::new(img_path)
+            .room(msg.msg.room.clone())
             .size(Some(globals::MAX_IMAGE_SIZE))
             .build(session_client);
 
@@ -729,6 +1143,7 @@ fn build_room_video_player(session_client: MatrixClient, msg: &Message) -> BodyA
             player.clone(),
             session_client,
             url,
+            msg.msg.room.clone(),
             bx.clone(),
             start_playing,
         );
@@ -859,15 +1274,127 @@ fn build_room_msg_file(msg: &Message) -> BodyAndType {
     (bx, MessageBodyType::File)
 }
 
-fn build_room_msg_body(container: &MessageBoxContainer, msg: &Message) -> BodyAndType {
+fn build_room_msg_body(
+    container: &MessageBoxContainer,
+    session_client: MatrixClient,
+    user_info_cache: UserInfoCache,
+    msg: &Message,
+) -> BodyAndType {
     let bx = match msg.msg.format.as_deref() {
-        Some("org.matrix.custom.html") => build_room_msg_body_html(container, &msg)
-            .unwrap_or_else(|_err| build_room_msg_body_text(container, &msg)),
+        Some("org.matrix.custom.html") => {
+            build_room_msg_body_html(container, session_client.clone(), user_info_cache, &msg)
+                .unwrap_or_else(|_err| build_room_msg_body_text(container, &msg))
+        }
         _ => build_room_msg_body_text(container, &msg),
     };
+
+    if msg.preview_urls {
+        if let Some(url) = previewable_url(&msg.msg.body) {
+            load_url_preview(&bx, session_client, msg, url);
+        }
+    }
+
     (bx, MessageBodyType::Text)
 }
 
+/// The first web link of a message body, the permalinks to users and rooms and the quoted
+/// message of a reply aside
+fn previewable_url(body: &str) -> Option<String> {
+    body.lines()
+        .filter(|line| !line.starts_with("> "))
+        .flat_map(|line| URL_REGEX.find_iter(line))
+        .map(|m| {
+            m.as_str()
+                .trim_end_matches(&['.', ',', ';', ':', '!', '?', ')', '\''][..])
+        })
+        .find(|url| !url.starts_with("https://matrix.to/"))
+        .map(String::from)
+}
+
+/// Adds the preview of `url` to the message body once the homeserver returned it
+fn load_url_preview(bx: &gtk::Box, session_client: MatrixClient, msg: &Message, url: String) {
+    let room_id = msg.msg.room.clone();
+    let response = {
+        let session_client = session_client.clone();
+        let room_id = room_id.clone();
+        let url = url.clone();
+        RUNTIME.spawn(
+            async move { media::get_url_preview(session_client, &url, Some(&room_id)).await },
+        )
+    };
+
+    let bx = bx.clone();
+    glib::MainContext::default().spawn_local(async move {
+        match response.await {
+            Ok(Ok(preview)) if !preview.is_empty() => {
+                let card = build_url_preview(session_client, room_id, &url, &preview);
+                bx.add(&card);
+                card.show_all();
+            }
+            Ok(Err(err)) => err.handle_error(),
+            _ => {}
+        }
+    });
+}
+
+fn build_url_preview(
+    session_client: MatrixClient,
+    room_id: RoomId,
+    url: &str,
+    preview: &media::UrlPreview,
+) -> gtk::Box {
+    let card = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    card.get_style_context().add_class("url-preview");
+
+    // Only the images the homeserver stored are loaded, for the others would be fetched
+    // from a third party
+    if let Some(mxc) = preview
+        .image
+        .as_deref()
+        .and_then(|src| MxcUri::try_from(src).ok())
+    {
+        let image = widgets::image::Image::new(Either::Left(mxc))
+            .room(room_id)
+            .size(Some(globals::URL_PREVIEW_IMAGE_SIZE))
+            .build(session_client);
+        image.widget.set_valign(gtk::Align::Start);
+        card.add(&image.widget);
+    }
+
+    let text = gtk::Box::new(gtk::Orientation::Vertical, 3);
+    text.set_hexpand(true);
+
+    if let Some(site_name) = &preview.site_name {
+        let w = gtk::Label::new(Some(site_name));
+        w.set_xalign(0.0);
+        w.set_ellipsize(pango::EllipsizeMode::End);
+        w.get_style_context().add_class("dim-label");
+        text.add(&w);
+    }
+
+    let title = preview.title.as_deref().unwrap_or(url);
+    let w = gtk::Label::new(None);
+    w.set_markup(&format!(
+        "<a href=\"{}\"><b>{}</b></a>",
+        html_escape(url),
+        html_escape(title)
+    ));
+    w.set_tooltip_text(Some(url));
+    set_label_styles(&w);
+    text.add(&w);
+
+    if let Some(description) = &preview.description {
+        let w = gtk::Label::new(Some(description));
+        set_label_styles(&w);
+        w.set_lines(3);
+        w.set_ellipsize(pango::EllipsizeMode::End);
+        text.add(&w);
+    }
+
+    card.add(&text);
+    card
+}
+
 fn build_room_msg_body_text(container: &MessageBoxContainer, msg: &Message) -> gtk::Box {
     let bx = gtk::Box::new(gtk::Orientation::Vertical, 6);
 
@@ -938,6 +1465,7 @@ pub struct MessageBoxInfoHeader {
     username_event_box: gtk::EventBox,
     username: gtk::Label,
     date: gtk::Label,
+    time: DateTime<Local>,
 }
 
 impl From<&Message> for MessageBoxInfoHeader {
@@ -966,6 +1494,7 @@ impl From<&Message> for MessageBoxInfoHeader {
             username_event_box,
             username,
             date,
+            time: msg.msg.date,
         }
     }
 }
@@ -982,20 +1511,8 @@ fn build_room_msg_username(uname: &str) -> gtk::Label {
 }
 
 fn build_room_msg_date(dt: &DateTime<Local>) -> gtk::Label {
-    // TODO: get system preference for 12h/24h
-    let use_ampm = false;
-    let format = if use_ampm {
-        // Use 12h time format (AM/PM)
-        i18n("%l∶%M %p")
-    } else {
-        // Use 24 time format
-        i18n("%R")
-    };
-
-    let d = dt.format(&format).to_string();
-
     let date = gtk::Label::new(None);
-    date.set_markup(&format!("<span alpha=\"60%\">{}</span>", d.trim()));
+    set_room_msg_date(&date, dt);
     date.set_line_wrap(true);
     date.set_justify(gtk::Justification::Right);
     date.set_valign(gtk::Align::Start);
@@ -1005,6 +1522,14 @@ fn build_room_msg_date(dt: &DateTime<Local>) -> gtk::Label {
     date
 }
 
+fn set_room_msg_date(date: &gtk::Label, dt: &DateTime<Local>) {
+    date.set_markup(&format!(
+        "<span alpha=\"60%\">{}</span>",
+        util::time::time(dt)
+    ));
+    date.set_tooltip_text(Some(&util::time::full(dt)));
+}
+
 #[derive(PartialEq)]
 enum MsgPartType {
     Normal,
@@ -1022,3 +1547,27 @@ fn kind_of_line(line: &str) -> MsgPartType {
 fn trim_start_quote(line: &str) -> &str {
     line.trim_start().get(1..).unwrap_or(line).trim_start()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previewable_url_skips_permalinks_and_quotes() {
+        assert_eq!(
+            previewable_url("See https://example.org/page."),
+            Some(String::from("https://example.org/page"))
+        );
+        assert_eq!(
+            previewable_url(
+                "> <@alice:example.org> https://example.org/quoted\n\n\
+                 Hi https://matrix.to/#/@bob:example.org, see (https://example.com/answer)"
+            ),
+            Some(String::from("https://example.com/answer"))
+        );
+        assert_eq!(
+            previewable_url("> https://example.org/quoted\n\nNo link"),
+            None
+        );
+    }
+}
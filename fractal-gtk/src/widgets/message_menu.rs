@@ -1,6 +1,7 @@
 use gdk::prelude::*;
 use gtk::prelude::*;
 use matrix_sdk::identifiers::EventId;
+use std::path::Path;
 
 use crate::ui::RowType;
 
@@ -16,6 +17,7 @@ struct Widgets {
     view_source_button: gtk::ModelButton,
     copy_text_button: gtk::ModelButton,
     copy_selected_button: gtk::ModelButton,
+    cancel_upload_button: gtk::ModelButton,
     menu_separator: gtk::Widget,
 }
 
@@ -66,6 +68,10 @@ impl Widgets {
             .get_object("copy_selected_text_button")
             .expect("Can't find copy_selected_text_button in ui file.");
 
+        let cancel_upload_button: gtk::ModelButton = builder
+            .get_object("cancel_upload_button")
+            .expect("Can't find cancel_upload_button in ui file.");
+
         let menu_separator: gtk::Widget = builder
             .get_object("message_menu_separator")
             .expect("Can't find message_menu_separator");
@@ -103,6 +109,7 @@ impl Widgets {
             view_source_button,
             copy_text_button,
             copy_selected_button,
+            cancel_upload_button,
             menu_separator,
         }
     }
@@ -170,6 +177,25 @@ impl MessageMenu {
         None
     }
 
+    /// Turns the menu into the one of a file being uploaded, which only allows to cancel it
+    pub fn set_upload(&self, path: &Path) {
+        self.widgets.reply_button.hide();
+        self.widgets.open_with_button.hide();
+        self.widgets.save_image_as_button.hide();
+        self.widgets.save_video_as_button.hide();
+        self.widgets.copy_image_button.hide();
+        self.widgets.copy_text_button.hide();
+        self.widgets.view_source_button.hide();
+        self.widgets.delete_message_button.hide();
+        self.widgets.menu_separator.hide();
+
+        let data = glib::Variant::from(path.to_string_lossy().as_ref());
+        self.widgets
+            .cancel_upload_button
+            .set_action_target_value(Some(&data));
+        self.widgets.cancel_upload_button.show();
+    }
+
     pub fn get_popover(&self) -> gtk::Popover {
         self.widgets.popover.clone()
     }
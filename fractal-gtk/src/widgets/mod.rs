@@ -1,12 +1,14 @@
 // TODO: Move everything to ui module
 
 mod address;
+mod attachment_tray;
 mod autocomplete;
 pub mod avatar;
 mod clip_container;
 mod divider;
 pub mod error_dialog;
 pub mod file_dialog;
+mod formatting_toolbar;
 pub mod image;
 pub mod inline_player;
 mod kicked_dialog;
@@ -21,9 +23,11 @@ mod roomrow;
 mod scroll_widget;
 mod source_dialog;
 mod sourceview_entry;
+mod spacelist;
 
 pub use self::address::Address;
 pub use self::address::AddressType;
+pub use self::attachment_tray::AttachmentTray;
 pub use self::autocomplete::Autocomplete;
 pub use self::avatar::Avatar;
 pub use self::avatar::AvatarBadgeColor;
@@ -33,6 +37,7 @@ pub use self::clip_container::ClipContainer;
 pub use self::divider::NewMessageDivider;
 pub use self::error_dialog as ErrorDialog;
 pub use self::file_dialog as FileDialog;
+pub use self::formatting_toolbar::FormattingToolbar;
 pub use self::inline_player::AudioPlayerWidget;
 pub use self::inline_player::MediaPlayer;
 pub use self::inline_player::PlayerExt;
@@ -44,9 +49,12 @@ pub use self::members_list::MembersList;
 pub use self::message::MessageBox;
 pub use self::room_history::RoomHistory;
 pub use self::roomlist::RoomList;
+pub use self::roomlist::RoomSortOrder;
+pub use self::roomlist::SpaceFilter;
 pub use self::roomrow::RoomRow;
 pub use self::scroll_widget::page_down;
 pub use self::scroll_widget::page_up;
 pub use self::scroll_widget::ScrollWidget;
 pub use self::source_dialog::SourceDialog;
 pub use self::sourceview_entry::SVEntry;
+pub use self::spacelist::SpaceList;
@@ -1,7 +1,6 @@
 use chrono::DateTime;
 use chrono::Datelike;
 use chrono::Local;
-use chrono::Timelike;
 use fragile::Fragile;
 use log::warn;
 use std::cell::RefCell;
@@ -12,12 +11,14 @@ use crate::appop::UserInfoCache;
 use crate::ui::MessageContent;
 use crate::ui::RowType;
 use crate::ui::UI;
+use crate::util;
 use crate::util::i18n::i18n;
 
 use crate::globals;
 use crate::widgets;
 use crate::widgets::{PlayerExt, VideoPlayerWidget};
 use gio::ActionMapExt;
+use gio::SettingsExt;
 use gio::SimpleActionGroup;
 use glib::clone;
 use glib::source;
@@ -147,6 +148,18 @@ impl List {
             .collect()
     }
 
+    fn refresh_timestamps(&self) {
+        for element in self.list.iter() {
+            if let Element::Message(MessageContent {
+                widget: Some(widget),
+                ..
+            }) = element
+            {
+                widget.refresh_timestamp();
+            }
+        }
+    }
+
     fn find_all_visible_indices(&self) -> Vec<usize> {
         let len = self.list.len();
         let mut indices = Vec::new();
@@ -272,6 +285,8 @@ pub struct RoomHistory {
     source_id: Rc<RefCell<Option<source::SourceId>>>,
     queue: Rc<RefCell<VecDeque<MessageContent>>>,
     edit_buffer: Rc<RefCell<VecDeque<MessageContent>>>,
+    /// The desktop settings, watched for changes of the clock format
+    clock_format: Option<(gio::Settings, SignalHandlerId)>,
 }
 
 impl RoomHistory {
@@ -297,10 +312,12 @@ impl RoomHistory {
             source_id: Rc::new(RefCell::new(None)),
             queue: Rc::new(RefCell::new(VecDeque::new())),
             edit_buffer: Rc::new(RefCell::new(VecDeque::new())),
+            clock_format: None,
         };
 
         rh.connect_video_auto_play();
         rh.connect_video_focus();
+        rh.connect_clock_format();
 
         Some(rh)
     }
@@ -535,10 +552,25 @@ impl RoomHistory {
         None
     }
 
+    fn connect_clock_format(&mut self) {
+        let settings = unwrap_or_unit_return!(util::time::interface_settings());
+        let rows = &self.rows;
+        let handler = settings.connect_changed(
+            Some("clock-format"),
+            clone!(@weak rows => move |_, _| {
+                rows.borrow().refresh_timestamps();
+            }),
+        );
+        self.clock_format = Some((settings, handler));
+    }
+
     pub fn destroy(self) {
         if let Some(id) = self.source_id.borrow_mut().take() {
             source::source_remove(id);
         }
+        if let Some((settings, handler)) = self.clock_format {
+            settings.disconnect(handler);
+        }
     }
 
     /* This is a temporary function to make the listbox accessible from outside the history, it is
@@ -785,28 +817,8 @@ fn should_group_message(msg: &MessageContent, prev: &MessageContent) -> bool {
 
 /* Create the day divider */
 fn create_day_divider(date: DateTime<Local>) -> gtk::ListBoxRow {
-    let gdate = glib::DateTime::new_local(
-        date.year(),
-        date.month() as i32,
-        date.day() as i32,
-        date.hour() as i32,
-        date.minute() as i32,
-        date.second() as f64,
-    );
     /* We show the year only when the message wasn't send in the current year */
-    let format = if date.year() == Local::now().year() {
-        // Translators: This is a date format in the day divider without the year
-        i18n("%B %e")
-    } else {
-        // Translators: This is a date format in the day divider with the year
-        i18n("%B %e, %Y")
-    };
-    let stamp = if let Some(gstr) = gdate.format(&format) {
-        gstr.to_string()
-    } else {
-        // Fallback to a non glib time string
-        date.format(&format).to_string()
-    };
+    let stamp = util::time::day(&date, &Local::now());
     let row = gtk::ListBoxRow::new();
     row.get_style_context().add_class("divider");
     row.set_margin_top(24);
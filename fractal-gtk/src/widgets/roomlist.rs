@@ -1,14 +1,19 @@
 use crate::util::i18n::i18n;
+use gio::prelude::*;
 use glib::clone;
+use glib::source::Continue;
 use matrix_sdk::identifiers::{MxcUri, RoomId};
 
 use gtk::prelude::*;
-use log::info;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use url::Url;
 
 use crate::globals;
-use crate::model::room::{Room, RoomTag};
+use crate::model::draft::Draft;
+use crate::model::message::Message;
+use crate::model::room::{Room, RoomMembership, RoomTag};
 use crate::widgets::roomrow::RoomRow;
 use std::convert::TryFrom;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -16,9 +21,12 @@ use std::sync::{Arc, Mutex, MutexGuard};
 
 use chrono::prelude::*;
 
+#[derive(Clone)]
 pub struct RoomUpdated {
     pub room: Room,
     pub updated: DateTime<Local>,
+    /// The preview of the last message and its date, once it changed since the room was added
+    pub last_message: Option<(String, DateTime<Local>)>,
 }
 
 impl RoomUpdated {
@@ -28,7 +36,11 @@ impl RoomUpdated {
             None => Local.ymd(1970, 1, 1).and_hms(0, 0, 0),
         };
 
-        RoomUpdated { room, updated }
+        RoomUpdated {
+            room,
+            updated,
+            last_message: None,
+        }
     }
 
     pub fn up(&mut self) {
@@ -36,33 +48,165 @@ impl RoomUpdated {
     }
 }
 
+/// How the rooms are ordered in each group
 #[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoomSortOrder {
+    /// The rooms with the latest messages first
+    Recent,
+    /// The rooms with unread messages first, then by activity
+    Unread,
+    Alphabetical,
+    /// The rooms with the most mentions first, then the most notifications
+    Importance,
+}
+
+impl RoomSortOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomSortOrder::Recent => "recent",
+            RoomSortOrder::Unread => "unread",
+            RoomSortOrder::Alphabetical => "alphabetical",
+            RoomSortOrder::Importance => "importance",
+        }
+    }
+
+    fn compare(self, a: &RoomUpdated, b: &RoomUpdated) -> std::cmp::Ordering {
+        let recent = b.updated.cmp(&a.updated);
+        match self {
+            RoomSortOrder::Recent => recent,
+            RoomSortOrder::Unread => is_unread(&b.room).cmp(&is_unread(&a.room)).then(recent),
+            RoomSortOrder::Alphabetical => {
+                let name =
+                    |r: &RoomUpdated| r.room.name.as_deref().unwrap_or_default().to_lowercase();
+                name(a).cmp(&name(b)).then(recent)
+            }
+            RoomSortOrder::Importance => b
+                .room
+                .highlight
+                .cmp(&a.room.highlight)
+                .then(b.room.notifications.cmp(&a.room.notifications))
+                .then(recent),
+        }
+    }
+}
+
+impl From<&str> for RoomSortOrder {
+    fn from(sort: &str) -> Self {
+        match sort {
+            "unread" => RoomSortOrder::Unread,
+            "alphabetical" => RoomSortOrder::Alphabetical,
+            "importance" => RoomSortOrder::Importance,
+            _ => RoomSortOrder::Recent,
+        }
+    }
+}
+
+fn is_unread(room: &Room) -> bool {
+    room.notifications > 0 || room.highlight > 0
+}
+
+/// The groups of the room list, in the order they are shown
+#[derive(Clone, Debug, PartialEq)]
 pub enum RoomListType {
     Invites,
-    Rooms,
     Favorites,
+    /// The full name of the tag, the groups of the custom tags go after the favorites
+    Custom(String),
+    Direct,
+    Rooms,
+    LowPriority,
+}
+
+impl RoomListType {
+    /// The groups showing the room, one for its built-in tag and one for each custom tag
+    fn types_of(room: &Room) -> Vec<Self> {
+        let tag = match &room.membership {
+            RoomMembership::Invited(_) => return vec![RoomListType::Invites],
+            RoomMembership::Joined(tag) => tag,
+            _ => &RoomTag::None,
+        };
+
+        let builtin = match tag {
+            RoomTag::Favourite => Some(RoomListType::Favorites),
+            RoomTag::LowPriority => Some(RoomListType::LowPriority),
+            _ => None,
+        };
+        let custom = room.user_tags.iter().cloned().map(RoomListType::Custom);
+        let mut types: Vec<Self> = builtin.into_iter().chain(custom).collect();
+
+        if types.is_empty() && room.direct {
+            types.push(RoomListType::Direct);
+        } else if types.is_empty() {
+            types.push(RoomListType::Rooms);
+        }
+        types
+    }
+
+    /// The tag given to the rooms dropped in the group, there's none for the invites
+    fn tag(&self) -> Option<RoomTag> {
+        match self {
+            RoomListType::Invites => None,
+            RoomListType::Favorites => Some(RoomTag::Favourite),
+            RoomListType::Custom(tag) => Some(RoomTag::Custom(tag.clone())),
+            RoomListType::Direct | RoomListType::Rooms => Some(RoomTag::None),
+            RoomListType::LowPriority => Some(RoomTag::LowPriority),
+        }
+    }
+}
+
+/// Limits the list to the rooms of the selected space
+#[derive(Clone, Debug, Default)]
+pub struct SpaceFilter {
+    /// `None` when no space is selected, all the rooms are shown then
+    pub rooms: Option<HashSet<RoomId>>,
+    /// The spaces are listed in the space sidebar instead
+    pub spaces: HashSet<RoomId>,
+}
+
+impl SpaceFilter {
+    fn contains(&self, room_id: &RoomId) -> bool {
+        !self.spaces.contains(room_id)
+            && self
+                .rooms
+                .as_ref()
+                .map_or(true, |rooms| rooms.contains(room_id))
+    }
 }
 
 pub struct RoomListGroup {
     pub rooms: HashMap<RoomId, RoomRow>,
     pub baseu: Url,
     pub list: gtk::ListBox,
-    rev: gtk::Revealer,
-    arrow: gtk::Image,
-    expanded: Arc<AtomicBool>,
     title: gtk::Label,
     empty: gtk::Label,
-    title_eb: gtk::EventBox,
+    /// Shown when right clicking the title, empty for the groups without one
+    menu: gio::Menu,
+    /// The custom tags of the room list, to move the rooms to them
+    tags: Rc<RefCell<Vec<String>>>,
+    /// The tag of the group, sent along with the rooms dragged out of it
+    tag: Rc<RefCell<RoomTag>>,
 
-    wbox: gtk::Box,
     pub widget: gtk::EventBox,
 
     roomvec: Arc<Mutex<Vec<RoomUpdated>>>,
     filter: Option<String>,
+    sort: RoomSortOrder,
+    unread_only: bool,
+    space_filter: SpaceFilter,
+    /// The upgraded rooms whose successor is joined
+    replaced_rooms: HashSet<RoomId>,
+    /// The rooms whose notifications are disabled
+    muted_rooms: HashSet<RoomId>,
+    compact: bool,
 }
 
 impl RoomListGroup {
-    pub fn new(url: &Url, name: &str, empty_text: &str) -> RoomListGroup {
+    pub fn new(
+        url: &Url,
+        name: &str,
+        empty_text: &str,
+        tags: Rc<RefCell<Vec<String>>>,
+    ) -> RoomListGroup {
         let list = gtk::ListBox::new();
         let baseu = url.clone();
         let rooms = HashMap::new();
@@ -89,13 +233,22 @@ impl RoomListGroup {
             gtk::Image::from_icon_name(Some("pan-down-symbolic"), gtk::IconSize::SmallToolbar);
         let expanded = Arc::new(AtomicBool::new(true));
         let title_eb = gtk::EventBox::new();
+        let menu = gio::Menu::new();
 
         title_eb.connect_button_press_event(clone!(
         @strong list,
         @strong arrow,
         @strong rev,
-        @strong expanded
-        => move |_, _| {
+        @strong expanded,
+        @strong menu
+        => move |w, e| {
+            if e.triggers_context_menu() {
+                if menu.get_n_items() > 0 {
+                    popup_menu(w, &menu, e.get_position());
+                }
+                return glib::signal::Inhibit(true);
+            }
+
             if expanded.load(Ordering::SeqCst) {
                 arrow.set_from_icon_name(Some("pan-end-symbolic"), gtk::IconSize::SmallToolbar);
                 rev.set_reveal_child(false);
@@ -110,8 +263,20 @@ impl RoomListGroup {
             glib::signal::Inhibit(true)
         }));
 
+        // building the heading
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        hbox.get_style_context().add_class("room-title");
+        hbox.pack_start(&title, true, true, 0);
+        hbox.pack_start(&arrow, false, false, 0);
+        title_eb.add(&hbox);
+
         let widget = gtk::EventBox::new();
         let wbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let wbox_ctx = wbox.get_style_context();
+        wbox_ctx.add_class("room-list");
+        wbox_ctx.add_class("sidebar");
+        wbox.pack_start(&title_eb, false, false, 0);
+        wbox.pack_start(&rev, true, true, 0);
         widget.add(&wbox);
 
         let filter = None;
@@ -121,60 +286,153 @@ impl RoomListGroup {
             baseu,
             rooms,
             roomvec,
-            rev,
             title,
-            arrow,
-            title_eb,
+            menu,
+            tags,
+            tag: Rc::new(RefCell::new(RoomTag::None)),
             widget,
             empty,
-            wbox,
-            expanded,
             filter,
+            sort: RoomSortOrder::Recent,
+            unread_only: false,
+            space_filter: Default::default(),
+            replaced_rooms: Default::default(),
+            muted_rooms: Default::default(),
+            compact: false,
         }
     }
 
     pub fn add_room(&mut self, r: Room) {
-        if self.rooms.contains_key(&r.id) {
+        self.add_room_up(RoomUpdated::new(r));
+    }
+
+    pub fn add_room_up(&mut self, r: RoomUpdated) {
+        if self.rooms.contains_key(&r.room.id) {
             // room added, we'll pass
             return;
         }
 
-        let rid = r.id.clone();
-        self.roomvec
+        let rid = r.room.id.clone();
+        let mut row = RoomRow::new(r.room.clone());
+        if let Some((preview, date)) = r.last_message.clone() {
+            row.set_last_message_preview(preview, date);
+        }
+        row.set_compact(self.compact);
+        row.set_muted(self.muted_rooms.contains(&rid));
+        row.connect_dnd(self.tag.clone());
+        let pos = self.insert_room(r);
+        self.list.insert(&row.widget(), pos as i32);
+        self.connect_room_menu(&row);
+
+        self.rooms.insert(rid, row);
+        self.show();
+    }
+
+    /// Adds the room to the sorted list, returning its position
+    fn insert_room(&self, r: RoomUpdated) -> usize {
+        let mut rv = self.roomvec.lock().unwrap();
+        let pos = rv
+            .iter()
+            .position(|ru| self.sort.compare(&r, ru) == std::cmp::Ordering::Less)
+            .unwrap_or_else(|| rv.len());
+        rv.insert(pos, r);
+        pos
+    }
+
+    /// Moves the row of the room to its place in the order, after it changed
+    fn reposition_room(&mut self, room_id: &RoomId) {
+        let selected = self.get_selected();
+        let idx = self
+            .roomvec
             .lock()
             .unwrap()
-            .push(RoomUpdated::new(r.clone()));
+            .iter()
+            .position(|x| &x.room.id == room_id);
+        let idx = unwrap_or_unit_return!(idx);
+        let r = self.roomvec.lock().unwrap().remove(idx);
+        let pos = self.insert_room(r);
 
-        let row = RoomRow::new(r);
-        self.list.add(&row.widget());
+        if pos != idx {
+            if let Some(row) = self.list.get_row_at_index(idx as i32) {
+                self.list.remove(&row);
+                self.list.insert(&row, pos as i32);
+            }
+            self.set_selected(selected);
+        }
 
-        self.rooms.insert(rid, row);
-        self.show();
+        let term = self.filter.clone();
+        self.filter_rooms(&term);
     }
 
-    pub fn add_room_up(&mut self, r: RoomUpdated) {
-        if self.rooms.contains_key(&r.room.id) {
-            // room added, we'll pass
+    pub fn set_sort_order(&mut self, sort: RoomSortOrder) {
+        if self.sort == sort {
             return;
         }
 
-        let rid = r.room.id.clone();
-        let mut rv = self.roomvec.lock().unwrap();
-        let mut pos = rv.len();
-        for (i, ru) in rv.iter().enumerate() {
-            if ru.updated < r.updated {
-                pos = i;
-                break;
+        let selected = self.get_selected();
+        // The rows are moved, not created again, to keep their handlers
+        let rows: HashMap<RoomId, gtk::ListBoxRow> = self
+            .roomvec
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, r)| {
+                let row = self.list.get_row_at_index(idx as i32)?;
+                Some((r.room.id.clone(), row))
+            })
+            .collect();
+
+        self.sort = sort;
+        self.roomvec
+            .lock()
+            .unwrap()
+            .sort_by(|a, b| sort.compare(a, b));
+
+        for (pos, r) in self.roomvec.lock().unwrap().iter().enumerate() {
+            if let Some(row) = rows.get(&r.room.id) {
+                if row.get_index() != pos as i32 {
+                    self.list.remove(row);
+                    self.list.insert(row, pos as i32);
+                }
             }
         }
 
-        rv.insert(pos, RoomUpdated::new(r.room.clone()));
+        self.set_selected(selected);
+        let term = self.filter.clone();
+        self.filter_rooms(&term);
+    }
 
-        let row = RoomRow::new(r.room);
-        self.list.insert(&row.widget(), pos as i32);
+    pub fn set_unread_only(&mut self, unread_only: bool) {
+        self.unread_only = unread_only;
+        let term = self.filter.clone();
+        self.filter_rooms(&term);
+    }
 
-        self.rooms.insert(rid, row);
-        self.show();
+    pub fn set_space_filter(&mut self, space_filter: SpaceFilter) {
+        self.space_filter = space_filter;
+        let term = self.filter.clone();
+        self.filter_rooms(&term);
+    }
+
+    pub fn set_replaced_rooms(&mut self, replaced_rooms: HashSet<RoomId>) {
+        self.replaced_rooms = replaced_rooms;
+        let term = self.filter.clone();
+        self.filter_rooms(&term);
+    }
+
+    pub fn set_muted_rooms(&mut self, muted_rooms: HashSet<RoomId>) {
+        for (room_id, r) in self.rooms.iter() {
+            r.set_muted(muted_rooms.contains(room_id));
+        }
+        self.muted_rooms = muted_rooms;
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        self.compact = compact;
+        for r in self.rooms.values_mut() {
+            r.set_compact(compact);
+        }
     }
 
     pub fn set_bold(&mut self, room_id: RoomId, bold: bool) {
@@ -183,11 +441,13 @@ impl RoomListGroup {
         }
     }
 
-    pub fn rooms_with_notifications(&self) -> usize {
+    /// The rooms of the group with unread messages
+    pub fn rooms_with_notifications(&self) -> Vec<RoomId> {
         self.rooms
             .iter()
-            .filter(|(_, r)| r.room.notifications > 0 || r.room.highlight > 0)
-            .count()
+            .filter(|(_, r)| is_unread(&r.room))
+            .map(|(room_id, _)| room_id.clone())
+            .collect()
     }
 
     pub fn set_room_notifications(&mut self, room_id: RoomId, n: u64, h: u64) {
@@ -199,6 +459,17 @@ impl RoomListGroup {
             rv.room.notifications = n;
             rv.room.highlight = h;
         });
+        self.reposition_room(&room_id);
+    }
+
+    /// A copy of the room in the group, to add it to another one
+    pub fn get_room(&self, room_id: &RoomId) -> Option<RoomUpdated> {
+        self.roomvec
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| &x.room.id == room_id)
+            .cloned()
     }
 
     pub fn remove_room(&mut self, room_id: &RoomId) -> Option<RoomUpdated> {
@@ -235,35 +506,99 @@ impl RoomListGroup {
         });
     }
 
+    pub fn set_room_draft(&mut self, room_id: RoomId, draft: Option<Draft>) {
+        if let Some(r) = self.rooms.get_mut(&room_id) {
+            r.set_draft(draft.clone());
+        }
+
+        self.edit_room(&room_id, move |rv| {
+            rv.room.draft = draft.clone();
+        });
+    }
+
+    pub fn set_room_last_message(&mut self, room_id: RoomId, msg: Message) {
+        let preview = self.rooms.get_mut(&room_id).and_then(|r| {
+            r.set_last_message(&msg);
+            r.last_message_preview()
+        });
+
+        // Kept for the row to be created again when the room moves to another group
+        self.edit_room(&room_id, move |rv| {
+            rv.last_message = preview.clone();
+        });
+    }
+
+    pub fn set_room_typing(&mut self, room_id: RoomId, names: Vec<String>) {
+        if let Some(r) = self.rooms.get_mut(&room_id) {
+            r.set_typing(&names);
+        }
+    }
+
     pub fn widget(&self) -> &gtk::EventBox {
-        let b = self.wbox.clone();
-        let b_ctx = b.get_style_context();
-        b_ctx.add_class("room-list");
-        b_ctx.add_class("sidebar");
+        self.show();
+        &self.widget
+    }
 
-        // building the heading
-        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
-        hbox.get_style_context().add_class("room-title");
-        hbox.pack_start(&self.title, true, true, 0);
-        hbox.pack_start(&self.arrow, false, false, 0);
+    pub fn set_title(&self, name: &str) {
+        self.title.set_text(name);
+    }
+
+    /// Sets the tag added to the rooms dropped in the group and removed from the ones
+    /// dragged out of it
+    pub fn set_tag(&self, tag: RoomTag) {
+        *self.tag.borrow_mut() = tag;
+    }
 
-        for ch in self.title_eb.get_children() {
-            self.title_eb.remove(&ch);
+    /// Fills the menu of the title with the actions on the custom tag of the group
+    pub fn set_tag_menu(&self, tag: &str) {
+        let target = tag.to_variant();
+        self.menu.remove_all();
+        for (label, action) in &[
+            (i18n("Rename Tag…"), "app.rename-room-tag"),
+            (i18n("Move Up"), "app.move-room-tag-up"),
+            (i18n("Move Down"), "app.move-room-tag-down"),
+            (i18n("Delete Tag"), "app.delete-room-tag"),
+        ] {
+            let item = gio::MenuItem::new(Some(label.as_str()), None);
+            item.set_action_and_target_value(Some(*action), Some(&target));
+            self.menu.append_item(&item);
         }
-        self.title_eb.add(&hbox);
+    }
 
-        self.arrow
-            .set_from_icon_name(Some("pan-down-symbolic"), gtk::IconSize::SmallToolbar);
-        self.expanded.store(true, Ordering::SeqCst);
-        self.rev.set_reveal_child(true);
-        self.list.get_style_context().remove_class("collapsed");
+    /// Renames the custom tag in the copies of the rooms
+    pub fn rename_user_tag(&mut self, tag: &str, new_tag: &str) {
+        let rename = |user_tags: &mut Vec<String>| {
+            for t in user_tags.iter_mut().filter(|t| *t == tag) {
+                *t = new_tag.to_string();
+            }
+        };
 
-        b.pack_start(&self.title_eb, false, false, 0);
-        b.pack_start(&self.rev, true, true, 0);
+        for row in self.rooms.values_mut() {
+            rename(&mut row.room.user_tags);
+        }
 
-        self.show();
+        for rv in self.roomvec.lock().unwrap().iter_mut() {
+            rename(&mut rv.room.user_tags);
+        }
+    }
 
-        &self.widget
+    fn connect_room_menu(&self, row: &RoomRow) {
+        if row.room.membership.is_invited() {
+            return;
+        }
+
+        let room_id = row.room.id.clone();
+        let tags = self.tags.clone();
+        let tag = self.tag.clone();
+        row.widget.connect_button_press_event(move |w, e| {
+            if e.triggers_context_menu() {
+                let menu = room_menu(&room_id, &tags.borrow(), &tag.borrow());
+                popup_menu(w, &menu, e.get_position());
+                gtk::Inhibit(true)
+            } else {
+                gtk::Inhibit(false)
+            }
+        });
     }
 
     pub fn show(&self) {
@@ -317,10 +652,10 @@ impl RoomListGroup {
             Some(row) => {
                 let rv = self.roomvec.lock().unwrap();
                 let mut idx = row.get_index() + direction;
-                while unread_only
-                    && idx >= 0
+                while idx >= 0
                     && (idx as usize) < rv.len()
-                    && rv[idx as usize].room.notifications == 0
+                    && (!self.is_shown(&rv[idx as usize])
+                        || (unread_only && rv[idx as usize].room.notifications == 0))
                 {
                     idx += direction;
                 }
@@ -340,13 +675,7 @@ impl RoomListGroup {
             .lock()
             .unwrap()
             .iter()
-            .find(|r| {
-                if unread_only {
-                    r.room.notifications > 0
-                } else {
-                    true
-                }
-            })
+            .find(|r| self.is_shown(r) && (!unread_only || r.room.notifications > 0))
             .map(|r| r.room.id.clone())
     }
 
@@ -355,41 +684,22 @@ impl RoomListGroup {
             .lock()
             .unwrap()
             .iter()
-            .filter(|r| {
-                if unread_only {
-                    r.room.notifications > 0
-                } else {
-                    true
-                }
-            })
+            .filter(|r| self.is_shown(r) && (!unread_only || r.room.notifications > 0))
             .last()
             .map(|r| r.room.id.clone())
     }
 
-    pub fn add_rooms(&mut self, mut array: Vec<Room>) {
-        array.sort_by_key(|ref x| match x.messages.iter().last() {
-            Some(l) => l.date,
-            None => Local.ymd(1970, 1, 1).and_hms(0, 0, 0),
-        });
-
-        for r in array.iter().rev() {
-            self.add_room(r.clone());
+    pub fn add_rooms(&mut self, array: Vec<Room>) {
+        for r in array {
+            self.add_room(r);
         }
     }
 
     pub fn moveup(&mut self, room_id: &RoomId) {
-        let s = self.get_selected();
-
         self.edit_room(room_id, move |rv| {
             rv.up();
         });
-        if let Some(r) = self.remove_room(room_id) {
-            self.add_room_up(r);
-        }
-
-        self.set_selected(s);
-        let term = self.filter.clone();
-        self.filter_rooms(&term);
+        self.reposition_room(room_id);
     }
 
     fn render_notifies(&self) {
@@ -398,6 +708,13 @@ impl RoomListGroup {
         }
     }
 
+    /// Shows again how long ago the last messages were received
+    pub fn refresh_activity(&self) {
+        for r in self.rooms.values() {
+            r.refresh_activity();
+        }
+    }
+
     fn edit_room<F: Fn(&mut RoomUpdated) + 'static>(&mut self, room_id: &RoomId, cb: F) {
         let mut rv = self.roomvec.lock().unwrap();
         if let Some(idx) = rv.iter().position(|x| x.room.id == *room_id) {
@@ -412,22 +729,32 @@ impl RoomListGroup {
 
         for (i, r) in self.roomvec.lock().unwrap().iter().enumerate() {
             if let Some(row) = self.list.get_row_at_index(i as i32) {
-                match term {
-                    Some(ref t) if !t.is_empty() => {
-                        let rname = r.room.name.clone().unwrap_or_default().to_lowercase();
-                        if rname.contains(&t.to_lowercase()) {
-                            row.show();
-                        } else {
-                            row.hide();
-                        }
-                    }
-                    _ => {
-                        row.show();
-                    }
-                };
+                // The selected room stays, even when it's read
+                if self.is_shown(r) || row.is_selected() {
+                    row.show();
+                } else {
+                    row.hide();
+                }
             }
         }
     }
+
+    /// Whether the room matches the search, the unread filter and the selected space
+    fn is_shown(&self, r: &RoomUpdated) -> bool {
+        if !self.space_filter.contains(&r.room.id) || self.replaced_rooms.contains(&r.room.id) {
+            return false;
+        }
+
+        let matches = match self.filter {
+            Some(ref t) if !t.is_empty() => {
+                let rname = r.room.name.clone().unwrap_or_default().to_lowercase();
+                rname.contains(&t.to_lowercase())
+            }
+            _ => true,
+        };
+
+        matches && (!self.unread_only || is_unread(&r.room) || r.room.membership.is_invited())
+    }
 }
 
 #[derive(Clone)]
@@ -436,8 +763,8 @@ struct RGroup {
 }
 
 impl RGroup {
-    pub fn new(url: &Url, name: &str, empty_text: &str) -> RGroup {
-        let r = RoomListGroup::new(url, name, empty_text);
+    pub fn new(url: &Url, name: &str, empty_text: &str, tags: Rc<RefCell<Vec<String>>>) -> RGroup {
+        let r = RoomListGroup::new(url, name, empty_text, tags);
         RGroup {
             g: Arc::new(Mutex::new(r)),
         }
@@ -453,98 +780,332 @@ pub struct RoomList {
     widget: gtk::Box,
     adj: Option<gtk::Adjustment>,
 
-    inv: RGroup,
-    fav: RGroup,
-    rooms: RGroup,
+    /// The groups in the order they are shown
+    groups: Vec<(RoomListType, RGroup)>,
+    /// The groups whose activity is refreshed every minute, in any order
+    timed_groups: Rc<RefCell<Vec<RGroup>>>,
+    /// The custom tags, in the order of their groups
+    tags: Rc<RefCell<Vec<String>>>,
+    /// The lists of the groups, for the keyboard navigation and the selection
+    lists: Rc<RefCell<Vec<gtk::ListBox>>>,
+    sort: RoomSortOrder,
+    unread_only: bool,
+    space_filter: SpaceFilter,
+    /// The upgraded rooms whose successor is joined
+    replaced_rooms: HashSet<RoomId>,
+    /// The rooms whose notifications are disabled
+    muted_rooms: HashSet<RoomId>,
+    compact: bool,
 }
 
+/// Runs the method in all the groups of the room
 macro_rules! run_in_group {
     ($self: expr, $room_id: expr, $fn: ident, $($arg: expr),*) => {{
-        if $self.inv.get().rooms.contains_key($room_id) {
-            $self.inv.get().$fn($($arg),*)
-        } else if $self.fav.get().rooms.contains_key($room_id) {
-            $self.fav.get().$fn($($arg),*)
-        } else {
-            $self.rooms.get().$fn($($arg),*)
+        for group in $self.groups_of($room_id) {
+            group.get().$fn($($arg.clone()),*);
         }
     }}
 }
 
 impl RoomList {
-    pub fn new(adj: Option<gtk::Adjustment>, url: Option<Url>) -> RoomList {
+    pub fn new(adj: Option<gtk::Adjustment>, url: Option<Url>, tags: Vec<String>) -> RoomList {
         let widget = gtk::Box::new(gtk::Orientation::Vertical, 6);
         let baseu = url.unwrap_or_else(|| globals::DEFAULT_HOMESERVER.clone());
 
-        let inv = RGroup::new(
-            &baseu,
-            i18n("Invites").as_str(),
-            i18n("You don’t have any invitations").as_str(),
-        );
-        let fav = RGroup::new(
-            &baseu,
-            i18n("Favorites").as_str(),
-            i18n("Drag and drop rooms here to add them to your favorites").as_str(),
-        );
-        let rooms = RGroup::new(
-            &baseu,
-            i18n("Rooms").as_str(),
-            i18n("You don’t have any rooms yet").as_str(),
-        );
-
-        RoomList {
+        let mut roomlist = RoomList {
             baseu,
             widget,
             adj,
-            inv,
-            fav,
-            rooms,
+            groups: vec![],
+            timed_groups: Rc::new(RefCell::new(vec![])),
+            tags: Rc::new(RefCell::new(vec![])),
+            lists: Rc::new(RefCell::new(vec![])),
+            sort: RoomSortOrder::Recent,
+            unread_only: false,
+            space_filter: Default::default(),
+            replaced_rooms: Default::default(),
+            muted_rooms: Default::default(),
+            compact: false,
+        };
+
+        let types = vec![
+            RoomListType::Invites,
+            RoomListType::Favorites,
+            RoomListType::Direct,
+            RoomListType::Rooms,
+            RoomListType::LowPriority,
+        ];
+        let custom = tags
+            .into_iter()
+            .filter(|tag| RoomTag::is_custom(tag))
+            .map(RoomListType::Custom);
+        for type_ in types.into_iter().chain(custom) {
+            roomlist.add_group(type_);
         }
+
+        // The relative times change with the time, not only with the messages
+        let groups = roomlist.timed_groups.clone();
+        glib::timeout_add_seconds_local(
+            60,
+            clone!(@weak groups => @default-return Continue(false), move || {
+                for group in groups.borrow().iter() {
+                    group.get().refresh_activity();
+                }
+                Continue(true)
+            }),
+        );
+
+        roomlist
     }
 
-    pub fn select(&self, room_id: &RoomId) {
-        run_in_group!(self, room_id, set_selected, Some(room_id.clone()));
+    fn group(&self, type_: &RoomListType) -> Option<&RGroup> {
+        self.groups
+            .iter()
+            .find(|(t, _)| t == type_)
+            .map(|(_, group)| group)
     }
 
-    fn sibling_id_inv(&self, unread_only: bool, direction: i32) -> Option<RoomId> {
-        let (room_id, _, next) = self.inv.get().sibling_id(unread_only, direction);
+    fn groups_of(&self, room_id: &RoomId) -> Vec<&RGroup> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| group.get().rooms.contains_key(room_id))
+            .map(|(_, group)| group)
+            .collect()
+    }
 
-        if let Some(room_id) = room_id {
-            Some(room_id)
-        } else if next {
-            self.fav.get().first_id(unread_only)
-        } else {
-            self.sibling_id_fav(unread_only, direction)
+    /// Returns the group of that type, creating it if it's one for a new custom tag
+    fn add_group(&mut self, type_: RoomListType) -> RGroup {
+        if let Some(group) = self.group(&type_) {
+            return group.clone();
         }
+
+        let (name, empty_text) = match &type_ {
+            RoomListType::Invites => (i18n("Invites"), i18n("You don’t have any invitations")),
+            RoomListType::Favorites => (
+                i18n("Favorites"),
+                i18n("Drag and drop rooms here to add them to your favorites"),
+            ),
+            RoomListType::Custom(tag) => (
+                RoomTag::display_name(tag).to_string(),
+                i18n("Drag and drop rooms here to add this tag to them"),
+            ),
+            RoomListType::Direct => (
+                i18n("Direct Messages"),
+                i18n("You don’t have any direct chats yet"),
+            ),
+            RoomListType::Rooms => (i18n("Rooms"), i18n("You don’t have any rooms yet")),
+            RoomListType::LowPriority => (
+                i18n("Low Priority"),
+                i18n("Drag and drop rooms here to lower their priority"),
+            ),
+        };
+        let group = RGroup::new(&self.baseu, &name, &empty_text, self.tags.clone());
+
+        group.get().set_sort_order(self.sort);
+        group.get().set_unread_only(self.unread_only);
+        group.get().set_space_filter(self.space_filter.clone());
+        group.get().set_replaced_rooms(self.replaced_rooms.clone());
+        group.get().set_muted_rooms(self.muted_rooms.clone());
+        group.get().set_compact(self.compact);
+        if let RoomListType::Custom(ref tag) = type_ {
+            group.get().set_tag_menu(tag);
+        }
+        if let Some(tag) = type_.tag() {
+            group.get().set_tag(tag.clone());
+            let widget = group.get().widget.clone();
+            self.connect_drop(widget, move |room_id, source| {
+                // Dragging moves the room, it loses the tag of the group it comes from
+                if source == tag {
+                    return;
+                }
+                if source != RoomTag::None {
+                    activate_room_tag_action("remove-room-tag", &room_id, &source);
+                }
+                if tag != RoomTag::None {
+                    activate_room_tag_action("set-room-tag", &room_id, &tag);
+                }
+            });
+        }
+
+        // The custom tags go after the favorites and the ones before
+        let pos = match type_ {
+            RoomListType::Custom(_) => self
+                .groups
+                .iter()
+                .position(|(t, _)| *t == RoomListType::Direct)
+                .unwrap_or_else(|| self.groups.len()),
+            _ => self.groups.len(),
+        };
+        self.groups.insert(pos, (type_, group.clone()));
+        self.timed_groups.borrow_mut().push(group.clone());
+
+        self.widget.add(group.get().widget());
+        self.connect_select(&group.get().list);
+        self.connect_keynav(&group.get().list);
+        self.update_order();
+
+        group
+    }
+
+    /// Puts the widgets and the lists in the order of the groups
+    fn update_order(&self) {
+        for (i, (_, group)) in self.groups.iter().enumerate() {
+            self.widget.reorder_child(&group.get().widget, i as i32);
+        }
+
+        *self.lists.borrow_mut() = self
+            .groups
+            .iter()
+            .map(|(_, group)| group.get().list.clone())
+            .collect();
+        *self.tags.borrow_mut() = self
+            .groups
+            .iter()
+            .filter_map(|(type_, _)| match type_ {
+                RoomListType::Custom(tag) => Some(tag.clone()),
+                _ => None,
+            })
+            .collect();
     }
 
-    fn sibling_id_fav(&self, unread_only: bool, direction: i32) -> Option<RoomId> {
-        let (room_id, prev, next) = self.fav.get().sibling_id(unread_only, direction);
+    /// The custom tags, in the order they are shown
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.borrow().clone()
+    }
 
-        if let Some(room_id) = room_id {
-            Some(room_id)
-        } else if prev {
-            self.inv.get().last_id(unread_only)
-        } else if next {
-            self.rooms.get().first_id(unread_only)
+    pub fn add_tag(&mut self, tag: String) {
+        self.add_group(RoomListType::Custom(tag));
+        self.show_and_hide();
+    }
+
+    /// Renames the group of the tag, the rooms keep their rows
+    pub fn rename_tag(&mut self, tag: &str, new_tag: String) {
+        let old_type = RoomListType::Custom(tag.to_string());
+        if self.group(&RoomListType::Custom(new_tag.clone())).is_some() {
+            return;
+        }
+
+        if let Some((type_, group)) = self.groups.iter_mut().find(|(t, _)| *t == old_type) {
+            let mut group = group.get();
+            group.set_title(RoomTag::display_name(&new_tag));
+            group.set_tag_menu(&new_tag);
+            group.set_tag(RoomTag::Custom(new_tag.clone()));
+            group.rename_user_tag(tag, &new_tag);
+            *type_ = RoomListType::Custom(new_tag);
+        }
+        self.update_order();
+    }
+
+    /// Moves the group of the tag before the previous one or after the next one
+    pub fn move_tag(&mut self, tag: &str, up: bool) {
+        let type_ = RoomListType::Custom(tag.to_string());
+        let idx = unwrap_or_unit_return!(self.groups.iter().position(|(t, _)| *t == type_));
+        let other = if up {
+            idx.checked_sub(1)
         } else {
-            self.sibling_id_rooms(unread_only, direction)
+            Some(idx + 1)
+        };
+
+        if let Some(other) = other.filter(|other| {
+            self.groups
+                .get(*other)
+                .map_or(false, |(t, _)| matches!(t, RoomListType::Custom(_)))
+        }) {
+            self.groups.swap(idx, other);
+            self.update_order();
+        }
+    }
+
+    /// Removes the group of the tag, its rooms have to be moved first
+    pub fn remove_tag(&mut self, tag: &str) {
+        let type_ = RoomListType::Custom(tag.to_string());
+        if let Some(idx) = self.groups.iter().position(|(t, _)| *t == type_) {
+            let (_, group) = self.groups.remove(idx);
+            self.timed_groups
+                .borrow_mut()
+                .retain(|g| !Arc::ptr_eq(&g.g, &group.g));
+            self.widget.remove(&group.get().widget);
+            self.update_order();
         }
     }
 
-    fn sibling_id_rooms(&self, unread_only: bool, direction: i32) -> Option<RoomId> {
-        let (room_id, prev, _) = self.rooms.get().sibling_id(unread_only, direction);
+    pub fn set_sort_order(&mut self, sort: RoomSortOrder) {
+        self.sort = sort;
+        for (_, group) in self.groups.iter() {
+            group.get().set_sort_order(sort);
+        }
+    }
 
-        if let Some(room_id) = room_id {
-            Some(room_id)
-        } else if prev {
-            self.fav.get().last_id(unread_only)
-        } else {
-            None
+    pub fn set_unread_only(&mut self, unread_only: bool) {
+        self.unread_only = unread_only;
+        for (_, group) in self.groups.iter() {
+            group.get().set_unread_only(unread_only);
+        }
+    }
+
+    pub fn set_space_filter(&mut self, space_filter: SpaceFilter) {
+        for (_, group) in self.groups.iter() {
+            group.get().set_space_filter(space_filter.clone());
         }
+        self.space_filter = space_filter;
+    }
+
+    pub fn set_replaced_rooms(&mut self, replaced_rooms: HashSet<RoomId>) {
+        for (_, group) in self.groups.iter() {
+            group.get().set_replaced_rooms(replaced_rooms.clone());
+        }
+        self.replaced_rooms = replaced_rooms;
+    }
+
+    pub fn set_muted_rooms(&mut self, muted_rooms: HashSet<RoomId>) {
+        for (_, group) in self.groups.iter() {
+            group.get().set_muted_rooms(muted_rooms.clone());
+        }
+        self.muted_rooms = muted_rooms;
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        for (_, group) in self.groups.iter() {
+            group.get().set_compact(compact);
+        }
+        self.compact = compact;
+    }
+
+    /// Selects the room in the first of its groups, a single row is selected at a time
+    pub fn select(&self, room_id: &RoomId) {
+        if let Some(group) = self.groups_of(room_id).first() {
+            group.get().set_selected(Some(room_id.clone()));
+        }
+    }
+
+    fn get_selected(&self) -> Option<RoomId> {
+        self.groups
+            .iter()
+            .find_map(|(_, group)| group.get().get_selected())
     }
 
     fn sibling_id(&self, unread_only: bool, direction: i32) -> Option<RoomId> {
-        self.sibling_id_inv(unread_only, direction)
+        let (idx, (room_id, prev, next)) = self
+            .groups
+            .iter()
+            .map(|(_, group)| group.get().sibling_id(unread_only, direction))
+            .enumerate()
+            .find(|(_, (room_id, prev, next))| room_id.is_some() || *prev || *next)?;
+
+        if room_id.is_some() {
+            room_id
+        } else if prev {
+            self.groups[..idx]
+                .iter()
+                .rev()
+                .find_map(|(_, group)| group.get().last_id(unread_only))
+        } else if next {
+            self.groups[idx + 1..]
+                .iter()
+                .find_map(|(_, group)| group.get().first_id(unread_only))
+        } else {
+            None
+        }
     }
 
     pub fn next_id(&self) -> Option<RoomId> {
@@ -564,85 +1125,99 @@ impl RoomList {
     }
 
     pub fn first_id(&self) -> Option<RoomId> {
-        self.inv
-            .get()
-            .first_id(false)
-            .or_else(|| self.fav.get().first_id(false))
-            .or_else(|| self.rooms.get().first_id(false))
+        self.groups
+            .iter()
+            .find_map(|(_, group)| group.get().first_id(false))
     }
 
     pub fn last_id(&self) -> Option<RoomId> {
-        self.rooms
-            .get()
-            .last_id(false)
-            .or_else(|| self.fav.get().last_id(false))
-            .or_else(|| self.inv.get().last_id(false))
+        self.groups
+            .iter()
+            .rev()
+            .find_map(|(_, group)| group.get().last_id(false))
     }
 
     pub fn unselect(&self) {
-        self.inv.get().set_selected(None);
-        self.fav.get().set_selected(None);
-        self.rooms.get().set_selected(None);
+        for (_, group) in self.groups.iter() {
+            group.get().set_selected(None);
+        }
     }
 
     pub fn add_rooms(&mut self, array: Vec<Room>) {
-        self.inv.get().add_rooms(
-            array
-                .iter()
-                .filter(|r| r.membership.is_invited())
-                .cloned()
-                .collect::<Vec<Room>>(),
-        );
-        self.fav.get().add_rooms(
-            array
-                .iter()
-                .filter(|r| r.membership.match_joined_tag(RoomTag::Favourite))
-                .cloned()
-                .collect::<Vec<Room>>(),
-        );
-        self.rooms.get().add_rooms(
-            array
-                .iter()
-                .filter(|r| !r.membership.match_joined_tag(RoomTag::Favourite))
-                .cloned()
-                .collect::<Vec<Room>>(),
-        );
+        let types: Vec<Vec<RoomListType>> = array.iter().map(RoomListType::types_of).collect();
+        for type_ in types.iter().flatten() {
+            self.add_group(type_.clone());
+        }
+
+        for (type_, group) in self.groups.iter() {
+            group.get().add_rooms(
+                array
+                    .iter()
+                    .zip(types.iter())
+                    .filter(|(_, types)| types.contains(type_))
+                    .map(|(r, _)| r.clone())
+                    .collect::<Vec<Room>>(),
+            );
+        }
         self.show_and_hide();
     }
 
-    pub fn connect_fav<F: Fn(Room, bool) + 'static>(&self, cb: F) {
-        let acb = Arc::new(cb);
+    /// Moves the room to the groups of its tags, after they changed
+    pub fn move_room(&mut self, room: &Room) {
+        let types = RoomListType::types_of(room);
+        let selected = self.get_selected().map_or(false, |id| id == room.id);
 
-        let favw = self.fav.get().widget.clone();
-        let r = self.rooms.clone();
-        let f = self.fav.clone();
-        let cb = acb.clone();
-        self.connect_drop(favw, move |room_id| {
-            if let Some(room) = r.get().remove_room(&room_id) {
-                cb(room.room.clone(), true);
-                f.get().add_room_up(room);
+        let mut current = None;
+        for (type_, group) in self.groups.iter() {
+            let mut group = group.get();
+            if !group.rooms.contains_key(&room.id) {
+                continue;
             }
-        });
 
-        let rw = self.rooms.get().widget.clone();
-        let r = self.rooms.clone();
-        let f = self.fav.clone();
-        self.connect_drop(rw, move |roomid| {
-            if let Some(room) = f.get().remove_room(&roomid) {
-                acb(room.room.clone(), false);
-                r.get().add_room_up(room);
+            let r = if types.contains(type_) {
+                group.get_room(&room.id)
+            } else {
+                group.remove_room(&room.id)
+            };
+            current = current.or(r);
+        }
+
+        if let Some(mut r) = current {
+            r.room.membership = room.membership.clone();
+            r.room.user_tags = room.user_tags.clone();
+            for type_ in types {
+                self.add_group(type_).get().add_room_up(r.clone());
             }
-        });
+            if selected && self.get_selected().is_none() {
+                self.select(&room.id);
+            }
+        }
+        self.show_and_hide();
     }
 
     pub fn set_room_avatar(&mut self, room_id: RoomId, av: Option<MxcUri>) {
         run_in_group!(self, &room_id, set_room_avatar, room_id, av);
     }
 
+    pub fn set_room_draft(&mut self, room_id: RoomId, draft: Option<Draft>) {
+        run_in_group!(self, &room_id, set_room_draft, room_id, draft);
+    }
+
+    pub fn set_room_last_message(&mut self, room_id: RoomId, msg: Message) {
+        run_in_group!(self, &room_id, set_room_last_message, room_id, msg);
+    }
+
+    pub fn set_room_typing(&mut self, room_id: RoomId, names: Vec<String>) {
+        run_in_group!(self, &room_id, set_room_typing, room_id, names);
+    }
+
+    /// The number of rooms with unread messages, counting once the ones in several groups
     pub fn rooms_with_notifications(&self) -> usize {
-        self.inv.get().rooms_with_notifications()
-            + self.fav.get().rooms_with_notifications()
-            + self.rooms.get().rooms_with_notifications()
+        self.groups
+            .iter()
+            .flat_map(|(_, group)| group.get().rooms_with_notifications())
+            .collect::<HashSet<RoomId>>()
+            .len()
     }
 
     pub fn set_room_notifications(&mut self, room_id: RoomId, n: u64, h: u64) {
@@ -650,24 +1225,22 @@ impl RoomList {
     }
 
     pub fn remove_room(&mut self, room_id: &RoomId) -> Option<RoomUpdated> {
-        let ret = run_in_group!(self, room_id, remove_room, room_id);
+        let ret = self
+            .groups_of(room_id)
+            .into_iter()
+            .filter_map(|group| group.get().remove_room(room_id))
+            .last();
         self.show_and_hide();
         ret
     }
 
     pub fn set_bold(&mut self, room_id: RoomId, bold: bool) {
-        run_in_group!(self, &room_id, set_bold, room_id, bold)
+        run_in_group!(self, &room_id, set_bold, room_id, bold);
     }
 
     pub fn add_room(&mut self, r: Room) {
-        if r.membership.is_invited() {
-            self.inv.get().add_room(r);
-        } else if r.membership.match_joined_tag(RoomTag::Favourite) {
-            info!("We have fav rooms");
-            self.fav.get().add_room(r);
-        } else {
-            info!("We have non fav rooms");
-            self.rooms.get().add_room(r);
+        for type_ in RoomListType::types_of(&r) {
+            self.add_group(type_).get().add_room(r.clone());
         }
         self.show_and_hide();
     }
@@ -682,15 +1255,6 @@ impl RoomList {
 
     // Roomlist widget
     pub fn widget(&self) -> &gtk::Box {
-        for ch in self.widget.get_children() {
-            self.widget.remove(&ch);
-        }
-        self.widget.add(self.inv.get().widget());
-        self.widget.add(self.fav.get().widget());
-        self.widget.add(self.rooms.get().widget());
-        self.connect_select();
-        self.connect_keynav();
-
         self.show_and_hide();
 
         &self.widget
@@ -699,45 +1263,31 @@ impl RoomList {
     pub fn show_and_hide(&self) {
         self.widget.show_all();
 
-        if self.inv.get().rooms.is_empty() {
-            self.inv.get().hide();
-        } else {
-            self.inv.get().show();
+        for (type_, group) in self.groups.iter() {
+            let group = group.get();
+            match type_ {
+                RoomListType::Invites | RoomListType::Direct if group.rooms.is_empty() => {
+                    group.hide()
+                }
+                _ => group.show(),
+            }
         }
-
-        self.fav.get().show();
-        self.rooms.get().show();
     }
 
     // Connect handlers for unselecting rooms from other categories when a room is selected
-    pub fn connect_select(&self) {
-        let inv = &self.inv.get().list;
-        let fav = &self.fav.get().list;
-        let rooms = &self.rooms.get().list;
-
-        inv.connect_row_selected(clone!(@weak fav, @weak rooms => move |_, row| {
+    fn connect_select(&self, list: &gtk::ListBox) {
+        let lists = self.lists.clone();
+        list.connect_row_selected(move |list, row| {
             if row.is_some() {
-                fav.unselect_all();
-                rooms.unselect_all();
-            }
-        }));
-
-        fav.connect_row_selected(clone!(@weak inv, @weak rooms => move |_, row| {
-            if row.is_some() {
-                inv.unselect_all();
-                rooms.unselect_all();
-            }
-        }));
-
-        rooms.connect_row_selected(clone!(@weak inv, @weak fav => move |_, row| {
-            if row.is_some() {
-                inv.unselect_all();
-                fav.unselect_all();
+                for other in lists.borrow().iter().filter(|other| *other != list) {
+                    other.unselect_all();
+                }
             }
-        }));
+        });
     }
 
-    pub fn connect_drop<F: Fn(RoomId) + 'static>(&self, widget: gtk::EventBox, cb: F) {
+    /// Connects the drops of the rooms, `cb` gets the tag of the group they come from
+    pub fn connect_drop<F: Fn(RoomId, RoomTag) + 'static>(&self, widget: gtk::EventBox, cb: F) {
         let flags = gtk::DestDefaults::empty();
         let action = gdk::DragAction::all();
         widget.drag_dest_set(flags, &[], action);
@@ -753,52 +1303,93 @@ impl RoomList {
             glib::signal::Inhibit(true)
         });
         widget.connect_drag_data_received(move |_w, _ctx, _x, _y, data, _info, _time| {
-            if let Some(room_id) = data
+            let text = data
                 .get_text()
-                .and_then(|rid| RoomId::try_from(rid.as_str()).ok())
-            {
-                cb(room_id);
+                .map(|text| text.to_string())
+                .unwrap_or_default();
+            let mut parts = text.splitn(2, ' ');
+            if let Some(room_id) = parts.next().and_then(|rid| RoomId::try_from(rid).ok()) {
+                let source = RoomTag::from_name(parts.next().unwrap_or_default());
+                cb(room_id, source);
             }
         });
     }
 
-    pub fn connect_keynav(&self) {
-        let inv_lb = &self.inv.get().list;
-        let fav_lb = &self.fav.get().list;
-        let rooms_lb = &self.rooms.get().list;
-
-        inv_lb.connect_keynav_failed(clone!(
+    fn connect_keynav(&self, list: &gtk::ListBox) {
+        list.connect_keynav_failed(clone!(
         @strong self.adj as adj,
-        @weak inv_lb,
-        @weak fav_lb,
-        @weak rooms_lb
-        => @default-return gtk::Inhibit(false), move |_, d| {
-            keynav_cb(d, &inv_lb, &fav_lb, &rooms_lb, adj.clone(), RoomListType::Invites)
+        @strong self.lists as lists
+        => move |list, d| {
+            keynav_cb(d, list, &lists.borrow(), adj.clone())
         }));
+    }
 
-        fav_lb.connect_keynav_failed(clone!(
-        @strong self.adj as adj,
-        @weak fav_lb,
-        @weak inv_lb,
-        @weak rooms_lb
-        => @default-return gtk::Inhibit(false), move |_, d| {
-            keynav_cb(d, &inv_lb, &fav_lb, &rooms_lb, adj.clone(), RoomListType::Favorites)
-        }));
+    pub fn filter_rooms(&self, term: Option<String>) {
+        for (_, group) in self.groups.iter() {
+            group.get().filter_rooms(&term);
+        }
+    }
+}
 
-        rooms_lb.connect_keynav_failed(clone!(
-        @strong self.adj as adj,
-        @weak rooms_lb,
-        @weak inv_lb,
-        @weak fav_lb
-        => @default-return gtk::Inhibit(false), move |_, d| {
-            keynav_cb(d, &inv_lb, &fav_lb, &rooms_lb, adj.clone(), RoomListType::Rooms)
-        }));
+/// The menu of a room row, to add tags to it or remove the one of its group
+fn room_menu(room_id: &RoomId, tags: &[String], group_tag: &RoomTag) -> gio::Menu {
+    let action_item = |label: &str, action: &str, tag: &RoomTag| {
+        let target = format!("{} {}", room_id, tag.name().unwrap_or_default());
+        let item = gio::MenuItem::new(Some(label), None);
+        item.set_action_and_target_value(Some(action), Some(&target.to_variant()));
+        item
+    };
+    let item = |label: &str, tag: &RoomTag| action_item(label, "app.set-room-tag", tag);
+
+    let groups = gio::Menu::new();
+    groups.append_item(&item(&i18n("Favorites"), &RoomTag::Favourite));
+    for tag in tags {
+        groups.append_item(&item(
+            RoomTag::display_name(tag),
+            &RoomTag::Custom(tag.clone()),
+        ));
+    }
+    groups.append_item(&item(&i18n("Low Priority"), &RoomTag::LowPriority));
+
+    let others = gio::Menu::new();
+    let new_tag = gio::MenuItem::new(Some(&i18n("New Tag…")), None);
+    new_tag.set_action_and_target_value(
+        Some("app.new-room-tag"),
+        Some(&room_id.as_str().to_variant()),
+    );
+    others.append_item(&new_tag);
+    if *group_tag != RoomTag::None {
+        others.append_item(&action_item(
+            &i18n("Remove Tag"),
+            "app.remove-room-tag",
+            group_tag,
+        ));
     }
 
-    pub fn filter_rooms(&self, term: Option<String>) {
-        self.inv.get().filter_rooms(&term);
-        self.fav.get().filter_rooms(&term);
-        self.rooms.get().filter_rooms(&term);
+    let menu = gio::Menu::new();
+    menu.append_section(None, &groups);
+    menu.append_section(None, &others);
+    menu
+}
+
+fn popup_menu<W: IsA<gtk::Widget>>(widget: &W, menu: &gio::Menu, coords: (f64, f64)) {
+    let popover = gtk::Popover::from_model(Some(widget), menu);
+    let rect = gtk::Rectangle {
+        x: coords.0 as i32,
+        y: coords.1 as i32,
+        width: 0,
+        height: 0,
+    };
+    popover.set_pointing_to(&rect);
+    popover.set_position(gtk::PositionType::Bottom);
+    popover.popup();
+}
+
+/// The room id and the name of the tag are sent together, room ids can't have spaces
+fn activate_room_tag_action(action: &str, room_id: &RoomId, tag: &RoomTag) {
+    let target = format!("{} {}", room_id, tag.name().unwrap_or_default());
+    if let Some(app) = gio::Application::get_default() {
+        app.activate_action(action, Some(&target.to_variant()));
     }
 }
 
@@ -810,20 +1401,19 @@ impl RoomList {
 /// Translated from https://gitlab.gnome.org/GNOME/gtk/blob/d3ad6425/gtk/inspector/general.c#L655
 fn keynav_cb(
     direction: gtk::DirectionType,
-    inv_lb: &gtk::ListBox,
-    fav_lb: &gtk::ListBox,
-    room_lb: &gtk::ListBox,
+    list: &gtk::ListBox,
+    lists: &[gtk::ListBox],
     adj: Option<gtk::Adjustment>,
-    type_: RoomListType,
 ) -> gtk::Inhibit {
-    let next: Option<&gtk::ListBox>;
-    next = match (direction, type_) {
-        (gtk::DirectionType::Down, RoomListType::Invites) => Some(fav_lb),
-        (gtk::DirectionType::Down, RoomListType::Favorites) => Some(room_lb),
-        (gtk::DirectionType::Up, RoomListType::Rooms) => Some(fav_lb),
-        (gtk::DirectionType::Up, RoomListType::Favorites) => Some(inv_lb),
-        _ => None,
-    };
+    // Skip the hidden and collapsed groups
+    let next = lists
+        .iter()
+        .position(|l| l == list)
+        .and_then(|idx| match direction {
+            gtk::DirectionType::Down => lists[idx + 1..].iter().find(|l| l.get_mapped()),
+            gtk::DirectionType::Up => lists[..idx].iter().rev().find(|l| l.get_mapped()),
+            _ => None,
+        });
 
     if let Some(widget) = next {
         widget.child_focus(direction);
@@ -849,3 +1439,84 @@ fn keynav_cb(
         gtk::Inhibit(false)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(id: &str, name: &str, notifications: u64, highlight: u64, hour: u32) -> RoomUpdated {
+        let room_id = RoomId::try_from(id).unwrap();
+        let mut room = Room::new(room_id, RoomMembership::Joined(RoomTag::None));
+        room.name = Some(name.to_string());
+        room.notifications = notifications;
+        room.highlight = highlight;
+
+        RoomUpdated {
+            room,
+            updated: Local.ymd(2021, 3, 10).and_hms(hour, 0, 0),
+            last_message: None,
+        }
+    }
+
+    fn sorted(sort: RoomSortOrder, mut rooms: Vec<RoomUpdated>) -> Vec<String> {
+        rooms.sort_by(|a, b| sort.compare(a, b));
+        rooms
+            .into_iter()
+            .map(|r| r.room.name.unwrap_or_default())
+            .collect()
+    }
+
+    fn rooms() -> Vec<RoomUpdated> {
+        vec![
+            room("!a:example.org", "beta", 0, 0, 12),
+            room("!b:example.org", "Alpha", 3, 0, 10),
+            room("!c:example.org", "gamma", 1, 1, 8),
+            room("!d:example.org", "delta", 0, 0, 14),
+        ]
+    }
+
+    #[test]
+    fn sort_recent() {
+        let out = sorted(RoomSortOrder::Recent, rooms());
+        assert_eq!(out, vec!["delta", "beta", "Alpha", "gamma"]);
+    }
+
+    #[test]
+    fn sort_unread() {
+        let out = sorted(RoomSortOrder::Unread, rooms());
+        assert_eq!(out, vec!["Alpha", "gamma", "delta", "beta"]);
+    }
+
+    #[test]
+    fn sort_alphabetical() {
+        let out = sorted(RoomSortOrder::Alphabetical, rooms());
+        assert_eq!(out, vec!["Alpha", "beta", "delta", "gamma"]);
+
+        // The same names go by activity
+        let same = vec![
+            room("!a:example.org", "room", 0, 0, 8),
+            room("!b:example.org", "Room", 0, 0, 9),
+        ];
+        let out = sorted(RoomSortOrder::Alphabetical, same);
+        assert_eq!(out, vec!["Room", "room"]);
+    }
+
+    #[test]
+    fn sort_importance() {
+        let out = sorted(RoomSortOrder::Importance, rooms());
+        assert_eq!(out, vec!["gamma", "Alpha", "delta", "beta"]);
+    }
+
+    #[test]
+    fn sort_order_names() {
+        for sort in &[
+            RoomSortOrder::Recent,
+            RoomSortOrder::Unread,
+            RoomSortOrder::Alphabetical,
+            RoomSortOrder::Importance,
+        ] {
+            assert_eq!(RoomSortOrder::from(sort.as_str()), *sort);
+        }
+        assert_eq!(RoomSortOrder::from("unknown"), RoomSortOrder::Recent);
+    }
+}
@@ -1,23 +1,44 @@
+use chrono::prelude::*;
 use gtk::prelude::*;
 use matrix_sdk::identifiers::MxcUri;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
-use crate::model::room::Room;
+use crate::model::draft::Draft;
+use crate::model::member::Member;
+use crate::model::message::Message;
+use crate::model::room::{Room, RoomTag};
 
+use crate::util;
+use crate::util::i18n::{i18n, i18n_f};
 use crate::widgets;
 use crate::widgets::AvatarExt;
 
 const ICON_SIZE: i32 = 24;
 
-// Room row for the room sidebar. This widget shows the room avatar, the room name and the unread
-// messages in the room
+// Room row for the room sidebar. This widget shows the room avatar, the room name, the time of
+// the last activity, the unread messages in the room and, unless the list is compact, who is
+// typing or the last message. The beginning of the unsent message replaces the last message.
 // +-----+--------------------------+------+
-// | IMG | Fractal                  |  32  |
+// | IMG | Fractal        5 min ago |  32  |
+// |     | Alice: Hello             |      |
 // +-----+--------------------------+------+
 pub struct RoomRow {
     pub room: Room,
     pub icon: widgets::Avatar,
     pub direct: gtk::Image,
     pub text: gtk::Label,
+    /// How long ago the last message was received
+    pub activity: gtk::Label,
+    activity_date: Cell<Option<DateTime<Local>>>,
+    pub draft: gtk::Label,
+    /// The last message or the users typing
+    pub preview: gtk::Label,
+    last_message: Option<String>,
+    typing: Option<String>,
+    /// Only the room name and the draft are shown in the compact mode
+    compact: bool,
+    pub muted: gtk::Image,
     pub notifications: gtk::Label,
     pub widget: gtk::EventBox,
 }
@@ -37,6 +58,35 @@ impl RoomRow {
         text.set_halign(gtk::Align::Start);
         text.set_ellipsize(pango::EllipsizeMode::End);
 
+        let activity = gtk::Label::new(None);
+        activity.set_valign(gtk::Align::Start);
+        activity.get_style_context().add_class("room-row-activity");
+        activity.get_style_context().add_class("dim-label");
+        let activity_date = Cell::new(room.last_message().map(|m| m.date));
+
+        let draft = gtk::Label::new(None);
+        draft.set_halign(gtk::Align::Start);
+        draft.set_ellipsize(pango::EllipsizeMode::End);
+        draft.set_single_line_mode(true);
+        draft.get_style_context().add_class("room-row-draft");
+        draft.get_style_context().add_class("dim-label");
+
+        let preview = gtk::Label::new(None);
+        preview.set_halign(gtk::Align::Start);
+        preview.set_ellipsize(pango::EllipsizeMode::End);
+        preview.set_single_line_mode(true);
+        preview.get_style_context().add_class("room-row-preview");
+        preview.get_style_context().add_class("dim-label");
+        let last_message = room.last_message().map(|msg| message_preview(&room, msg));
+
+        let muted = gtk::Image::from_icon_name(
+            Some("notifications-disabled-symbolic"),
+            gtk::IconSize::Menu,
+        );
+        muted.set_tooltip_text(Some(i18n("Muted").as_str()));
+        muted.set_no_show_all(true);
+        muted.get_style_context().add_class("dim-label");
+
         let n = room.notifications;
         let h = room.highlight;
         let ntext = if room.membership.is_invited() {
@@ -66,12 +116,21 @@ impl RoomRow {
             room,
             icon,
             text,
+            activity,
+            activity_date,
+            draft,
+            preview,
+            last_message,
+            typing: None,
+            compact: false,
+            muted,
             notifications,
             widget,
             direct,
         };
 
-        rr.connect_dnd();
+        rr.render_draft();
+        render_activity(&rr.activity, rr.activity_date.get());
 
         rr
     }
@@ -117,6 +176,84 @@ impl RoomRow {
         self.text.set_text(&name);
     }
 
+    pub fn set_draft(&mut self, draft: Option<Draft>) {
+        self.room.draft = draft;
+        self.render_draft();
+    }
+
+    pub fn set_last_message(&mut self, msg: &Message) {
+        self.set_last_message_preview(message_preview(&self.room, msg), msg.date);
+    }
+
+    /// Shows the preview of the last message, received at `date`
+    pub fn set_last_message_preview(&mut self, preview: String, date: DateTime<Local>) {
+        self.last_message = Some(preview);
+        self.set_activity(date);
+        self.render_draft();
+    }
+
+    /// The preview of the last message along with its date, to show it in another row
+    pub fn last_message_preview(&self) -> Option<(String, DateTime<Local>)> {
+        self.last_message.clone().zip(self.activity_date.get())
+    }
+
+    /// Shows who is typing instead of the last message, the names being empty when nobody is
+    pub fn set_typing(&mut self, names: &[String]) {
+        self.typing = match names {
+            [] => None,
+            [name] => Some(i18n_f("{} is typing…", &[name.as_str()])),
+            [first, second] => Some(i18n_f(
+                "{} and {} are typing…",
+                &[first.as_str(), second.as_str()],
+            )),
+            _ => Some(i18n("Several users are typing…")),
+        };
+        self.render_draft();
+    }
+
+    pub fn set_compact(&mut self, compact: bool) {
+        self.compact = compact;
+        self.render_draft();
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.set_visible(muted);
+    }
+
+    pub fn set_activity(&self, date: DateTime<Local>) {
+        self.activity_date.set(Some(date));
+        render_activity(&self.activity, Some(date));
+    }
+
+    /// Shows again how long ago the last message was received, as the time goes by
+    pub fn refresh_activity(&self) {
+        render_activity(&self.activity, self.activity_date.get());
+    }
+
+    /// Shows the draft, or what's going on in the room when there's none
+    fn render_draft(&self) {
+        let snippet = self.room.draft.as_ref().and_then(Draft::snippet);
+        let typing = self.typing.as_deref().filter(|_| !self.compact);
+        let last_message = self
+            .last_message
+            .as_deref()
+            .filter(|_| !self.compact && snippet.is_none());
+
+        self.draft.set_text(snippet.as_deref().unwrap_or_default());
+        self.draft
+            .set_visible(typing.is_none() && snippet.is_some());
+
+        let preview = typing.or(last_message);
+        self.preview.set_text(preview.unwrap_or_default());
+        self.preview.set_visible(preview.is_some());
+        let style = self.preview.get_style_context();
+        if typing.is_some() {
+            style.add_class("room-row-typing");
+        } else {
+            style.remove_class("room-row-typing");
+        }
+    }
+
     pub fn set_avatar(&mut self, avatar: Option<MxcUri>) {
         self.room.avatar = avatar;
 
@@ -140,15 +277,25 @@ impl RoomRow {
         if self.room.direct {
             b.pack_start(&self.direct, false, false, 0);
         }
-        self.text.set_valign(gtk::Align::Center);
+        let labels = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        labels.set_valign(gtk::Align::Center);
+        let title = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        title.pack_start(&self.text, true, true, 0);
+        title.pack_end(&self.activity, false, false, 0);
+        labels.pack_start(&title, false, false, 0);
+        labels.pack_start(&self.draft, false, false, 0);
+        labels.pack_start(&self.preview, false, false, 0);
         self.notifications.set_valign(gtk::Align::Center);
-        b.pack_start(&self.text, true, true, 0);
+        b.pack_start(&labels, true, true, 0);
+        b.pack_start(&self.muted, false, false, 0);
         b.pack_start(&self.notifications, false, false, 5);
         self.widget.show_all();
 
         if self.room.notifications == 0 {
             self.notifications.hide();
         }
+        self.render_draft();
+        render_activity(&self.activity, self.activity_date.get());
 
         let row = gtk::ListBoxRow::new();
         row.add(&self.widget);
@@ -159,7 +306,8 @@ impl RoomRow {
         row
     }
 
-    pub fn connect_dnd(&self) {
+    /// Lets the row be dragged to another group, `tag` is the one of its group
+    pub fn connect_dnd(&self, tag: Rc<RefCell<RoomTag>>) {
         if self.room.membership.is_invited() {
             return;
         }
@@ -183,10 +331,38 @@ impl RoomRow {
             ctx.drag_set_icon_surface(&image);
         });
 
+        // Room ids can't have spaces, the name of the tag goes after it
         let id = self.room.id.to_string();
         self.widget
             .connect_drag_data_get(move |_w, _, data, _x, _y| {
-                data.set_text(&id);
+                let text = format!("{} {}", id, tag.borrow().name().unwrap_or_default());
+                data.set_text(&text);
             });
     }
 }
+
+fn render_activity(label: &gtk::Label, date: Option<DateTime<Local>>) {
+    match date {
+        Some(date) => {
+            label.set_text(&util::time::relative(&date, &Local::now()));
+            label.set_tooltip_text(Some(&util::time::full(&date)));
+            label.show();
+        }
+        None => label.hide(),
+    }
+}
+
+/// The sender and the beginning of the message, for the second line of the row
+fn message_preview(room: &Room, msg: &Message) -> String {
+    let sender = room
+        .members
+        .get(&msg.sender)
+        .map(Member::get_alias)
+        .unwrap_or_else(|| msg.sender.localpart().to_string());
+
+    if msg.mtype == "m.emote" {
+        format!("* {} {}", sender, msg.snippet())
+    } else {
+        i18n_f("{}: {}", &[&sender, &msg.snippet()])
+    }
+}
@@ -1,6 +1,12 @@
+use crate::model::draft::TextFormat;
+use crate::model::member::Member;
+use crate::widgets::formatting_toolbar;
+use crate::widgets::FormattingToolbar;
 use gtk::prelude::*;
 use libhandy::prelude::*;
 use sourceview4::ViewExt;
+use std::cell::RefCell;
+use std::rc::Rc;
 // This alias is necessary to avoid conflict with gtk's TextViewExt
 use gspell::TextViewExt as GspellTextViewExt;
 
@@ -15,7 +21,10 @@ pub struct SVEntry {
     pub scroll: gtk::ScrolledWindow,
     pub view: sourceview4::View,
     pub buffer: sourceview4::Buffer,
+    pub toolbar: FormattingToolbar,
     pub send: gtk::Button,
+    /// The mention pills inserted in the buffer, with the member they stand for
+    pub mentions: Rc<RefCell<Vec<(Member, gtk::TextChildAnchor)>>>,
 }
 
 impl Default for SVEntry {
@@ -71,6 +80,9 @@ impl Default for SVEntry {
         let gspell_view = gspell::TextView::get_from_gtk_text_view(textview).unwrap();
         gspell_view.basic_setup();
 
+        let toolbar = FormattingToolbar::new(view.upcast_ref(), buffer.upcast_ref());
+        entry_box.add(&toolbar.revealer);
+
         scroll.add(&view);
         scroll.set_hexpand(true);
         scroll.set_vexpand(true);
@@ -105,7 +117,115 @@ impl Default for SVEntry {
             scroll,
             view,
             buffer,
+            toolbar,
             send,
+            mentions: Default::default(),
+        }
+    }
+}
+
+impl SVEntry {
+    /// Gets the text in the entry, with the mention pills replaced by the name of their member.
+    /// The members are returned along with the byte offset of their name in the text.
+    pub fn get_text_with_mentions(&self) -> (String, Vec<(usize, Member)>) {
+        let start = self.buffer.get_start_iter();
+        let end = self.buffer.get_end_iter();
+        let slice = self
+            .buffer
+            .get_slice(&start, &end, false)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        let mentions = self.mentions.borrow();
+        let mut text = String::with_capacity(slice.len());
+        let mut members = vec![];
+        for (offset, c) in slice.chars().enumerate() {
+            if c != '\u{FFFC}' {
+                text.push(c);
+                continue;
+            }
+
+            let anchor = self
+                .buffer
+                .get_iter_at_offset(offset as i32)
+                .get_child_anchor();
+            let member = anchor.and_then(|anchor| {
+                mentions
+                    .iter()
+                    .find(|(_, a)| *a == anchor)
+                    .map(|(member, _)| member.clone())
+            });
+
+            if let Some(member) = member {
+                let offset = text.len();
+                text.push_str(&member.get_alias());
+                members.push((offset, member));
+            }
+        }
+
+        (text, members)
+    }
+
+    /// Gets the text in the entry as a plain body and an HTML body, if it was formatted
+    /// with the toolbar
+    pub fn get_formatted_text(&self) -> Option<(String, String)> {
+        formatting_toolbar::serialize(self.buffer.upcast_ref(), &self.mentions.borrow())
+    }
+
+    /// Gets the formats of the entry, with their offsets in the text returned by
+    /// `get_text_with_mentions`
+    pub fn get_formats(&self) -> Vec<TextFormat> {
+        let start = self.buffer.get_start_iter();
+        let end = self.buffer.get_end_iter();
+        let slice = self
+            .buffer
+            .get_slice(&start, &end, false)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        // The offset in the text of each offset in the buffer, the pills being replaced by
+        // the name of their member
+        let mentions = self.mentions.borrow();
+        let mut offsets = vec![0];
+        let mut offset = 0;
+        for (n, c) in slice.chars().enumerate() {
+            if c != '\u{FFFC}' {
+                offset += 1;
+            } else if let Some(anchor) = self.buffer.get_iter_at_offset(n as i32).get_child_anchor()
+            {
+                offset += mentions
+                    .iter()
+                    .find(|(_, a)| *a == anchor)
+                    .map_or(0, |(member, _)| member.get_alias().chars().count() as i32);
+            }
+            offsets.push(offset);
         }
+        let text_offset = |n: i32| offsets.get(n as usize).copied().unwrap_or(offset);
+
+        self.toolbar
+            .formats()
+            .into_iter()
+            .map(|(name, start, end)| TextFormat {
+                name,
+                start: text_offset(start),
+                end: text_offset(end),
+            })
+            .filter(|format| format.start < format.end)
+            .collect()
+    }
+
+    /// Formats the text of the entry again, the mention pills having been replaced by the
+    /// name of their member
+    pub fn apply_formats(&self, formats: &[TextFormat]) {
+        let formats: Vec<(String, i32, i32)> = formats
+            .iter()
+            .map(|format| (format.name.clone(), format.start, format.end))
+            .collect();
+        self.toolbar.apply_formats(&formats);
+    }
+
+    pub fn clear(&self) {
+        self.buffer.set_text("");
+        self.mentions.borrow_mut().clear();
     }
 }
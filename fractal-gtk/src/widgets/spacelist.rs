@@ -0,0 +1,100 @@
+use gtk::prelude::*;
+use matrix_sdk::identifiers::RoomId;
+
+use crate::model::room::Room;
+use crate::util::i18n::i18n;
+
+use crate::widgets;
+use crate::widgets::AvatarExt;
+
+const ICON_SIZE: i32 = 36;
+
+// Space sidebar, next to the room list. The first row shows all the rooms and the other ones
+// only the rooms of a space
+// +-----+
+// |  ⌂  |
+// +-----+
+// | IMG |
+// +-----+
+// | IMG |
+// +-----+
+pub struct SpaceList {
+    pub widget: gtk::ListBox,
+    /// The spaces of the rows after the first one, in order
+    spaces: Vec<RoomId>,
+}
+
+impl Default for SpaceList {
+    fn default() -> Self {
+        let widget = gtk::ListBox::new();
+        widget.get_style_context().add_class("space-list");
+
+        let icon =
+            gtk::Image::from_icon_name(Some("user-home-symbolic"), gtk::IconSize::LargeToolbar);
+        icon.set_size_request(ICON_SIZE, ICON_SIZE);
+        let row = space_row(&icon, &i18n("All Rooms"), "");
+        widget.add(&row);
+        widget.select_row(Some(&row));
+
+        SpaceList {
+            widget,
+            spaces: vec![],
+        }
+    }
+}
+
+impl SpaceList {
+    /// Replaces the rows of the spaces, the first row stays
+    pub fn set_spaces(&mut self, spaces: &[&Room]) {
+        for row in self.widget.get_children().iter().skip(1) {
+            self.widget.remove(row);
+        }
+
+        for space in spaces {
+            let name = space.name.clone().unwrap_or_else(|| "...".to_string());
+            let icon = widgets::Avatar::avatar_new(Some(ICON_SIZE));
+            icon.circle(
+                space.id.to_string(),
+                Some(name.clone()),
+                ICON_SIZE,
+                None,
+                None,
+            );
+
+            let row = space_row(&icon, &name, space.id.as_str());
+            self.widget.add(&row);
+        }
+
+        self.spaces = spaces.iter().map(|space| space.id.clone()).collect();
+    }
+
+    /// Selects the row of the space, or the first one for `None`
+    pub fn select(&self, space_id: Option<&RoomId>) {
+        let idx = space_id
+            .and_then(|space_id| self.spaces.iter().position(|id| id == space_id))
+            .map_or(0, |idx| idx + 1);
+
+        if let Some(row) = self.widget.get_row_at_index(idx as i32) {
+            self.widget.select_row(Some(&row));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spaces.is_empty()
+    }
+}
+
+/// The row opens the space with the `app.select-space` action, an empty `target` is for all
+/// the rooms
+fn space_row<W: IsA<gtk::Widget>>(icon: &W, name: &str, target: &str) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    row.get_style_context().add_class("space-row");
+    row.set_tooltip_text(Some(name));
+    row.add(icon);
+    row.show_all();
+
+    row.set_action_target_value(Some(&target.to_variant()));
+    row.set_action_name(Some("app.select-space"));
+
+    row
+}